@@ -0,0 +1,380 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::transport::{
+    DeviceSelector, EmulatorTransport, GpioLineConfig, OpenConfig, Parity, ReplayConfig,
+    ReplayTransport, SpiConfig, SpiTransport, StopBits, TcpTransport, Transport, UartTransport,
+    UnixAddress, UnixTransport, VsockTransport,
+};
+use crate::uwb_chip::{
+    UwbChip, DEFAULT_CLOSE_TIMEOUT, DEFAULT_WRITE_QUEUE_CAPACITY, DEFAULT_WRITE_TIMEOUT,
+};
+
+/// Reason why [`UwbChipBuilder::build`] failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// A mandatory setting was not given.
+    MissingField(&'static str),
+    /// Two settings cannot be used together.
+    InvalidCombination(&'static str, &'static str),
+    /// The path cannot be parsed.
+    InvalidPath(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "missing {}", field),
+            Self::InvalidCombination(first, second) => {
+                write!(f, "{} cannot be used with {}", first, second)
+            }
+            Self::InvalidPath(err) => f.write_str(err),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Builds a chip from the path of its UWBS. The path is a UART device
+/// node unless it is of the form `spi://<spidev node>`, or an SPI clock
+/// speed is given. The UART of a USB device can also be selected by its
+/// hexadecimal vendor and product identifiers with `usb://<vid>:<pid>`,
+/// see [`DeviceSelector`]. A path of the form
+/// `tcp://<host>:<port>` connects to an emulated UWBS instead,
+/// `unix://<path>` or `unix-abstract://<name>` to a daemon exposing the
+/// UWBS over a unix socket, and `vsock://<cid>:<port>` to a device model
+/// reached over vsock. A `mock://` path emulates a UWBS, and
+/// `replay://<path>` replays the trace at `path`, see [`ReplayTransport`].
+/// The name and the path are mandatory.
+#[derive(Default)]
+pub struct UwbChipBuilder {
+    name: Option<String>,
+    path: Option<String>,
+    spi_speed_hz: Option<u32>,
+    spi_mode: Option<u8>,
+    spi_bits_per_word: Option<u8>,
+    spi_data_ready: Option<GpioLineConfig>,
+    baud_rate: Option<u32>,
+    hw_flow_control: bool,
+    parity: Parity,
+    stop_bits: StopBits,
+    monitor: bool,
+    mock_latency: Duration,
+    replay: ReplayConfig,
+    close_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    watchdog_timeout: Option<Duration>,
+    reset_gpio: Option<PathBuf>,
+    write_queue_capacity: Option<usize>,
+}
+
+impl UwbChipBuilder {
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn path(mut self, path: String) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// Reach the UWBS over SPI, clocked at `speed_hz`.
+    pub fn spi_speed_hz(mut self, speed_hz: u32) -> Self {
+        self.spi_speed_hz = Some(speed_hz);
+        self
+    }
+
+    /// Set the SPI mode of the bus, from 0 to 3.
+    pub fn spi_mode(mut self, mode: u8) -> Self {
+        self.spi_mode = Some(mode);
+        self
+    }
+
+    pub fn spi_bits_per_word(mut self, bits_per_word: u8) -> Self {
+        self.spi_bits_per_word = Some(bits_per_word);
+        self
+    }
+
+    /// Wait for the UWBS to assert `line` before reading its packets,
+    /// instead of polling it, see [`SpiTransport`].
+    pub fn spi_data_ready(mut self, line: GpioLineConfig) -> Self {
+        self.spi_data_ready = Some(line);
+        self
+    }
+
+    /// Set the line speed of a UART, in bauds. Opening the chip fails if
+    /// the speed is not supported.
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = Some(baud_rate);
+        self
+    }
+
+    /// Set the parity of the frames of a UART.
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Set the stop bits of the frames of a UART.
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Enable the RTS/CTS flow control of a UART.
+    pub fn hw_flow_control(mut self, hw_flow_control: bool) -> Self {
+        self.hw_flow_control = hw_flow_control;
+        self
+    }
+
+    /// Only capture the traffic of the UWBS, see [`UwbChip::with_monitor`].
+    /// A UART is then opened read-only.
+    pub fn monitor(mut self, monitor: bool) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    /// Delay the responses of an emulated UWBS.
+    pub fn mock_latency(mut self, latency: Duration) -> Self {
+        self.mock_latency = latency;
+        self
+    }
+
+    /// Set how a trace is replayed.
+    pub fn replay(mut self, config: ReplayConfig) -> Self {
+        self.replay = config;
+        self
+    }
+
+    /// See [`UwbChip::with_close_timeout`].
+    pub fn close_timeout(mut self, close_timeout: Duration) -> Self {
+        self.close_timeout = Some(close_timeout);
+        self
+    }
+
+    /// See [`UwbChip::with_write_timeout`].
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    /// See [`UwbChip::with_watchdog_timeout`].
+    pub fn watchdog_timeout(mut self, watchdog_timeout: Duration) -> Self {
+        self.watchdog_timeout = Some(watchdog_timeout);
+        self
+    }
+
+    /// See [`UwbChip::with_reset_gpio`].
+    pub fn reset_gpio(mut self, path: PathBuf) -> Self {
+        self.reset_gpio = Some(path);
+        self
+    }
+
+    /// See [`UwbChip::with_write_queue_capacity`].
+    pub fn write_queue_capacity(mut self, capacity: usize) -> Self {
+        self.write_queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Create the chip. Fails if the name or the path is missing, or if
+    /// UART or SPI settings are given for another transport.
+    pub fn build(self) -> Result<UwbChip<Box<dyn Transport>>, BuildError> {
+        let name = self.name.ok_or(BuildError::MissingField("name"))?;
+        let path = self.path.ok_or(BuildError::MissingField("path"))?;
+        // Device nodes are the only paths without a scheme, besides USB
+        // devices.
+        let transport_name = if path.starts_with("spi://") || self.spi_speed_hz.is_some() {
+            "SPI"
+        } else if path.contains("://") && !path.starts_with("usb://") {
+            "socket or mock path"
+        } else {
+            "UART"
+        };
+        if transport_name != "SPI"
+            && (self.spi_mode.is_some()
+                || self.spi_bits_per_word.is_some()
+                || self.spi_data_ready.is_some())
+        {
+            return Err(BuildError::InvalidCombination(
+                "SPI settings",
+                transport_name,
+            ));
+        }
+        if transport_name != "UART" {
+            if self.hw_flow_control {
+                return Err(BuildError::InvalidCombination(
+                    "hardware flow control",
+                    transport_name,
+                ));
+            }
+            if self.baud_rate.is_some() {
+                return Err(BuildError::InvalidCombination("baud rate", transport_name));
+            }
+            if self.parity != Parity::None || self.stop_bits != StopBits::One {
+                return Err(BuildError::InvalidCombination(
+                    "UART framing",
+                    transport_name,
+                ));
+            }
+        }
+
+        let transport: Box<dyn Transport> = if transport_name == "SPI" {
+            let default = SpiConfig::default();
+            let config = SpiConfig {
+                speed_hz: self.spi_speed_hz.unwrap_or(default.speed_hz),
+                mode: self.spi_mode.unwrap_or(default.mode),
+                bits_per_word: self.spi_bits_per_word.unwrap_or(default.bits_per_word),
+                data_ready: self.spi_data_ready,
+            };
+            let path = path.strip_prefix("spi://").unwrap_or(&path);
+            Box::new(SpiTransport::new(path, config))
+        } else if let Some(address) = path.strip_prefix("tcp://") {
+            Box::new(TcpTransport::new(address.to_owned()))
+        } else if let Some(socket) = path.strip_prefix("unix://") {
+            Box::new(UnixTransport::new(UnixAddress::Path(socket.to_owned())))
+        } else if let Some(name) = path.strip_prefix("unix-abstract://") {
+            Box::new(UnixTransport::new(UnixAddress::Abstract(name.to_owned())))
+        } else if let Some(address) = path.strip_prefix("vsock://") {
+            Box::new(VsockTransport::new(address.to_owned()))
+        } else if path.starts_with("mock://") {
+            Box::new(EmulatorTransport::new(self.mock_latency))
+        } else if let Some(trace) = path.strip_prefix("replay://") {
+            Box::new(ReplayTransport::new(PathBuf::from(trace), self.replay))
+        } else {
+            let open_config = OpenConfig {
+                read_only: self.monitor,
+                baud_rate: self.baud_rate,
+                hw_flow_control: self.hw_flow_control,
+                parity: self.parity,
+                stop_bits: self.stop_bits,
+            };
+            let device: DeviceSelector = path.parse().map_err(BuildError::InvalidPath)?;
+            Box::new(UartTransport::new(device, open_config))
+        };
+        Ok(UwbChip::with_transport(name, transport)
+            .with_close_timeout(self.close_timeout.unwrap_or(DEFAULT_CLOSE_TIMEOUT))
+            .with_write_timeout(self.write_timeout.unwrap_or(DEFAULT_WRITE_TIMEOUT))
+            .with_watchdog_timeout(self.watchdog_timeout)
+            .with_reset_gpio(self.reset_gpio)
+            .with_write_queue_capacity(
+                self.write_queue_capacity
+                    .unwrap_or(DEFAULT_WRITE_QUEUE_CAPACITY),
+            )
+            .with_monitor(self.monitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_requires_name_and_path() {
+        assert_eq!(
+            UwbChipBuilder::default()
+                .path("/dev/ttyUWB0".to_owned())
+                .build()
+                .err(),
+            Some(BuildError::MissingField("name"))
+        );
+        assert_eq!(
+            UwbChipBuilder::default()
+                .name("main".to_owned())
+                .build()
+                .err(),
+            Some(BuildError::MissingField("path"))
+        );
+
+        let chip = UwbChipBuilder::default()
+            .name("main".to_owned())
+            .path("/dev/ttyUWB0".to_owned())
+            .baud_rate(115200)
+            .hw_flow_control(true)
+            .build()
+            .unwrap();
+        assert_eq!(chip.name(), "main");
+    }
+
+    #[test]
+    fn build_rejects_uart_settings_for_other_transports() {
+        let builder = || {
+            UwbChipBuilder::default()
+                .name("main".to_owned())
+                .path("/dev/spidev1.0".to_owned())
+                .spi_speed_hz(1000000)
+        };
+        assert_eq!(
+            builder().hw_flow_control(true).build().err(),
+            Some(BuildError::InvalidCombination(
+                "hardware flow control",
+                "SPI"
+            ))
+        );
+        assert_eq!(
+            builder().baud_rate(115200).build().err(),
+            Some(BuildError::InvalidCombination("baud rate", "SPI"))
+        );
+        assert_eq!(
+            builder().stop_bits(StopBits::Two).build().err(),
+            Some(BuildError::InvalidCombination("UART framing", "SPI"))
+        );
+        assert!(builder().build().is_ok());
+
+        // USB devices are behind a UART.
+        let usb = || {
+            UwbChipBuilder::default()
+                .name("main".to_owned())
+                .path("usb://1fc9:000b".to_owned())
+        };
+        assert!(usb().baud_rate(3000000).build().is_ok());
+        assert_eq!(
+            usb().spi_mode(1).build().err(),
+            Some(BuildError::InvalidCombination("SPI settings", "UART"))
+        );
+        assert!(matches!(
+            usb().path("usb://1fc9".to_owned()).build().err(),
+            Some(BuildError::InvalidPath(_))
+        ));
+
+        assert_eq!(
+            UwbChipBuilder::default()
+                .name("main".to_owned())
+                .path("tcp://localhost:7000".to_owned())
+                .hw_flow_control(true)
+                .build()
+                .err()
+                .map(|err| err.to_string()),
+            Some("hardware flow control cannot be used with socket or mock path".to_owned())
+        );
+    }
+
+    #[test]
+    fn build_rejects_spi_settings_for_other_transports() {
+        let builder = |path: &str| {
+            UwbChipBuilder::default()
+                .name("main".to_owned())
+                .path(path.to_owned())
+                .spi_mode(3)
+                .spi_bits_per_word(16)
+                .spi_data_ready("/dev/gpiochip0:17".parse().unwrap())
+        };
+        assert!(builder("spi:///dev/spidev0.0").build().is_ok());
+        assert!(builder("/dev/spidev0.0")
+            .spi_speed_hz(8000000)
+            .build()
+            .is_ok());
+        assert_eq!(
+            builder("/dev/ttyUWB0").build().err(),
+            Some(BuildError::InvalidCombination("SPI settings", "UART"))
+        );
+        assert_eq!(
+            builder("spi:///dev/spidev0.0")
+                .baud_rate(115200)
+                .build()
+                .err(),
+            Some(BuildError::InvalidCombination("baud rate", "SPI"))
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+use crate::health;
+
+/// OEM_GET_CALIBRATION_CMD, answered with a status followed by the
+/// calibration data held in the RAM of the UWBS.
+pub const OEM_GET_CALIBRATION_CMD: [u8; 4] = [0x2e, 0x30, 0x00, 0x00];
+/// Opcode of OEM_SET_CALIBRATION_CMD, carrying the calibration data.
+const OEM_SET_CALIBRATION_OID: u8 = 0x31;
+const UCI_STATUS_OK: u8 = 0x00;
+
+/// Version of the calibration file format:
+/// `version | length (u16 LE) | data | CRC32 (LE) of the previous bytes`.
+const FILE_VERSION: u8 = 1;
+const FILE_HEADER_SIZE: usize = 3;
+const FILE_CRC_SIZE: usize = 4;
+
+/// Return the calibration data of an OEM_GET_CALIBRATION_RSP, failing
+/// unless its status is OK.
+pub fn calibration_data(response: &[u8]) -> io::Result<&[u8]> {
+    match response.get(UCI_HEADER_SIZE) {
+        Some(&UCI_STATUS_OK) => Ok(&response[UCI_HEADER_SIZE + 1..]),
+        status => Err(io::Error::other(format!(
+            "calibration read failed with status {:02x?}",
+            status
+        ))),
+    }
+}
+
+/// Return OEM_SET_CALIBRATION_CMD writing `data` to the UWBS.
+pub fn set_calibration_cmd(data: &[u8]) -> io::Result<Vec<u8>> {
+    let length = u8::try_from(data.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} bytes of calibration data", data.len()),
+        )
+    })?;
+    let mut command = OEM_GET_CALIBRATION_CMD.to_vec();
+    command[1] = OEM_SET_CALIBRATION_OID;
+    command[3] = length;
+    command.extend_from_slice(data);
+    Ok(command)
+}
+
+/// Check the status of an OEM_SET_CALIBRATION_RSP.
+pub fn check_set_calibration_rsp(response: &[u8]) -> io::Result<()> {
+    match response.get(UCI_HEADER_SIZE) {
+        Some(&UCI_STATUS_OK) => Ok(()),
+        status => Err(io::Error::other(format!(
+            "calibration write failed with status {:02x?}",
+            status
+        ))),
+    }
+}
+
+/// Write `data` to `path`, replacing the previous file only once the new
+/// one is complete.
+pub fn save(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = Vec::with_capacity(FILE_HEADER_SIZE + data.len() + FILE_CRC_SIZE);
+    file.push(FILE_VERSION);
+    file.extend((data.len() as u16).to_le_bytes());
+    file.extend_from_slice(data);
+    file.extend(crc32(&file).to_le_bytes());
+    let partial = path.with_extension("partial");
+    fs::write(&partial, &file)?;
+    fs::rename(&partial, path)
+}
+
+/// Read the calibration data saved to `path`, failing with `InvalidData`
+/// if the file is corrupted or of another version.
+pub fn load(path: &Path) -> io::Result<Vec<u8>> {
+    let file = fs::read(path)?;
+    decode(&file).map(<[u8]>::to_vec).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), err),
+        )
+    })
+}
+
+fn decode(file: &[u8]) -> Result<&[u8], String> {
+    if file.len() < FILE_HEADER_SIZE + FILE_CRC_SIZE {
+        return Err(format!("truncated to {} bytes", file.len()));
+    }
+    let (content, crc) = file.split_at(file.len() - FILE_CRC_SIZE);
+    let crc = u32::from_le_bytes(crc.try_into().unwrap());
+    if crc32(content) != crc {
+        return Err("CRC mismatch".to_owned());
+    }
+    if content[0] != FILE_VERSION {
+        return Err(format!("unsupported version {}", content[0]));
+    }
+    let length = u16::from_le_bytes([content[1], content[2]]) as usize;
+    let data = &content[FILE_HEADER_SIZE..];
+    if data.len() != length {
+        return Err(format!("{} bytes of data, expected {}", data.len(), length));
+    }
+    Ok(data)
+}
+
+/// Read the UCI commands sent to the UWBS on coreInit from `path`, one
+/// per line in hexadecimal, e.g. `2e0100021a2b`. Empty lines and lines
+/// starting with `#` are ignored. Fails with `InvalidData` if a line is
+/// not a single control command.
+pub fn load_commands(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let content = fs::read_to_string(path)?;
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            health::parse_command(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: invalid command {:?}",
+                        path.display(),
+                        index + 1,
+                        line
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Return whether `response` to a calibration command has an OK status.
+pub fn is_ok_rsp(response: &[u8]) -> bool {
+    response.get(UCI_HEADER_SIZE) == Some(&UCI_STATUS_OK)
+}
+
+/// CRC-32 (IEEE 802.3) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(prefix: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}_{}", prefix, std::process::id()))
+    }
+
+    #[test]
+    fn calibration_file_is_checked() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let path = temp_path("uwb_calibration_file");
+        save(&path, &[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(load(&path).unwrap(), [0x01, 0x02, 0x03]);
+
+        // A flipped bit.
+        let mut file = fs::read(&path).unwrap();
+        file[4] ^= 0x10;
+        fs::write(&path, &file).unwrap();
+        let err = load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(": CRC mismatch"), "{}", err);
+
+        fs::write(&path, [FILE_VERSION, 0x00]).unwrap();
+        assert_eq!(load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn calibration_commands_are_listed() {
+        let path = temp_path("uwb_calibration_commands");
+        fs::write(&path, "# Country code.\n2e010002\t4652\n\n  2e0200011a  \n").unwrap();
+        let err = load_commands(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string()
+                .ends_with(":2: invalid command \"2e010002\\t4652\""),
+            "{}",
+            err
+        );
+
+        fs::write(&path, "# Country code.\n2e0100024652\n\n  2e0200011a  \n").unwrap();
+        assert_eq!(
+            load_commands(&path).unwrap(),
+            [
+                vec![0x2e, 0x01, 0x00, 0x02, 0x46, 0x52],
+                vec![0x2e, 0x02, 0x00, 0x01, 0x1a]
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,541 @@
+use android_hardware_uwb::aidl::android::hardware::uwb::{
+    IUwb::{self, IUwb as _},
+    IUwbChip::{self, IUwbChip as _, IUwbChipAsyncServer},
+    IUwbClientCallback::IUwbClientCallback,
+};
+use android_hardware_uwb::binder;
+use async_trait::async_trait;
+use binder::{Result, Strong};
+use binder_tokio::TokioRuntime;
+use tokio::runtime::Handle as TokioHandle;
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::HalError;
+use crate::health;
+use crate::report::DumpFormat;
+use crate::transport::Transport;
+use crate::uwb::Uwb;
+use crate::uwb_chip::{CrashReporter, UwbChip, UwbHealthChecker};
+
+/// Owns the chips served by the HAL, and coordinates operations
+/// spanning all of them.
+pub struct UwbChipManager<T: Transport> {
+    chips: HashMap<String, Arc<UwbChip<T>>>,
+    /// Chip names, in registration order.
+    names: Vec<String>,
+}
+
+impl<T: Transport + 'static> UwbChipManager<T> {
+    pub fn new() -> Self {
+        Self {
+            chips: HashMap::new(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Add a chip, replacing the chip previously registered with the
+    /// same name, if any.
+    pub fn register(&mut self, chip: UwbChip<T>) {
+        let name = chip.name().to_owned();
+        if self.chips.insert(name.clone(), Arc::new(chip)).is_some() {
+            log::warn!("replacing chip {}", name);
+        } else {
+            self.names.push(name);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<UwbChip<T>>> {
+        self.chips.get(name).cloned()
+    }
+
+    /// Return all the chips, in registration order.
+    pub fn chips(&self) -> Vec<Arc<UwbChip<T>>> {
+        self.names
+            .iter()
+            .map(|name| self.chips[name].clone())
+            .collect()
+    }
+
+    pub fn crash_reporters(&self) -> Vec<CrashReporter> {
+        self.names
+            .iter()
+            .map(|name| self.chips[name].crash_reporter())
+            .collect()
+    }
+
+    /// Register the IUwb service exposing all the chips, served on the
+    /// runtime behind `handle`, along with the tasks reopening the chips
+    /// when their client dies and watching the hot-pluggable chips.
+    pub fn add_service(&self, handle: TokioHandle) -> Result<()> {
+        for name in &self.names {
+            handle.spawn(self.chips[name].clone().reconnect_on_client_death());
+        }
+        let chips = self.chip_binders(&handle);
+        // Every chip is also registered on its own, for the clients
+        // dedicated to one of them. The instances must be declared in
+        // the VINTF manifest of the device.
+        for (name, chip) in self.names.iter().zip(&chips) {
+            let instance = format!("{}/{}", IUwbChip::BpUwbChip::get_descriptor(), name);
+            let binder = chip.as_binder();
+            let register = move || {
+                if let Err(err) = binder::add_service(&instance, binder) {
+                    log::warn!("failed to register {}: {:?}", instance, err);
+                }
+            };
+            let chip = &self.chips[name];
+            if chip.hot_pluggable() {
+                // Services cannot be unregistered: a hot-pluggable chip is
+                // registered when its device first appears, and refuses to
+                // open while unplugged.
+                let mut register = Some(register);
+                handle.spawn(chip.clone().watch_device(move || {
+                    if let Some(register) = register.take() {
+                        register();
+                    }
+                }));
+            } else {
+                register();
+            }
+        }
+        binder::add_service(
+            &format!("{}/default", IUwb::BpUwb::get_descriptor()),
+            IUwb::BnUwb::new_binder(Uwb::from_chips(chips), binder::BinderFeatures::default())
+                .as_binder(),
+        )?;
+        Ok(())
+    }
+
+    /// Create the binder objects of the chips, in registration order,
+    /// served on the runtime behind `handle`.
+    pub fn chip_binders(&self, handle: &TokioHandle) -> Vec<Strong<dyn IUwbChip::IUwbChip>> {
+        self.names
+            .iter()
+            .filter_map(|name| self.get(name))
+            .map(|chip| {
+                IUwbChip::BnUwbChip::new_async_binder(
+                    SharedChip(chip, handle.clone()),
+                    TokioRuntime(handle.clone()),
+                    binder::BinderFeatures::default(),
+                )
+            })
+            .collect()
+    }
+
+    /// Close all the opened chips concurrently, giving up on a chip after
+    /// `timeout`. Chips already closed are skipped. Returns the chips which
+    /// failed to close, with their error.
+    pub async fn close_all(&self, timeout: Duration) -> Vec<(String, binder::Status)> {
+        self.for_each_chip(|chip| async move {
+            match tokio::time::timeout(timeout, chip.close()).await {
+                Ok(result) => result,
+                Err(_) => Err(HalError::CommandTimeout(timeout).into()),
+            }
+        })
+        .await
+    }
+
+    /// Run `operation` on all the chips concurrently. Returns the chips for
+    /// which it failed, except with ILLEGAL_STATE, with their error.
+    async fn for_each_chip<F, Fut>(&self, operation: F) -> Vec<(String, binder::Status)>
+    where
+        F: Fn(Arc<UwbChip<T>>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut tasks = tokio::task::JoinSet::new();
+        for name in &self.names {
+            let name = name.clone();
+            let operation = operation(self.chips[&name].clone());
+            tasks.spawn(async move { (name, operation.await) });
+        }
+        let mut errors = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            match result.unwrap() {
+                (_, Ok(())) => (),
+                // The chip was not in the state required.
+                (_, Err(err)) if err.exception_code() == binder::ExceptionCode::ILLEGAL_STATE => {}
+                (name, Err(err)) => errors.push((name, err)),
+            }
+        }
+        errors
+    }
+
+    /// Write the packet trace of every chip to `<dir>/uwb_chip_<name>.pcapng`.
+    /// Returns the chips whose trace could not be written, with their error.
+    pub fn dump_traces(&self, dir: &Path) -> Vec<(String, io::Error)> {
+        self.names
+            .iter()
+            .filter_map(|name| {
+                let path = dir.join(format!("uwb_chip_{}.pcapng", name));
+                self.chips[name]
+                    .dump_pcapng(&path)
+                    .err()
+                    .map(|err| (name.clone(), err))
+            })
+            .collect()
+    }
+}
+
+/// Uid of root.
+const AID_ROOT: u32 = 0;
+/// Uid of the shell, as in `adb shell`.
+const AID_SHELL: u32 = 2000;
+
+/// Fail with PERMISSION_DENIED unless the dumpsys command comes from root
+/// or the shell: the commands reading or writing files, or reprogramming
+/// the UWBS, are not for every client allowed to dump the service.
+fn check_shell_caller() -> std::result::Result<(), binder::StatusCode> {
+    match binder::ThreadState::get_calling_uid() {
+        AID_ROOT | AID_SHELL => Ok(()),
+        uid => {
+            log::warn!("refusing dumpsys command from uid {}", uid);
+            Err(binder::StatusCode::PERMISSION_DENIED)
+        }
+    }
+}
+
+/// Parse the `[--format text|json]` options of the dumps, text by default.
+fn parse_format(
+    writer: &mut dyn Write,
+    options: &[&CStr],
+) -> std::result::Result<DumpFormat, binder::StatusCode> {
+    let format = match options {
+        [] => return Ok(DumpFormat::Text),
+        [flag, format] if flag.to_bytes() == b"--format" => {
+            format.to_str().map_err(|_| binder::StatusCode::BAD_VALUE)?
+        }
+        _ => {
+            writeln!(writer, "unknown arguments {:?}", options)
+                .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
+            return Err(binder::StatusCode::BAD_VALUE);
+        }
+    };
+    format.parse().map_err(|err| {
+        // The dump is refused whether or not the error could be written.
+        let _ = writeln!(writer, "{}", err);
+        binder::StatusCode::BAD_VALUE
+    })
+}
+
+/// Chip shared between the manager and its binder object, along with the
+/// runtime serving it.
+struct SharedChip<T: Transport>(Arc<UwbChip<T>>, TokioHandle);
+
+impl<T: Transport + 'static> binder::Interface for SharedChip<T> {
+    /// `dumpsys <instance> [--verbose] [--format text|json]` writes the
+    /// state of the chip and its last packets, in full if verbose,
+    /// `dumpsys <instance> stats [--format text|json]` writes the same
+    /// without the packets, `dumpsys <instance> snoop
+    /// off|filtered|full` changes what the snoop log of the chip records,
+    /// `dumpsys <instance> --log-level off|headers|full
+    /// [--unsafe-data-payloads]` changes how much of the packets is logged,
+    /// `dumpsys <instance> session <id>` writes the ranging statistics of a
+    /// session, `dumpsys <instance> calibration save|restore <path>`
+    /// saves the calibration of the UWBS to a file or restores it, for
+    /// root and the shell only, `dumpsys <instance> loopback <hex payload>` runs a loopback test,
+    /// `dumpsys <instance> chip_info` writes the identity of the UWBS,
+    /// `dumpsys <instance> health` checks that the UWBS answers,
+    /// `dumpsys <instance> suspend|resume` puts the UWBS in low power or
+    /// wakes it up, and `dumpsys <instance> firmware_update <path>` flashes
+    /// the image at `path` to the UWBS, both for root and the shell only.
+    fn dump(
+        &self,
+        writer: &mut dyn Write,
+        args: &[&CStr],
+    ) -> std::result::Result<(), binder::StatusCode> {
+        let mut verbose = false;
+        let mut format = DumpFormat::Text;
+        match args {
+            [flag, options @ ..] if flag.to_bytes() == b"--verbose" => {
+                verbose = true;
+                format = parse_format(writer, options)?;
+            }
+            [command, options @ ..] if command.to_bytes() == b"stats" => {
+                let format = parse_format(writer, options)?;
+                return self
+                    .0
+                    .dump_stats(writer, format)
+                    .map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            [flag, level, options @ ..]
+                if flag.to_bytes() == b"--log-level"
+                    && options
+                        .iter()
+                        .all(|option| option.to_bytes() == b"--unsafe-data-payloads") =>
+            {
+                let level = level.to_str().map_err(|_| binder::StatusCode::BAD_VALUE)?;
+                match level.parse() {
+                    Ok(level) => self.0.set_packet_log_level(level, !options.is_empty()),
+                    Err(err) => {
+                        writeln!(writer, "{}", err)
+                            .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
+                        return Err(binder::StatusCode::BAD_VALUE);
+                    }
+                }
+            }
+            [command, mode] if command.to_bytes() == b"snoop" => {
+                let mode = mode.to_str().map_err(|_| binder::StatusCode::BAD_VALUE)?;
+                match mode.parse() {
+                    Ok(mode) => {
+                        if let Err(err) = self.0.set_snoop_mode(mode) {
+                            log::warn!("failed to set the snoop log mode: {:?}", err);
+                            return Err(binder::StatusCode::INVALID_OPERATION);
+                        }
+                    }
+                    Err(err) => {
+                        writeln!(writer, "{}", err)
+                            .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
+                        return Err(binder::StatusCode::BAD_VALUE);
+                    }
+                }
+            }
+            [command, id] if command.to_bytes() == b"session" => {
+                let id = id
+                    .to_str()
+                    .ok()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or(binder::StatusCode::BAD_VALUE)?;
+                let result = match self.0.session_stats(id) {
+                    Ok(stats) => writeln!(writer, "session {}: {}", id, stats),
+                    Err(status) => writeln!(writer, "session {}: {}", id, status),
+                };
+                return result.map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            [command, action, path] if command.to_bytes() == b"calibration" => {
+                check_shell_caller()?;
+                let path = Path::new(path.to_str().map_err(|_| binder::StatusCode::BAD_VALUE)?);
+                // Dump runs on a binder thread, outside of the runtime.
+                let result = match action.to_bytes() {
+                    b"save" => self.1.block_on(self.0.save_calibration(path)),
+                    b"restore" => self.1.block_on(self.0.restore_calibration(path)),
+                    _ => return Err(binder::StatusCode::BAD_VALUE),
+                };
+                if let Err(err) = result {
+                    log::warn!("calibration {:?} failed: {}", action, err);
+                    writeln!(writer, "{}", err).map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
+                    return Err(binder::StatusCode::INVALID_OPERATION);
+                }
+                return Ok(());
+            }
+            [command] if command.to_bytes() == b"health" => {
+                let checker = UwbHealthChecker::new(self.0.clone());
+                let status = self.1.block_on(checker.check());
+                return writeln!(writer, "health: {}", status)
+                    .map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            [command, payload] if command.to_bytes() == b"loopback" => {
+                let payload = payload
+                    .to_str()
+                    .ok()
+                    .and_then(health::parse_hex)
+                    .ok_or(binder::StatusCode::BAD_VALUE)?;
+                let result = match self.1.block_on(self.0.loopback_test(&payload)) {
+                    Ok(echoed) => writeln!(writer, "loopback ok: {:02x?}", echoed),
+                    Err(status) => writeln!(writer, "loopback failed: {}", status),
+                };
+                return result.map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            [command] if command.to_bytes() == b"chip_info" => {
+                let result = match self.1.block_on(self.0.chip_info()) {
+                    Ok(info) => writeln!(writer, "chip info: {}", info),
+                    Err(status) => writeln!(writer, "chip info failed: {}", status),
+                };
+                return result.map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            [command] if matches!(command.to_bytes(), b"suspend" | b"resume") => {
+                check_shell_caller()?;
+                let (name, result) = match command.to_bytes() {
+                    b"suspend" => ("suspend", self.1.block_on(self.0.suspend())),
+                    _ => ("resume", self.1.block_on(self.0.resume())),
+                };
+                let result = match result {
+                    Ok(()) => writeln!(writer, "{} ok", name),
+                    Err(status) => writeln!(writer, "{} failed: {}", name, status),
+                };
+                return result.map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            [command, path] if command.to_bytes() == b"firmware_update" => {
+                check_shell_caller()?;
+                let path = Path::new(path.to_str().map_err(|_| binder::StatusCode::BAD_VALUE)?);
+                let image = match std::fs::read(path) {
+                    Ok(image) => image,
+                    Err(err) => {
+                        writeln!(writer, "failed to read {}: {}", path.display(), err)
+                            .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
+                        return Err(binder::StatusCode::BAD_VALUE);
+                    }
+                };
+                let result = match self.1.block_on(self.0.firmware_update(&image)) {
+                    Ok(()) => writeln!(writer, "firmware updated"),
+                    Err(status) => writeln!(writer, "firmware update failed: {}", status),
+                };
+                return result.map_err(|_| binder::StatusCode::UNKNOWN_ERROR);
+            }
+            _ => format = parse_format(writer, args)?,
+        }
+        self.0
+            .dump(writer, verbose, format)
+            .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)
+    }
+}
+
+#[async_trait]
+impl<T: Transport + 'static> IUwbChipAsyncServer for SharedChip<T> {
+    async fn getName(&self) -> Result<String> {
+        self.0.getName().await
+    }
+
+    async fn open(&self, callbacks: &Strong<dyn IUwbClientCallback>) -> Result<()> {
+        self.0.open(callbacks).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.0.close().await
+    }
+
+    async fn coreInit(&self) -> Result<()> {
+        self.0.coreInit().await
+    }
+
+    async fn sessionInit(&self, id: i32) -> Result<()> {
+        self.0.sessionInit(id).await
+    }
+
+    async fn getSupportedAndroidUciVersion(&self) -> Result<i32> {
+        self.0.getSupportedAndroidUciVersion().await
+    }
+
+    async fn sendUciMessage(&self, data: &[u8]) -> Result<i32> {
+        self.0.sendUciMessage(data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{configure_tty, DeviceSelector, OpenConfig, UartTransport};
+    use android_hardware_uwb::aidl::android::hardware::uwb::{
+        IUwbClientCallback::BnUwbClientCallback, UwbEvent::UwbEvent, UwbStatus::UwbStatus,
+    };
+    use std::fs::File;
+    use std::time::{Duration, Instant};
+
+    struct NullCallbacks;
+
+    impl binder::Interface for NullCallbacks {}
+
+    impl IUwbClientCallback for NullCallbacks {
+        fn onUciMessage(&self, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn onHalEvent(&self, _event: UwbEvent, _status: UwbStatus) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn uart_chip(name: &str, path: String) -> UwbChip<UartTransport> {
+        UwbChip::with_transport(
+            name.to_owned(),
+            UartTransport::new(DeviceSelector::Path(path.into()), OpenConfig::default()),
+        )
+    }
+
+    #[test]
+    fn chips_are_registered_by_name() {
+        let mut manager = UwbChipManager::new();
+        manager.register(uart_chip("0", "/dev/null".to_owned()));
+        manager.register(uart_chip("1", "/dev/zero".to_owned()));
+        assert_eq!(manager.get("1").unwrap().name(), "1");
+        assert!(manager.get("2").is_none());
+        assert_eq!(manager.crash_reporters().len(), 2);
+
+        manager.register(uart_chip("0", "/dev/zero".to_owned()));
+        assert_eq!(manager.names, vec!["0", "1"]);
+    }
+
+    // Close waits for the reset response on a runtime worker, as in the
+    // service.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn close_all_closes_chips_concurrently() {
+        // The UWBS never answers the reset, so every close lasts for the
+        // whole close timeout.
+        let timeout = Duration::from_millis(300);
+        let mut ptys = Vec::new();
+        let mut manager = UwbChipManager::new();
+        for name in ["0", "1", "2"] {
+            let pty = nix::pty::openpty(None, None).unwrap();
+            let path = nix::unistd::ttyname(&pty.slave).unwrap();
+            let slave = configure_tty(File::from(pty.slave), &OpenConfig::default()).unwrap();
+            manager.register(
+                uart_chip(name, path.to_str().unwrap().to_owned()).with_close_timeout(timeout),
+            );
+            ptys.push((File::from(pty.master), slave));
+        }
+        // Not opened.
+        manager.register(uart_chip("3", "/dev/null".to_owned()));
+
+        let callbacks =
+            BnUwbClientCallback::new_binder(NullCallbacks, binder::BinderFeatures::default());
+        for name in ["0", "1", "2"] {
+            manager.get(name).unwrap().open(&callbacks).await.unwrap();
+        }
+
+        let start = Instant::now();
+        assert!(manager.close_all(Duration::from_secs(5)).await.is_empty());
+        assert!(start.elapsed() < 2 * timeout);
+        for name in ["0", "1", "2"] {
+            assert!(manager.get(name).unwrap().close().await.is_err());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn close_all_gives_up_on_stuck_chips() {
+        // Neither UWBS answers the reset, and the first chip waits for it
+        // longer than the shutdown allows.
+        let mut manager = UwbChipManager::new();
+        let mut masters = Vec::new();
+        let mut slaves = Vec::new();
+        for (name, close_timeout) in [("stuck", Duration::from_secs(2)), ("0", Duration::ZERO)] {
+            let pty = nix::pty::openpty(None, None).unwrap();
+            let path = nix::unistd::ttyname(&pty.slave).unwrap();
+            slaves.push(configure_tty(File::from(pty.slave), &OpenConfig::default()).unwrap());
+            manager.register(
+                uart_chip(name, path.to_str().unwrap().to_owned())
+                    .with_close_timeout(close_timeout),
+            );
+            masters.push(File::from(pty.master));
+        }
+        let callbacks =
+            BnUwbClientCallback::new_binder(NullCallbacks, binder::BinderFeatures::default());
+        for name in ["stuck", "0"] {
+            manager.get(name).unwrap().open(&callbacks).await.unwrap();
+        }
+
+        let timeout = Duration::from_millis(300);
+        let start = Instant::now();
+        let errors = manager.close_all(timeout).await;
+        assert!(start.elapsed() < 2 * timeout);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "stuck");
+        assert_eq!(
+            errors[0].1.service_specific_error(),
+            UwbStatus::ERR_CMD_TIMEOUT.0
+        );
+
+        // Both chips were reset before giving up.
+        for mut master in masters {
+            let mut command = [0; 5];
+            std::io::Read::read_exact(&mut master, &mut command).unwrap();
+            assert_eq!(command, [0x20, 0x00, 0x00, 0x01, 0x00]);
+        }
+        // Closed chips are skipped.
+        assert!(manager.close_all(timeout).await.is_empty());
+    }
+}
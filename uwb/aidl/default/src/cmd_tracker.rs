@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+
+/// Time after which a command of the client left without response is
+/// reported.
+pub const DEFAULT_RESPONSE_WINDOW: Duration = Duration::from_secs(2);
+
+const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+const COMMAND_MESSAGE_TYPE: u8 = 0b001;
+const RESPONSE_MESSAGE_TYPE: u8 = 0b010;
+const PACKET_BOUNDARY_FLAG: u8 = 0b00010000;
+const GROUP_ID_MASK: u8 = 0b00001111;
+const OPCODE_ID_MASK: u8 = 0b00111111;
+
+/// GID and OID, which a response shares with its command.
+type CommandKey = (u8, u8);
+
+/// Return the GID and OID of a control packet.
+fn command_key(packet: &[u8]) -> CommandKey {
+    (packet[0] & GROUP_ID_MASK, packet[1] & OPCODE_ID_MASK)
+}
+
+/// Wait for a response.
+struct Pending {
+    sender: oneshot::Sender<Vec<u8>>,
+    /// Also deliver the response to the client.
+    forward: bool,
+}
+
+/// Correlates the commands sent by the HAL itself with their responses,
+/// which are handed over to the HAL instead of being delivered to the
+/// client, unless awaited with [`CmdResponseTracker::register_forwarded`].
+#[derive(Default)]
+pub struct CmdResponseTracker {
+    pending: Mutex<HashMap<CommandKey, Pending>>,
+}
+
+impl CmdResponseTracker {
+    /// Wait for the response to `command`, a control packet. Must be
+    /// called before the command is written, so that a response received
+    /// right away is not missed. Replaces any previous wait for the
+    /// response to the same command.
+    pub fn register(&self, command: &[u8]) -> oneshot::Receiver<Vec<u8>> {
+        self.insert(command, false)
+    }
+
+    /// Like [`Self::register`], but the response is delivered to the
+    /// client as well.
+    pub fn register_forwarded(&self, command: &[u8]) -> oneshot::Receiver<Vec<u8>> {
+        self.insert(command, true)
+    }
+
+    fn insert(&self, command: &[u8], forward: bool) -> oneshot::Receiver<Vec<u8>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(command_key(command), Pending { sender, forward });
+        receiver
+    }
+
+    /// Stop waiting for the response to `command`, once the receiver
+    /// returned by [`Self::register`] was dropped. A wait registered
+    /// since is kept.
+    pub fn cancel(&self, command: &[u8]) {
+        let mut pending = self.pending.lock().unwrap();
+        let key = command_key(command);
+        if pending
+            .get(&key)
+            .is_some_and(|pending| pending.sender.is_closed())
+        {
+            pending.remove(&key);
+        }
+    }
+
+    /// Hand a copy of `packet` over if it is an awaited response. Returns
+    /// whether the packet is to be delivered to the client, which is the
+    /// case of forwarded responses.
+    pub fn deliver(&self, packet: &[u8]) -> bool {
+        if packet.len() < UCI_HEADER_SIZE
+            || (packet[0] & MESSAGE_TYPE_MASK) >> 5 != RESPONSE_MESSAGE_TYPE
+        {
+            return true;
+        }
+        let Some(pending) = self.pending.lock().unwrap().remove(&command_key(packet)) else {
+            return true;
+        };
+        // The receiver is dropped when the HAL stopped waiting.
+        let _ = pending.sender.send(packet.to_vec());
+        pending.forward
+    }
+}
+
+/// Round trips of the commands of the client, and responses which never
+/// came.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResponseStats {
+    pub responses: u64,
+    pub lost_responses: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl fmt::Display for ResponseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} responses", self.responses)?;
+        if self.responses > 0 {
+            write!(
+                f,
+                ", latency mean {:?} max {:?}",
+                self.total_latency / self.responses as u32,
+                self.max_latency
+            )?;
+        }
+        write!(f, ", {} lost", self.lost_responses)
+    }
+}
+
+/// Command of the client awaiting its response.
+struct Outstanding {
+    header: [u8; UCI_HEADER_SIZE],
+    sent_at: Instant,
+}
+
+#[derive(Default)]
+struct ClientCommands {
+    outstanding: HashMap<CommandKey, Outstanding>,
+    stats: ResponseStats,
+}
+
+/// Correlates the commands written for the client with their responses,
+/// only to measure the round trips and report the responses which never
+/// came: unlike [`CmdResponseTracker`], the responses are delivered to
+/// the client all the same.
+#[derive(Default)]
+pub struct ClientCommandTracker {
+    commands: Mutex<ClientCommands>,
+}
+
+impl ClientCommandTracker {
+    /// Record a packet written for the client. Only commands, and the
+    /// last segment of a segmented command, await a response. A command
+    /// retransmitted before its response awaits it from then on.
+    pub fn sent(&self, packet: &[u8]) {
+        if packet.len() < UCI_HEADER_SIZE
+            || (packet[0] & MESSAGE_TYPE_MASK) >> 5 != COMMAND_MESSAGE_TYPE
+            || packet[0] & PACKET_BOUNDARY_FLAG != 0
+        {
+            return;
+        }
+        let outstanding = Outstanding {
+            header: packet[..UCI_HEADER_SIZE].try_into().unwrap(),
+            sent_at: Instant::now(),
+        };
+        let mut commands = self.commands.lock().unwrap();
+        if let Some(previous) = commands
+            .outstanding
+            .insert(command_key(packet), outstanding)
+        {
+            log::debug!(
+                "command {:02x?} retransmitted after {:?}",
+                previous.header,
+                previous.sent_at.elapsed()
+            );
+        }
+    }
+
+    /// Record a packet delivered to the client, returning the round trip
+    /// of the command it answers, if any.
+    pub fn received(&self, packet: &[u8]) -> Option<Duration> {
+        if packet.len() < UCI_HEADER_SIZE
+            || (packet[0] & MESSAGE_TYPE_MASK) >> 5 != RESPONSE_MESSAGE_TYPE
+            || packet[0] & PACKET_BOUNDARY_FLAG != 0
+        {
+            return None;
+        }
+        let mut commands = self.commands.lock().unwrap();
+        let latency = commands
+            .outstanding
+            .remove(&command_key(packet))?
+            .sent_at
+            .elapsed();
+        commands.stats.responses += 1;
+        commands.stats.total_latency += latency;
+        commands.stats.max_latency = commands.stats.max_latency.max(latency);
+        Some(latency)
+    }
+
+    /// Give up on the commands sent more than `window` ago, counting
+    /// their responses as lost, and return their headers with the time
+    /// they waited.
+    pub fn expire(&self, window: Duration) -> Vec<([u8; UCI_HEADER_SIZE], Duration)> {
+        let mut commands = self.commands.lock().unwrap();
+        let mut expired = Vec::new();
+        commands.outstanding.retain(|_, command| {
+            let elapsed = command.sent_at.elapsed();
+            if elapsed < window {
+                return true;
+            }
+            expired.push((command.header, elapsed));
+            false
+        });
+        commands.stats.lost_responses += expired.len() as u64;
+        expired
+    }
+
+    /// Return whether a command awaits its response, sent less than
+    /// `max_age` ago.
+    pub fn is_outstanding(&self, max_age: Duration) -> bool {
+        self.commands
+            .lock()
+            .unwrap()
+            .outstanding
+            .values()
+            .any(|command| command.sent_at.elapsed() < max_age)
+    }
+
+    /// Forget the commands awaiting their response, whose responses can
+    /// no longer come after a close.
+    pub fn clear(&self) {
+        self.commands.lock().unwrap().outstanding.clear();
+    }
+
+    pub fn stats(&self) -> ResponseStats {
+        self.commands.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_DEVICE_INFO_CMD: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+    const GET_DEVICE_INFO_RSP: [u8; 5] = [0x40, 0x02, 0x00, 0x01, 0x00];
+
+    #[test]
+    fn response_is_handed_over() {
+        let tracker = CmdResponseTracker::default();
+        let mut response = tracker.register(&GET_DEVICE_INFO_CMD);
+        // Notification and response with the same GID and OID, and
+        // response to another command.
+        for packet in [
+            [0x60, 0x02, 0x00, 0x01, 0x00],
+            [0x40, 0x03, 0x00, 0x01, 0x00],
+        ] {
+            assert!(tracker.deliver(&packet));
+        }
+        assert!(!tracker.deliver(&GET_DEVICE_INFO_RSP));
+        assert_eq!(response.try_recv().unwrap(), GET_DEVICE_INFO_RSP);
+        // Only the first response is awaited.
+        assert!(tracker.deliver(&GET_DEVICE_INFO_RSP));
+    }
+
+    #[test]
+    fn forwarded_response_is_handed_over_and_given_back() {
+        let tracker = CmdResponseTracker::default();
+        let mut response = tracker.register_forwarded(&GET_DEVICE_INFO_CMD);
+        assert!(tracker.deliver(&GET_DEVICE_INFO_RSP));
+        assert_eq!(response.try_recv().unwrap(), GET_DEVICE_INFO_RSP);
+    }
+
+    #[test]
+    fn cancel_keeps_newer_wait() {
+        let tracker = CmdResponseTracker::default();
+        let response = tracker.register(&GET_DEVICE_INFO_CMD);
+        drop(response);
+        tracker.cancel(&GET_DEVICE_INFO_CMD);
+        assert!(tracker.deliver(&GET_DEVICE_INFO_RSP));
+
+        let stale = tracker.register(&GET_DEVICE_INFO_CMD);
+        let mut response = tracker.register(&GET_DEVICE_INFO_CMD);
+        drop(stale);
+        tracker.cancel(&GET_DEVICE_INFO_CMD);
+        assert!(!tracker.deliver(&GET_DEVICE_INFO_RSP));
+        assert_eq!(response.try_recv().unwrap(), GET_DEVICE_INFO_RSP);
+    }
+
+    #[test]
+    fn client_commands_are_correlated_with_responses() {
+        let tracker = ClientCommandTracker::default();
+        let max_age = Duration::from_secs(60);
+        // Data packets and first segments do not await a response.
+        tracker.sent(&[0x01, 0x00, 0x00, 0x00]);
+        tracker.sent(&[0x31, 0x03, 0x00, 0x01, 0x00]);
+        assert!(!tracker.is_outstanding(max_age));
+
+        tracker.sent(&[0x21, 0x03, 0x00, 0x01, 0x00]);
+        tracker.sent(&GET_DEVICE_INFO_CMD);
+        // Retransmitted.
+        tracker.sent(&GET_DEVICE_INFO_CMD);
+        assert!(tracker.is_outstanding(max_age));
+        assert!(!tracker.is_outstanding(Duration::ZERO));
+
+        // Notifications, even with the same GID and OID, and responses
+        // out of order.
+        assert_eq!(tracker.received(&[0x6e, 0x02, 0x00, 0x01, 0x00]), None);
+        assert!(tracker.received(&GET_DEVICE_INFO_RSP).is_some());
+        assert_eq!(tracker.received(&GET_DEVICE_INFO_RSP), None);
+        assert!(tracker.is_outstanding(max_age));
+
+        assert!(tracker.expire(max_age).is_empty());
+        let expired = tracker.expire(Duration::ZERO);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, [0x21, 0x03, 0x00, 0x01]);
+        assert!(!tracker.is_outstanding(max_age));
+        // Too late.
+        assert_eq!(tracker.received(&[0x41, 0x03, 0x00, 0x01, 0x00]), None);
+
+        let stats = tracker.stats();
+        assert_eq!((stats.responses, stats.lost_responses), (1, 1));
+        assert_eq!(stats.max_latency, stats.total_latency);
+    }
+}
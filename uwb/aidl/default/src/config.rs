@@ -0,0 +1,549 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cmd_tracker::DEFAULT_RESPONSE_WINDOW;
+use crate::firmware::{DEFAULT_FIRMWARE_CHUNK_SIZE, DEFAULT_FIRMWARE_RETRIES};
+use crate::fragmentation::DEFAULT_MAX_PACKET_SIZE;
+use crate::health::{self, HealthCheckConfig};
+use crate::snoop::SnoopMode;
+use crate::trace::DEFAULT_TRACE_CAPACITY;
+use crate::transport::{GpioLineConfig, OpenConfig, ReplayConfig, ReplayEnd};
+use crate::uwb_chip::{
+    Framing, ReaderConfig, DEFAULT_CLOSE_TIMEOUT, DEFAULT_CONNECT_BACKOFF,
+    DEFAULT_CORE_INIT_TIMEOUT, DEFAULT_WRITE_QUEUE_CAPACITY, DEFAULT_WRITE_TIMEOUT,
+};
+
+/// File listing the chips served by the HAL, one per line:
+/// `<name> <chip description>`. Empty lines and lines starting with `#`
+/// are ignored.
+pub const CONFIG_PATH: &str = "/vendor/etc/uwb/uwb_hal.conf";
+
+/// Configuration of a chip, parsed from its description:
+/// `<path>[,monitor][,close_timeout_ms=<ms>][,core_init_timeout_ms=<ms>][,write_timeout_ms=<ms>]
+/// [,reader_stop_timeout_ms=<ms>][,dedup_window_ms=<ms>][,write_queue_capacity=<packets>][,spi_speed_hz=<hz>]
+/// [,spi_mode=0|1|2|3][,spi_bits_per_word=<bits>][,spi_data_ready=<gpiochip>:<line>]
+/// [,baud=<rate>][,crtscts][,parity=none|even|odd][,stop_bits=1|2][,max_packet_size=<bytes>][,max_rx_data_payload=<bytes>]
+/// [,max_resync_bytes=<bytes>][,framing=stream|packet|hdlc][,reassemble][,reset_on_open]
+/// [,trace_capacity=<packets>][,connect_retries=<count>][,connect_backoff_ms=<ms>][,mock_latency_ms=<ms>]
+/// [,replay_time_scale=<factor>][,replay_strict][,replay_end=idle|error]
+/// [,watchdog_timeout_ms=<ms>][,reconnect_service=<name>][,hotplug][,reset_gpio=<path>]
+/// [,snoop=off|filtered|full][,log_vendor_messages][,android_uci_version=<version>]
+/// [,health_check_idle_ms=<ms>][,health_check_timeout_ms=<ms>][,health_check_misses=<count>]
+/// [,health_check_cmd=<hex>][,response_window_ms=<ms>][,firmware=<path>]
+/// [,firmware_retries=<count>][,firmware_chunk_size=<bytes>][,calibration_commands=<path>]
+/// [,reader_nice=<nice>]`.
+/// With `log_vendor_messages`, the vendor messages of the UWBS are logged
+/// instead of being delivered to the client. The health check is only
+/// enabled by `health_check_idle_ms`. The reader task runs on the threads
+/// of the runtime, shared by all the chips: the lowest `reader_nice` of the
+/// chips applies to the whole process.
+#[derive(Clone, Debug)]
+pub struct ChipConfig {
+    pub name: String,
+    pub path: String,
+    pub monitor: bool,
+    pub reassembly: bool,
+    /// The UWBS is reset on open, before the client gets any packet.
+    pub reset_on_open: bool,
+    /// The device node may appear after the HAL started, and disappear.
+    pub hotplug: bool,
+    pub log_vendor_messages: bool,
+    pub close_timeout: Duration,
+    pub core_init_timeout: Duration,
+    pub reader_stop_timeout: Duration,
+    /// Window within which a repeated DEVICE_STATUS_NTF is dropped.
+    pub dedup_window: Duration,
+    pub write_timeout: Duration,
+    pub write_queue_capacity: usize,
+    /// Time after which a command of the client left without response is
+    /// reported.
+    pub response_window: Duration,
+    /// Android UCI version advertised to the client, queried from the
+    /// UWBS if not set.
+    pub android_uci_version: Option<i32>,
+    pub max_packet_size: usize,
+    /// Maximum payload size of the data packets read from the UWBS.
+    pub max_rx_data_payload: usize,
+    /// Maximum number of bytes skipped to resynchronize a byte stream.
+    pub max_resync_bytes: usize,
+    pub spi_speed_hz: Option<u32>,
+    pub spi_mode: Option<u8>,
+    pub spi_bits_per_word: Option<u8>,
+    /// GPIO line asserted by a UWBS on SPI while it has a packet to send.
+    pub spi_data_ready: Option<GpioLineConfig>,
+    pub trace_capacity: usize,
+    pub connect_retries: u32,
+    /// Delay before the first retry to connect, doubled on every retry.
+    pub connect_backoff: Duration,
+    pub mock_latency: Duration,
+    /// How the trace of a `replay://` path is replayed.
+    pub replay: ReplayConfig,
+    pub watchdog_timeout: Option<Duration>,
+    pub health_check: Option<HealthCheckConfig>,
+    pub reconnect_service: Option<String>,
+    /// Value file of the sysfs GPIO driving the RESET_N line of the UWBS.
+    pub reset_gpio: Option<PathBuf>,
+    /// Firmware image pushed to the UWBS on open, in chunks.
+    pub firmware: Option<PathBuf>,
+    pub firmware_retries: u32,
+    pub firmware_chunk_size: usize,
+    /// File listing the UCI commands sent to the UWBS on coreInit.
+    pub calibration_commands: Option<PathBuf>,
+    /// Nice value requested for the reader task, from -20 to 19.
+    pub reader_nice: Option<i32>,
+    pub snoop: SnoopMode,
+    pub framing: Framing,
+    pub open_config: OpenConfig,
+}
+
+impl ChipConfig {
+    /// Parse the description of the chip `name`. Unknown options and
+    /// invalid values are ignored with a warning; fails with
+    /// `InvalidInput` if the path is missing.
+    pub fn parse(name: String, description: &str) -> io::Result<Self> {
+        let mut options = description.split(',');
+        let path = options.next().unwrap_or_default().trim().to_owned();
+        if path.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("missing path of chip {}", name),
+            ));
+        }
+        let mut config = Self {
+            name,
+            path,
+            monitor: false,
+            reassembly: false,
+            reset_on_open: false,
+            hotplug: false,
+            log_vendor_messages: false,
+            close_timeout: DEFAULT_CLOSE_TIMEOUT,
+            core_init_timeout: DEFAULT_CORE_INIT_TIMEOUT,
+            reader_stop_timeout: ReaderConfig::default().stop_timeout,
+            dedup_window: ReaderConfig::default().dedup_window,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            write_queue_capacity: DEFAULT_WRITE_QUEUE_CAPACITY,
+            response_window: DEFAULT_RESPONSE_WINDOW,
+            android_uci_version: None,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            max_rx_data_payload: ReaderConfig::default().max_data_payload,
+            max_resync_bytes: ReaderConfig::default().max_resync_bytes,
+            spi_speed_hz: None,
+            spi_mode: None,
+            spi_bits_per_word: None,
+            spi_data_ready: None,
+            trace_capacity: DEFAULT_TRACE_CAPACITY,
+            connect_retries: 0,
+            connect_backoff: DEFAULT_CONNECT_BACKOFF,
+            mock_latency: Duration::ZERO,
+            replay: ReplayConfig::default(),
+            watchdog_timeout: None,
+            health_check: None,
+            reconnect_service: None,
+            reset_gpio: None,
+            firmware: None,
+            firmware_retries: DEFAULT_FIRMWARE_RETRIES,
+            firmware_chunk_size: DEFAULT_FIRMWARE_CHUNK_SIZE,
+            calibration_commands: None,
+            reader_nice: None,
+            snoop: SnoopMode::Off,
+            framing: Framing::default(),
+            open_config: OpenConfig::default(),
+        };
+        // The health check options may come in any order.
+        let mut health_check_idle = None;
+        let mut health_check = HealthCheckConfig::new(Duration::ZERO);
+        for option in options {
+            match option.split_once('=') {
+                None if option == "monitor" => config.monitor = true,
+                None if option == "crtscts" => config.open_config.hw_flow_control = true,
+                None if option == "reassemble" => config.reassembly = true,
+                None if option == "reset_on_open" => config.reset_on_open = true,
+                None if option == "hotplug" => config.hotplug = true,
+                None if option == "log_vendor_messages" => config.log_vendor_messages = true,
+                None if option == "replay_strict" => config.replay.strict = true,
+                Some(("close_timeout_ms", value)) => match value.parse() {
+                    Ok(value) => config.close_timeout = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid close timeout {:?}", value),
+                },
+                Some(("core_init_timeout_ms", value)) => match value.parse() {
+                    Ok(value) => config.core_init_timeout = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid core init timeout {:?}", value),
+                },
+                Some(("reader_stop_timeout_ms", value)) => match value.parse() {
+                    Ok(value) => config.reader_stop_timeout = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid reader stop timeout {:?}", value),
+                },
+                Some(("write_timeout_ms", value)) => match value.parse() {
+                    Ok(value) => config.write_timeout = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid write timeout {:?}", value),
+                },
+                Some(("write_queue_capacity", value)) => match value.parse() {
+                    Ok(value) if value > 0 => config.write_queue_capacity = value,
+                    _ => log::warn!("invalid write queue capacity {:?}", value),
+                },
+                Some(("response_window_ms", value)) => match value.parse() {
+                    Ok(value) if value > 0 => config.response_window = Duration::from_millis(value),
+                    _ => log::warn!("invalid response window {:?}", value),
+                },
+                Some(("android_uci_version", value)) => match value.parse() {
+                    Ok(value) if value > 0 => config.android_uci_version = Some(value),
+                    _ => log::warn!("invalid Android UCI version {:?}", value),
+                },
+                Some(("max_rx_data_payload", value)) => match value.parse() {
+                    Ok(value) => config.max_rx_data_payload = value,
+                    Err(_) => log::warn!("invalid maximum data payload size {:?}", value),
+                },
+                Some(("max_resync_bytes", value)) => match value.parse() {
+                    Ok(value) => config.max_resync_bytes = value,
+                    Err(_) => log::warn!("invalid maximum resynchronization size {:?}", value),
+                },
+                Some(("max_packet_size", value)) => match value.parse() {
+                    Ok(value) => config.max_packet_size = value,
+                    Err(_) => log::warn!("invalid maximum packet size {:?}", value),
+                },
+                Some(("watchdog_timeout_ms", value)) => match value.parse() {
+                    Ok(value) => config.watchdog_timeout = Some(Duration::from_millis(value)),
+                    Err(_) => log::warn!("invalid watchdog timeout {:?}", value),
+                },
+                Some(("health_check_idle_ms", value)) => match value.parse() {
+                    Ok(value) if value > 0 => {
+                        health_check_idle = Some(Duration::from_millis(value))
+                    }
+                    _ => log::warn!("invalid health check idle time {:?}", value),
+                },
+                Some(("health_check_timeout_ms", value)) => match value.parse() {
+                    Ok(value) if value > 0 => health_check.timeout = Duration::from_millis(value),
+                    _ => log::warn!("invalid health check timeout {:?}", value),
+                },
+                Some(("health_check_misses", value)) => match value.parse() {
+                    Ok(value) if value > 0 => health_check.max_misses = value,
+                    _ => log::warn!("invalid health check miss count {:?}", value),
+                },
+                Some(("health_check_cmd", value)) => match health::parse_command(value) {
+                    Some(command) => health_check.command = command,
+                    None => log::warn!("invalid health check command {:?}", value),
+                },
+                Some(("mock_latency_ms", value)) => match value.parse() {
+                    Ok(value) => config.mock_latency = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid mock latency {:?}", value),
+                },
+                Some(("replay_time_scale", value)) => match value.parse::<f64>() {
+                    Ok(value) if value >= 0.0 && value.is_finite() => {
+                        config.replay.time_scale = value
+                    }
+                    _ => log::warn!("invalid replay time scale {:?}", value),
+                },
+                Some(("replay_end", value)) => match value.parse::<ReplayEnd>() {
+                    Ok(end) => config.replay.end = end,
+                    Err(err) => log::warn!("{}", err),
+                },
+                Some(("connect_retries", value)) => match value.parse() {
+                    Ok(value) => config.connect_retries = value,
+                    Err(_) => log::warn!("invalid connection retry count {:?}", value),
+                },
+                Some(("dedup_window_ms", value)) => match value.parse() {
+                    Ok(value) => config.dedup_window = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid dedup window {:?}", value),
+                },
+                Some(("connect_backoff_ms", value)) => match value.parse() {
+                    Ok(value) => config.connect_backoff = Duration::from_millis(value),
+                    Err(_) => log::warn!("invalid connection backoff {:?}", value),
+                },
+                Some(("trace_capacity", value)) => match value.parse() {
+                    Ok(value) => config.trace_capacity = value,
+                    Err(_) => log::warn!("invalid trace capacity {:?}", value),
+                },
+                Some(("spi_speed_hz", value)) => match value.parse() {
+                    Ok(value) => config.spi_speed_hz = Some(value),
+                    Err(_) => log::warn!("invalid SPI speed {:?}", value),
+                },
+                Some(("spi_mode", value)) => match value.parse() {
+                    Ok(value @ 0..=3) => config.spi_mode = Some(value),
+                    _ => log::warn!("invalid SPI mode {:?}", value),
+                },
+                Some(("spi_bits_per_word", value)) => match value.parse() {
+                    Ok(value) if value > 0 => config.spi_bits_per_word = Some(value),
+                    _ => log::warn!("invalid SPI word size {:?}", value),
+                },
+                Some(("spi_data_ready", value)) => match value.parse() {
+                    Ok(line) => config.spi_data_ready = Some(line),
+                    Err(err) => log::warn!("{}", err),
+                },
+                Some(("reconnect_service", value)) => {
+                    config.reconnect_service = Some(value.to_owned())
+                }
+                Some(("reset_gpio", value)) => config.reset_gpio = Some(PathBuf::from(value)),
+                Some(("firmware", value)) => config.firmware = Some(PathBuf::from(value)),
+                Some(("firmware_retries", value)) => match value.parse() {
+                    Ok(value) => config.firmware_retries = value,
+                    Err(_) => log::warn!("invalid firmware retry count {:?}", value),
+                },
+                Some(("firmware_chunk_size", value)) => match value.parse() {
+                    Ok(value) if value > 0 => config.firmware_chunk_size = value,
+                    _ => log::warn!("invalid firmware chunk size {:?}", value),
+                },
+                Some(("calibration_commands", value)) => {
+                    config.calibration_commands = Some(PathBuf::from(value))
+                }
+                Some(("reader_nice", value)) => match value.parse() {
+                    Ok(value @ -20..=19) => config.reader_nice = Some(value),
+                    _ => log::warn!("invalid reader nice value {:?}", value),
+                },
+                Some(("snoop", value)) => match value.parse() {
+                    Ok(mode) => config.snoop = mode,
+                    Err(err) => log::warn!("{}", err),
+                },
+                Some(("framing", "stream")) => config.framing = Framing::ByteStream,
+                Some(("framing", "packet")) => config.framing = Framing::PacketPerRead,
+                Some(("framing", "hdlc")) => config.framing = Framing::Hdlc,
+                // Checked on open, which fails rather than running at
+                // another speed.
+                Some(("baud", value)) => match value.parse() {
+                    Ok(value) => config.open_config.baud_rate = Some(value),
+                    Err(_) => log::warn!("invalid baud rate {:?}", value),
+                },
+                Some(("parity", value)) => match value.parse() {
+                    Ok(parity) => config.open_config.parity = parity,
+                    Err(err) => log::warn!("{}", err),
+                },
+                Some(("stop_bits", value)) => match value.parse() {
+                    Ok(stop_bits) => config.open_config.stop_bits = stop_bits,
+                    Err(err) => log::warn!("{}", err),
+                },
+                _ => log::warn!("ignoring unknown chip option {:?}", option),
+            }
+        }
+        config.health_check = health_check_idle.map(|idle| HealthCheckConfig {
+            idle,
+            ..health_check
+        });
+        Ok(config)
+    }
+}
+
+/// Parse the contents of the configuration file. Fails with
+/// `InvalidData` if a chip has no path, if two chips have the same
+/// name, or if no chip is listed.
+pub fn parse_config(contents: &str) -> io::Result<Vec<ChipConfig>> {
+    let invalid = |line: usize, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line + 1, message),
+        )
+    };
+    let mut names = HashSet::new();
+    let mut chips = Vec::new();
+    for (line, entry) in contents.lines().enumerate() {
+        let entry = entry.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        let (name, description) = entry.split_once(char::is_whitespace).unwrap_or((entry, ""));
+        if !names.insert(name) {
+            return Err(invalid(line, format!("duplicate chip name {}", name)));
+        }
+        let chip = ChipConfig::parse(name.to_owned(), description.trim())
+            .map_err(|err| invalid(line, err.to_string()))?;
+        chips.push(chip);
+    }
+    if chips.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no chip listed"));
+    }
+    Ok(chips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{Parity, StopBits};
+
+    #[test]
+    fn config_lists_chips() {
+        let chips = parse_config(
+            "# Main board.\n\
+             main /dev/ttyACM0,framing=hdlc,baud=3000000,crtscts,parity=even,stop_bits=2,\
+             hotplug,snoop=filtered,log_vendor_messages,reset_on_open\n\
+             \n\
+             accessory  spi:///dev/spidev1.0,spi_speed_hz=1000000,spi_mode=3,spi_bits_per_word=16,\
+             spi_data_ready=/dev/gpiochip0:17,max_packet_size=128,\
+             max_rx_data_payload=1024,max_resync_bytes=64,\
+             write_queue_capacity=8,android_uci_version=2,response_window_ms=500,\
+             reset_gpio=/sys/class/gpio/gpio42/value,firmware=/vendor/firmware/uwb.bin,\
+             firmware_retries=4,calibration_commands=/vendor/etc/uwb/calibration.txt,\
+             connect_retries=5,connect_backoff_ms=400,dedup_window_ms=0\n",
+        )
+        .unwrap();
+        assert_eq!(chips.len(), 2);
+        assert_eq!(chips[0].name, "main");
+        assert_eq!(chips[0].path, "/dev/ttyACM0");
+        assert_eq!(chips[0].framing, Framing::Hdlc);
+        assert_eq!(chips[1].framing, Framing::ByteStream);
+        assert!(chips[0].hotplug);
+        assert!(chips[0].log_vendor_messages);
+        assert!(!chips[1].log_vendor_messages);
+        assert!(chips[0].reset_on_open);
+        assert!(!chips[1].reset_on_open);
+        assert_eq!(chips[0].snoop, SnoopMode::Filtered);
+        assert_eq!(
+            chips[0].open_config,
+            OpenConfig {
+                read_only: false,
+                baud_rate: Some(3000000),
+                hw_flow_control: true,
+                parity: Parity::Even,
+                stop_bits: StopBits::Two,
+            }
+        );
+        assert_eq!(chips[1].open_config, OpenConfig::default());
+        assert_eq!(chips[1].name, "accessory");
+        assert_eq!(chips[1].path, "spi:///dev/spidev1.0");
+        assert_eq!(chips[1].spi_speed_hz, Some(1000000));
+        assert_eq!(chips[1].spi_mode, Some(3));
+        assert_eq!(chips[1].spi_bits_per_word, Some(16));
+        assert_eq!(
+            chips[1].spi_data_ready,
+            Some(GpioLineConfig {
+                chip: PathBuf::from("/dev/gpiochip0"),
+                offset: 17
+            })
+        );
+        assert_eq!(chips[0].spi_data_ready, None);
+        assert_eq!(chips[1].max_packet_size, 128);
+        assert_eq!(chips[1].max_rx_data_payload, 1024);
+        assert_eq!(chips[1].max_resync_bytes, 64);
+        assert_eq!(
+            chips[0].max_rx_data_payload,
+            ReaderConfig::default().max_data_payload
+        );
+        assert_eq!(chips[1].write_queue_capacity, 8);
+        assert_eq!(chips[0].response_window, DEFAULT_RESPONSE_WINDOW);
+        assert_eq!(chips[1].response_window, Duration::from_millis(500));
+        assert_eq!(chips[0].android_uci_version, None);
+        assert_eq!(chips[1].android_uci_version, Some(2));
+        assert_eq!(chips[0].firmware, None);
+        assert_eq!(
+            chips[1].firmware.as_deref(),
+            Some(std::path::Path::new("/vendor/firmware/uwb.bin"))
+        );
+        assert_eq!(chips[1].firmware_retries, 4);
+        assert_eq!(chips[1].connect_retries, 5);
+        assert_eq!(chips[1].connect_backoff, Duration::from_millis(400));
+        assert_eq!(chips[0].connect_backoff, DEFAULT_CONNECT_BACKOFF);
+        assert_eq!(chips[1].dedup_window, Duration::ZERO);
+        assert_eq!(chips[0].dedup_window, Duration::from_millis(100));
+        assert_eq!(chips[1].firmware_chunk_size, DEFAULT_FIRMWARE_CHUNK_SIZE);
+        assert_eq!(chips[0].calibration_commands, None);
+        assert_eq!(
+            chips[1].calibration_commands.as_deref(),
+            Some(std::path::Path::new("/vendor/etc/uwb/calibration.txt"))
+        );
+        assert_eq!(
+            chips[1].reset_gpio.as_deref(),
+            Some(std::path::Path::new("/sys/class/gpio/gpio42/value"))
+        );
+    }
+
+    #[test]
+    fn config_ignores_unknown_options() {
+        let chips = parse_config("main /dev/ttyUWB0,low_power,colour=blue,monitor").unwrap();
+        assert_eq!(chips[0].path, "/dev/ttyUWB0");
+        assert!(chips[0].monitor);
+    }
+
+    #[test]
+    fn config_parses_replay_options() {
+        let chips = parse_config(
+            "main replay:///data/local/tmp/uwb.pcapng,replay_time_scale=0.5,replay_strict,\
+             replay_end=error\n\
+             accessory replay:///data/local/tmp/uwb.txt,replay_time_scale=-1,replay_end=never\n",
+        )
+        .unwrap();
+        assert_eq!(
+            chips[0].replay,
+            ReplayConfig {
+                time_scale: 0.5,
+                strict: true,
+                end: ReplayEnd::Error,
+            }
+        );
+        assert_eq!(chips[1].replay, ReplayConfig::default());
+    }
+
+    #[test]
+    fn config_ignores_invalid_android_uci_version() {
+        for version in ["0", "-1", "two"] {
+            let chips = parse_config(&format!(
+                "main /dev/ttyUWB0,android_uci_version={}",
+                version
+            ))
+            .unwrap();
+            assert_eq!(chips[0].android_uci_version, None, "{}", version);
+        }
+    }
+
+    #[test]
+    fn config_parses_reader_nice() {
+        let chips = parse_config(
+            "main /dev/ttyUWB0,reader_nice=-10
+             accessory /dev/ttyUWB1
+             low /dev/ttyUWB2,reader_nice=-21
+             high /dev/ttyUWB3,reader_nice=high
+",
+        )
+        .unwrap();
+        assert_eq!(chips[0].reader_nice, Some(-10));
+        assert_eq!(chips[1].reader_nice, None);
+        assert_eq!(chips[2].reader_nice, None);
+        assert_eq!(chips[3].reader_nice, None);
+    }
+
+    #[test]
+    fn config_enables_health_check() {
+        let chips = parse_config(
+            "main /dev/ttyUWB0,health_check_misses=2,health_check_idle_ms=5000,\
+             health_check_cmd=2e0100011a\n\
+             accessory /dev/ttyUWB1,health_check_timeout_ms=200,health_check_cmd=4002\n",
+        )
+        .unwrap();
+        assert_eq!(
+            chips[0].health_check,
+            Some(HealthCheckConfig {
+                idle: Duration::from_secs(5),
+                timeout: health::DEFAULT_HEALTH_CHECK_TIMEOUT,
+                max_misses: 2,
+                command: vec![0x2e, 0x01, 0x00, 0x01, 0x1a],
+            })
+        );
+        // Disabled without idle time.
+        assert_eq!(chips[1].health_check, None);
+    }
+
+    #[test]
+    fn config_rejects_invalid_chips() {
+        for contents in [
+            // Missing paths.
+            "main /dev/ttyUWB0\naccessory\n",
+            "main ,monitor",
+            // Duplicate names.
+            "main /dev/ttyUWB0\nmain /dev/ttyUWB1\n",
+            // No chip.
+            "# Main board.\n\n",
+        ] {
+            assert_eq!(
+                parse_config(contents).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                contents
+            );
+        }
+        assert_eq!(
+            parse_config("main /dev/ttyUWB0\nmain /dev/ttyUWB1\n")
+                .unwrap_err()
+                .to_string(),
+            "line 2: duplicate chip name main"
+        );
+    }
+}
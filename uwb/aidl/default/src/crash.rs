@@ -0,0 +1,77 @@
+use std::fmt::{self, Write};
+use std::panic;
+
+use crate::uwb_chip::CrashReporter;
+
+/// Fixed size buffer used to format crash reports without allocating.
+/// Output exceeding the capacity is truncated.
+struct StackBuffer<const N: usize> {
+    data: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> StackBuffer<N> {
+    fn new() -> Self {
+        Self {
+            data: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever copied into the buffer.
+        std::str::from_utf8(&self.data[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Write for StackBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(N - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.data[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Install a panic hook that logs the panic message and the state of every
+/// chip to logcat before delegating to the default hook.
+pub fn install_panic_hook(reporters: Vec<CrashReporter>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |panic_info| {
+        log::error!("{}", panic_info);
+        for reporter in &reporters {
+            let mut buffer = StackBuffer::<512>::new();
+            // A truncated report is still worth logging.
+            let _ = reporter.report(&mut buffer);
+            log::error!("{}", buffer.as_str());
+        }
+        default_hook(panic_info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_buffer_truncates() {
+        let mut buffer = StackBuffer::<8>::new();
+        assert!(write!(buffer, "0123").is_ok());
+        assert!(write!(buffer, "456789").is_err());
+        assert_eq!(buffer.as_str(), "01234567");
+    }
+
+    #[test]
+    fn stack_buffer_truncates_on_char_boundary() {
+        let mut buffer = StackBuffer::<4>::new();
+        assert!(write!(buffer, "ab\u{00e9}\u{00e9}").is_err());
+        assert_eq!(buffer.as_str(), "ab\u{00e9}");
+    }
+}
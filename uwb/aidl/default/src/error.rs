@@ -0,0 +1,115 @@
+use android_hardware_uwb::aidl::android::hardware::uwb::UwbStatus::UwbStatus;
+use android_hardware_uwb::binder;
+
+use std::fmt;
+use std::time::Duration;
+
+/// Service-specific error codes of the HAL: the failure values of the
+/// UwbStatus AIDL enum.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HalErrorCode {
+    Failed = UwbStatus::FAILED.0,
+    ErrTransport = UwbStatus::ERR_TRANSPORT.0,
+    ErrCmdTimeout = UwbStatus::ERR_CMD_TIMEOUT.0,
+    Refused = UwbStatus::REFUSED.0,
+}
+
+/// Failure of a call to the HAL, returned to the client as a
+/// service-specific error carrying its [`HalErrorCode`] and message.
+/// Calls made in the wrong state or with invalid arguments fail with the
+/// ILLEGAL_STATE and ILLEGAL_ARGUMENT exceptions instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HalError {
+    /// The UWBS cannot be reached, e.g. its device is absent.
+    NotAvailable(String),
+    /// The UWBS answered a command of the HAL with an error status.
+    CommandFailed {
+        command: &'static str,
+        status: Option<u8>,
+    },
+    /// Writing to or reading from the UWBS failed.
+    Transport(String),
+    /// The UWBS did not accept a packet in time.
+    WriteTimeout(Duration),
+    /// The UWBS did not answer a command of the HAL in time.
+    CommandTimeout(Duration),
+    /// The HAL refused the call, e.g. with its write queue full.
+    Refused(String),
+    /// The UWBS did not echo the payload of a loopback test.
+    LoopbackMismatch { sent: usize, received: usize },
+    /// Updating the firmware of the UWBS failed, closing the chip.
+    FirmwareUpdateFailed(String),
+}
+
+impl HalError {
+    pub fn code(&self) -> HalErrorCode {
+        match self {
+            Self::NotAvailable(_)
+            | Self::CommandFailed { .. }
+            | Self::LoopbackMismatch { .. }
+            | Self::FirmwareUpdateFailed(_) => HalErrorCode::Failed,
+            Self::Transport(_) | Self::WriteTimeout(_) => HalErrorCode::ErrTransport,
+            Self::CommandTimeout(_) => HalErrorCode::ErrCmdTimeout,
+            Self::Refused(_) => HalErrorCode::Refused,
+        }
+    }
+}
+
+impl fmt::Display for HalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAvailable(message) | Self::Transport(message) | Self::Refused(message) => {
+                f.write_str(message)
+            }
+            Self::CommandFailed { command, status } => {
+                write!(f, "{} failed with status {:02x?}", command, status)
+            }
+            Self::CommandTimeout(timeout) => write!(f, "no response after {:?}", timeout),
+            Self::WriteTimeout(timeout) => write!(f, "write timed out after {:?}", timeout),
+            Self::LoopbackMismatch { sent, received } => write!(
+                f,
+                "loopback payload mismatch: sent {} bytes, received {}",
+                sent, received
+            ),
+            Self::FirmwareUpdateFailed(message) => write!(f, "firmware update failed: {}", message),
+        }
+    }
+}
+
+impl From<HalError> for binder::Status {
+    fn from(err: HalError) -> Self {
+        binder::Status::new_service_specific_error_str(err.code() as i32, Some(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_carry_their_code() {
+        let status = binder::Status::from(HalError::CommandFailed {
+            command: "core init",
+            status: Some(0x01),
+        });
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::SERVICE_SPECIFIC
+        );
+        assert_eq!(status.service_specific_error(), UwbStatus::FAILED.0);
+        assert!(
+            status
+                .to_string()
+                .contains("core init failed with status Some(01)"),
+            "{}",
+            status
+        );
+
+        let status = binder::Status::from(HalError::CommandTimeout(Duration::from_millis(20)));
+        assert_eq!(
+            status.service_specific_error(),
+            HalErrorCode::ErrCmdTimeout as i32
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::transport::AsyncTransport;
+
+pub const DEFAULT_FIRMWARE_CHUNK_SIZE: usize = 256;
+pub const DEFAULT_FIRMWARE_RETRIES: u32 = 2;
+/// Largest image accepted by [`crate::uwb_chip::UwbChip::firmware_update`].
+pub const MAX_FW_IMAGE_SIZE: usize = 2 * 1024 * 1024;
+/// Size of the image chunks sent to a UWBS updated over UCI, each filling
+/// the payload of a control packet.
+pub const MAX_FW_CHUNK_SIZE: usize = u8::MAX as usize;
+/// Time allowed for the bootloader to accept a chunk.
+const CHUNK_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Protocol pushing a firmware image to the bootloader of a UWBS, before
+/// it speaks UCI.
+#[async_trait]
+pub trait FirmwareDownloader: Send + Sync {
+    /// Push `image` through `transport`, returning once the UWBS runs it.
+    /// The download is started over on failure.
+    async fn download(&self, transport: &mut dyn AsyncTransport, image: &[u8]) -> io::Result<()>;
+}
+
+/// Downloader writing the raw image in chunks, for bootloaders which
+/// expect nothing else.
+pub struct ChunkedDownloader {
+    chunk_size: usize,
+}
+
+impl ChunkedDownloader {
+    /// Write the image in chunks of `chunk_size` bytes, at least one.
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+        }
+    }
+}
+
+impl Default for ChunkedDownloader {
+    fn default() -> Self {
+        Self::new(DEFAULT_FIRMWARE_CHUNK_SIZE)
+    }
+}
+
+#[async_trait]
+impl FirmwareDownloader for ChunkedDownloader {
+    async fn download(&self, transport: &mut dyn AsyncTransport, image: &[u8]) -> io::Result<()> {
+        let count = image.len().div_ceil(self.chunk_size);
+        let mut progress = ProgressLog::new("firmware download", count);
+        for (index, chunk) in image.chunks(self.chunk_size).enumerate() {
+            write_chunk(transport, chunk).await.map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("chunk {} of {}: {}", index + 1, count, err),
+                )
+            })?;
+            progress.record(index + 1);
+        }
+        Ok(())
+    }
+}
+
+/// Logs the progress of a transfer every tenth of its chunks.
+pub struct ProgressLog {
+    what: &'static str,
+    count: usize,
+    logged_percent: usize,
+}
+
+impl ProgressLog {
+    pub fn new(what: &'static str, count: usize) -> Self {
+        Self {
+            what,
+            count,
+            logged_percent: 0,
+        }
+    }
+
+    /// Record that `done` of the chunks were transferred.
+    pub fn record(&mut self, done: usize) {
+        let percent = done * 100 / self.count.max(1) / 10 * 10;
+        if percent > self.logged_percent {
+            log::info!("{} {}%", self.what, percent);
+            self.logged_percent = percent;
+        }
+    }
+}
+
+/// Write all of `chunk`, failing with `TimedOut` if the bootloader does
+/// not accept it in time.
+async fn write_chunk(transport: &mut dyn AsyncTransport, mut chunk: &[u8]) -> io::Result<()> {
+    let result = tokio::time::timeout(CHUNK_WRITE_TIMEOUT, async {
+        while !chunk.is_empty() {
+            match transport.get_mut().write(chunk) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => chunk = &chunk[written..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => transport.writable().await?,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    })
+    .await;
+    result.unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("not accepted after {:?}", CHUNK_WRITE_TIMEOUT),
+        ))
+    })
+}
+
+/// Firmware pushed to the UWBS on open.
+#[derive(Clone)]
+pub struct FirmwareConfig {
+    /// File holding the image, read on every open.
+    pub path: PathBuf,
+    /// Attempts after the first failed download.
+    pub retries: u32,
+    pub downloader: Arc<dyn FirmwareDownloader>,
+}
+
+impl FirmwareConfig {
+    /// Push the image at `path` in chunks, see [`ChunkedDownloader`].
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            retries: DEFAULT_FIRMWARE_RETRIES,
+            downloader: Arc::new(ChunkedDownloader::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Transport;
+    use std::fmt;
+
+    /// Bootloader recording each write as a chunk, and failing the write
+    /// of chunk `fail_at`, if any.
+    #[derive(Default)]
+    struct Bootloader {
+        chunks: Vec<Vec<u8>>,
+        fail_at: Option<usize>,
+    }
+
+    impl fmt::Display for Bootloader {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bootloader")
+        }
+    }
+
+    impl Transport for Bootloader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail_at == Some(self.chunks.len()) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.chunks.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.write(buf).map(|_| ())
+        }
+
+        fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        fn into_async(self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    #[async_trait]
+    impl AsyncTransport for Bootloader {
+        async fn readable(&mut self) -> io::Result<()> {
+            std::future::pending().await
+        }
+
+        async fn writable(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn get_mut(&mut self) -> &mut dyn Transport {
+            self
+        }
+    }
+
+    #[tokio::test]
+    async fn image_is_written_in_chunks() {
+        let image: Vec<u8> = (0..=255).cycle().take(600).collect();
+        let mut bootloader = Bootloader::default();
+        ChunkedDownloader::new(256)
+            .download(&mut bootloader, &image)
+            .await
+            .unwrap();
+        assert_eq!(
+            bootloader.chunks,
+            [&image[..256], &image[256..512], &image[512..]]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_chunk_is_reported() {
+        let image = [0xaa; 600];
+        let mut bootloader = Bootloader {
+            fail_at: Some(1),
+            ..Default::default()
+        };
+        let err = ChunkedDownloader::new(256)
+            .download(&mut bootloader, &image)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert!(err.to_string().starts_with("chunk 2 of 3: "), "{}", err);
+        assert_eq!(bootloader.chunks, [&image[..256]]);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Thresholds of the open/close rate limiter.
+#[derive(Clone, Copy, Debug)]
+pub struct FlapGuardConfig {
+    /// Minimum delay between a close and the next open.
+    pub min_interval: Duration,
+    /// Maximum number of open/close cycles allowed within `window`.
+    pub max_cycles: usize,
+    /// Sliding window used to count open/close cycles.
+    pub window: Duration,
+    /// Backoff applied on the first rejected open, doubled on every
+    /// consecutive rejection.
+    pub base_backoff: Duration,
+    /// Upper bound of the backoff.
+    pub max_backoff: Duration,
+}
+
+impl Default for FlapGuardConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_millis(100),
+            max_cycles: 20,
+            window: Duration::from_secs(60),
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Rate limiter protecting the UWBS against a client flapping
+/// open/close: every cycle resets the chip.
+#[derive(Debug, Default)]
+pub struct FlapGuard {
+    config: FlapGuardConfig,
+    closes: VecDeque<Instant>,
+    backoff_until: Option<Instant>,
+    backoff_level: u32,
+    /// Total number of open/close cycles.
+    pub cycles: u64,
+    /// Total number of rejected opens.
+    pub rejected: u64,
+}
+
+impl FlapGuard {
+    pub fn new(config: FlapGuardConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Check whether the chip may be opened at `now`.
+    /// Returns the remaining backoff delay if the open must be rejected.
+    pub fn check_open(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(backoff_until) = self.backoff_until {
+            if now < backoff_until {
+                self.rejected += 1;
+                return Err(backoff_until - now);
+            }
+        }
+
+        while let Some(close) = self.closes.front() {
+            if now.duration_since(*close) <= self.config.window {
+                break;
+            }
+            self.closes.pop_front();
+        }
+
+        let too_soon = self
+            .closes
+            .back()
+            .is_some_and(|close| now.duration_since(*close) < self.config.min_interval);
+        let too_many = self.closes.len() >= self.config.max_cycles;
+
+        if !too_soon && !too_many {
+            self.backoff_level = 0;
+            self.backoff_until = None;
+            return Ok(());
+        }
+
+        let backoff = self
+            .config
+            .base_backoff
+            .saturating_mul(1 << self.backoff_level.min(16))
+            .min(self.config.max_backoff);
+        self.backoff_level += 1;
+        self.backoff_until = Some(now + backoff);
+        self.rejected += 1;
+        log::warn!(
+            "open/close flapping: {} cycles in the last {:?}, rejecting opens for {:?}",
+            self.closes.len(),
+            self.config.window,
+            backoff
+        );
+        Err(backoff)
+    }
+
+    /// Record the completion of a close at `now`.
+    pub fn record_close(&mut self, now: Instant) {
+        self.cycles += 1;
+        self.closes.push_back(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FlapGuardConfig {
+        FlapGuardConfig {
+            min_interval: Duration::from_millis(100),
+            max_cycles: 3,
+            window: Duration::from_secs(10),
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(4),
+        }
+    }
+
+    #[test]
+    fn slow_cycles_are_not_limited() {
+        let mut guard = FlapGuard::new(config());
+        let mut now = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(guard.check_open(now), Ok(()));
+            now += Duration::from_secs(5);
+            guard.record_close(now);
+            now += Duration::from_secs(1);
+        }
+        assert_eq!(guard.rejected, 0);
+        assert_eq!(guard.cycles, 10);
+    }
+
+    #[test]
+    fn open_right_after_close_is_rejected() {
+        let mut guard = FlapGuard::new(config());
+        let now = Instant::now();
+        assert_eq!(guard.check_open(now), Ok(()));
+        guard.record_close(now);
+        assert_eq!(
+            guard.check_open(now + Duration::from_millis(10)),
+            Err(Duration::from_secs(1))
+        );
+        // Recovered once the backoff has expired.
+        assert_eq!(guard.check_open(now + Duration::from_secs(2)), Ok(()));
+    }
+
+    #[test]
+    fn backoff_escalates_until_window_passes() {
+        let mut guard = FlapGuard::new(config());
+        let mut now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(guard.check_open(now), Ok(()));
+            now += Duration::from_millis(200);
+            guard.record_close(now);
+            now += Duration::from_millis(200);
+        }
+
+        assert_eq!(guard.check_open(now), Err(Duration::from_secs(1)));
+        now += Duration::from_secs(1);
+        assert_eq!(guard.check_open(now), Err(Duration::from_secs(2)));
+        now += Duration::from_secs(2);
+        assert_eq!(guard.check_open(now), Err(Duration::from_secs(4)));
+        now += Duration::from_secs(4);
+        assert_eq!(guard.check_open(now), Err(Duration::from_secs(4)));
+        assert_eq!(guard.rejected, 4);
+
+        // All the cycles have left the window.
+        now += Duration::from_secs(10);
+        assert_eq!(guard.check_open(now), Ok(()));
+    }
+}
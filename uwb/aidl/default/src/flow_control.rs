@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Mutex;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+
+const DATA_MESSAGE_SND_HEADER: u8 = 0b0000_0001;
+const DATA_CREDIT_NTF_HEADER: [u8; 2] = [0x62, 0x04];
+const HEADER_TYPE_MASK: u8 = 0b1110_1111;
+const SESSION_TOKEN_SIZE: usize = 4;
+const CREDIT_AVAILABLE: u8 = 0x01;
+const DATA_TRANSFER_STATUS_NTF_HEADER: [u8; 2] = [0x62, 0x05];
+/// Offset of the status of a DATA_TRANSFER_STATUS_NTF, after the session
+/// token and the 16-bit sequence number.
+const DATA_TRANSFER_STATUS_OFFSET: usize = UCI_HEADER_SIZE + SESSION_TOKEN_SIZE + 2;
+const DATA_TRANSFER_ERROR_NO_CREDIT_AVAILABLE: u8 = 0x03;
+
+/// Data packets queued per session while the UWBS grants no credit.
+pub const DATA_QUEUE_CAPACITY: usize = 16;
+
+/// Return the session token of a DATA_MESSAGE_SND packet, or `None` for
+/// any other packet.
+pub fn data_message_snd_session(packet: &[u8]) -> Option<u32> {
+    if packet.len() < UCI_HEADER_SIZE + SESSION_TOKEN_SIZE
+        || packet[0] & HEADER_TYPE_MASK != DATA_MESSAGE_SND_HEADER
+    {
+        return None;
+    }
+    Some(u32::from_le_bytes(packet[4..8].try_into().unwrap()))
+}
+
+/// Return the session token and credit availability carried by a
+/// DATA_CREDIT_NTF packet, or `None` for any other packet.
+pub fn data_credit_ntf(packet: &[u8]) -> Option<(u32, bool)> {
+    if packet.len() < UCI_HEADER_SIZE + SESSION_TOKEN_SIZE + 1
+        || packet[0] & HEADER_TYPE_MASK != DATA_CREDIT_NTF_HEADER[0]
+        || packet[1] & 0x3f != DATA_CREDIT_NTF_HEADER[1]
+    {
+        return None;
+    }
+    Some((
+        u32::from_le_bytes(packet[4..8].try_into().unwrap()),
+        packet[8] == CREDIT_AVAILABLE,
+    ))
+}
+
+/// Return the session token of a DATA_TRANSFER_STATUS_NTF reporting that a
+/// data message was sent without credit, or `None` for any other packet.
+pub fn data_transfer_no_credit_ntf(packet: &[u8]) -> Option<u32> {
+    if packet.len() <= DATA_TRANSFER_STATUS_OFFSET
+        || packet[0] & HEADER_TYPE_MASK != DATA_TRANSFER_STATUS_NTF_HEADER[0]
+        || packet[1] & 0x3f != DATA_TRANSFER_STATUS_NTF_HEADER[1]
+        || packet[DATA_TRANSFER_STATUS_OFFSET] != DATA_TRANSFER_ERROR_NO_CREDIT_AVAILABLE
+    {
+        return None;
+    }
+    Some(u32::from_le_bytes(packet[4..8].try_into().unwrap()))
+}
+
+/// Fragments of a data message, written back to back.
+pub type DataMessage = Vec<Vec<u8>>;
+
+/// Outcome of [`CreditTracker::submit`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Submission {
+    /// The credit of the session was consumed: the message is to be
+    /// written.
+    Send(DataMessage),
+    /// The message is queued until the UWBS grants a credit. The oldest
+    /// message was dropped to make room for it if `dropped` is set.
+    Queued { dropped: bool },
+}
+
+/// Tracks the data credits granted by the UWBS with DATA_CREDIT_NTF.
+/// Every session holds at most one credit, available when the session
+/// is first used, and consumed by each DATA_MESSAGE_SND. The messages
+/// submitted without credit are queued, and released in order as the
+/// credits are granted.
+pub struct CreditTracker {
+    capacity: usize,
+    sessions: Mutex<Sessions>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    sessions: HashMap<u32, SessionCredit>,
+    closed: bool,
+}
+
+struct SessionCredit {
+    available: bool,
+    queued: VecDeque<DataMessage>,
+}
+
+impl Default for SessionCredit {
+    fn default() -> Self {
+        Self {
+            available: true,
+            queued: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for CreditTracker {
+    fn default() -> Self {
+        Self::new(DATA_QUEUE_CAPACITY)
+    }
+}
+
+impl CreditTracker {
+    /// Queue at most `capacity` messages per session, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            sessions: Mutex::default(),
+        }
+    }
+
+    /// Consume the credit of a session for `message`, or queue it if the
+    /// credit is not available, dropping the oldest message of a full
+    /// queue. Fails with `NotConnected` once the tracker is closed.
+    pub fn submit(&self, session_token: u32, message: DataMessage) -> io::Result<Submission> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.closed {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "chip closed"));
+        }
+        let session = sessions.sessions.entry(session_token).or_default();
+        if session.available {
+            session.available = false;
+            return Ok(Submission::Send(message));
+        }
+        let dropped = session.queued.len() == self.capacity;
+        if dropped {
+            session.queued.pop_front();
+        }
+        session.queued.push_back(message);
+        Ok(Submission::Queued { dropped })
+    }
+
+    /// Record the credit availability reported for a session. Returns the
+    /// oldest message queued for the session if a credit was granted,
+    /// consuming it.
+    pub fn grant(&self, session_token: u32, available: bool) -> Option<DataMessage> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.closed {
+            return None;
+        }
+        let session = sessions.sessions.entry(session_token).or_default();
+        if !available {
+            session.available = false;
+            return None;
+        }
+        let message = session.queued.pop_front();
+        session.available = message.is_none();
+        message
+    }
+
+    /// Forget the credit and the queued messages of a deinitialized
+    /// session.
+    pub fn remove(&self, session_token: u32) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(&session_token);
+    }
+
+    /// Number of messages queued, over all sessions.
+    pub fn queued(&self) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .sessions
+            .values()
+            .map(|session| session.queued.len())
+            .sum()
+    }
+
+    /// Drop the queued messages, and fail the future calls to
+    /// [`Self::submit`].
+    pub fn close(&self) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.closed = true;
+        sessions.sessions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_credit_ntf_is_parsed() {
+        assert_eq!(
+            data_credit_ntf(&[0x62, 0x04, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x01]),
+            Some((0x04030201, true))
+        );
+        assert_eq!(
+            data_credit_ntf(&[0x62, 0x04, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x00]),
+            Some((0x04030201, false))
+        );
+        assert_eq!(data_credit_ntf(&[0x62, 0x03, 0x00, 0x01, 0x01]), None);
+        assert_eq!(
+            data_message_snd_session(&[0x01, 0x00, 0x04, 0x00, 0x01, 0x02, 0x03, 0x04]),
+            Some(0x04030201)
+        );
+        // DATA_MESSAGE_RCV.
+        assert_eq!(
+            data_message_snd_session(&[0x02, 0x00, 0x04, 0x00, 0x01, 0x02, 0x03, 0x04]),
+            None
+        );
+        assert_eq!(
+            data_transfer_no_credit_ntf(&[
+                0x62, 0x05, 0x00, 0x08, 0x01, 0x02, 0x03, 0x04, 0x07, 0x00, 0x03, 0x00
+            ]),
+            Some(0x04030201)
+        );
+        // DATA_TRANSFER_STATUS_OK.
+        assert_eq!(
+            data_transfer_no_credit_ntf(&[
+                0x62, 0x05, 0x00, 0x08, 0x01, 0x02, 0x03, 0x04, 0x07, 0x00, 0x01, 0x00
+            ]),
+            None
+        );
+    }
+
+    #[test]
+    fn messages_wait_for_grant() {
+        let tracker = CreditTracker::new(2);
+        let message = |byte| vec![vec![byte]];
+        assert_eq!(
+            tracker.submit(1, message(1)).unwrap(),
+            Submission::Send(message(1))
+        );
+        // Other sessions have their own credit.
+        assert_eq!(
+            tracker.submit(2, message(2)).unwrap(),
+            Submission::Send(message(2))
+        );
+
+        for byte in 3..=4 {
+            assert_eq!(
+                tracker.submit(1, message(byte)).unwrap(),
+                Submission::Queued { dropped: false }
+            );
+        }
+        assert_eq!(
+            tracker.submit(1, message(5)).unwrap(),
+            Submission::Queued { dropped: true }
+        );
+        assert_eq!(tracker.queued(), 2);
+
+        // The queued messages are released in order, one per credit.
+        assert_eq!(tracker.grant(1, true), Some(message(4)));
+        assert_eq!(tracker.grant(1, true), Some(message(5)));
+        assert_eq!(tracker.grant(1, true), None);
+        // Credits do not accumulate.
+        assert_eq!(tracker.grant(1, true), None);
+        assert_eq!(
+            tracker.submit(1, message(6)).unwrap(),
+            Submission::Send(message(6))
+        );
+
+        // A revoked credit must be granted again.
+        tracker.grant(2, true);
+        tracker.grant(2, false);
+        assert_eq!(
+            tracker.submit(2, message(7)).unwrap(),
+            Submission::Queued { dropped: false }
+        );
+    }
+
+    #[test]
+    fn removed_sessions_start_over() {
+        let tracker = CreditTracker::default();
+        tracker.submit(1, vec![]).unwrap();
+        tracker.submit(1, vec![vec![0x01]]).unwrap();
+        tracker.remove(1);
+        assert_eq!(tracker.queued(), 0);
+        assert_eq!(tracker.submit(1, vec![]).unwrap(), Submission::Send(vec![]));
+
+        tracker.submit(1, vec![vec![0x01]]).unwrap();
+        tracker.close();
+        assert_eq!(tracker.queued(), 0);
+        assert_eq!(tracker.grant(1, true), None);
+        assert_eq!(
+            tracker.submit(2, vec![]).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+}
@@ -0,0 +1,795 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+
+pub const UCI_HEADER_SIZE: usize = 4;
+const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+const DATA_MESSAGE_TYPE: u8 = 0b000;
+/// Notifications have the highest message type in use.
+const MAX_MESSAGE_TYPE: u8 = 0b011;
+const PACKET_BOUNDARY_FLAG: u8 = 0b00010000;
+const GROUP_ID_MASK: u8 = 0b00001111;
+const OPCODE_ID_MASK: u8 = 0b00111111;
+
+/// Default maximum payload size of a control packet fragment.
+pub const DEFAULT_MAX_PACKET_SIZE: usize = u8::MAX as usize;
+
+/// Default maximum payload size of a data packet segment: the largest
+/// payload its header can advertise, so that data packets are not
+/// segmented unless the UWBS asks for it.
+pub const DEFAULT_MAX_DATA_PAYLOAD_SIZE: usize = u16::MAX as usize;
+
+/// Default maximum payload size of the data packets received from the
+/// UWBS, see [`check_header`].
+pub const DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE: usize = 4096;
+
+/// Default maximum number of bytes skipped by [`StreamSync`] to find a
+/// plausible header.
+pub const DEFAULT_MAX_RESYNC_BYTES: usize = 1024;
+/// Group identifiers reserved by the UCI specification, between the
+/// standard groups and the proprietary ones.
+const RESERVED_GROUP_IDS: std::ops::RangeInclusive<u8> = 0x4..=0x8;
+
+/// Data Packet Format of DATA_MESSAGE_SND.
+const DATA_MESSAGE_SND_DPF: u8 = 0b0001;
+const DATA_PACKET_FORMAT_MASK: u8 = 0b00001111;
+
+/// Default maximum number of fragments in a logical packet.
+pub const DEFAULT_MAX_FRAGMENTS: usize = 256;
+
+/// Default maximum payload size of a reassembled logical packet: the
+/// largest payload a data packet header can advertise.
+pub const DEFAULT_MAX_REASSEMBLED_SIZE: usize = u16::MAX as usize;
+
+/// Return whether `header` is the header of a data packet.
+pub fn is_data(header: &[u8]) -> bool {
+    (header[0] & MESSAGE_TYPE_MASK) >> 5 == DATA_MESSAGE_TYPE
+}
+
+/// Return the payload length advertised in a UCI packet header.
+pub fn payload_length(header: &[u8]) -> usize {
+    if is_data(header) {
+        u16::from_le_bytes([header[2], header[3]]) as usize
+    } else {
+        header[3] as usize
+    }
+}
+
+/// Write the payload length in a packet header. Fails if the length
+/// cannot be represented.
+fn set_payload_length(header: &mut [u8], length: usize) -> io::Result<()> {
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("payload of {} bytes does not fit in the header", length),
+        )
+    };
+    if is_data(header) {
+        let length = u16::try_from(length).map_err(|_| too_large())?;
+        header[2..4].copy_from_slice(&length.to_le_bytes());
+    } else {
+        header[3] = u8::try_from(length).map_err(|_| too_large())?;
+    }
+    Ok(())
+}
+
+/// Check that a packet is well formed: its message type is known and
+/// the payload length advertised in its header matches the buffer.
+/// The length of control packets too large for their header, which must
+/// be fragmented before being sent, is not checked.
+pub fn check_packet(packet: &[u8]) -> io::Result<()> {
+    let (header, payload) = split_header(packet)?;
+    check_message_type(&header)?;
+    let oversized = !is_data(&header) && payload.len() > u8::MAX as usize;
+    if !oversized && payload.len() != payload_length(&header) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "payload length {} does not match the header: {:02x?}",
+                payload.len(),
+                header
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Check the header of a packet received from the UWBS before reading
+/// its payload, and return the payload length it advertises. Fails with
+/// `InvalidData` if the message type is unknown, or if a data packet
+/// advertises more than `max_data_payload` bytes: the header is likely
+/// garbage, and the payload may never come. The payload of control
+/// packets is bounded to 255 bytes by their header.
+pub fn check_header(header: &[u8], max_data_payload: usize) -> io::Result<usize> {
+    let header = &header[..UCI_HEADER_SIZE];
+    check_message_type(header)?;
+    let length = payload_length(header);
+    if is_data(header) && length > max_data_payload {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "data payload of {} bytes larger than {} bytes: {:02x?}",
+                length, max_data_payload, header
+            ),
+        ));
+    }
+    Ok(length)
+}
+
+fn check_message_type(header: &[u8]) -> io::Result<()> {
+    let mt = (header[0] & MESSAGE_TYPE_MASK) >> 5;
+    if mt > MAX_MESSAGE_TYPE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown message type {}: {:02x?}", mt, header),
+        ));
+    }
+    Ok(())
+}
+
+fn split_header(packet: &[u8]) -> io::Result<([u8; UCI_HEADER_SIZE], &[u8])> {
+    if packet.len() < UCI_HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("packet shorter than the header: {:02x?}", packet),
+        ));
+    }
+    let (header, payload) = packet.split_at(UCI_HEADER_SIZE);
+    Ok((header.try_into().unwrap(), payload))
+}
+
+/// Splits logical UCI control packets into fragments of bounded size,
+/// chained with the Packet Boundary Flag. Data packets are left untouched,
+/// see [`Segmenter`].
+pub struct Fragmenter {
+    max_packet_size: usize,
+}
+
+impl Fragmenter {
+    /// Create a fragmenter emitting control packet fragments with at most
+    /// `max_packet_size` bytes of payload. Sizes larger than what the
+    /// header can represent are capped.
+    pub fn new(max_packet_size: usize) -> Self {
+        Self {
+            max_packet_size: max_packet_size.clamp(1, u8::MAX as usize),
+        }
+    }
+
+    /// Split `packet` into fragments. The payload is the whole buffer past
+    /// the header, regardless of the length advertised in the header.
+    /// Data packets and control packets which fit in a single fragment
+    /// are returned unchanged.
+    pub fn fragment(&self, packet: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let (header, payload) = split_header(packet)?;
+        if is_data(&header) || payload.len() <= self.max_packet_size {
+            return Ok(vec![packet.to_vec()]);
+        }
+
+        let chunks = payload.chunks(self.max_packet_size);
+        let count = chunks.len();
+        chunks
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut fragment = header.to_vec();
+                if index + 1 < count {
+                    fragment[0] |= PACKET_BOUNDARY_FLAG;
+                } else {
+                    fragment[0] &= !PACKET_BOUNDARY_FLAG;
+                }
+                set_payload_length(&mut fragment, chunk.len())?;
+                fragment.extend_from_slice(chunk);
+                Ok(fragment)
+            })
+            .collect()
+    }
+}
+
+impl Default for Fragmenter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PACKET_SIZE)
+    }
+}
+
+/// Splits DATA_MESSAGE_SND packets into segments of at most the payload
+/// size accepted by the UWBS, chained with the Packet Boundary Flag. Only
+/// the first segment carries the session handle, destination address and
+/// sequence number of the message. Other packets are left untouched.
+pub struct Segmenter {
+    max_payload_size: usize,
+}
+
+impl Segmenter {
+    /// Create a segmenter emitting segments with at most
+    /// `max_payload_size` bytes of payload. Sizes larger than what the
+    /// header can represent are capped.
+    pub fn new(max_payload_size: usize) -> Self {
+        Self {
+            max_payload_size: max_payload_size.clamp(1, u16::MAX as usize),
+        }
+    }
+
+    /// Split `packet` into segments. DATA_MESSAGE_SND packets which fit
+    /// in a single segment, and other packets, are returned unchanged.
+    pub fn segment(&self, packet: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let (header, payload) = split_header(packet)?;
+        if !is_data(&header)
+            || header[0] & DATA_PACKET_FORMAT_MASK != DATA_MESSAGE_SND_DPF
+            || payload.len() <= self.max_payload_size
+        {
+            return Ok(vec![packet.to_vec()]);
+        }
+
+        let chunks = payload.chunks(self.max_payload_size);
+        let count = chunks.len();
+        chunks
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut segment = header.to_vec();
+                if index + 1 < count {
+                    segment[0] |= PACKET_BOUNDARY_FLAG;
+                } else {
+                    segment[0] &= !PACKET_BOUNDARY_FLAG;
+                }
+                set_payload_length(&mut segment, chunk.len())?;
+                segment.extend_from_slice(chunk);
+                Ok(segment)
+            })
+            .collect()
+    }
+}
+
+/// Fragments accumulated for a logical packet.
+struct Partial {
+    header: [u8; UCI_HEADER_SIZE],
+    payload: Vec<u8>,
+    fragments: usize,
+}
+
+/// Reassembles the logical UCI packets fragmented with the Packet
+/// Boundary Flag. Fragments are accumulated per message type, group
+/// identifier (or data packet format) and opcode identifier.
+///
+/// Control packets are reassembled up to 255 bytes of payload, the most
+/// their header can advertise; larger ones are rejected.
+pub struct Defragmenter {
+    max_fragments: usize,
+    max_size: usize,
+    partials: HashMap<(u8, u8, u8), Partial>,
+}
+
+impl Defragmenter {
+    /// Create a defragmenter rejecting logical packets made of more than
+    /// `max_fragments` fragments, or carrying more than `max_size` bytes
+    /// of payload.
+    pub fn new(max_fragments: usize, max_size: usize) -> Self {
+        Self {
+            max_fragments,
+            max_size: max_size.min(DEFAULT_MAX_REASSEMBLED_SIZE),
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Discard the fragments accumulated so far. Returns the headers of
+    /// the incomplete logical packets.
+    pub fn discard(&mut self) -> Vec<[u8; UCI_HEADER_SIZE]> {
+        self.partials
+            .drain()
+            .map(|(_, partial)| partial.header)
+            .collect()
+    }
+
+    /// Process a fragment. Returns the complete logical packet once its
+    /// last fragment is received, or `fragment` itself if unfragmented.
+    /// On error the fragments accumulated for the same logical packet are
+    /// discarded.
+    pub fn push<'a>(&mut self, fragment: &'a [u8]) -> io::Result<Option<Cow<'a, [u8]>>> {
+        check_packet(fragment)?;
+        let (header, payload) = split_header(fragment)?;
+
+        let last = header[0] & PACKET_BOUNDARY_FLAG == 0;
+        let key = (
+            header[0] & MESSAGE_TYPE_MASK,
+            header[0] & GROUP_ID_MASK,
+            header[1] & OPCODE_ID_MASK,
+        );
+        let Some(mut partial) = self.partials.remove(&key) else {
+            if last {
+                // Unfragmented packet.
+                return Ok(Some(Cow::Borrowed(fragment)));
+            }
+            self.partials.insert(
+                key,
+                Partial {
+                    header,
+                    payload: payload.to_vec(),
+                    fragments: 1,
+                },
+            );
+            return Ok(None);
+        };
+
+        partial.fragments += 1;
+        if partial.fragments > self.max_fragments {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet {:02x?} has too many fragments", partial.header),
+            ));
+        }
+        let max_size = match is_data(&partial.header) {
+            true => self.max_size,
+            false => self.max_size.min(DEFAULT_MAX_PACKET_SIZE),
+        };
+        if partial.payload.len() + payload.len() > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet {:02x?} exceeds {} bytes", partial.header, max_size),
+            ));
+        }
+        partial.payload.extend_from_slice(payload);
+        if !last {
+            self.partials.insert(key, partial);
+            return Ok(None);
+        }
+
+        let mut packet = partial.header.to_vec();
+        packet[0] &= !PACKET_BOUNDARY_FLAG;
+        if is_data(&packet) {
+            set_payload_length(&mut packet, partial.payload.len())?;
+        } else {
+            packet[3] = partial.payload.len() as u8;
+        }
+        packet.extend_from_slice(&partial.payload);
+        Ok(Some(Cow::Owned(packet)))
+    }
+}
+
+impl Default for Defragmenter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAGMENTS, DEFAULT_MAX_REASSEMBLED_SIZE)
+    }
+}
+
+/// Finds the packet boundaries in a byte stream from the UWBS. A byte
+/// lost or corrupted, as happens with noise on a UART, leaves the
+/// following packets parsed at the wrong offset: headers which fail
+/// [`check_header`] or address a reserved group are skipped one byte at
+/// a time until a plausible header is found.
+pub struct StreamSync {
+    max_data_payload: usize,
+    max_skipped: usize,
+    /// Bytes skipped since the count was last taken.
+    skipped: usize,
+}
+
+impl StreamSync {
+    /// Create a stream synchronizer accepting data packets of at most
+    /// `max_data_payload` bytes of payload, and declaring the link broken
+    /// after skipping more than `max_skipped` bytes in a row.
+    pub fn new(max_data_payload: usize, max_skipped: usize) -> Self {
+        Self {
+            max_data_payload,
+            max_skipped,
+            skipped: 0,
+        }
+    }
+
+    /// Check the header at the start of `bytes`, and return the payload
+    /// length it advertises, or `None` if the first byte is to be skipped
+    /// and the header checked again one byte further. Fails with
+    /// `InvalidData` once more bytes than the maximum were skipped since
+    /// the count was last taken, see [`StreamSync::take_skipped`].
+    pub fn header(&mut self, bytes: &[u8]) -> io::Result<Option<usize>> {
+        let header = &bytes[..UCI_HEADER_SIZE];
+        let reserved =
+            !is_data(header) && RESERVED_GROUP_IDS.contains(&(header[0] & GROUP_ID_MASK));
+        match check_header(header, self.max_data_payload) {
+            Ok(length) if !reserved => return Ok(Some(length)),
+            _ => (),
+        }
+        self.skipped += 1;
+        if self.skipped > self.max_skipped {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no valid header found in {} bytes", self.max_skipped),
+            ));
+        }
+        Ok(None)
+    }
+
+    /// Bytes skipped since the count was last taken.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Return the number of bytes skipped since the count was last taken,
+    /// and start counting again. Called once a packet is found, so that
+    /// the maximum applies to the bytes skipped in a row.
+    pub fn take_skipped(&mut self) -> usize {
+        std::mem::take(&mut self.skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data packet for session 0 carrying `len` bytes.
+    fn data_packet(len: usize) -> Vec<u8> {
+        let mut packet = vec![0x01, 0x00];
+        packet.extend_from_slice(&(len as u16).to_le_bytes());
+        packet.extend((0..len).map(|i| i as u8));
+        packet
+    }
+
+    #[test]
+    fn single_packet_is_unchanged() {
+        let packet = vec![0x60, 0x01, 0x00, 0x01, 0x01];
+        assert_eq!(
+            Fragmenter::default().fragment(&packet).unwrap(),
+            vec![packet.clone()]
+        );
+        assert_eq!(
+            Defragmenter::default().push(&packet).unwrap().as_deref(),
+            Some(&packet[..])
+        );
+    }
+
+    /// Vendor command carrying `len` bytes. The length field of the header
+    /// is truncated when the payload does not fit.
+    fn vendor_command(len: usize) -> Vec<u8> {
+        let mut packet = vec![0x2e, 0x01, 0x00, len as u8];
+        packet.extend((0..len).map(|i| i as u8));
+        packet
+    }
+
+    #[test]
+    fn two_fragments() {
+        let packet = vendor_command(300);
+        let fragments = Fragmenter::default().fragment(&packet).unwrap();
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0][..4], [0x3e, 0x01, 0x00, 0xff]);
+        assert_eq!(fragments[0][4..], packet[4..259]);
+        assert_eq!(fragments[1][..4], [0x2e, 0x01, 0x00, 0x2d]);
+        assert_eq!(fragments[1][4..], packet[259..]);
+    }
+
+    #[test]
+    fn fragment_size_is_configurable() {
+        let packet = vendor_command(25);
+        let fragments = Fragmenter::new(10).fragment(&packet).unwrap();
+        let headers: Vec<_> = fragments.iter().map(|fragment| &fragment[..4]).collect();
+        assert_eq!(
+            headers,
+            [
+                [0x3e, 0x01, 0x00, 0x0a],
+                [0x3e, 0x01, 0x00, 0x0a],
+                [0x2e, 0x01, 0x00, 0x05]
+            ]
+        );
+        let payload: Vec<u8> = fragments
+            .iter()
+            .flat_map(|fragment| fragment[4..].to_vec())
+            .collect();
+        assert_eq!(payload, packet[4..]);
+
+        let mut defragmenter = Defragmenter::default();
+        assert_eq!(defragmenter.push(&fragments[0]).unwrap(), None);
+        assert_eq!(defragmenter.push(&fragments[1]).unwrap(), None);
+        assert_eq!(
+            defragmenter.push(&fragments[2]).unwrap().as_deref(),
+            Some(&packet[..])
+        );
+    }
+
+    #[test]
+    fn packet_at_limit_is_not_fragmented() {
+        let packet = vendor_command(255);
+        assert_eq!(
+            Fragmenter::default().fragment(&packet).unwrap(),
+            vec![packet.clone()]
+        );
+        let packet = vendor_command(10);
+        assert_eq!(
+            Fragmenter::new(10).fragment(&packet).unwrap(),
+            vec![packet.clone()]
+        );
+    }
+
+    #[test]
+    fn data_packets_are_not_fragmented() {
+        let packet = data_packet(1000);
+        assert_eq!(
+            Fragmenter::new(10).fragment(&packet).unwrap(),
+            vec![packet.clone()]
+        );
+    }
+
+    /// DATA_MESSAGE_SND of session 1 carrying `len` bytes of payload,
+    /// including the session handle, destination address, sequence
+    /// number and application data size.
+    fn data_message_snd(len: usize) -> Vec<u8> {
+        let mut packet = vec![0x01, 0x00];
+        packet.extend_from_slice(&(len as u16).to_le_bytes());
+        packet.extend_from_slice(&1u32.to_le_bytes());
+        packet.extend((4..len).map(|i| i as u8));
+        packet
+    }
+
+    #[test]
+    fn data_messages_are_segmented() {
+        let packet = data_message_snd(2200);
+        let segments = Segmenter::new(1022).segment(&packet).unwrap();
+        let headers: Vec<_> = segments.iter().map(|segment| &segment[..4]).collect();
+        assert_eq!(
+            headers,
+            [
+                [0x11, 0x00, 0xfe, 0x03],
+                [0x11, 0x00, 0xfe, 0x03],
+                [0x01, 0x00, 0x9c, 0x00]
+            ]
+        );
+        let payload: Vec<u8> = segments
+            .iter()
+            .flat_map(|segment| segment[4..].to_vec())
+            .collect();
+        assert_eq!(payload, packet[4..]);
+
+        let mut defragmenter = Defragmenter::default();
+        assert_eq!(defragmenter.push(&segments[0]).unwrap(), None);
+        assert_eq!(defragmenter.push(&segments[1]).unwrap(), None);
+        assert_eq!(
+            defragmenter.push(&segments[2]).unwrap().as_deref(),
+            Some(&packet[..])
+        );
+    }
+
+    #[test]
+    fn small_and_other_packets_are_not_segmented() {
+        let segmenter = Segmenter::new(1022);
+        let packet = data_message_snd(1022);
+        assert_eq!(segmenter.segment(&packet).unwrap(), vec![packet.clone()]);
+
+        // Other data packets, and control packets.
+        let mut packet = data_packet(2000);
+        packet[0] = 0x02;
+        assert_eq!(segmenter.segment(&packet).unwrap(), vec![packet.clone()]);
+        let packet = vendor_command(255);
+        assert_eq!(
+            Segmenter::new(10).segment(&packet).unwrap(),
+            vec![packet.clone()]
+        );
+    }
+
+    #[test]
+    fn interleaved_packets_are_reassembled_separately() {
+        // Two fragments of a vendor notification, with a core notification
+        // in between.
+        let mut defragmenter = Defragmenter::default();
+        assert_eq!(
+            defragmenter.push(&[0x7e, 0x01, 0x00, 0x01, 0xaa]).unwrap(),
+            None
+        );
+        assert_eq!(
+            defragmenter
+                .push(&[0x60, 0x01, 0x00, 0x01, 0x01])
+                .unwrap()
+                .as_deref(),
+            Some(&[0x60, 0x01, 0x00, 0x01, 0x01][..])
+        );
+        assert_eq!(
+            defragmenter
+                .push(&[0x6e, 0x01, 0x00, 0x01, 0xbb])
+                .unwrap()
+                .as_deref(),
+            Some(&[0x6e, 0x01, 0x00, 0x02, 0xaa, 0xbb][..])
+        );
+    }
+
+    #[test]
+    fn max_fragment_count() {
+        let max_fragments = 4;
+        let fragmenter = Fragmenter::new(10);
+        let fragments = fragmenter
+            .fragment(&vendor_command(10 * max_fragments))
+            .unwrap();
+        assert_eq!(fragments.len(), max_fragments);
+        let mut defragmenter = Defragmenter::new(max_fragments, DEFAULT_MAX_REASSEMBLED_SIZE);
+        let packets: Vec<_> = fragments
+            .iter()
+            .map(|fragment| defragmenter.push(fragment).unwrap())
+            .collect();
+        assert_eq!(packets.last().unwrap().as_ref().unwrap().len(), 44);
+
+        // One fragment too many.
+        let fragments = fragmenter
+            .fragment(&vendor_command(10 * max_fragments + 1))
+            .unwrap();
+        assert_eq!(fragments.len(), max_fragments + 1);
+        for fragment in &fragments[..max_fragments] {
+            assert_eq!(defragmenter.push(fragment).unwrap(), None);
+        }
+        assert!(defragmenter.push(&fragments[max_fragments]).is_err());
+    }
+
+    #[test]
+    fn well_formed_packets_are_accepted() {
+        // Control packets.
+        check_packet(&[0x20, 0x00, 0x00, 0x01, 0x00]).unwrap();
+        check_packet(&[0x60, 0x01, 0x00, 0x00]).unwrap();
+        // Vendor command.
+        check_packet(&[0x2e, 0x01, 0x00, 0x02, 0x01, 0x02]).unwrap();
+        // Data packet.
+        check_packet(&data_packet(300)).unwrap();
+        // Control packet to be fragmented.
+        check_packet(&vendor_command(300)).unwrap();
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        // Shorter than the header.
+        assert!(check_packet(&[]).is_err());
+        assert!(check_packet(&[0x20, 0x00, 0x00]).is_err());
+        // Truncated payload.
+        assert!(check_packet(&[0x20, 0x00, 0x00, 0xc8, 0x00]).is_err());
+        assert!(check_packet(&data_packet(300)[..200]).is_err());
+        // Trailing bytes.
+        assert!(check_packet(&[0x20, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert!(check_packet(&[&data_packet(300)[..], &[0x00]].concat()).is_err());
+        // The data length is 16-bit: 0x0100 bytes, not 0x00.
+        assert!(check_packet(&[0x01, 0x00, 0x00, 0x01]).is_err());
+        // Reserved message type.
+        assert!(check_packet(&[0x80, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    /// Split `stream` into the packets found by `sync`, as the reader task
+    /// does, ignoring the incomplete packet at its end. Returns the
+    /// packets and the bytes skipped before each of them.
+    fn split(sync: &mut StreamSync, mut stream: &[u8]) -> io::Result<Vec<(Vec<u8>, usize)>> {
+        let mut packets = vec![];
+        while stream.len() >= UCI_HEADER_SIZE {
+            match sync.header(stream)? {
+                Some(length) if stream.len() >= UCI_HEADER_SIZE + length => {
+                    let (packet, rest) = stream.split_at(UCI_HEADER_SIZE + length);
+                    packets.push((packet.to_vec(), sync.take_skipped()));
+                    stream = rest;
+                }
+                Some(_) => break,
+                None => stream = &stream[1..],
+            }
+        }
+        Ok(packets)
+    }
+
+    #[test]
+    fn stream_is_resynchronized_after_junk() {
+        const DEVICE_STATUS_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x01];
+        const CORE_GET_CONFIG_RSP: [u8; 6] = [0x40, 0x05, 0x00, 0x02, 0x00, 0x00];
+        let stream = [
+            &DEVICE_STATUS_NTF[..],
+            // Unknown message type.
+            &[0xff, 0xe0],
+            &data_packet(16),
+            // Data header longer than allowed, then reserved group.
+            &[0x02, 0x00, 0xff, 0xff],
+            &[0x28, 0x01, 0xf0, 0xf5],
+            &CORE_GET_CONFIG_RSP,
+        ]
+        .concat();
+        // The limit applies to the bytes skipped in a row.
+        let mut sync = StreamSync::new(1024, 8);
+        assert_eq!(
+            split(&mut sync, &stream).unwrap(),
+            vec![
+                (DEVICE_STATUS_NTF.to_vec(), 0),
+                (data_packet(16), 2),
+                (CORE_GET_CONFIG_RSP.to_vec(), 8)
+            ]
+        );
+        assert_eq!(sync.skipped(), 0);
+    }
+
+    #[test]
+    fn stream_without_valid_header_is_broken() {
+        let mut sync = StreamSync::new(1024, 8);
+        let err = split(&mut sync, &[0xff; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(sync.skipped(), 9);
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        assert_eq!(check_header(&[0x60, 0x01, 0x00, 0xff], 16).unwrap(), 255);
+        assert_eq!(check_header(&[0x01, 0x00, 0x00, 0x10], 4096).unwrap(), 4096);
+        // A glitch advertising 0xffff bytes of data.
+        let err = check_header(&[0x01, 0x00, 0xff, 0xff], 4096).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(check_header(&[0x01, 0x00, 0x01, 0x10], 4096).is_err());
+        assert!(check_header(&[0xe0, 0x00, 0x00, 0x00], 4096).is_err());
+    }
+
+    #[test]
+    fn large_control_packets_are_reassembled() {
+        // GET_CAPS_INFO response in three fragments.
+        let packet = [&[0x40, 0x03, 0x00, 0xfa][..], &[0xaa; 250]].concat();
+        let fragments = Fragmenter::new(100).fragment(&packet).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        let mut defragmenter = Defragmenter::default();
+        assert_eq!(defragmenter.push(&fragments[0]).unwrap(), None);
+        // Notification received between two fragments.
+        assert_eq!(
+            defragmenter
+                .push(&[0x60, 0x01, 0x00, 0x01, 0x01])
+                .unwrap()
+                .as_deref(),
+            Some(&[0x60, 0x01, 0x00, 0x01, 0x01][..])
+        );
+        assert_eq!(defragmenter.push(&fragments[1]).unwrap(), None);
+        assert_eq!(
+            defragmenter.push(&fragments[2]).unwrap().as_deref(),
+            Some(&packet[..])
+        );
+    }
+
+    #[test]
+    fn control_packets_over_255_bytes_are_rejected() {
+        // GET_CAPS_INFO response of 600 bytes, whose length no header can
+        // advertise.
+        let packet = [&[0x40, 0x03, 0x00, 0x58][..], &[0xaa; 600]].concat();
+        let fragments = Fragmenter::default().fragment(&packet).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        let mut defragmenter = Defragmenter::default();
+        assert_eq!(defragmenter.push(&fragments[0]).unwrap(), None);
+        let err = defragmenter.push(&fragments[1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(defragmenter.discard().is_empty());
+
+        // The same with a payload of exactly 255 bytes is reassembled.
+        let packet = [&[0x40, 0x03, 0x00, 0xff][..], &[0xaa; 255]].concat();
+        let fragments = Fragmenter::new(200).fragment(&packet).unwrap();
+        assert_eq!(defragmenter.push(&fragments[0]).unwrap(), None);
+        assert_eq!(
+            defragmenter.push(&fragments[1]).unwrap().as_deref(),
+            Some(&packet[..])
+        );
+    }
+
+    #[test]
+    fn max_reassembled_size() {
+        let mut defragmenter = Defragmenter::new(DEFAULT_MAX_FRAGMENTS, 200);
+        let fragments = Fragmenter::new(100).fragment(&vendor_command(200)).unwrap();
+        assert_eq!(defragmenter.push(&fragments[0]).unwrap(), None);
+        assert!(defragmenter.push(&fragments[1]).unwrap().is_some());
+
+        let fragments = Fragmenter::new(100).fragment(&vendor_command(201)).unwrap();
+        assert_eq!(defragmenter.push(&fragments[0]).unwrap(), None);
+        assert_eq!(defragmenter.push(&fragments[1]).unwrap(), None);
+        assert!(defragmenter.push(&fragments[2]).is_err());
+        assert!(defragmenter.discard().is_empty());
+    }
+
+    #[test]
+    fn partial_packets_are_discarded() {
+        let mut defragmenter = Defragmenter::default();
+        assert_eq!(
+            defragmenter.push(&[0x7e, 0x01, 0x00, 0x01, 0xaa]).unwrap(),
+            None
+        );
+        assert_eq!(defragmenter.discard(), vec![[0x7e, 0x01, 0x00, 0x01]]);
+        assert_eq!(
+            defragmenter
+                .push(&[0x6e, 0x01, 0x00, 0x01, 0xbb])
+                .unwrap()
+                .as_deref(),
+            Some(&[0x6e, 0x01, 0x00, 0x01, 0xbb][..])
+        );
+    }
+
+    #[test]
+    fn inconsistent_length_is_rejected() {
+        let mut defragmenter = Defragmenter::default();
+        assert!(defragmenter.push(&[0x60, 0x01]).is_err());
+        assert!(defragmenter.push(&[0x60, 0x01, 0x00, 0x02, 0x01]).is_err());
+    }
+}
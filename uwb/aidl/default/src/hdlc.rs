@@ -0,0 +1,238 @@
+use std::io;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+
+/// Delimiter of the frames.
+const FLAG: u8 = 0x7e;
+/// Escape of the flag and escape bytes within a frame, followed by the
+/// escaped byte XORed with `ESCAPE_MASK`.
+const ESCAPE: u8 = 0x7d;
+const ESCAPE_MASK: u8 = 0x20;
+const CRC_SIZE: usize = 2;
+/// Size of the largest frame once unstuffed: data packets have a 16-bit
+/// length.
+const MAX_FRAME_SIZE: usize = UCI_HEADER_SIZE + u16::MAX as usize + CRC_SIZE;
+
+/// Wrap `packet` in a frame: the packet followed by its CRC (LE), with the
+/// flag and escape bytes escaped, between two flags.
+pub fn encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + CRC_SIZE + 2);
+    frame.push(FLAG);
+    for &byte in packet.iter().chain(&crc16(packet).to_le_bytes()) {
+        if byte == FLAG || byte == ESCAPE {
+            frame.extend([ESCAPE, byte ^ ESCAPE_MASK]);
+        } else {
+            frame.push(byte);
+        }
+    }
+    frame.push(FLAG);
+    frame
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum DeframerState {
+    /// Waiting for a flag, before the first frame or after an invalid
+    /// one.
+    #[default]
+    Hunting,
+    Frame,
+    /// Within a frame, after an escape byte.
+    Escaped,
+}
+
+/// Extracts the packets from a stream of frames, see [`encode`]. The
+/// flag closing a frame may open the next one.
+#[derive(Debug, Default)]
+pub struct Deframer {
+    state: DeframerState,
+    frame: Vec<u8>,
+}
+
+impl Deframer {
+    /// Process the next byte of the stream. Returns the packet of the frame
+    /// it closes, if any. Fails with `InvalidData` when it closes an
+    /// invalid frame, which is dropped: bytes are then ignored until the
+    /// next flag.
+    pub fn push(&mut self, byte: u8) -> io::Result<Option<Vec<u8>>> {
+        match (self.state, byte) {
+            (DeframerState::Hunting, FLAG) => self.state = DeframerState::Frame,
+            (DeframerState::Hunting, _) => (),
+            (DeframerState::Frame, FLAG) => {
+                // Consecutive flags delimit empty frames, which are not
+                // packets.
+                if self.frame.is_empty() {
+                    return Ok(None);
+                }
+                let frame = std::mem::take(&mut self.frame);
+                return check_frame(frame).map(Some);
+            }
+            (DeframerState::Escaped, FLAG) => {
+                self.frame.clear();
+                self.state = DeframerState::Frame;
+                return Err(invalid_frame("frame aborted".to_owned()));
+            }
+            (DeframerState::Frame, ESCAPE) => self.state = DeframerState::Escaped,
+            (_, byte) => {
+                if self.frame.len() == MAX_FRAME_SIZE {
+                    self.frame.clear();
+                    self.state = DeframerState::Hunting;
+                    return Err(invalid_frame(format!(
+                        "frame longer than {} bytes",
+                        MAX_FRAME_SIZE
+                    )));
+                }
+                self.frame.push(match self.state {
+                    DeframerState::Escaped => byte ^ ESCAPE_MASK,
+                    _ => byte,
+                });
+                self.state = DeframerState::Frame;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Return the packet of an unstuffed frame, checking its CRC.
+fn check_frame(mut frame: Vec<u8>) -> io::Result<Vec<u8>> {
+    if frame.len() <= CRC_SIZE {
+        return Err(invalid_frame(format!("truncated frame {:02x?}", frame)));
+    }
+    let crc = frame.split_off(frame.len() - CRC_SIZE);
+    let crc = u16::from_le_bytes([crc[0], crc[1]]);
+    let expected = crc16(&frame);
+    if crc != expected {
+        return Err(invalid_frame(format!(
+            "CRC {:04x} instead of {:04x}: {:02x?}",
+            crc, expected, frame
+        )));
+    }
+    Ok(frame)
+}
+
+fn invalid_frame(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// CRC-16/X-25 of `bytes`, the frame check sequence of RFC 1662.
+fn crc16(bytes: &[u8]) -> u16 {
+    !bytes.iter().fold(!0u16, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u16, |crc, _| {
+            (crc >> 1) ^ (0x8408 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Push `bytes`, returning the packets and the errors.
+    fn push_all(deframer: &mut Deframer, bytes: &[u8]) -> Vec<Result<Vec<u8>, String>> {
+        bytes
+            .iter()
+            .filter_map(|&byte| match deframer.push(byte) {
+                Ok(packet) => packet.map(Ok),
+                Err(err) => Some(Err(err.to_string())),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packets_are_framed() {
+        assert_eq!(crc16(b"123456789"), 0x906e);
+        let packet = [0x60, 0x01, 0x00, 0x02, 0x7e, 0x7d];
+        let frame = encode(&packet);
+        assert_eq!(
+            frame[..9],
+            [0x7e, 0x60, 0x01, 0x00, 0x02, 0x7d, 0x5e, 0x7d, 0x5d]
+        );
+        assert_eq!(frame.last(), Some(&FLAG));
+        assert_eq!(frame.iter().filter(|&&byte| byte == FLAG).count(), 2);
+        assert_eq!(
+            push_all(&mut Deframer::default(), &frame),
+            [Ok(packet.to_vec())]
+        );
+    }
+
+    #[test]
+    fn back_to_back_frames() {
+        let first = [0x40, 0x00, 0x00, 0x01, 0x00];
+        let second = [0x60, 0x01, 0x00, 0x01, 0x01];
+        let mut stream = encode(&first);
+        stream.extend(encode(&second));
+        let mut deframer = Deframer::default();
+        assert_eq!(
+            push_all(&mut deframer, &stream),
+            [Ok(first.to_vec()), Ok(second.to_vec())]
+        );
+
+        // A single flag between the frames.
+        let mut stream = encode(&first);
+        stream.extend(&encode(&second)[1..]);
+        assert_eq!(
+            push_all(&mut deframer, &stream),
+            [Ok(first.to_vec()), Ok(second.to_vec())]
+        );
+    }
+
+    #[test]
+    fn corrupted_frames_are_dropped() {
+        let packet = [0x60, 0x01, 0x00, 0x01, 0x01];
+        let mut corrupted = encode(&packet);
+        corrupted[3] ^= 0x01;
+        let mut stream = corrupted;
+        stream.extend(encode(&packet));
+        let results = push_all(&mut Deframer::default(), &stream);
+        assert_eq!(results.len(), 2);
+        assert!(
+            results[0].as_ref().unwrap_err().starts_with("CRC "),
+            "{:?}",
+            results[0]
+        );
+        assert_eq!(results[1], Ok(packet.to_vec()));
+
+        // Aborted by an escaped flag.
+        let mut stream = encode(&packet);
+        stream.truncate(4);
+        stream.extend([ESCAPE, FLAG]);
+        stream.extend(&encode(&packet)[1..]);
+        assert_eq!(
+            push_all(&mut Deframer::default(), &stream),
+            [Err("frame aborted".to_owned()), Ok(packet.to_vec())]
+        );
+    }
+
+    #[test]
+    fn truncated_frames_are_dropped() {
+        let packet = [0x60, 0x01, 0x00, 0x01, 0x01];
+        let mut deframer = Deframer::default();
+        let results = push_all(&mut deframer, &[FLAG, 0x60, 0x01, FLAG, FLAG]);
+        assert_eq!(results, [Err("truncated frame [60, 01]".to_owned())]);
+        assert_eq!(
+            push_all(&mut deframer, &encode(&packet)),
+            [Ok(packet.to_vec())]
+        );
+
+        // A frame missing its start is dropped along with the garbage
+        // preceding it: the deframer resynchronizes on the next flag.
+        let frame = encode(&packet);
+        let mut stream = frame[3..].to_vec();
+        stream.extend(&frame);
+        assert_eq!(
+            push_all(&mut Deframer::default(), &stream),
+            [Ok(packet.to_vec())]
+        );
+    }
+
+    #[test]
+    fn oversized_frames_are_dropped() {
+        let packet = [0x60, 0x01, 0x00, 0x01, 0x01];
+        let mut stream = vec![FLAG; 1];
+        stream.resize(MAX_FRAME_SIZE + 2, 0x00);
+        stream.extend(encode(&packet));
+        let results = push_all(&mut Deframer::default(), &stream);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1], Ok(packet.to_vec()));
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+
+const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+const COMMAND_MESSAGE_TYPE: u8 = 0b001;
+
+/// CORE_GET_DEVICE_INFO_CMD, answered by the UWBS in any state.
+pub const CORE_GET_DEVICE_INFO_CMD: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+pub const DEFAULT_HEALTH_CHECK_MISSES: u32 = 3;
+/// Time given to the UWBS to answer the probe of a health status check,
+/// well within the 5 seconds an external watchdog waits for the HAL.
+pub const HEALTH_STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Probing of an idle UWBS, which is declared unresponsive after
+/// `max_misses` consecutive probes left unanswered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthCheckConfig {
+    /// Time without any packet from the UWBS after which it is probed.
+    pub idle: Duration,
+    /// Time given to the UWBS to answer a probe.
+    pub timeout: Duration,
+    pub max_misses: u32,
+    /// Control command sent as probe, whose response is not delivered to
+    /// the client.
+    pub command: Vec<u8>,
+}
+
+impl HealthCheckConfig {
+    /// Probe the UWBS idle for `idle` with CORE_GET_DEVICE_INFO_CMD.
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            max_misses: DEFAULT_HEALTH_CHECK_MISSES,
+            command: CORE_GET_DEVICE_INFO_CMD.to_vec(),
+        }
+    }
+}
+
+/// Health of a chip, as checked on demand by
+/// [`crate::uwb_chip::UwbHealthChecker`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// The UWBS answered the probe.
+    Ok,
+    /// The chip is opened but its UWBS did not answer the probe properly,
+    /// for the reason given.
+    Degraded(String),
+    /// The chip is closed, or in monitor mode: there is no UWBS to probe.
+    NotApplicable,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ok => f.write_str("ok"),
+            Self::Degraded(reason) => write!(f, "degraded: {}", reason),
+            Self::NotApplicable => f.write_str("not applicable"),
+        }
+    }
+}
+
+/// Parse a probe command written in hexadecimal, e.g. `2e000000`.
+/// Returns None unless it is a single control command whose length
+/// matches its header.
+pub fn parse_command(value: &str) -> Option<Vec<u8>> {
+    let command = parse_hex(value)?;
+    if command.len() < UCI_HEADER_SIZE
+        || (command[0] & MESSAGE_TYPE_MASK) >> 5 != COMMAND_MESSAGE_TYPE
+        || command.len() != UCI_HEADER_SIZE + command[3] as usize
+    {
+        return None;
+    }
+    Some(command)
+}
+
+/// Parse bytes written in hexadecimal, e.g. `1a2b`.
+pub fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_commands_are_parsed() {
+        assert_eq!(
+            parse_command("20020000"),
+            Some(CORE_GET_DEVICE_INFO_CMD.to_vec())
+        );
+        assert_eq!(
+            parse_command("2e0100021a2B"),
+            Some(vec![0x2e, 0x01, 0x00, 0x02, 0x1a, 0x2b])
+        );
+        // Length mismatch.
+        assert_eq!(parse_command("2e010001"), None);
+        assert_eq!(parse_command("2e0100010000"), None);
+        // Not a command.
+        assert_eq!(parse_command("40020000"), None);
+        // Not hexadecimal.
+        assert_eq!(parse_command("2002000"), None);
+        assert_eq!(parse_command("2002000g"), None);
+        assert_eq!(parse_command(""), None);
+    }
+}
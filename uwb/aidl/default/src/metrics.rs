@@ -0,0 +1,262 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixListener;
+
+use crate::transport::Transport;
+use crate::uwb_chip::{UwbChip, UwbChipStats};
+
+/// Counter exported for every chip.
+struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: fn(&UwbChipStats) -> u64,
+}
+
+const COUNTERS: [Counter; 17] = [
+    Counter {
+        name: "uwb_tx_packets_total",
+        help: "Packets written to the UWBS.",
+        value: |stats| stats.tx_packets,
+    },
+    Counter {
+        name: "uwb_rx_packets_total",
+        help: "Packets read from the UWBS.",
+        value: |stats| stats.rx_packets,
+    },
+    Counter {
+        name: "uwb_tx_bytes_total",
+        help: "Bytes of the packets written to the UWBS.",
+        value: |stats| stats.tx_bytes,
+    },
+    Counter {
+        name: "uwb_rx_bytes_total",
+        help: "Bytes of the packets read from the UWBS.",
+        value: |stats| stats.rx_bytes,
+    },
+    Counter {
+        name: "uwb_tx_errors_total",
+        help: "Packets which could not be written to the UWBS.",
+        value: |stats| stats.tx_errors,
+    },
+    Counter {
+        name: "uwb_rx_errors_total",
+        help: "Packets dropped by the reader task, and reader task failures.",
+        value: |stats| stats.rx_errors,
+    },
+    Counter {
+        name: "uwb_opens_total",
+        help: "Times the chip was opened.",
+        value: |stats| stats.open_count,
+    },
+    Counter {
+        name: "uwb_closes_total",
+        help: "Times the chip was closed.",
+        value: |stats| stats.close_count,
+    },
+    Counter {
+        name: "uwb_reconnect_attempts_total",
+        help: "Attempts to reach the client after it died.",
+        value: |stats| stats.reconnect_attempts,
+    },
+    Counter {
+        name: "uwb_reader_failures_total",
+        help: "Reader task failures, after which the chip was closed.",
+        value: |stats| stats.reader_failures,
+    },
+    Counter {
+        name: "uwb_firmware_downloads_total",
+        help: "Firmware downloads completed on open.",
+        value: |stats| stats.firmware_downloads,
+    },
+    Counter {
+        name: "uwb_firmware_download_failures_total",
+        help: "Failed attempts to download the firmware on open.",
+        value: |stats| stats.firmware_download_failures,
+    },
+    Counter {
+        name: "uwb_device_removals_total",
+        help: "Reader task failures caused by the removal of the device.",
+        value: |stats| stats.device_removals,
+    },
+    Counter {
+        name: "uwb_framing_errors_total",
+        help: "HDLC frames dropped for an invalid CRC or a truncated frame.",
+        value: |stats| stats.framing_errors,
+    },
+    Counter {
+        name: "uwb_data_messages_dropped_total",
+        help: "Data messages dropped while awaiting data credits.",
+        value: |stats| stats.data_messages_dropped,
+    },
+    Counter {
+        name: "uwb_rx_deduped_total",
+        help: "Repeated device status notifications not forwarded to the client.",
+        value: |stats| stats.rx_deduped,
+    },
+    Counter {
+        name: "uwb_rx_skipped_bytes_total",
+        help: "Bytes skipped to find a valid header after the byte stream got corrupted.",
+        value: |stats| stats.rx_skipped_bytes,
+    },
+];
+
+/// Serves the counters of the chips in the Prometheus text format over a
+/// unix socket, to every client connecting to it. The socket is removed
+/// when the exporter is dropped.
+pub struct MetricsExporter {
+    path: PathBuf,
+    listener: tokio::task::AbortHandle,
+}
+
+impl MetricsExporter {
+    /// Listen on `socket_path`, replacing any stale socket. Must be called
+    /// from the runtime serving the connections.
+    pub fn new<T: Transport + 'static>(
+        socket_path: &Path,
+        chips: Vec<Arc<UwbChip<T>>>,
+    ) -> io::Result<Self> {
+        match std::fs::remove_file(socket_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+        let listener = UnixListener::bind(socket_path)?;
+        let chips: Arc<[_]> = chips.into();
+        let listener = tokio::task::spawn(async move {
+            loop {
+                let mut stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        log::warn!("failed to accept a metrics client: {}", err);
+                        continue;
+                    }
+                };
+                let chips = chips.clone();
+                tokio::task::spawn(async move {
+                    let metrics = render(
+                        chips
+                            .iter()
+                            .map(|chip| (chip.name(), chip.stats()))
+                            .collect(),
+                    );
+                    if let Err(err) = stream.write_all(metrics.as_bytes()).await {
+                        log::debug!("failed to write the metrics: {}", err);
+                    }
+                });
+            }
+        })
+        .abort_handle();
+        log::info!("serving metrics on {}", socket_path.display());
+        Ok(Self {
+            path: socket_path.to_owned(),
+            listener,
+        })
+    }
+}
+
+impl Drop for MetricsExporter {
+    fn drop(&mut self) {
+        self.listener.abort();
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            log::warn!("failed to remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Write the counters of every chip in the Prometheus text format.
+fn render(chips: Vec<(&str, UwbChipStats)>) -> String {
+    let mut metrics = String::new();
+    for Counter { name, help, value } in COUNTERS {
+        let _ = writeln!(metrics, "# HELP {} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE {} counter", name);
+        for (chip, stats) in &chips {
+            let _ = writeln!(
+                metrics,
+                "{}{{chip=\"{}\"}} {}",
+                name,
+                escape_label(chip),
+                value(stats)
+            );
+        }
+    }
+    metrics
+}
+
+/// Escape a label value as required by the text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use std::collections::HashMap;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixStream;
+
+    /// Parse the samples of the text format, by metric name and chip.
+    fn parse(metrics: &str) -> HashMap<(String, String), u64> {
+        metrics
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, rest) = line.split_once("{chip=\"").unwrap();
+                let (chip, value) = rest.split_once("\"} ").unwrap();
+                ((name.to_owned(), chip.to_owned()), value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    async fn scrape(path: &Path) -> String {
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        let mut metrics = String::new();
+        stream.read_to_string(&mut metrics).await.unwrap();
+        metrics
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_for_every_chip() {
+        let path = std::env::temp_dir().join(format!("uwb-metrics-{}", std::process::id()));
+        let chips = ["main", "accessory"]
+            .map(|name| {
+                let (transport, _uwbs) = MockTransport::with_uwbs();
+                Arc::new(UwbChip::with_transport(name.to_owned(), transport))
+            })
+            .to_vec();
+        let exporter = MetricsExporter::new(&path, chips).unwrap();
+
+        // Concurrent clients are all served.
+        let (first, second) = tokio::join!(scrape(&path), scrape(&path));
+        assert_eq!(first, second);
+        assert!(first.contains("# TYPE uwb_tx_packets_total counter\n"));
+        let samples = parse(&first);
+        assert_eq!(samples.len(), COUNTERS.len() * 2);
+        assert_eq!(
+            samples[&("uwb_rx_errors_total".to_owned(), "accessory".to_owned())],
+            0
+        );
+
+        drop(exporter);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        let stats = UwbChipStats {
+            tx_packets: 3,
+            ..Default::default()
+        };
+        let metrics = render(vec![("a\"b\\c", stats)]);
+        assert!(
+            metrics.contains("uwb_tx_packets_total{chip=\"a\\\"b\\\\c\"} 3\n"),
+            "{}",
+            metrics
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// Direction of a UCI packet relative to the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Packet received from the UWBS.
+    Rx,
+    /// Packet written to the UWBS, either by the stack or by the HAL itself.
+    Tx,
+    /// Packet captured on a link the HAL is only monitoring, in either
+    /// direction.
+    Monitored,
+}
+
+/// Identifier returned by [`ObserverRegistry::register`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObserverId(u64);
+
+/// Packet observer callback.
+///
+/// Observers are invoked synchronously from the reader task and the write
+/// path, and must not block. Returning an error is logged and has no effect
+/// on packet delivery.
+pub type Observer = dyn Fn(Direction, SystemTime, &[u8]) -> anyhow::Result<()> + Send + Sync;
+
+/// Registry of components observing every UCI packet going through a chip.
+#[derive(Default)]
+pub struct ObserverRegistry {
+    next_id: AtomicU64,
+    observers: RwLock<Vec<(ObserverId, &'static str, Arc<Observer>)>>,
+}
+
+impl ObserverRegistry {
+    /// Register a new observer. `name` is only used for logging.
+    pub fn register(
+        &self,
+        name: &'static str,
+        observer: impl Fn(Direction, SystemTime, &[u8]) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> ObserverId {
+        let id = ObserverId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.observers
+            .write()
+            .unwrap()
+            .push((id, name, Arc::new(observer)));
+        id
+    }
+
+    /// Remove a previously registered observer.
+    /// Returns false if the observer was not registered.
+    pub fn unregister(&self, id: ObserverId) -> bool {
+        let mut observers = self.observers.write().unwrap();
+        let len = observers.len();
+        observers.retain(|(observer_id, _, _)| *observer_id != id);
+        observers.len() != len
+    }
+
+    /// Notify all registered observers of a packet.
+    pub fn notify(&self, direction: Direction, packet: &[u8]) {
+        let timestamp = SystemTime::now();
+        // Take a snapshot of the list so that observers can be registered
+        // or unregistered, including from within a callback, while packets
+        // are being dispatched.
+        let observers = self.observers.read().unwrap().clone();
+        for (id, name, observer) in observers {
+            match panic::catch_unwind(AssertUnwindSafe(|| observer(direction, timestamp, packet))) {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => log::debug!("observer {} failed: {:?}", name, err),
+                Err(_) => {
+                    log::error!("observer {} panicked, unregistering it", name);
+                    self.unregister(id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn observers_receive_packets_in_order() {
+        let registry = ObserverRegistry::default();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen_clone = seen.clone();
+        registry.register("test", move |direction, _, packet| {
+            seen_clone
+                .lock()
+                .unwrap()
+                .push((direction, packet.to_vec()));
+            Ok(())
+        });
+
+        registry.notify(Direction::Tx, &[0x20, 0x00, 0x00, 0x00]);
+        registry.notify(Direction::Rx, &[0x40, 0x00, 0x00, 0x01, 0x00]);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (Direction::Tx, vec![0x20, 0x00, 0x00, 0x00]),
+                (Direction::Rx, vec![0x40, 0x00, 0x00, 0x01, 0x00])
+            ]
+        );
+    }
+
+    #[test]
+    fn failing_observer_does_not_affect_others() {
+        let registry = ObserverRegistry::default();
+        let count = Arc::new(AtomicU64::new(0));
+        let count_clone = count.clone();
+        registry.register("failing", |_, _, _| Err(anyhow::anyhow!("failure")));
+        registry.register("counting", move |_, _, _| {
+            count_clone.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        });
+
+        registry.notify(Direction::Rx, &[0x60, 0x01, 0x00, 0x00]);
+        registry.notify(Direction::Rx, &[0x60, 0x01, 0x00, 0x00]);
+
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn panicking_observer_is_unregistered() {
+        let registry = ObserverRegistry::default();
+        let count = Arc::new(AtomicU64::new(0));
+        let count_clone = count.clone();
+        registry.register("panicking", move |_, _, _| {
+            count_clone.fetch_add(1, Ordering::Relaxed);
+            panic!("observer panic")
+        });
+
+        registry.notify(Direction::Rx, &[0x60, 0x01, 0x00, 0x00]);
+        registry.notify(Direction::Rx, &[0x60, 0x01, 0x00, 0x00]);
+
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert!(registry.observers.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unregister_under_load() {
+        let registry = Arc::new(ObserverRegistry::default());
+        let count = Arc::new(AtomicU64::new(0));
+        let count_clone = count.clone();
+        let id = registry.register("counting", move |_, _, _| {
+            count_clone.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        });
+
+        let notifier = {
+            let registry = registry.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10000 {
+                    registry.notify(Direction::Rx, &[0x60, 0x01, 0x00, 0x00]);
+                }
+            })
+        };
+
+        while count.load(Ordering::Relaxed) < 100 {
+            std::thread::yield_now();
+        }
+        assert!(registry.unregister(id));
+        let count_after_unregister = count.load(Ordering::Relaxed);
+        notifier.join().unwrap();
+
+        // At most one in-flight notification can complete after unregister.
+        assert!(count.load(Ordering::Relaxed) <= count_after_unregister + 1);
+        assert!(!registry.unregister(id));
+    }
+}
@@ -0,0 +1,160 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+use crate::observer::Direction;
+
+const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+const DATA_MESSAGE_TYPE: u8 = 0b000;
+const GID_MASK: u8 = 0x0f;
+const OID_MASK: u8 = 0x3f;
+
+/// How much of the UCI packets is written to logcat.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PacketLogLevel {
+    #[default]
+    Off,
+    /// The header fields of every packet, without the payload.
+    Headers,
+    /// Every packet in hex, except the payload of the data packets unless
+    /// allowed with [`PacketLog::set_data_payloads`].
+    Full,
+}
+
+impl FromStr for PacketLogLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "off" => Ok(Self::Off),
+            "headers" => Ok(Self::Headers),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("unknown log level {:?}", level)),
+        }
+    }
+}
+
+/// Logs the UCI packets going through a chip, at a level which can be
+/// changed at runtime. The payload of the data packets is user data, and
+/// is only logged if explicitly allowed.
+#[derive(Default)]
+pub struct PacketLog {
+    level: AtomicU8,
+    data_payloads: AtomicBool,
+}
+
+impl PacketLog {
+    pub fn level(&self) -> PacketLogLevel {
+        match self.level.load(Ordering::Relaxed) {
+            0 => PacketLogLevel::Off,
+            1 => PacketLogLevel::Headers,
+            _ => PacketLogLevel::Full,
+        }
+    }
+
+    pub fn set_level(&self, level: PacketLogLevel) {
+        self.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    /// Whether the payload of the data packets is logged at
+    /// [`PacketLogLevel::Full`].
+    pub fn data_payloads(&self) -> bool {
+        self.data_payloads.load(Ordering::Relaxed)
+    }
+
+    pub fn set_data_payloads(&self, data_payloads: bool) {
+        self.data_payloads.store(data_payloads, Ordering::Relaxed);
+    }
+
+    /// Log a packet, according to the level.
+    pub fn record(&self, direction: Direction, packet: &[u8]) {
+        if let Some(line) = self.format(direction, packet) {
+            log::info!("{}", line);
+        }
+    }
+
+    fn format(&self, direction: Direction, packet: &[u8]) -> Option<String> {
+        let arrow = match direction {
+            Direction::Rx => " <--",
+            Direction::Tx => " -->",
+            Direction::Monitored => " <~~",
+        };
+        match self.level() {
+            PacketLogLevel::Off => None,
+            PacketLogLevel::Full if !is_data(packet) || self.data_payloads() => {
+                Some(format!("{} {:02x?}", arrow, packet))
+            }
+            PacketLogLevel::Headers | PacketLogLevel::Full => {
+                Some(format!("{} {}", arrow, format_header(packet)))
+            }
+        }
+    }
+}
+
+fn is_data(packet: &[u8]) -> bool {
+    packet
+        .first()
+        .is_some_and(|byte| (byte & MESSAGE_TYPE_MASK) >> 5 == DATA_MESSAGE_TYPE)
+}
+
+/// Describe the header of `packet`, leaving its payload out.
+pub fn format_header(packet: &[u8]) -> String {
+    if packet.len() < UCI_HEADER_SIZE {
+        return format!("truncated packet, {} bytes", packet.len());
+    }
+    let payload_length = packet.len() - UCI_HEADER_SIZE;
+    let message_type = (packet[0] & MESSAGE_TYPE_MASK) >> 5;
+    if message_type == DATA_MESSAGE_TYPE {
+        format!("data, {} bytes of payload", payload_length)
+    } else {
+        format!(
+            "mt {} gid {:#04x} oid {:#04x}, {} bytes of payload",
+            message_type,
+            packet[0] & GID_MASK,
+            packet[1] & OID_MASK,
+            payload_length
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROL_PACKET: [u8; 6] = [0x21, 0x03, 0x00, 0x02, 0xab, 0xcd];
+    const DATA_PACKET: [u8; 7] = [0x01, 0x00, 0x03, 0x00, 0xde, 0xad, 0xbe];
+
+    #[test]
+    fn headers_leave_payloads_out() {
+        let log = PacketLog::default();
+        assert_eq!(log.format(Direction::Tx, &CONTROL_PACKET), None);
+        log.set_level(PacketLogLevel::Headers);
+        let line = log.format(Direction::Tx, &CONTROL_PACKET).unwrap();
+        assert_eq!(line, " --> mt 1 gid 0x01 oid 0x03, 2 bytes of payload");
+        let line = log.format(Direction::Rx, &DATA_PACKET).unwrap();
+        assert_eq!(line, " <-- data, 3 bytes of payload");
+    }
+
+    #[test]
+    fn data_payloads_are_redacted_unless_allowed() {
+        let log = PacketLog::default();
+        log.set_level(PacketLogLevel::Full);
+        assert_eq!(
+            log.format(Direction::Tx, &CONTROL_PACKET).unwrap(),
+            " --> [21, 03, 00, 02, ab, cd]"
+        );
+        let line = log.format(Direction::Tx, &DATA_PACKET).unwrap();
+        assert_eq!(line, " --> data, 3 bytes of payload");
+
+        log.set_data_payloads(true);
+        assert_eq!(
+            log.format(Direction::Tx, &DATA_PACKET).unwrap(),
+            " --> [01, 00, 03, 00, de, ad, be]"
+        );
+
+        log.set_level(PacketLogLevel::Off);
+        assert_eq!(log.format(Direction::Tx, &DATA_PACKET), None);
+        assert_eq!("headers".parse(), Ok(PacketLogLevel::Headers));
+        assert!("verbose".parse::<PacketLogLevel>().is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use std::io;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::fragmentation::{is_data, StreamSync, UCI_HEADER_SIZE};
+
+/// Kind of a UCI packet, as told by its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketKind {
+    Control,
+    Data,
+}
+
+/// First bytes of a packet, read by [`read_first_bytes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FirstBytes {
+    /// Number of bytes read.
+    pub len: usize,
+    /// Whether no bytes were available right away, i.e. the UWBS is not
+    /// sending packets back to back.
+    pub waited: bool,
+}
+
+/// Wait for the first bytes of a packet, and read at most `buf.len()` of
+/// them. This is cancellation safe: the bytes are only read when the
+/// future completes, so that nothing is lost when the reader task stops
+/// while the UWBS is silent. Fails with `UnexpectedEof` at end of file,
+/// and with `InvalidInput` if `buf` is empty.
+pub async fn read_first_bytes<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> io::Result<FirstBytes> {
+    if buf.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no room for the first bytes",
+        ));
+    }
+    let mut waited = false;
+    let len = std::future::poll_fn(|cx| {
+        let mut read_buf = ReadBuf::new(&mut *buf);
+        match Pin::new(&mut *reader).poll_read(cx, &mut read_buf) {
+            Poll::Ready(result) => Poll::Ready(result.map(|()| read_buf.filled().len())),
+            Poll::Pending => {
+                waited = true;
+                Poll::Pending
+            }
+        }
+    })
+    .await?;
+    if len == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "file unexpectedly closed",
+        ));
+    }
+    Ok(FirstBytes { len, waited })
+}
+
+/// Read the rest of a UCI packet from a byte stream into `buf`, which
+/// holds the first bytes of the packet, e.g. read with
+/// [`read_first_bytes`]. The header is checked with `sync` before the
+/// payload is read: returns `None` if the header is to be skipped, in
+/// which case it is left in `buf` for the caller to drop its first byte
+/// and read on.
+///
+/// Each of the header and the payload must be received within `timeout`,
+/// or this fails with `TimedOut`. Fails with `UnexpectedEof` if the
+/// stream ends within the packet, with `InvalidData` once `sync` gives
+/// up, and with `InvalidInput` if `buf` holds bytes past the end of the
+/// packet. The bytes read are lost if the future is dropped.
+pub async fn read_uci_packet<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    sync: &mut StreamSync,
+    timeout: Duration,
+) -> io::Result<Option<PacketKind>> {
+    let filled = buf.len();
+    if filled < UCI_HEADER_SIZE {
+        buf.resize(UCI_HEADER_SIZE, 0);
+        read_exact(reader, &mut buf[filled..], timeout).await?;
+    }
+
+    // Do not wait for the payload of a header which is garbage.
+    let Some(length) = sync.header(buf)? else {
+        return Ok(None);
+    };
+    let length = length + UCI_HEADER_SIZE;
+    if filled > length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes read past the end of the packet", filled - length),
+        ));
+    }
+    buf.resize(length, 0);
+    read_exact(reader, &mut buf[filled.max(UCI_HEADER_SIZE)..], timeout).await?;
+
+    Ok(Some(match is_data(buf) {
+        true => PacketKind::Data,
+        false => PacketKind::Control,
+    }))
+}
+
+/// Fill `buf` from `reader`, failing with `TimedOut` if it could not be
+/// filled within `timeout`, and with `UnexpectedEof` if the end of file
+/// is reached first.
+pub async fn read_exact<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+    timeout: Duration,
+) -> io::Result<()> {
+    let len = buf.len();
+    let mut filled = 0;
+    let result = tokio::time::timeout(timeout, async {
+        while filled < len {
+            match reader.read(&mut buf[filled..]).await? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("{} bytes missing at end of file", len - filled),
+                    ))
+                }
+                read_len => filled += read_len,
+            }
+        }
+        Ok(())
+    })
+    .await;
+    result.unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} bytes missing after {:?}", len - filled, timeout),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::task::Context;
+    use tokio::io::AsyncWriteExt;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+    const DEVICE_STATUS_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x01];
+    const DATA_PACKET: [u8; 7] = [0x02, 0x00, 0x03, 0x00, 0xde, 0xad, 0xbe];
+
+    fn sync() -> StreamSync {
+        StreamSync::new(1024, 16)
+    }
+
+    /// Reader returning one chunk per read.
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]]) -> Self {
+            Self(chunks.iter().map(|chunk| chunk.to_vec()).collect())
+        }
+    }
+
+    impl AsyncRead for Chunks {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if let Some(mut chunk) = self.0.pop_front() {
+                let len = chunk.len().min(buf.remaining());
+                buf.put_slice(&chunk[..len]);
+                if len < chunk.len() {
+                    self.0.push_front(chunk.split_off(len));
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Read a whole packet the way the reader task does.
+    async fn read_packet<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        sync: &mut StreamSync,
+    ) -> io::Result<Option<(PacketKind, Vec<u8>)>> {
+        let mut buf = vec![0; UCI_HEADER_SIZE];
+        let first_bytes = read_first_bytes(reader, &mut buf).await?;
+        buf.truncate(first_bytes.len);
+        let kind = read_uci_packet(reader, &mut buf, sync, TIMEOUT).await?;
+        Ok(kind.map(|kind| (kind, buf)))
+    }
+
+    #[tokio::test]
+    async fn control_and_data_packets_are_read() {
+        let stream = [&DEVICE_STATUS_NTF[..], &DATA_PACKET].concat();
+        let mut reader = &stream[..];
+        let mut sync = sync();
+        assert_eq!(
+            read_packet(&mut reader, &mut sync).await.unwrap(),
+            Some((PacketKind::Control, DEVICE_STATUS_NTF.to_vec()))
+        );
+        assert_eq!(
+            read_packet(&mut reader, &mut sync).await.unwrap(),
+            Some((PacketKind::Data, DATA_PACKET.to_vec()))
+        );
+        let err = read_packet(&mut reader, &mut sync).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn empty_payloads_are_read() {
+        let control = [0x40, 0x02, 0x00, 0x00];
+        let data = [0x02, 0x00, 0x00, 0x00];
+        let stream = [control, data].concat();
+        let mut reader = &stream[..];
+        let mut sync = sync();
+        assert_eq!(
+            read_packet(&mut reader, &mut sync).await.unwrap(),
+            Some((PacketKind::Control, control.to_vec()))
+        );
+        assert_eq!(
+            read_packet(&mut reader, &mut sync).await.unwrap(),
+            Some((PacketKind::Data, data.to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn packets_split_across_reads_are_read() {
+        let mut sync = sync();
+        let mut reader = Chunks::new(&[&[0x02], &[0x00, 0x03], &[0x00, 0xde], &[0xad], &[0xbe]]);
+        assert_eq!(
+            read_packet(&mut reader, &mut sync).await.unwrap(),
+            Some((PacketKind::Data, DATA_PACKET.to_vec()))
+        );
+
+        // The first read may return more than the first byte.
+        let mut reader = Chunks::new(&[&DEVICE_STATUS_NTF[..3], &DEVICE_STATUS_NTF[3..]]);
+        assert_eq!(
+            read_packet(&mut reader, &mut sync).await.unwrap(),
+            Some((PacketKind::Control, DEVICE_STATUS_NTF.to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn premature_eof_is_reported() {
+        let mut sync = sync();
+        for stream in [&DATA_PACKET[..2], &DATA_PACKET[..5]] {
+            let mut reader = stream;
+            let err = read_packet(&mut reader, &mut sync).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{:02x?}", stream);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_bytes_time_out() {
+        let (mut uwbs, mut reader) = tokio::io::duplex(64);
+        uwbs.write_all(&DEVICE_STATUS_NTF[..4]).await.unwrap();
+        let err = read_packet(&mut reader, &mut sync()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            err.to_string(),
+            format!("1 bytes missing after {:?}", TIMEOUT)
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_headers_are_left_to_skip() {
+        // Message type 7 is unknown.
+        let mut buf = vec![0xe0, 0x01];
+        let mut reader = &[0x00, 0x01, 0x01][..];
+        let mut sync = sync();
+        let kind = read_uci_packet(&mut reader, &mut buf, &mut sync, TIMEOUT).await;
+        assert_eq!(kind.unwrap(), None);
+        assert_eq!(buf, [0xe0, 0x01, 0x00, 0x01]);
+        assert_eq!(sync.skipped(), 1);
+        // The payload is not waited for.
+        assert_eq!(reader, [0x01]);
+    }
+
+    #[tokio::test]
+    async fn bytes_past_the_packet_are_refused() {
+        let mut buf = [0x40, 0x02, 0x00, 0x00, 0xff].to_vec();
+        let err = read_uci_packet(&mut &[][..], &mut buf, &mut sync(), TIMEOUT)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn waiting_for_the_first_bytes_is_cancellation_safe() {
+        let (mut uwbs, mut reader) = tokio::io::duplex(64);
+        let mut buf = [0; UCI_HEADER_SIZE];
+        let read = read_first_bytes(&mut reader, &mut buf);
+        assert!(tokio::time::timeout(TIMEOUT, read).await.is_err());
+
+        uwbs.write_all(&DEVICE_STATUS_NTF).await.unwrap();
+        let first_bytes = read_first_bytes(&mut reader, &mut buf).await.unwrap();
+        assert_eq!(
+            first_bytes,
+            FirstBytes {
+                len: UCI_HEADER_SIZE,
+                waited: false
+            }
+        );
+        assert_eq!(buf, DEVICE_STATUS_NTF[..UCI_HEADER_SIZE]);
+        let first_bytes = read_first_bytes(&mut reader, &mut buf[..1]).await.unwrap();
+        assert!(!first_bytes.waited);
+
+        let (first_bytes, ()) = tokio::join!(read_first_bytes(&mut reader, &mut buf), async {
+            tokio::task::yield_now().await;
+            uwbs.write_all(&DATA_PACKET).await.unwrap();
+        });
+        assert_eq!(
+            first_bytes.unwrap(),
+            FirstBytes {
+                len: UCI_HEADER_SIZE,
+                waited: true
+            }
+        );
+        assert_eq!(buf, DATA_PACKET[..UCI_HEADER_SIZE]);
+        let err = read_first_bytes(&mut reader, &mut []).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
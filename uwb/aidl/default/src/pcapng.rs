@@ -0,0 +1,354 @@
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::observer::Direction;
+
+/// Link type of the FiRa UCI packets, as registered with tcpdump.org.
+const DLT_FIRA_UCI: u16 = 299;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_ENDOFOPT: u16 = 0;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+/// Timestamps are in nanoseconds.
+const TSRESOL_NANOSECONDS: u8 = 9;
+/// Resolution of the timestamps of an interface without if_tsresol.
+const TSRESOL_DEFAULT: u8 = 6;
+/// Set in if_tsresol for a resolution in powers of two.
+const TSRESOL_POWER_OF_TWO: u8 = 0x80;
+const EPB_FLAGS_INBOUND: u32 = 0b01;
+const EPB_FLAGS_OUTBOUND: u32 = 0b10;
+
+/// Writes UCI packets as a pcapng capture, for inspection with the
+/// FiRa UCI dissector of Wireshark. The capture has a single section and
+/// a single interface; all values are little-endian.
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Start a capture, writing the section header and the interface
+    /// description.
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut pcapng = Self { writer };
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        // Version 1.0.
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // Unspecified section length.
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        pcapng.write_block(SECTION_HEADER_BLOCK, &body)?;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&DLT_FIRA_UCI.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // No snapshot length limit.
+        body.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut body, IF_TSRESOL, &[TSRESOL_NANOSECONDS]);
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        pcapng.write_block(INTERFACE_DESCRIPTION_BLOCK, &body)?;
+        Ok(pcapng)
+    }
+
+    pub fn write_packet(
+        &mut self,
+        timestamp: SystemTime,
+        direction: Direction,
+        packet: &[u8],
+    ) -> io::Result<()> {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let flags = match direction {
+            Direction::Rx => EPB_FLAGS_INBOUND,
+            Direction::Tx => EPB_FLAGS_OUTBOUND,
+            Direction::Monitored => 0,
+        };
+        let mut body = Vec::with_capacity(packet.len() + 36);
+        // Interface identifier.
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        // Captured and original lengths.
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet);
+        pad(&mut body);
+        push_option(&mut body, EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        self.write_block(ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush the capture and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let length = (body.len() + 12) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&length.to_le_bytes())
+    }
+}
+
+/// Packet read from a capture, see [`read_packets`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// Time elapsed since the epoch.
+    pub timestamp: Duration,
+    /// [`Direction::Monitored`] for the packets of unknown direction.
+    pub direction: Direction,
+    pub packet: Vec<u8>,
+    /// Length of the packet before it was truncated by the capture, e.g.
+    /// a data packet of a filtered snoop log.
+    pub original_length: usize,
+}
+
+/// Read the packets of a little-endian pcapng capture, such as written by
+/// [`PcapngWriter`]. Blocks other than the interface descriptions and the
+/// enhanced packets are skipped. Fails with `InvalidData` if the capture
+/// is truncated or malformed.
+pub fn read_packets(capture: &[u8]) -> io::Result<Vec<CapturedPacket>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+    let mut packets = Vec::new();
+    // Resolution of the timestamps of every interface.
+    let mut resolutions = Vec::new();
+    let mut offset = 0;
+    while offset < capture.len() {
+        let (Some(block_type), Some(length)) =
+            (u32_at(capture, offset), u32_at(capture, offset + 4))
+        else {
+            return Err(invalid("truncated block header"));
+        };
+        let length = length as usize;
+        if length < 12 || !length.is_multiple_of(4) || capture.len() - offset < length {
+            return Err(invalid("invalid block length"));
+        }
+        let body = &capture[offset + 8..offset + length - 4];
+        match block_type {
+            SECTION_HEADER_BLOCK if u32_at(body, 0) != Some(BYTE_ORDER_MAGIC) => {
+                return Err(invalid("not a little-endian capture"));
+            }
+            SECTION_HEADER_BLOCK => resolutions.clear(),
+            INTERFACE_DESCRIPTION_BLOCK => {
+                let resolution = options(body, 8)
+                    .into_iter()
+                    .find(|&(code, _)| code == IF_TSRESOL)
+                    .map_or(TSRESOL_DEFAULT, |(_, value)| value[0]);
+                resolutions.push(resolution);
+            }
+            ENHANCED_PACKET_BLOCK => {
+                let (Some(interface), Some(high), Some(low), Some(captured), Some(original)) = (
+                    u32_at(body, 0),
+                    u32_at(body, 4),
+                    u32_at(body, 8),
+                    u32_at(body, 12),
+                    u32_at(body, 16),
+                ) else {
+                    return Err(invalid("truncated packet block"));
+                };
+                let resolution = *resolutions
+                    .get(interface as usize)
+                    .ok_or_else(|| invalid("packet of an unknown interface"))?;
+                if resolution & TSRESOL_POWER_OF_TWO != 0 || resolution > 19 {
+                    return Err(invalid("unsupported timestamp resolution"));
+                }
+                let units_per_second = 10u128.pow(resolution as u32);
+                let timestamp = ((high as u128) << 32 | low as u128) * 1_000_000_000;
+                let timestamp = Duration::from_nanos((timestamp / units_per_second) as u64);
+                let captured = captured as usize;
+                let packet = body
+                    .get(20..20 + captured)
+                    .ok_or_else(|| invalid("truncated packet"))?;
+                let flags = options(body, 20 + captured.next_multiple_of(4))
+                    .into_iter()
+                    .find(|&(code, value)| code == EPB_FLAGS && value.len() == 4)
+                    .map_or(0, |(_, value)| {
+                        u32::from_le_bytes(value.try_into().unwrap())
+                    });
+                let direction = match flags & 0b11 {
+                    EPB_FLAGS_INBOUND => Direction::Rx,
+                    EPB_FLAGS_OUTBOUND => Direction::Tx,
+                    _ => Direction::Monitored,
+                };
+                packets.push(CapturedPacket {
+                    timestamp,
+                    direction,
+                    packet: packet.to_vec(),
+                    original_length: (original as usize).max(captured),
+                });
+            }
+            _ => (),
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    let value = bytes.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes(value.try_into().unwrap()))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let value = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(value.try_into().unwrap()))
+}
+
+/// Return the options of a block, as (code, value), starting at `offset`
+/// of its body.
+fn options(body: &[u8], mut offset: usize) -> Vec<(u16, &[u8])> {
+    let mut options = Vec::new();
+    while let (Some(code), Some(length)) = (u16_at(body, offset), u16_at(body, offset + 2)) {
+        let value = body.get(offset + 4..offset + 4 + length as usize);
+        match (code, value) {
+            (OPT_ENDOFOPT, _) | (_, None) => break,
+            (_, Some(value)) => options.push((code, value)),
+        }
+        offset += 4 + (length as usize).next_multiple_of(4);
+    }
+    options
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+/// Pad `body` to a multiple of 32 bits.
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const CAPTURE: [u8; 160] = [
+        // Section header block.
+        0x0a, 0x0d, 0x0d, 0x0a, 0x1c, 0x00, 0x00, 0x00,
+        0x4d, 0x3c, 0x2b, 0x1a, 0x01, 0x00, 0x00, 0x00,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0x1c, 0x00, 0x00, 0x00,
+        // Interface description block, with if_tsresol.
+        0x01, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00,
+        0x2b, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x09, 0x00, 0x01, 0x00, 0x09, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x20, 0x00, 0x00, 0x00,
+        // Enhanced packet block: outbound CORE_DEVICE_INFO_CMD.
+        0x06, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x03, 0xf2, 0x05, 0x2a,
+        0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
+        0x20, 0x02, 0x00, 0x00,
+        0x02, 0x00, 0x04, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x30, 0x00, 0x00, 0x00,
+        // Enhanced packet block: inbound CORE_DEVICE_STATUS_NTF, padded.
+        0x06, 0x00, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x04, 0xf2, 0x05, 0x2a,
+        0x05, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x60, 0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x34, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn capture_matches_known_bytes() {
+        // 0x1_2a05_f203 nanoseconds.
+        let timestamp = UNIX_EPOCH + Duration::new(5, 3);
+        let mut pcapng = PcapngWriter::new(Vec::new()).unwrap();
+        pcapng
+            .write_packet(timestamp, Direction::Tx, &[0x20, 0x02, 0x00, 0x00])
+            .unwrap();
+        pcapng
+            .write_packet(
+                timestamp + Duration::from_nanos(1),
+                Direction::Rx,
+                &[0x60, 0x01, 0x00, 0x01, 0x01],
+            )
+            .unwrap();
+        assert_eq!(pcapng.finish().unwrap(), CAPTURE);
+    }
+
+    #[test]
+    fn capture_parses_back() {
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(CAPTURE[offset..offset + 4].try_into().unwrap());
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while offset < CAPTURE.len() {
+            let length = u32_at(offset + 4) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32_at(offset + length - 4) as usize, length);
+            if u32_at(offset) == ENHANCED_PACKET_BLOCK {
+                let timestamp = (u32_at(offset + 12) as u64) << 32 | u32_at(offset + 16) as u64;
+                let captured_length = u32_at(offset + 20) as usize;
+                packets.push((
+                    timestamp,
+                    CAPTURE[offset + 28..offset + 28 + captured_length].to_vec(),
+                ));
+            }
+            offset += length;
+        }
+        assert_eq!(offset, CAPTURE.len());
+        assert_eq!(
+            packets,
+            vec![
+                (5_000_000_003, vec![0x20, 0x02, 0x00, 0x00]),
+                (5_000_000_004, vec![0x60, 0x01, 0x00, 0x01, 0x01]),
+            ]
+        );
+    }
+
+    #[test]
+    fn packets_are_read_back() {
+        assert_eq!(
+            read_packets(&CAPTURE).unwrap(),
+            vec![
+                CapturedPacket {
+                    timestamp: Duration::new(5, 3),
+                    direction: Direction::Tx,
+                    packet: vec![0x20, 0x02, 0x00, 0x00],
+                    original_length: 4,
+                },
+                CapturedPacket {
+                    timestamp: Duration::new(5, 4),
+                    direction: Direction::Rx,
+                    packet: vec![0x60, 0x01, 0x00, 0x01, 0x01],
+                    original_length: 5,
+                },
+            ]
+        );
+        assert_eq!(
+            read_packets(&CAPTURE[..CAPTURE.len() - 4])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use android_hardware_uwb::aidl::android::hardware::uwb::IUwbClientCallback::IUwbClientCallback;
+use android_hardware_uwb::binder;
+use binder::{StatusCode, Strong};
+
+use std::time::Duration;
+
+/// Delay before the first attempt to reach the client after it died.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(100);
+/// Upper bound of the delay between two attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Finds the client to reopen the chip for once the previous one died.
+pub trait ClientLocator: Send + Sync {
+    /// Return the callbacks of the client, or the error met reaching it.
+    fn locate(&self) -> Result<Strong<dyn IUwbClientCallback>, StatusCode>;
+}
+
+/// Locates a client publishing its callbacks with the service manager.
+pub struct ServiceLocator {
+    service: String,
+}
+
+impl ServiceLocator {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+impl ClientLocator for ServiceLocator {
+    fn locate(&self) -> Result<Strong<dyn IUwbClientCallback>, StatusCode> {
+        // Does not wait for the service: the caller polls.
+        binder::check_interface(&self.service)
+    }
+}
+
+/// Return the delay before the reconnection attempt `attempt`, counted
+/// from zero: 100 ms, doubled after every failed attempt, up to 30 s.
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_BASE_DELAY
+        .checked_mul(1 << attempt.min(31))
+        .map_or(RECONNECT_MAX_DELAY, |delay| delay.min(RECONNECT_MAX_DELAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_cap() {
+        let delays: Vec<_> = (0..12).map(reconnect_delay).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1600, 3200, 6400, 12800, 25600, 30000, 30000, 30000]
+                .map(Duration::from_millis)
+        );
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
+    }
+}
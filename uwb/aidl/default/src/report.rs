@@ -0,0 +1,904 @@
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::cmd_tracker::ResponseStats;
+use crate::observer::Direction;
+use crate::packet_log::PacketLogLevel;
+use crate::session::SessionStats;
+use crate::snoop::SnoopMode;
+use crate::stats::{MessageTypeCounts, PacketCount, Stats, TrafficStats};
+use crate::trace::TraceEntry;
+use crate::uwb_chip::{ReaderScheduling, UwbChipStats};
+
+/// Version of the JSON document written by [`ChipReport::write_json`],
+/// incremented when a field is removed or changes meaning. Tools parsing
+/// the document can rely on the fields of the version they know.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How a [`ChipReport`] is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Human readable text.
+    #[default]
+    Text,
+    /// A single JSON document, see [`SCHEMA_VERSION`].
+    Json,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format {:?}", format)),
+        }
+    }
+}
+
+/// State of an opened or suspended chip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpenedState {
+    /// Time since the chip was opened.
+    pub elapsed: Duration,
+    pub references: u32,
+    pub client_alive: bool,
+    /// Device info of the UWBS, once read on coreInit.
+    pub device: Option<String>,
+    pub session_capacity: usize,
+    /// Ranging statistics of the initialized sessions, in order.
+    pub sessions: Vec<(i32, SessionStats)>,
+    pub data_messages_awaiting_credits: usize,
+}
+
+/// State of a chip, as far as it could be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChipState {
+    /// The state lock was held, possibly by a close stuck on the UWBS.
+    Unknown,
+    Closed,
+    Opening,
+    Opened(OpenedState),
+    Suspended(OpenedState),
+    Resetting,
+    AwaitingClient,
+}
+
+impl ChipState {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Closed => "closed",
+            Self::Opening => "opening",
+            Self::Opened(_) => "opened",
+            Self::Suspended(_) => "suspended",
+            Self::Resetting => "resetting",
+            Self::AwaitingClient => "awaiting client",
+        }
+    }
+}
+
+/// Everything dumped about a chip, see [`crate::uwb_chip::UwbChip::report`].
+/// Collected once, then written as text or JSON, so that both formats
+/// carry the same data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChipReport {
+    pub name: String,
+    pub transport: String,
+    pub state: ChipState,
+    pub stats: UwbChipStats,
+    pub client_commands: ResponseStats,
+    pub hdlc: bool,
+    /// Firmware image downloaded on open, if any.
+    pub firmware: Option<PathBuf>,
+    pub invalid_packets: u64,
+    pub forced_yields: u64,
+    pub reader_scheduling: ReaderScheduling,
+    pub packet_log: PacketLogLevel,
+    pub data_payloads: bool,
+    /// Mode of the snoop log and packets it dropped, if the chip has one.
+    pub snoop: Option<(SnoopMode, u64)>,
+    pub packet_stats: Stats,
+    /// Last packets exchanged with the UWBS, oldest first, left out of the
+    /// statistics.
+    pub last_packets: Option<Vec<TraceEntry>>,
+}
+
+fn since_epoch(timestamp: SystemTime) -> Duration {
+    timestamp
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+impl ChipReport {
+    pub fn write(&self, writer: &mut dyn io::Write, format: DumpFormat) -> io::Result<()> {
+        match format {
+            DumpFormat::Text => self.write_text(writer),
+            DumpFormat::Json => self.write_json(writer),
+        }
+    }
+
+    /// Write the report as text, for dumpsys.
+    pub fn write_text(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(writer, "{}: {}", self.name, self.transport)?;
+        match self.state {
+            ChipState::Unknown => writeln!(writer, "  state lock held, possibly stuck in close")?,
+            ChipState::Opened(ref opened) | ChipState::Suspended(ref opened) => {
+                write!(
+                    writer,
+                    "  state: {} for {:?} ({} references), client {}, sessions {{",
+                    self.state.name(),
+                    opened.elapsed,
+                    opened.references,
+                    if opened.client_alive { "alive" } else { "dead" },
+                )?;
+                for (index, (id, _)) in opened.sessions.iter().enumerate() {
+                    let separator = if index > 0 { ", " } else { "" };
+                    write!(writer, "{}{}", separator, id)?;
+                }
+                writeln!(writer, "}} of {}", opened.session_capacity)?;
+                match opened.device {
+                    Some(ref info) => writeln!(writer, "  device: {}", info)?,
+                    None => writeln!(writer, "  device: unknown")?,
+                }
+                writeln!(
+                    writer,
+                    "  data messages awaiting credits: {}",
+                    opened.data_messages_awaiting_credits
+                )?;
+                for (id, stats) in opened.sessions.iter() {
+                    writeln!(writer, "  session {}: {}", id, stats)?;
+                }
+            }
+            _ => writeln!(writer, "  state: {}", self.state.name())?,
+        }
+        let stats = &self.stats;
+        writeln!(
+            writer,
+            "  tx: {} packets, {} bytes, {} errors; rx: {} packets, {} bytes, {} errors",
+            stats.tx_packets,
+            stats.tx_bytes,
+            stats.tx_errors,
+            stats.rx_packets,
+            stats.rx_bytes,
+            stats.rx_errors
+        )?;
+        if stats.data_messages_dropped > 0 {
+            writeln!(
+                writer,
+                "  data messages dropped awaiting credits: {}",
+                stats.data_messages_dropped
+            )?;
+        }
+        if stats.rx_deduped > 0 {
+            writeln!(
+                writer,
+                "  repeated device status notifications dropped: {}",
+                stats.rx_deduped
+            )?;
+        }
+        if stats.rx_skipped_bytes > 0 {
+            writeln!(
+                writer,
+                "  bytes skipped to resynchronize: {}",
+                stats.rx_skipped_bytes
+            )?;
+        }
+        if self.hdlc {
+            writeln!(writer, "  hdlc: {} frames dropped", stats.framing_errors)?;
+        }
+        writeln!(writer, "  client commands: {}", self.client_commands)?;
+        if let Some(ref firmware) = self.firmware {
+            writeln!(
+                writer,
+                "  firmware: {}, {} downloads, {} failed attempts, last took {:?}",
+                firmware.display(),
+                stats.firmware_downloads,
+                stats.firmware_download_failures,
+                stats.last_firmware_download
+            )?;
+        }
+        writeln!(
+            writer,
+            "  opens: {}, closes: {}, reader failures: {} (last {:?}), invalid packets: {}, \
+             forced yields: {}",
+            stats.open_count,
+            stats.close_count,
+            stats.reader_failures,
+            stats.last_reader_failure,
+            self.invalid_packets,
+            self.forced_yields
+        )?;
+        writeln!(writer, "  reader scheduling: {}", self.reader_scheduling)?;
+        if let Some(errno) = stats.last_device_removal {
+            writeln!(
+                writer,
+                "  device removals: {} (last {})",
+                stats.device_removals,
+                io::Error::from_raw_os_error(errno)
+            )?;
+        }
+        writeln!(
+            writer,
+            "  packet log: {:?}{}",
+            self.packet_log,
+            if self.data_payloads {
+                ", with data payloads"
+            } else {
+                ""
+            }
+        )?;
+        if let Some((mode, dropped)) = self.snoop {
+            writeln!(
+                writer,
+                "  snoop log: {:?}, {} packets dropped",
+                mode, dropped
+            )?;
+        }
+        writeln!(writer, "  since open: {}", self.packet_stats.since_open)?;
+        writeln!(writer, "  lifetime: {}", self.packet_stats.lifetime)?;
+        for (event, timestamp) in self.last_events() {
+            if let Some(timestamp) = timestamp {
+                let timestamp = since_epoch(timestamp);
+                writeln!(
+                    writer,
+                    "  last {}: {}.{:06}",
+                    event,
+                    timestamp.as_secs(),
+                    timestamp.subsec_micros()
+                )?;
+            }
+        }
+        if let Some(ref packets) = self.last_packets {
+            writeln!(writer, "  last packets:")?;
+            for entry in packets {
+                let timestamp = since_epoch(entry.timestamp);
+                writeln!(
+                    writer,
+                    "    {}.{:06} {:?} {:02x?}",
+                    timestamp.as_secs(),
+                    timestamp.subsec_micros(),
+                    entry.direction,
+                    entry.packet
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the report as a single JSON document on one line, for tools.
+    /// Timestamps are in microseconds since the epoch, durations in
+    /// microseconds, and packets in hex.
+    pub fn write_json(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        let mut out = String::new();
+        let mut report = JsonObject::new(&mut out);
+        report
+            .number("schema_version", SCHEMA_VERSION)
+            .string("name", &self.name)
+            .string("transport", &self.transport)
+            .string("state", &self.state.name().replace(' ', "_"));
+        match self.state {
+            ChipState::Opened(ref opened) | ChipState::Suspended(ref opened) => {
+                report.object("session", |session| {
+                    session
+                        .number("elapsed_us", opened.elapsed.as_micros())
+                        .number("references", opened.references)
+                        .boolean("client_alive", opened.client_alive);
+                    match opened.device {
+                        Some(ref info) => session.string("device", info),
+                        None => session.null("device"),
+                    };
+                    session
+                        .number("session_capacity", opened.session_capacity)
+                        .objects("sessions", &opened.sessions, |entry, (id, stats)| {
+                            entry
+                                .number("id", id)
+                                .number("ranging_count", stats.ranging_count)
+                                .number("ranging_success", stats.ranging_success)
+                                .number("ranging_oor", stats.ranging_oor)
+                                .number("last_status", stats.last_status);
+                        });
+                });
+            }
+            _ => {
+                report.null("session");
+            }
+        }
+        let stats = &self.stats;
+        report.object("counters", |counters| {
+            counters
+                .number("tx_packets", stats.tx_packets)
+                .number("tx_bytes", stats.tx_bytes)
+                .number("tx_errors", stats.tx_errors)
+                .number("rx_packets", stats.rx_packets)
+                .number("rx_bytes", stats.rx_bytes)
+                .number("rx_errors", stats.rx_errors)
+                .number("data_messages_dropped", stats.data_messages_dropped)
+                .number("rx_deduped", stats.rx_deduped)
+                .number("rx_skipped_bytes", stats.rx_skipped_bytes)
+                .number("framing_errors", stats.framing_errors)
+                .number("open_count", stats.open_count)
+                .number("close_count", stats.close_count)
+                .number("reconnect_attempts", stats.reconnect_attempts)
+                .number("reader_failures", stats.reader_failures)
+                .number("firmware_downloads", stats.firmware_downloads)
+                .number(
+                    "firmware_download_failures",
+                    stats.firmware_download_failures,
+                )
+                .number("device_removals", stats.device_removals)
+                .number("invalid_packets", self.invalid_packets)
+                .number("forced_yields", self.forced_yields);
+        });
+        report.object("gauges", |gauges| {
+            let queued = match self.state {
+                ChipState::Opened(ref opened) | ChipState::Suspended(ref opened) => {
+                    opened.data_messages_awaiting_credits
+                }
+                _ => 0,
+            };
+            gauges.number("data_messages_awaiting_credits", queued);
+            match self.snoop {
+                Some((_, dropped)) => gauges.number("snoop_packets_dropped", dropped),
+                None => gauges.null("snoop_packets_dropped"),
+            };
+        });
+        report.object("last_errors", |errors| {
+            match stats.last_reader_failure {
+                Some(failure) => errors.string("reader_failure", &format!("{:?}", failure)),
+                None => errors.null("reader_failure"),
+            };
+            match stats.last_reconnect_error {
+                Some(status) => errors.string("reconnect_error", &format!("{:?}", status)),
+                None => errors.null("reconnect_error"),
+            };
+            match stats.last_device_removal {
+                Some(errno) => errors.number("device_removal_errno", errno),
+                None => errors.null("device_removal_errno"),
+            };
+        });
+        let commands = &self.client_commands;
+        report.object("client_commands", |object| {
+            object
+                .number("responses", commands.responses)
+                .number("lost_responses", commands.lost_responses)
+                .number("total_latency_us", commands.total_latency.as_micros())
+                .number("max_latency_us", commands.max_latency.as_micros());
+        });
+        match self.firmware {
+            Some(ref path) => report.object("firmware", |firmware| {
+                firmware.string("path", &path.display().to_string());
+                match stats.last_firmware_download {
+                    Some(duration) => firmware.number("last_download_us", duration.as_micros()),
+                    None => firmware.null("last_download_us"),
+                };
+            }),
+            None => report.null("firmware"),
+        };
+        report.object("packet_log", |log| {
+            log.string("level", &format!("{:?}", self.packet_log).to_lowercase())
+                .boolean("data_payloads", self.data_payloads);
+        });
+        match self.snoop {
+            Some((mode, _)) => report.string("snoop", &format!("{:?}", mode).to_lowercase()),
+            None => report.null("snoop"),
+        };
+        report.object("reader_scheduling", |scheduling| {
+            match self.reader_scheduling {
+                ReaderScheduling::Default => scheduling.null("nice"),
+                ReaderScheduling::Nice(nice) => scheduling.number("nice", nice),
+                ReaderScheduling::Refused { nice, errno } => scheduling
+                    .null("nice")
+                    .number("refused_nice", nice)
+                    .number("errno", errno),
+            };
+        });
+        report
+            .object("since_open", |traffic| {
+                traffic_stats(traffic, &self.packet_stats.since_open)
+            })
+            .object("lifetime", |traffic| {
+                traffic_stats(traffic, &self.packet_stats.lifetime)
+            });
+        for (event, timestamp) in self.last_events() {
+            let key = format!("last_{}_us", event);
+            match timestamp {
+                Some(timestamp) => report.number(&key, since_epoch(timestamp).as_micros()),
+                None => report.null(&key),
+            };
+        }
+        if let Some(ref packets) = self.last_packets {
+            report.objects("last_packets", packets, |object, entry| {
+                let direction = match entry.direction {
+                    Direction::Rx => "rx",
+                    Direction::Tx => "tx",
+                    Direction::Monitored => "monitored",
+                };
+                let mut packet = String::with_capacity(2 * entry.packet.len());
+                for byte in entry.packet.iter() {
+                    let _ = write!(packet, "{:02x}", byte);
+                }
+                object
+                    .number("timestamp_us", since_epoch(entry.timestamp).as_micros())
+                    .string("direction", direction)
+                    .string("packet", &packet);
+            });
+        }
+        report.finish();
+        writeln!(writer, "{}", out)
+    }
+
+    fn last_events(&self) -> [(&'static str, Option<SystemTime>); 3] {
+        [
+            ("open", self.packet_stats.last_open),
+            ("close", self.packet_stats.last_close),
+            ("error", self.packet_stats.last_error),
+        ]
+    }
+}
+
+fn traffic_stats(object: &mut JsonObject, stats: &TrafficStats) {
+    let counts = |object: &mut JsonObject, counts: &MessageTypeCounts| {
+        for (name, count) in [
+            ("cmd", counts.command),
+            ("rsp", counts.response),
+            ("ntf", counts.notification),
+            ("data", counts.data),
+        ] {
+            object.object(name, |object| packet_count(object, count));
+        }
+    };
+    object
+        .object("tx", |object| counts(object, &stats.tx))
+        .object("rx", |object| counts(object, &stats.rx))
+        .number("write_would_block", stats.write_would_block)
+        .number("malformed_packets", stats.malformed_packets)
+        .number("delivery_failures", stats.delivery_failures);
+}
+
+fn packet_count(object: &mut JsonObject, count: PacketCount) {
+    object
+        .number("packets", count.packets)
+        .number("bytes", count.bytes);
+}
+
+/// Writes the members of a JSON object to a string, separated by commas.
+/// The object is closed by [`JsonObject::finish`].
+struct JsonObject<'a> {
+    out: &'a mut String,
+    empty: bool,
+}
+
+impl<'a> JsonObject<'a> {
+    fn new(out: &'a mut String) -> Self {
+        out.push('{');
+        Self { out, empty: true }
+    }
+
+    /// Start the member `key`, returning the string its value is to be
+    /// written to.
+    fn key(&mut self, key: &str) -> &mut String {
+        if !self.empty {
+            self.out.push(',');
+        }
+        self.empty = false;
+        write_json_string(self.out, key);
+        self.out.push(':');
+        self.out
+    }
+
+    fn number(&mut self, key: &str, value: impl fmt::Display) -> &mut Self {
+        let _ = write!(self.key(key), "{}", value);
+        self
+    }
+
+    fn string(&mut self, key: &str, value: &str) -> &mut Self {
+        write_json_string(self.key(key), value);
+        self
+    }
+
+    fn boolean(&mut self, key: &str, value: bool) -> &mut Self {
+        self.key(key).push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    fn null(&mut self, key: &str) -> &mut Self {
+        self.key(key).push_str("null");
+        self
+    }
+
+    fn object(&mut self, key: &str, write: impl FnOnce(&mut JsonObject)) -> &mut Self {
+        let mut object = JsonObject::new(self.key(key));
+        write(&mut object);
+        object.finish();
+        self
+    }
+
+    /// Write an array of objects, one for each of `items`.
+    fn objects<I: IntoIterator>(
+        &mut self,
+        key: &str,
+        items: I,
+        mut write: impl FnMut(&mut JsonObject, I::Item),
+    ) -> &mut Self {
+        let out = self.key(key);
+        out.push('[');
+        for (index, item) in items.into_iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            let mut object = JsonObject::new(out);
+            write(&mut object, item);
+            object.finish();
+        }
+        out.push(']');
+        self
+    }
+
+    fn finish(self) {
+        self.out.push('}');
+    }
+}
+
+/// Write `value` as a JSON string, quoted and escaped.
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        fn get(&self, key: &str) -> &Value {
+            match self {
+                Value::Object(members) => members
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value)
+                    .unwrap_or_else(|| panic!("no member {:?} in {:?}", key, self)),
+                _ => panic!("{:?} is not an object", self),
+            }
+        }
+
+        fn number(&self) -> f64 {
+            match self {
+                Value::Number(number) => *number,
+                _ => panic!("{:?} is not a number", self),
+            }
+        }
+
+        fn string(&self) -> &str {
+            match self {
+                Value::String(string) => string,
+                _ => panic!("{:?} is not a string", self),
+            }
+        }
+    }
+
+    /// Strict parser of the JSON documents, panicking on malformed input.
+    struct Parser<'a> {
+        input: &'a [u8],
+        position: usize,
+    }
+
+    fn parse(input: &str) -> Value {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value();
+        parser.skip_whitespace();
+        assert_eq!(parser.position, input.len(), "trailing characters");
+        value
+    }
+
+    impl Parser<'_> {
+        fn skip_whitespace(&mut self) {
+            while self.position < self.input.len()
+                && self.input[self.position].is_ascii_whitespace()
+            {
+                self.position += 1;
+            }
+        }
+
+        fn next(&mut self) -> u8 {
+            let byte = self.input[self.position];
+            self.position += 1;
+            byte
+        }
+
+        fn literal(&mut self, literal: &str, value: Value) -> Value {
+            let end = self.position + literal.len();
+            assert_eq!(&self.input[self.position..end], literal.as_bytes());
+            self.position = end;
+            value
+        }
+
+        fn value(&mut self) -> Value {
+            self.skip_whitespace();
+            match self.input[self.position] {
+                b'{' => {
+                    self.position += 1;
+                    let mut members = Vec::new();
+                    self.skip_whitespace();
+                    if self.input[self.position] == b'}' {
+                        self.position += 1;
+                        return Value::Object(members);
+                    }
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string();
+                        self.skip_whitespace();
+                        assert_eq!(self.next(), b':');
+                        members.push((key, self.value()));
+                        self.skip_whitespace();
+                        match self.next() {
+                            b',' => (),
+                            b'}' => return Value::Object(members),
+                            byte => panic!("unexpected {:?} in object", byte as char),
+                        }
+                    }
+                }
+                b'[' => {
+                    self.position += 1;
+                    let mut elements = Vec::new();
+                    self.skip_whitespace();
+                    if self.input[self.position] == b']' {
+                        self.position += 1;
+                        return Value::Array(elements);
+                    }
+                    loop {
+                        elements.push(self.value());
+                        self.skip_whitespace();
+                        match self.next() {
+                            b',' => (),
+                            b']' => return Value::Array(elements),
+                            byte => panic!("unexpected {:?} in array", byte as char),
+                        }
+                    }
+                }
+                b'"' => Value::String(self.string()),
+                b't' => self.literal("true", Value::Bool(true)),
+                b'f' => self.literal("false", Value::Bool(false)),
+                b'n' => self.literal("null", Value::Null),
+                _ => {
+                    let start = self.position;
+                    while self.position < self.input.len()
+                        && b"+-.eE0123456789".contains(&self.input[self.position])
+                    {
+                        self.position += 1;
+                    }
+                    let number = std::str::from_utf8(&self.input[start..self.position]).unwrap();
+                    Value::Number(number.parse().expect("invalid number"))
+                }
+            }
+        }
+
+        fn string(&mut self) -> String {
+            assert_eq!(self.next(), b'"');
+            let mut bytes = Vec::new();
+            loop {
+                match self.next() {
+                    b'"' => return String::from_utf8(bytes).unwrap(),
+                    b'\\' => match self.next() {
+                        b'"' => bytes.push(b'"'),
+                        b'\\' => bytes.push(b'\\'),
+                        b'/' => bytes.push(b'/'),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'u' => {
+                            let hex = std::str::from_utf8(&self.input[self.position..][..4]);
+                            let code = u32::from_str_radix(hex.unwrap(), 16).unwrap();
+                            self.position += 4;
+                            let c = char::from_u32(code).unwrap();
+                            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        byte => panic!("invalid escape {:?}", byte as char),
+                    },
+                    byte if byte < 0x20 => panic!("unescaped control character {:#04x}", byte),
+                    byte => bytes.push(byte),
+                }
+            }
+        }
+    }
+
+    fn opened_report() -> ChipReport {
+        ChipReport {
+            name: "uwb\"0\"".to_owned(),
+            transport: "/dev/tty\\UWB\n".to_owned(),
+            state: ChipState::Opened(OpenedState {
+                elapsed: Duration::from_millis(1500),
+                references: 2,
+                client_alive: true,
+                device: Some("UCI 2.0.0".to_owned()),
+                session_capacity: 5,
+                sessions: vec![
+                    (1, SessionStats::default()),
+                    (
+                        7,
+                        SessionStats {
+                            ranging_count: 3,
+                            ranging_success: 2,
+                            ranging_oor: 1,
+                            last_status: 0x21,
+                        },
+                    ),
+                ],
+                data_messages_awaiting_credits: 4,
+            }),
+            stats: UwbChipStats {
+                tx_packets: 10,
+                rx_packets: 12,
+                reader_failures: 1,
+                last_reader_failure: Some(crate::uwb_chip::ReaderFailure::DeviceRemoved),
+                ..Default::default()
+            },
+            client_commands: ResponseStats::default(),
+            hdlc: true,
+            firmware: Some(PathBuf::from("/vendor/firmware/uwb.bin")),
+            invalid_packets: 0,
+            forced_yields: 0,
+            reader_scheduling: ReaderScheduling::Nice(-10),
+            packet_log: PacketLogLevel::Headers,
+            data_payloads: false,
+            snoop: Some((SnoopMode::Filtered, 6)),
+            packet_stats: Stats {
+                last_open: Some(std::time::UNIX_EPOCH + Duration::from_micros(1_000_042)),
+                ..Default::default()
+            },
+            last_packets: Some(vec![TraceEntry {
+                timestamp: std::time::UNIX_EPOCH + Duration::from_secs(2),
+                direction: Direction::Tx,
+                packet: vec![0x20, 0x00, 0x00, 0x01],
+            }]),
+        }
+    }
+
+    fn json(report: &ChipReport) -> String {
+        let mut output = Vec::new();
+        report.write(&mut output, DumpFormat::Json).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn json_report_has_the_required_fields() {
+        let output = json(&opened_report());
+        assert!(output.ends_with("}\n"), "{}", output);
+        assert_eq!(output.lines().count(), 1, "{}", output);
+        let report = parse(&output);
+
+        assert_eq!(report.get("schema_version").number(), SCHEMA_VERSION as f64);
+        assert_eq!(report.get("name").string(), "uwb\"0\"");
+        assert_eq!(report.get("transport").string(), "/dev/tty\\UWB\n");
+        assert_eq!(report.get("state").string(), "opened");
+        let session = report.get("session");
+        assert_eq!(session.get("elapsed_us").number(), 1_500_000.0);
+        assert_eq!(session.get("client_alive"), &Value::Bool(true));
+        assert_eq!(session.get("device").string(), "UCI 2.0.0");
+        let Value::Array(sessions) = session.get("sessions") else {
+            panic!("sessions is not an array");
+        };
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1].get("id").number(), 7.0);
+        assert_eq!(sessions[1].get("ranging_oor").number(), 1.0);
+        assert_eq!(sessions[1].get("last_status").number(), 33.0);
+
+        let counters = report.get("counters");
+        assert_eq!(counters.get("tx_packets").number(), 10.0);
+        assert_eq!(counters.get("rx_packets").number(), 12.0);
+        assert_eq!(counters.get("reader_failures").number(), 1.0);
+        let gauges = report.get("gauges");
+        assert_eq!(gauges.get("data_messages_awaiting_credits").number(), 4.0);
+        assert_eq!(gauges.get("snoop_packets_dropped").number(), 6.0);
+        let errors = report.get("last_errors");
+        assert_eq!(errors.get("reader_failure").string(), "DeviceRemoved");
+        assert_eq!(errors.get("reconnect_error"), &Value::Null);
+
+        assert_eq!(
+            report.get("firmware").get("path").string(),
+            "/vendor/firmware/uwb.bin"
+        );
+        assert_eq!(report.get("packet_log").get("level").string(), "headers");
+        assert_eq!(report.get("snoop").string(), "filtered");
+        assert_eq!(report.get("reader_scheduling").get("nice").number(), -10.0);
+        let since_open = report.get("since_open");
+        assert_eq!(since_open.get("tx").get("cmd").get("packets").number(), 0.0);
+        assert_eq!(report.get("last_open_us").number(), 1_000_042.0);
+        assert_eq!(report.get("last_close_us"), &Value::Null);
+        let Value::Array(packets) = report.get("last_packets") else {
+            panic!("last_packets is not an array");
+        };
+        assert_eq!(packets[0].get("timestamp_us").number(), 2_000_000.0);
+        assert_eq!(packets[0].get("direction").string(), "tx");
+        assert_eq!(packets[0].get("packet").string(), "20000001");
+    }
+
+    #[test]
+    fn json_report_of_a_closed_chip() {
+        let report = ChipReport {
+            state: ChipState::AwaitingClient,
+            firmware: None,
+            snoop: None,
+            reader_scheduling: ReaderScheduling::Refused {
+                nice: -10,
+                errno: libc::EPERM,
+            },
+            last_packets: None,
+            ..opened_report()
+        };
+        let output = parse(&json(&report));
+        let scheduling = output.get("reader_scheduling");
+        assert_eq!(scheduling.get("nice"), &Value::Null);
+        assert_eq!(scheduling.get("refused_nice").number(), -10.0);
+        assert_eq!(output.get("state").string(), "awaiting_client");
+        assert_eq!(output.get("session"), &Value::Null);
+        assert_eq!(output.get("firmware"), &Value::Null);
+        assert_eq!(output.get("snoop"), &Value::Null);
+        assert_eq!(
+            output
+                .get("gauges")
+                .get("data_messages_awaiting_credits")
+                .number(),
+            0.0
+        );
+        assert!(matches!(output, Value::Object(ref members)
+            if !members.iter().any(|(name, _)| name == "last_packets")));
+    }
+
+    #[test]
+    fn text_report_lists_the_sessions() {
+        let mut output = Vec::new();
+        opened_report()
+            .write(&mut output, DumpFormat::Text)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains(
+                "state: opened for 1.5s (2 references), client alive, sessions {1, 7} of 5\n"
+            ),
+            "{}",
+            output
+        );
+        assert!(output.contains("  session 7: ranging 3, success 2, out of range 1"));
+        assert!(output.contains("  snoop log: Filtered, 6 packets dropped\n"));
+        assert!(output.contains("  reader scheduling: nice -10\n"));
+        assert!(output.contains("    2.000000 Tx [20, 00, 00, 01]\n"));
+    }
+
+    #[test]
+    fn dump_format_is_parsed() {
+        assert_eq!("text".parse(), Ok(DumpFormat::Text));
+        assert_eq!("json".parse(), Ok(DumpFormat::Json));
+        assert!("proto".parse::<DumpFormat>().is_err());
+    }
+}
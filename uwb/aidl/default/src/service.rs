@@ -1,16 +1,221 @@
-use android_hardware_uwb::aidl::android::hardware::uwb::IUwb::{self, IUwb as _};
 use android_hardware_uwb::binder;
 
-use tokio::runtime::Runtime;
+use tokio::runtime;
+use tokio::signal::unix::{signal, SignalKind};
 
 use std::env;
-use std::panic;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use log::LevelFilter;
 
+mod builder;
+mod calibration;
+mod chip_manager;
+mod cmd_tracker;
+mod config;
+mod crash;
+mod error;
+mod firmware;
+mod flap_guard;
+mod flow_control;
+mod fragmentation;
+mod hdlc;
+mod health;
+mod metrics;
+mod observer;
+mod packet_log;
+mod packet_reader;
+mod pcapng;
+mod reconnect;
+mod report;
+mod session;
+mod snoop;
+mod stats;
+mod trace;
+mod transport;
 mod uwb;
 mod uwb_chip;
 
+/// Directory receiving the packet traces dumped on SIGUSR1, and the snoop
+/// logs.
+const TRACE_DIR: &str = "/data/vendor/uwb";
+
+/// Time given to each chip to close when the service is stopped.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Socket serving the counters of the chips in the Prometheus text format.
+const METRICS_SOCKET: &str = "/data/vendor/uwb/metrics.sock";
+
+/// Create a chip from its configuration, see [`builder::UwbChipBuilder`]
+/// for the paths. A `hotplug` chip can only be opened while its device
+/// node exists. A USB device selected by its identifiers is looked up on
+/// open instead, and can wait for it with `connect_retries`. When the
+/// client dies, the chip is reopened once the client callbacks are
+/// published again as the `reconnect_service` service, if set. A UWBS
+/// not answering the reset on close is reset through its `reset_gpio`,
+/// if set. Every chip has a snoop log, off unless set by the `snoop`
+/// option or with dumpsys. Vendor messages are logged rather than
+/// delivered to the client with `log_vendor_messages`. The reader task
+/// runs with `reader_scheduling`, applied to the whole process.
+fn create_chip(
+    config: config::ChipConfig,
+    reader_scheduling: uwb_chip::ReaderScheduling,
+) -> Result<uwb_chip::UwbChip<Box<dyn transport::Transport>>, builder::BuildError> {
+    let config::ChipConfig {
+        name,
+        path,
+        monitor,
+        reassembly,
+        reset_on_open,
+        hotplug,
+        log_vendor_messages,
+        close_timeout,
+        core_init_timeout,
+        reader_stop_timeout,
+        dedup_window,
+        write_timeout,
+        write_queue_capacity,
+        response_window,
+        android_uci_version,
+        max_packet_size,
+        max_rx_data_payload,
+        max_resync_bytes,
+        spi_speed_hz,
+        spi_mode,
+        spi_bits_per_word,
+        spi_data_ready,
+        trace_capacity,
+        connect_retries,
+        connect_backoff,
+        mock_latency,
+        replay,
+        watchdog_timeout,
+        health_check,
+        reconnect_service,
+        reset_gpio,
+        firmware,
+        firmware_retries,
+        firmware_chunk_size,
+        calibration_commands,
+        reader_nice: _,
+        snoop: snoop_mode,
+        framing,
+        open_config,
+    } = config;
+    let reconnect = reconnect_service.map(|service| {
+        Arc::new(reconnect::ServiceLocator::new(service)) as Arc<dyn reconnect::ClientLocator>
+    });
+    let usb = path.starts_with("usb://");
+    if hotplug && usb {
+        log::warn!("{}: hotplug ignored for {}", name, path);
+    }
+    let hotplug = (hotplug && !usb).then(|| PathBuf::from(&path));
+    let firmware = firmware.map(|path| firmware::FirmwareConfig {
+        retries: firmware_retries,
+        downloader: Arc::new(firmware::ChunkedDownloader::new(firmware_chunk_size)),
+        ..firmware::FirmwareConfig::new(path)
+    });
+    let snoop_log = snoop::SnoopLog::new(
+        Path::new(TRACE_DIR).join(format!("uwb_snoop_{}.pcapng", name)),
+        snoop::DEFAULT_SNOOP_FILE_SIZE,
+        snoop_mode,
+    );
+    let vendor_callback = log_vendor_messages.then(|| {
+        let name = name.clone();
+        Arc::new(move |packet: &[u8]| log::info!("{}: vendor message {:02x?}", name, packet))
+            as uwb_chip::VendorCallback
+    });
+    let mut builder = builder::UwbChipBuilder::default()
+        .name(name)
+        .path(path)
+        .hw_flow_control(open_config.hw_flow_control)
+        .parity(open_config.parity)
+        .stop_bits(open_config.stop_bits)
+        .monitor(monitor)
+        .mock_latency(mock_latency)
+        .replay(replay)
+        .close_timeout(close_timeout)
+        .write_timeout(write_timeout)
+        .write_queue_capacity(write_queue_capacity);
+    if let Some(speed_hz) = spi_speed_hz {
+        builder = builder.spi_speed_hz(speed_hz);
+    }
+    if let Some(mode) = spi_mode {
+        builder = builder.spi_mode(mode);
+    }
+    if let Some(bits_per_word) = spi_bits_per_word {
+        builder = builder.spi_bits_per_word(bits_per_word);
+    }
+    if let Some(line) = spi_data_ready {
+        builder = builder.spi_data_ready(line);
+    }
+    if let Some(baud_rate) = open_config.baud_rate {
+        builder = builder.baud_rate(baud_rate);
+    }
+    if let Some(watchdog_timeout) = watchdog_timeout {
+        builder = builder.watchdog_timeout(watchdog_timeout);
+    }
+    if let Some(reset_gpio) = reset_gpio {
+        builder = builder.reset_gpio(reset_gpio);
+    }
+    let chip = builder
+        .build()?
+        .with_core_init_timeout(core_init_timeout)
+        .with_android_uci_version(android_uci_version)
+        .with_reader_stop_timeout(reader_stop_timeout)
+        .with_dedup_window(dedup_window)
+        .with_response_window(response_window)
+        .with_max_packet_size(max_packet_size)
+        .with_max_rx_data_payload(max_rx_data_payload)
+        .with_max_resync_bytes(max_resync_bytes)
+        .with_reader_scheduling(reader_scheduling)
+        .with_framing(framing)
+        .with_reassembly(reassembly)
+        .with_reset_on_open(reset_on_open)
+        .with_trace_capacity(trace_capacity)
+        .with_connect_retries(connect_retries)
+        .with_connect_backoff(connect_backoff)
+        .with_health_check(health_check)
+        .with_firmware(firmware)
+        .with_calibration_commands(calibration_commands)
+        .with_reconnect(reconnect)
+        .with_hotplug(hotplug)
+        .with_snoop_log(Some(snoop_log));
+    chip.set_vendor_callback(vendor_callback);
+    Ok(chip)
+}
+
+/// Read the chips from the configuration file, falling back to the
+/// chips given on the command line, named after their position, if the
+/// file is missing or invalid.
+fn load_chips() -> Vec<config::ChipConfig> {
+    match fs::read_to_string(config::CONFIG_PATH)
+        .and_then(|contents| config::parse_config(&contents))
+    {
+        Ok(chips) => return chips,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => log::error!("ignoring invalid {}: {}", config::CONFIG_PATH, err),
+    }
+    env::args()
+        .skip(1) // Skip binary name
+        .enumerate()
+        .filter_map(
+            |(i, arg)| match config::ChipConfig::parse(i.to_string(), &arg) {
+                Ok(chip) => Some(chip),
+                Err(err) => {
+                    log::error!("ignoring chip: {}", err);
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
 fn main() -> anyhow::Result<()> {
     logger::init(
         logger::Config::default()
@@ -18,29 +223,83 @@ fn main() -> anyhow::Result<()> {
             .with_tag_on_device("android.hardware.uwb"),
     );
 
-    // Redirect panic messages to logcat.
-    panic::set_hook(Box::new(|panic_info| {
-        log::error!("{}", panic_info);
-    }));
-
     log::info!("UWB HAL starting up");
 
-    // Create the tokio runtime
-    let rt = Runtime::new()?;
+    // The reader tasks run on any thread of the runtime: the nice value is
+    // set before the runtime creates its threads, which inherit it.
+    let chips = load_chips();
+    let reader_scheduling = match chips.iter().filter_map(|chip| chip.reader_nice).min() {
+        Some(nice) => match uwb_chip::ReaderScheduling::apply(nice) {
+            scheduling @ uwb_chip::ReaderScheduling::Refused { .. } => {
+                log::warn!("reader scheduling: {}", scheduling);
+                scheduling
+            }
+            scheduling => {
+                log::info!("reader scheduling: {}", scheduling);
+                scheduling
+            }
+        },
+        None => uwb_chip::ReaderScheduling::Default,
+    };
 
-    let chips = env::args()
-        .skip(1) // Skip binary name
-        .enumerate()
-        .map(|(i, arg)| uwb_chip::UwbChip::new(i.to_string(), arg));
+    // Create the tokio runtime, with named worker threads for `ps -T`.
+    let rt = runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name_fn(|| {
+            static WORKER_ID: AtomicUsize = AtomicUsize::new(0);
+            format!("uwb-worker-{}", WORKER_ID.fetch_add(1, Ordering::Relaxed))
+        })
+        .build()?;
 
-    binder::add_service(
-        &format!("{}/default", IUwb::BpUwb::get_descriptor()),
-        IUwb::BnUwb::new_binder(
-            uwb::Uwb::from_chips(chips, rt.handle().clone()),
-            binder::BinderFeatures::default(),
-        )
-        .as_binder(),
-    )?;
+    let mut manager = chip_manager::UwbChipManager::new();
+    for chip in chips {
+        let name = chip.name.clone();
+        match create_chip(chip, reader_scheduling) {
+            Ok(chip) => manager.register(chip),
+            Err(err) => log::error!("ignoring chip {}: {}", name, err),
+        }
+    }
+    let manager = Arc::new(manager);
+
+    // Redirect panic messages to logcat, along with the state of every chip.
+    crash::install_panic_hook(manager.crash_reporters());
+
+    manager.add_service(rt.handle().clone())?;
+
+    let _metrics = rt
+        .block_on(async {
+            metrics::MetricsExporter::new(Path::new(METRICS_SOCKET), manager.chips())
+        })
+        .inspect_err(|err| log::error!("failed to serve the metrics: {}", err));
+
+    // Dump the packet traces on demand, for post-mortem debugging.
+    let mut dump = rt.block_on(async { signal(SignalKind::user_defined1()) })?;
+    rt.spawn({
+        let manager = manager.clone();
+        async move {
+            while dump.recv().await.is_some() {
+                for (name, err) in manager.dump_traces(Path::new(TRACE_DIR)) {
+                    log::error!("failed to dump the trace of chip {}: {}", name, err);
+                }
+            }
+        }
+    });
+
+    // Reset the opened chips when the service is stopped, so that no UWBS
+    // is left ranging. A chip stuck in close does not hold the others.
+    let mut terminate = rt.block_on(async { signal(SignalKind::terminate()) })?;
+    let mut interrupt = rt.block_on(async { signal(SignalKind::interrupt()) })?;
+    rt.spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => (),
+            _ = interrupt.recv() => (),
+        }
+        log::info!("UWB HAL shutting down");
+        for (name, err) in manager.close_all(SHUTDOWN_TIMEOUT).await {
+            log::error!("failed to close chip {}: {:?}", name, err);
+        }
+        std::process::exit(0);
+    });
 
     binder::ProcessState::join_thread_pool();
     Ok(())
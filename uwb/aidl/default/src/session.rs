@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+
+/// Number of concurrent sessions supported by a UWBS not reporting it.
+pub const DEFAULT_MAX_SESSIONS: usize = 5;
+/// Upper bound of the capacity, whatever the UWBS reports.
+pub const MAX_SESSIONS_LIMIT: usize = 256;
+
+/// Reason why [`SessionPool::insert`] failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionPoolError {
+    /// The pool already holds as many sessions as the UWBS supports.
+    Capacity(usize),
+    /// The session is already initialized.
+    Duplicate(i32),
+}
+
+impl fmt::Display for SessionPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Capacity(capacity) => write!(f, "already {} sessions", capacity),
+            Self::Duplicate(id) => write!(f, "session {} is already initialized", id),
+        }
+    }
+}
+
+impl std::error::Error for SessionPoolError {}
+
+const RANGE_DATA_NTF_HEADER: [u8; 2] = [0x62, 0x00];
+const TWO_WAY_RANGING: u8 = 0x01;
+/// Offsets in RANGE_DATA_NTF.
+const SESSION_TOKEN_OFFSET: usize = UCI_HEADER_SIZE + 4;
+const MEASUREMENT_TYPE_OFFSET: usize = UCI_HEADER_SIZE + 13;
+const MEASUREMENT_COUNT_OFFSET: usize = UCI_HEADER_SIZE + 24;
+/// Size of a two-way ranging measurement, with a short or extended MAC
+/// address, and offset of its status after the MAC address.
+const TWO_WAY_MEASUREMENT_SIZE: usize = 31;
+const MAC_ADDRESS_INDICATOR_OFFSET: usize = UCI_HEADER_SIZE + 15;
+const EXTENDED_MAC_ADDRESS: u8 = 0x01;
+
+/// Header of a SESSION_DEINIT_CMD, whose payload is the session token.
+pub const SESSION_DEINIT_CMD_HEADER: [u8; 4] = [0x21, 0x01, 0x00, 0x04];
+const SESSION_INIT_CMD_HEADER: [u8; 2] = [0x21, 0x00];
+const SESSION_INIT_RSP_HEADER: [u8; 2] = [0x41, 0x00];
+const SESSION_DEINIT_RSP_HEADER: [u8; 2] = [0x41, 0x01];
+const SESSION_STATUS_NTF_HEADER: [u8; 2] = [0x61, 0x02];
+const SESSION_STATE_DEINIT: u8 = 0x01;
+
+const STATUS_OK: u8 = 0x00;
+/// The peer did not answer, taken as out of range.
+const STATUS_RANGING_RX_TIMEOUT: u8 = 0x21;
+
+/// Return the session token and the status of each measurement carried
+/// by a two-way RANGE_DATA_NTF, or `None` for any other packet. The
+/// notifications of the other ranging types are not inspected.
+pub fn range_data_ntf(packet: &[u8]) -> Option<(i32, Vec<u8>)> {
+    if packet.len() <= MEASUREMENT_COUNT_OFFSET
+        || packet[0] != RANGE_DATA_NTF_HEADER[0]
+        || packet[1] & 0x3f != RANGE_DATA_NTF_HEADER[1]
+        || packet[MEASUREMENT_TYPE_OFFSET] != TWO_WAY_RANGING
+    {
+        return None;
+    }
+    let mac_address_size = match packet[MAC_ADDRESS_INDICATOR_OFFSET] {
+        EXTENDED_MAC_ADDRESS => 8,
+        _ => 2,
+    };
+    let count = packet[MEASUREMENT_COUNT_OFFSET] as usize;
+    let measurements = &packet[MEASUREMENT_COUNT_OFFSET + 1..];
+    if measurements.len() < count * TWO_WAY_MEASUREMENT_SIZE {
+        return None;
+    }
+    let statuses = measurements
+        .chunks_exact(TWO_WAY_MEASUREMENT_SIZE)
+        .take(count)
+        .map(|measurement| measurement[mac_address_size])
+        .collect();
+    let token = &packet[SESSION_TOKEN_OFFSET..SESSION_TOKEN_OFFSET + 4];
+    Some((i32::from_le_bytes(token.try_into().unwrap()), statuses))
+}
+
+/// Return the first `len` bytes of the payload of `packet` if it has the
+/// group and opcode of `header`.
+fn payload<'a>(packet: &'a [u8], header: &[u8], len: usize) -> Option<&'a [u8]> {
+    if packet.len() < UCI_HEADER_SIZE + len
+        || packet[0] != header[0]
+        || packet[1] & 0x3f != header[1]
+    {
+        return None;
+    }
+    Some(&packet[UCI_HEADER_SIZE..UCI_HEADER_SIZE + len])
+}
+
+fn read_token(bytes: &[u8]) -> i32 {
+    i32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// Session command of the client awaiting its response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SessionCommand {
+    /// SESSION_INIT_CMD of the session ID.
+    Init(i32),
+    /// SESSION_DEINIT_CMD of the session token.
+    Deinit(i32),
+}
+
+/// Ranging measurements reported by the UWBS for a session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub ranging_count: u64,
+    pub ranging_success: u64,
+    /// Measurements where the peer did not answer.
+    pub ranging_oor: u64,
+    /// Status of the last measurement.
+    pub last_status: u8,
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ranging {}, success {}, out of range {}, last status {:#04x}",
+            self.ranging_count, self.ranging_success, self.ranging_oor, self.last_status
+        )
+    }
+}
+
+/// State of a session kept by the HAL.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct SessionEntry {
+    stats: SessionStats,
+}
+
+/// Sessions initialized on a UWBS, at most as many as it supports
+/// concurrently. Sessions are kept by token, which the UCI packets of the
+/// session carry: the session handle assigned by the UWBS in
+/// SESSION_INIT_RSP on UCI 2.0, the session ID on earlier versions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionPool {
+    capacity: usize,
+    sessions: BTreeMap<i32, SessionEntry>,
+    /// Session handles assigned by the UWBS, by session ID, until the
+    /// session is removed.
+    handles: HashMap<i32, i32>,
+    /// Session command of the client awaiting its response: the UWBS
+    /// answers the commands one at a time.
+    pending: Option<SessionCommand>,
+}
+
+impl Default for SessionPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SESSIONS)
+    }
+}
+
+impl SessionPool {
+    /// Create a pool of `capacity` sessions, at most
+    /// [`MAX_SESSIONS_LIMIT`].
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.min(MAX_SESSIONS_LIMIT),
+            sessions: BTreeMap::new(),
+            handles: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Change the capacity, at most [`MAX_SESSIONS_LIMIT`]. Sessions
+    /// beyond a smaller capacity are kept, but no session can be added
+    /// until enough of them are removed.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.min(MAX_SESSIONS_LIMIT);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the token of session `id`: its handle if the UWBS assigned
+    /// one, or the ID itself.
+    pub fn token(&self, id: i32) -> i32 {
+        self.handles.get(&id).copied().unwrap_or(id)
+    }
+
+    /// Add session `id`, by its token.
+    pub fn insert(&mut self, id: i32) -> Result<(), SessionPoolError> {
+        let token = self.token(id);
+        if self.sessions.contains_key(&token) {
+            return Err(SessionPoolError::Duplicate(id));
+        }
+        if self.sessions.len() >= self.capacity {
+            return Err(SessionPoolError::Capacity(self.capacity));
+        }
+        self.sessions.insert(token, SessionEntry::default());
+        Ok(())
+    }
+
+    /// Remove a session by token, returning whether it was in the pool.
+    pub fn remove(&mut self, token: i32) -> bool {
+        self.handles.retain(|_, handle| *handle != token);
+        self.sessions.remove(&token).is_some()
+    }
+
+    /// Follow a packet of the client written to the UWBS, noting the
+    /// session commands which the next packets received answer.
+    pub fn command_sent(&mut self, packet: &[u8]) {
+        if let Some(id) = payload(packet, &SESSION_INIT_CMD_HEADER, 4) {
+            self.pending = Some(SessionCommand::Init(read_token(id)));
+        } else if let Some(token) = payload(packet, &SESSION_DEINIT_CMD_HEADER, 4) {
+            self.pending = Some(SessionCommand::Deinit(read_token(token)));
+        }
+    }
+
+    /// Follow a packet received from the UWBS: learn the handle of a
+    /// session from SESSION_INIT_RSP, and remove the session deinitialized
+    /// by a successful SESSION_DEINIT_RSP or reported as such by
+    /// SESSION_STATUS_NTF. Returns the token of the session deinitialized,
+    /// whether or not it was in the pool.
+    pub fn received(&mut self, packet: &[u8]) -> Option<i32> {
+        if payload(packet, &SESSION_INIT_RSP_HEADER, 1).is_some() {
+            // Only the responses of UCI 2.0 carry a handle.
+            let rsp = payload(packet, &SESSION_INIT_RSP_HEADER, 5);
+            if let (Some(SessionCommand::Init(id)), Some(rsp)) = (self.pending.take(), rsp) {
+                if rsp[0] == STATUS_OK {
+                    self.handles.insert(id, read_token(&rsp[1..]));
+                }
+            }
+            return None;
+        }
+        if let Some(rsp) = payload(packet, &SESSION_DEINIT_RSP_HEADER, 1) {
+            return match self.pending.take() {
+                Some(SessionCommand::Deinit(token)) if rsp[0] == STATUS_OK => {
+                    self.remove(token);
+                    Some(token)
+                }
+                _ => None,
+            };
+        }
+        let ntf = payload(packet, &SESSION_STATUS_NTF_HEADER, 5)?;
+        if ntf[4] != SESSION_STATE_DEINIT {
+            return None;
+        }
+        let token = read_token(ntf);
+        self.remove(token);
+        Some(token)
+    }
+
+    /// Count the measurements of a RANGE_DATA_NTF of session `id`,
+    /// ignored if the session is not in the pool.
+    pub fn record_ranging(&mut self, id: i32, statuses: &[u8]) {
+        let Some(entry) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        let stats = &mut entry.stats;
+        for &status in statuses {
+            stats.ranging_count += 1;
+            match status {
+                STATUS_OK => stats.ranging_success += 1,
+                STATUS_RANGING_RX_TIMEOUT => stats.ranging_oor += 1,
+                _ => (),
+            }
+            stats.last_status = status;
+        }
+    }
+
+    /// Return the ranging statistics of session `id`, or None if the
+    /// session is not in the pool.
+    pub fn stats(&self, id: i32) -> Option<SessionStats> {
+        self.sessions.get(&id).map(|entry| entry.stats)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Return the identifiers of the sessions, in order.
+    pub fn ids(&self) -> Vec<i32> {
+        self.sessions.keys().copied().collect()
+    }
+}
+
+impl fmt::Display for SessionPool {
+    /// Write the sessions, in order, and the capacity.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.sessions.keys()).finish()?;
+        write!(f, " of {}", self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_stops_at_capacity() {
+        let mut pool = SessionPool::new(2);
+        pool.insert(1).unwrap();
+        pool.insert(2).unwrap();
+        assert_eq!(pool.insert(3), Err(SessionPoolError::Capacity(2)));
+        assert_eq!(pool.insert(2), Err(SessionPoolError::Duplicate(2)));
+
+        assert!(pool.remove(1));
+        assert!(!pool.remove(1));
+        pool.insert(3).unwrap();
+        assert_eq!(pool.to_string(), "{2, 3} of 2");
+    }
+
+    #[test]
+    fn sessions_are_kept_by_token() {
+        const SESSION_INIT_CMD: [u8; 9] = [0x21, 0x00, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00];
+        const SESSION_DEINIT_CMD: [u8; 8] = [0x21, 0x01, 0x00, 0x04, 0x04, 0x03, 0x02, 0x01];
+        let mut pool = SessionPool::default();
+
+        // UCI 1.x: no handle, the session is kept by ID.
+        pool.command_sent(&SESSION_INIT_CMD);
+        assert_eq!(pool.received(&[0x41, 0x00, 0x00, 0x01, 0x00]), None);
+        assert_eq!(pool.token(1), 1);
+
+        // UCI 2.0: the session is kept by the handle of the response.
+        pool.command_sent(&SESSION_INIT_CMD);
+        let rsp = [0x41, 0x00, 0x00, 0x05, 0x00, 0x04, 0x03, 0x02, 0x01];
+        assert_eq!(pool.received(&rsp), None);
+        assert_eq!(pool.token(1), 0x01020304);
+        pool.insert(1).unwrap();
+        assert_eq!(pool.ids(), vec![0x01020304]);
+        assert_eq!(pool.insert(1), Err(SessionPoolError::Duplicate(1)));
+
+        // Removed on a successful SESSION_DEINIT_RSP only.
+        pool.command_sent(&SESSION_DEINIT_CMD);
+        assert_eq!(pool.received(&[0x41, 0x01, 0x00, 0x01, 0x01]), None);
+        assert_eq!(pool.ids(), vec![0x01020304]);
+        pool.command_sent(&SESSION_DEINIT_CMD);
+        assert_eq!(
+            pool.received(&[0x41, 0x01, 0x00, 0x01, 0x00]),
+            Some(0x01020304)
+        );
+        assert!(pool.is_empty());
+        assert_eq!(pool.token(1), 1);
+
+        // Or on SESSION_STATUS_NTF reporting it deinitialized.
+        pool.insert(1).unwrap();
+        let ntf = [0x61, 0x02, 0x00, 0x06, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00];
+        assert_eq!(pool.received(&ntf), None);
+        let ntf = [0x61, 0x02, 0x00, 0x06, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00];
+        assert_eq!(pool.received(&ntf), Some(1));
+        assert!(pool.is_empty());
+    }
+
+    /// Two-way RANGE_DATA_NTF of session 0x04030201 with short MAC
+    /// addresses, carrying a measurement of each status.
+    fn range_data_ntf_packet(statuses: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x62, 0x00, 0x00, 0x00];
+        packet.extend([0x00; 4]);
+        packet.extend([0x01, 0x02, 0x03, 0x04]);
+        packet.extend([0x00; 5]);
+        packet.push(TWO_WAY_RANGING);
+        packet.extend([0x00; 10]);
+        packet.push(statuses.len() as u8);
+        for &status in statuses {
+            let mut measurement = [0x00; TWO_WAY_MEASUREMENT_SIZE];
+            measurement[2] = status;
+            packet.extend(measurement);
+        }
+        packet[3] = (packet.len() - UCI_HEADER_SIZE) as u8;
+        packet
+    }
+
+    #[test]
+    fn range_data_ntf_is_parsed() {
+        let packet = range_data_ntf_packet(&[0x00, 0x21]);
+        assert_eq!(
+            range_data_ntf(&packet),
+            Some((0x04030201, vec![0x00, 0x21]))
+        );
+
+        // Extended MAC addresses.
+        let mut packet = range_data_ntf_packet(&[0x00]);
+        packet[MAC_ADDRESS_INDICATOR_OFFSET] = EXTENDED_MAC_ADDRESS;
+        packet[MEASUREMENT_COUNT_OFFSET + 1 + 8] = 0x22;
+        assert_eq!(range_data_ntf(&packet), Some((0x04030201, vec![0x22])));
+
+        // Truncated, other ranging type, and other notification.
+        let packet = range_data_ntf_packet(&[0x00]);
+        assert_eq!(range_data_ntf(&packet[..packet.len() - 1]), None);
+        let mut other = packet.clone();
+        other[MEASUREMENT_TYPE_OFFSET] = 0x02;
+        assert_eq!(range_data_ntf(&other), None);
+        let mut other = packet.clone();
+        other[1] = 0x04;
+        assert_eq!(range_data_ntf(&other), None);
+    }
+
+    #[test]
+    fn ranging_is_counted_per_session() {
+        let mut pool = SessionPool::default();
+        pool.insert(1).unwrap();
+        pool.record_ranging(1, &[0x00, 0x21, 0x00, 0x22]);
+        pool.record_ranging(2, &[0x00]);
+        assert_eq!(
+            pool.stats(1),
+            Some(SessionStats {
+                ranging_count: 4,
+                ranging_success: 2,
+                ranging_oor: 1,
+                last_status: 0x22,
+            })
+        );
+        assert_eq!(pool.stats(2), None);
+        assert_eq!(
+            pool.stats(1).unwrap().to_string(),
+            "ranging 4, success 2, out of range 1, last status 0x22"
+        );
+
+        // A session initialized again starts over.
+        assert!(pool.remove(1));
+        pool.insert(1).unwrap();
+        assert_eq!(pool.stats(1), Some(SessionStats::default()));
+    }
+
+    #[test]
+    fn smaller_capacity_keeps_sessions() {
+        let mut pool = SessionPool::default();
+        for id in 0..DEFAULT_MAX_SESSIONS as i32 {
+            pool.insert(id).unwrap();
+        }
+        assert_eq!(
+            pool.insert(DEFAULT_MAX_SESSIONS as i32),
+            Err(SessionPoolError::Capacity(DEFAULT_MAX_SESSIONS))
+        );
+
+        pool.set_capacity(3);
+        assert!(pool.remove(0));
+        assert_eq!(pool.insert(0), Err(SessionPoolError::Capacity(3)));
+        assert!(pool.remove(1));
+        assert!(pool.remove(2));
+        pool.insert(0).unwrap();
+        assert_eq!(pool.ids(), [0, 3, 4]);
+
+        pool.set_capacity(usize::MAX);
+        assert_eq!(pool.capacity, MAX_SESSIONS_LIMIT);
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+use crate::observer::Direction;
+use crate::pcapng::PcapngWriter;
+
+/// Default size past which the snoop log is rotated.
+pub const DEFAULT_SNOOP_FILE_SIZE: u64 = 1 << 20;
+
+/// Packets queued for the writer thread, past which packets are dropped.
+const SNOOP_QUEUE_CAPACITY: usize = 1024;
+
+const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+const DATA_MESSAGE_TYPE: u8 = 0b000;
+
+/// What the snoop log records.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SnoopMode {
+    #[default]
+    Off,
+    /// Every packet, with the payload of the data packets left out.
+    Filtered,
+    /// Every packet.
+    Full,
+}
+
+impl FromStr for SnoopMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(Self::Off),
+            "filtered" => Ok(Self::Filtered),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("unknown snoop mode {:?}", mode)),
+        }
+    }
+}
+
+type SnoopEntry = (SystemTime, Direction, Vec<u8>);
+
+/// Records the UCI packets going through a chip in a pcapng file. The
+/// file is rotated once larger than its maximum size, the previous one
+/// being kept with the `.1` suffix. Packets are written by a dedicated
+/// thread, and dropped if it falls behind.
+pub struct SnoopLog {
+    mode: AtomicU8,
+    dropped: AtomicU64,
+    sender: Option<SyncSender<SnoopEntry>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl SnoopLog {
+    /// Record the packets to `path`, rotated past `max_file_size` bytes.
+    /// Nothing is written until a mode other than [`SnoopMode::Off`] is
+    /// set.
+    pub fn new(path: PathBuf, max_file_size: u64, mode: SnoopMode) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(SNOOP_QUEUE_CAPACITY);
+        let writer = std::thread::spawn(move || write_entries(receiver, path, max_file_size));
+        Self {
+            mode: AtomicU8::new(mode as u8),
+            dropped: AtomicU64::new(0),
+            sender: Some(sender),
+            writer: Some(writer),
+        }
+    }
+
+    pub fn mode(&self) -> SnoopMode {
+        match self.mode.load(Ordering::Relaxed) {
+            0 => SnoopMode::Off,
+            1 => SnoopMode::Filtered,
+            _ => SnoopMode::Full,
+        }
+    }
+
+    pub fn set_mode(&self, mode: SnoopMode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+
+    /// Number of packets dropped because the writer thread fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue a packet for writing, according to the mode. Does not block.
+    pub fn record(&self, timestamp: SystemTime, direction: Direction, packet: &[u8]) {
+        let packet = match self.mode() {
+            SnoopMode::Off => return,
+            SnoopMode::Filtered if is_data(packet) => &packet[..UCI_HEADER_SIZE],
+            SnoopMode::Filtered | SnoopMode::Full => packet,
+        };
+        let sender = self.sender.as_ref().unwrap();
+        match sender.try_send((timestamp, direction, packet.to_vec())) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => _ = self.dropped.fetch_add(1, Ordering::Relaxed),
+            Err(TrySendError::Disconnected(_)) => log::warn!("snoop log writer stopped"),
+        }
+    }
+}
+
+impl Drop for SnoopLog {
+    fn drop(&mut self) {
+        // Let the writer thread write the queued packets and stop.
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn is_data(packet: &[u8]) -> bool {
+    packet.len() >= UCI_HEADER_SIZE && (packet[0] & MESSAGE_TYPE_MASK) >> 5 == DATA_MESSAGE_TYPE
+}
+
+/// Counts the bytes written to a file.
+struct CountingWriter {
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// Body of the writer thread. Runs until the log is dropped.
+fn write_entries(receiver: Receiver<SnoopEntry>, path: PathBuf, max_file_size: u64) {
+    let mut pcapng: Option<PcapngWriter<CountingWriter>> = None;
+    while let Ok(entry) = receiver.recv() {
+        let result = std::iter::once(entry)
+            .chain(receiver.try_iter())
+            .try_for_each(|(timestamp, direction, packet)| {
+                if pcapng.is_none() {
+                    let file = BufWriter::new(File::create(&path)?);
+                    pcapng = Some(PcapngWriter::new(CountingWriter { file, written: 0 })?);
+                }
+                let writer = pcapng.as_mut().unwrap();
+                writer.write_packet(timestamp, direction, &packet)?;
+                if writer.get_ref().written >= max_file_size {
+                    pcapng.take().unwrap().finish()?;
+                    fs::rename(&path, rotated_path(&path))?;
+                }
+                Ok(())
+            })
+            .and_then(|()| match pcapng {
+                Some(ref mut writer) => writer.flush(),
+                None => Ok(()),
+            });
+        if let Err(err) = result {
+            log::error!("failed to write the snoop log {:?}: {}", path, err);
+            // Start over with a new file.
+            pcapng = None;
+        }
+    }
+    if let Some(writer) = pcapng {
+        if let Err(err) = writer.finish() {
+            log::error!("failed to write the snoop log {:?}: {}", path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const DEVICE_STATUS_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x01];
+    const DATA_MESSAGE_RCV: [u8; 8] = [0x02, 0x00, 0x04, 0x00, 0xde, 0xad, 0xbe, 0xef];
+
+    fn temp_path(prefix: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        std::env::temp_dir().join(format!(
+            "{}-{}-{}.pcapng",
+            prefix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// Return the timestamps and packets of the enhanced packet blocks of
+    /// a pcapng capture.
+    fn read_capture(path: &Path) -> Vec<(u64, Vec<u8>)> {
+        let capture = fs::read(path).unwrap();
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(capture[offset..offset + 4].try_into().unwrap());
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while offset < capture.len() {
+            let length = u32_at(offset + 4) as usize;
+            assert_eq!(u32_at(offset + length - 4) as usize, length);
+            // Enhanced packet block.
+            if u32_at(offset) == 6 {
+                let timestamp = (u32_at(offset + 12) as u64) << 32 | u32_at(offset + 16) as u64;
+                let captured_length = u32_at(offset + 20) as usize;
+                packets.push((
+                    timestamp,
+                    capture[offset + 28..offset + 28 + captured_length].to_vec(),
+                ));
+            }
+            offset += length;
+        }
+        assert_eq!(offset, capture.len());
+        packets
+    }
+
+    #[test]
+    fn snoop_log_round_trips() {
+        let path = temp_path("snoop");
+        let log = SnoopLog::new(path.clone(), DEFAULT_SNOOP_FILE_SIZE, SnoopMode::Off);
+        let timestamp = UNIX_EPOCH + Duration::from_secs(5);
+        log.record(timestamp, Direction::Rx, &DEVICE_STATUS_NTF);
+        log.set_mode(SnoopMode::Full);
+        log.record(timestamp, Direction::Rx, &DEVICE_STATUS_NTF);
+        log.record(timestamp, Direction::Rx, &DATA_MESSAGE_RCV);
+        log.set_mode(SnoopMode::Filtered);
+        log.record(timestamp, Direction::Rx, &DEVICE_STATUS_NTF);
+        log.record(timestamp, Direction::Rx, &DATA_MESSAGE_RCV);
+        drop(log);
+
+        assert_eq!(
+            read_capture(&path),
+            vec![
+                (5_000_000_000, DEVICE_STATUS_NTF.to_vec()),
+                (5_000_000_000, DATA_MESSAGE_RCV.to_vec()),
+                (5_000_000_000, DEVICE_STATUS_NTF.to_vec()),
+                (5_000_000_000, DATA_MESSAGE_RCV[..UCI_HEADER_SIZE].to_vec()),
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn snoop_log_rotates_at_size_limit() {
+        let path = temp_path("snoop");
+        let max_file_size = 512;
+        let log = SnoopLog::new(path.clone(), max_file_size, SnoopMode::Full);
+        let packets: Vec<_> = (0..20u8).map(|i| vec![0x60, 0x01, 0x00, 0x01, i]).collect();
+        for packet in &packets {
+            log.record(UNIX_EPOCH, Direction::Rx, packet);
+        }
+        drop(log);
+
+        let rotated = rotated_path(&path);
+        for path in [&path, &rotated] {
+            // A file exceeds the limit by a packet block at most.
+            assert!(fs::metadata(path).unwrap().len() < max_file_size + 64);
+        }
+        // The most recent packets are kept, in order.
+        let kept: Vec<_> = [&rotated, &path]
+            .into_iter()
+            .flat_map(|path| read_capture(path))
+            .map(|(_, packet)| packet)
+            .collect();
+        assert!(kept.len() < packets.len());
+        assert!(packets.ends_with(&kept));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::fragmentation::UCI_HEADER_SIZE;
+use crate::observer::Direction;
+
+const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+
+/// Packets and bytes of a kind of UCI message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketCount {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Traffic in one direction, by UCI message type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageTypeCounts {
+    pub data: PacketCount,
+    pub command: PacketCount,
+    pub response: PacketCount,
+    pub notification: PacketCount,
+}
+
+/// Traffic and error counters, over some period.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Packets written to the UWBS, including those of the HAL itself,
+    /// counted per fragment.
+    pub tx: MessageTypeCounts,
+    /// Packets read from the UWBS, or captured on a monitored link.
+    pub rx: MessageTypeCounts,
+    /// Writes which had to wait for the UWBS to accept more bytes.
+    pub write_would_block: u64,
+    /// Packets dropped, or refused to the client, because of a malformed
+    /// header.
+    pub malformed_packets: u64,
+    /// Packets the client callbacks failed to receive.
+    pub delivery_failures: u64,
+}
+
+impl fmt::Display for MessageTypeCounts {
+    /// Write the packets and bytes of every message type, as
+    /// `<packets>/<bytes>B`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, count) in [
+            ("cmd", self.command),
+            ("rsp", self.response),
+            ("ntf", self.notification),
+            ("data", self.data),
+        ] {
+            write!(f, " {} {}/{}B", name, count.packets, count.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TrafficStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tx{}; rx{}; would block {}, malformed {}, delivery failures {}",
+            self.tx,
+            self.rx,
+            self.write_would_block,
+            self.malformed_packets,
+            self.delivery_failures
+        )
+    }
+}
+
+/// Statistics of a chip, see [`crate::uwb_chip::UwbChip::packet_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Counters reset when the chip is opened.
+    pub since_open: TrafficStats,
+    /// Counters kept for the lifetime of the HAL.
+    pub lifetime: TrafficStats,
+    pub last_open: Option<SystemTime>,
+    pub last_close: Option<SystemTime>,
+    /// Last error reported to the client, or write failure.
+    pub last_error: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct Counter {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counter {
+    fn snapshot(&self) -> PacketCount {
+        PacketCount {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of a period, indexed by direction and message type.
+#[derive(Default)]
+struct Counters {
+    traffic: [[Counter; 4]; 2],
+    write_would_block: AtomicU64,
+    malformed_packets: AtomicU64,
+    delivery_failures: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> TrafficStats {
+        let counts = |direction: &[Counter; 4]| MessageTypeCounts {
+            data: direction[0].snapshot(),
+            command: direction[1].snapshot(),
+            response: direction[2].snapshot(),
+            notification: direction[3].snapshot(),
+        };
+        TrafficStats {
+            tx: counts(&self.traffic[0]),
+            rx: counts(&self.traffic[1]),
+            write_would_block: self.write_would_block.load(Ordering::Relaxed),
+            malformed_packets: self.malformed_packets.load(Ordering::Relaxed),
+            delivery_failures: self.delivery_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in self.traffic.iter().flatten() {
+            counter.packets.store(0, Ordering::Relaxed);
+            counter.bytes.store(0, Ordering::Relaxed);
+        }
+        self.write_would_block.store(0, Ordering::Relaxed);
+        self.malformed_packets.store(0, Ordering::Relaxed);
+        self.delivery_failures.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Records the statistics of a chip. Recording a packet or an error only
+/// takes atomic increments, so that the reader task can record every
+/// packet; the timestamps, updated on open, close and errors, have a
+/// lock.
+#[derive(Default)]
+pub struct StatsRecorder {
+    since_open: Counters,
+    lifetime: Counters,
+    last_open: Mutex<Option<SystemTime>>,
+    last_close: Mutex<Option<SystemTime>>,
+    last_error: Mutex<Option<SystemTime>>,
+}
+
+impl StatsRecorder {
+    /// Count a packet. Packets without a complete header or with a
+    /// reserved message type are not counted.
+    pub fn record_packet(&self, direction: Direction, packet: &[u8]) {
+        if packet.len() < UCI_HEADER_SIZE {
+            return;
+        }
+        let message_type = ((packet[0] & MESSAGE_TYPE_MASK) >> 5) as usize;
+        if message_type >= 4 {
+            return;
+        }
+        let direction = match direction {
+            Direction::Tx => 0,
+            Direction::Rx | Direction::Monitored => 1,
+        };
+        for counters in [&self.since_open, &self.lifetime] {
+            let counter = &counters.traffic[direction][message_type];
+            counter.packets.fetch_add(1, Ordering::Relaxed);
+            counter
+                .bytes
+                .fetch_add(packet.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_write_would_block(&self) {
+        for counters in [&self.since_open, &self.lifetime] {
+            counters.write_would_block.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_malformed_packet(&self) {
+        for counters in [&self.since_open, &self.lifetime] {
+            counters.malformed_packets.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_delivery_failure(&self) {
+        for counters in [&self.since_open, &self.lifetime] {
+            counters.delivery_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Reset the counters of the period since open.
+    pub fn record_open(&self) {
+        self.since_open.reset();
+        *self.last_open.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub fn record_close(&self) {
+        *self.last_close.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub fn record_error(&self) {
+        *self.last_error.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            since_open: self.since_open.snapshot(),
+            lifetime: self.lifetime.snapshot(),
+            last_open: *self.last_open.lock().unwrap(),
+            last_close: *self.last_close.lock().unwrap(),
+            last_error: *self.last_error.lock().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_counted_by_message_type() {
+        let recorder = StatsRecorder::default();
+        recorder.record_packet(Direction::Tx, &[0x20, 0x02, 0x00, 0x00]);
+        recorder.record_packet(Direction::Rx, &[0x40, 0x02, 0x00, 0x01, 0x00]);
+        recorder.record_packet(Direction::Rx, &[0x60, 0x01, 0x00, 0x01, 0x01]);
+        recorder.record_packet(Direction::Monitored, &[0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb]);
+        // Reserved message type, and truncated header.
+        recorder.record_packet(Direction::Rx, &[0xe0, 0x01, 0x00, 0x00]);
+        recorder.record_packet(Direction::Tx, &[0x20, 0x02]);
+
+        let stats = recorder.snapshot().lifetime;
+        assert_eq!(
+            stats.tx,
+            MessageTypeCounts {
+                command: PacketCount {
+                    packets: 1,
+                    bytes: 4
+                },
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            stats.rx,
+            MessageTypeCounts {
+                data: PacketCount {
+                    packets: 1,
+                    bytes: 6
+                },
+                response: PacketCount {
+                    packets: 1,
+                    bytes: 5
+                },
+                notification: PacketCount {
+                    packets: 1,
+                    bytes: 5
+                },
+                ..Default::default()
+            }
+        );
+        assert_eq!(stats.malformed_packets, 0);
+        assert_eq!(
+            stats.to_string(),
+            "tx cmd 1/4B rsp 0/0B ntf 0/0B data 0/0B; rx cmd 0/0B rsp 1/5B ntf 1/5B data 1/6B; \
+             would block 0, malformed 0, delivery failures 0"
+        );
+    }
+
+    #[test]
+    fn open_resets_counters_since_open() {
+        let recorder = StatsRecorder::default();
+        recorder.record_packet(Direction::Tx, &[0x20, 0x02, 0x00, 0x00]);
+        recorder.record_delivery_failure();
+        recorder.record_write_would_block();
+        recorder.record_open();
+        recorder.record_delivery_failure();
+
+        let stats = recorder.snapshot();
+        assert!(stats.last_open.is_some());
+        assert_eq!(stats.last_close, None);
+        assert_eq!(
+            stats.since_open,
+            TrafficStats {
+                delivery_failures: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(stats.lifetime.tx.command.packets, 1);
+        assert_eq!(stats.lifetime.write_would_block, 1);
+        assert_eq!(stats.lifetime.delivery_failures, 2);
+    }
+}
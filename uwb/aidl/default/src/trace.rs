@@ -0,0 +1,133 @@
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::observer::Direction;
+
+/// Default number of packets kept by [`PacketTrace`].
+pub const DEFAULT_TRACE_CAPACITY: usize = 256;
+
+/// UCI packet recorded by [`PacketTrace`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub packet: Vec<u8>,
+}
+
+/// Ring buffer of the last UCI packets exchanged with the UWBS, kept for
+/// post-mortem debugging. Once full, the oldest entry is overwritten in
+/// place, reusing its packet buffer.
+#[derive(Default)]
+pub struct PacketTrace(Mutex<Ring>);
+
+#[derive(Default)]
+struct Ring {
+    capacity: usize,
+    entries: Vec<TraceEntry>,
+    /// Index of the oldest entry, once the ring is full.
+    oldest: usize,
+}
+
+impl PacketTrace {
+    /// Create a trace keeping the last `capacity` packets. A capacity of
+    /// zero disables the trace.
+    pub fn new(capacity: usize) -> Self {
+        let trace = Self::default();
+        trace.set_capacity(capacity);
+        trace
+    }
+
+    /// Change the number of packets kept, discarding the current entries.
+    pub fn set_capacity(&self, capacity: usize) {
+        *self.0.lock().unwrap() = Ring {
+            capacity,
+            entries: Vec::with_capacity(capacity),
+            oldest: 0,
+        };
+    }
+
+    /// Record a packet, overwriting the oldest one if the trace is full.
+    pub fn push(&self, timestamp: SystemTime, direction: Direction, packet: &[u8]) {
+        let mut ring = self.0.lock().unwrap();
+        let ring = &mut *ring;
+        if ring.entries.len() < ring.capacity {
+            ring.entries.push(TraceEntry {
+                timestamp,
+                direction,
+                packet: packet.to_vec(),
+            });
+        } else if let Some(entry) = ring.entries.get_mut(ring.oldest) {
+            entry.timestamp = timestamp;
+            entry.direction = direction;
+            entry.packet.clear();
+            entry.packet.extend_from_slice(packet);
+            ring.oldest = (ring.oldest + 1) % ring.capacity;
+        }
+    }
+
+    /// Copy the last `count` entries of the trace, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<TraceEntry> {
+        let ring = self.0.lock().unwrap();
+        let (newest, oldest) = ring.entries.split_at(ring.oldest);
+        let skip = ring.entries.len().saturating_sub(count);
+        oldest.iter().chain(newest).skip(skip).cloned().collect()
+    }
+
+    /// Take all the entries out of the trace, oldest first.
+    pub fn drain(&self) -> Vec<TraceEntry> {
+        let mut ring = self.0.lock().unwrap();
+        let oldest = ring.oldest;
+        let capacity = ring.capacity;
+        let mut entries = std::mem::replace(&mut ring.entries, Vec::with_capacity(capacity));
+        ring.oldest = 0;
+        drop(ring);
+        entries.rotate_left(oldest);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn packet(index: u8) -> Vec<u8> {
+        vec![0x60, 0x01, 0x00, 0x01, index]
+    }
+
+    #[test]
+    fn trace_wraps_around() {
+        let trace = PacketTrace::new(3);
+        let start = UNIX_EPOCH + Duration::from_secs(1);
+        for index in 0..5 {
+            let timestamp = start + Duration::from_millis(index as u64);
+            trace.push(timestamp, Direction::Rx, &packet(index));
+        }
+        // Copying the last entries leaves them in the trace.
+        let recent = trace.recent(2);
+        let entries = trace.drain();
+        assert_eq!(recent, entries[1..]);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.packet.clone())
+                .collect::<Vec<_>>(),
+            vec![packet(2), packet(3), packet(4)]
+        );
+        assert!(entries
+            .windows(2)
+            .all(|pair| pair[0].timestamp < pair[1].timestamp));
+
+        // The trace starts over once drained.
+        assert!(trace.drain().is_empty());
+        trace.push(start, Direction::Tx, &packet(5));
+        assert_eq!(trace.drain().len(), 1);
+    }
+
+    #[test]
+    fn empty_trace_keeps_nothing() {
+        let trace = PacketTrace::new(0);
+        trace.push(SystemTime::now(), Direction::Rx, &packet(0));
+        assert!(trace.drain().is_empty());
+    }
+}
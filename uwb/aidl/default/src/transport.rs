@@ -0,0 +1,800 @@
+use async_trait::async_trait;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+
+mod emulator;
+#[cfg(test)]
+mod mock;
+mod replay;
+mod spi;
+mod tcp;
+mod unix;
+mod vsock;
+
+pub use emulator::EmulatorTransport;
+#[cfg(test)]
+pub use mock::{MockTransport, MockUwbs};
+pub use replay::{ReplayConfig, ReplayEnd, ReplayTransport};
+pub use spi::{GpioLineConfig, SpiConfig, SpiTransport};
+pub use tcp::TcpTransport;
+pub use unix::{UnixAddress, UnixTransport};
+pub use vsock::VsockTransport;
+
+/// Return whether `err` means that the device of the UWBS is gone, e.g.
+/// a USB module unplugged while in use.
+pub fn is_device_gone(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENODEV | libc::EIO | libc::ENXIO)
+    )
+}
+
+/// Link carrying UCI packets between the HAL and the UWBS.
+pub trait Transport: fmt::Display + Send + Sync {
+    /// Read the available bytes without blocking.
+    /// Fails with `WouldBlock` if no bytes are available.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Write some bytes of `buf` without blocking.
+    /// Fails with `WouldBlock` if no bytes can be written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Write all the bytes of `buf` to the UWBS.
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Create a new handle to the same UWBS.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+
+    /// Convert the transport into one whose readiness can be awaited.
+    fn into_async(self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>>;
+}
+
+impl Transport for Box<dyn Transport> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        (**self).try_clone()
+    }
+
+    fn into_async(self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        (*self).into_async()
+    }
+}
+
+/// Transport whose readiness can be awaited from the reader task.
+#[async_trait]
+pub trait AsyncTransport: Send {
+    /// Wait until bytes can be read from the transport.
+    /// Readiness may be edge-triggered: only call this method after a read
+    /// has failed with `WouldBlock`. This is cancellation safe.
+    async fn readable(&mut self) -> io::Result<()>;
+
+    /// Wait until bytes can be written to the transport.
+    /// Readiness may be edge-triggered: only call this method after a write
+    /// has failed with `WouldBlock`. This is cancellation safe.
+    async fn writable(&mut self) -> io::Result<()>;
+
+    /// Access the underlying transport.
+    fn get_mut(&mut self) -> &mut dyn Transport;
+}
+
+/// Future waiting for a transport to be readable, and handing it back.
+type Readable = Pin<Box<dyn Future<Output = (Box<dyn AsyncTransport>, io::Result<()>)> + Send>>;
+
+/// Reads an [`AsyncTransport`] through [`AsyncRead`], waiting for the
+/// transport to be readable whenever a read fails with `WouldBlock`.
+pub struct TransportReader {
+    /// The transport, moved into `readable` while waiting for it.
+    transport: Option<Box<dyn AsyncTransport>>,
+    readable: Option<Readable>,
+}
+
+impl TransportReader {
+    pub fn new(transport: Box<dyn AsyncTransport>) -> Self {
+        Self {
+            transport: Some(transport),
+            readable: None,
+        }
+    }
+}
+
+impl AsyncRead for TransportReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if let Some(readable) = self.readable.as_mut() {
+                let (transport, result) = ready!(readable.as_mut().poll(cx));
+                self.readable = None;
+                self.transport = Some(transport);
+                result?;
+            }
+            let mut transport = self.transport.take().expect("transport not handed back");
+            // Readiness may be edge-triggered: it is only awaited once a
+            // read failed with WouldBlock.
+            match transport.get_mut().read(buf.initialize_unfilled()) {
+                Ok(read_len) => {
+                    buf.advance(read_len);
+                    self.transport = Some(transport);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.readable = Some(Box::pin(async move {
+                        let result = transport.readable().await;
+                        (transport, result)
+                    }));
+                }
+                Err(err) => {
+                    self.transport = Some(transport);
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Asynchronous wrapper for transports backed by a file descriptor.
+pub struct AsyncFdTransport<T: Transport + AsRawFd>(AsyncFd<T>);
+
+impl<T: Transport + AsRawFd> AsyncFdTransport<T> {
+    pub fn new(transport: T) -> io::Result<Self> {
+        Ok(Self(AsyncFd::new(transport)?))
+    }
+}
+
+#[async_trait]
+impl<T: Transport + AsRawFd> AsyncTransport for AsyncFdTransport<T> {
+    async fn readable(&mut self) -> io::Result<()> {
+        self.0.readable_mut().await?.clear_ready();
+        Ok(())
+    }
+
+    async fn writable(&mut self) -> io::Result<()> {
+        self.0.writable_mut().await?.clear_ready();
+        Ok(())
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Transport {
+        self.0.get_mut()
+    }
+}
+
+/// Standard UART line speeds, in bauds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BaudRate {
+    B9600,
+    B19200,
+    B38400,
+    B57600,
+    B115200,
+    B230400,
+    B460800,
+    B500000,
+    B576000,
+    B921600,
+    B1000000,
+    B1152000,
+    B1500000,
+    B2000000,
+    B2500000,
+    B3000000,
+    B3500000,
+    B4000000,
+}
+
+impl TryFrom<u32> for BaudRate {
+    type Error = io::Error;
+
+    fn try_from(baud_rate: u32) -> io::Result<Self> {
+        Ok(match baud_rate {
+            9600 => Self::B9600,
+            19200 => Self::B19200,
+            38400 => Self::B38400,
+            57600 => Self::B57600,
+            115200 => Self::B115200,
+            230400 => Self::B230400,
+            460800 => Self::B460800,
+            500000 => Self::B500000,
+            576000 => Self::B576000,
+            921600 => Self::B921600,
+            1000000 => Self::B1000000,
+            1152000 => Self::B1152000,
+            1500000 => Self::B1500000,
+            2000000 => Self::B2000000,
+            2500000 => Self::B2500000,
+            3000000 => Self::B3000000,
+            3500000 => Self::B3500000,
+            4000000 => Self::B4000000,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a standard baud rate", baud_rate),
+                ))
+            }
+        })
+    }
+}
+
+impl From<BaudRate> for libc::speed_t {
+    fn from(baud_rate: BaudRate) -> Self {
+        match baud_rate {
+            BaudRate::B9600 => libc::B9600,
+            BaudRate::B19200 => libc::B19200,
+            BaudRate::B38400 => libc::B38400,
+            BaudRate::B57600 => libc::B57600,
+            BaudRate::B115200 => libc::B115200,
+            BaudRate::B230400 => libc::B230400,
+            BaudRate::B460800 => libc::B460800,
+            BaudRate::B500000 => libc::B500000,
+            BaudRate::B576000 => libc::B576000,
+            BaudRate::B921600 => libc::B921600,
+            BaudRate::B1000000 => libc::B1000000,
+            BaudRate::B1152000 => libc::B1152000,
+            BaudRate::B1500000 => libc::B1500000,
+            BaudRate::B2000000 => libc::B2000000,
+            BaudRate::B2500000 => libc::B2500000,
+            BaudRate::B3000000 => libc::B3000000,
+            BaudRate::B3500000 => libc::B3500000,
+            BaudRate::B4000000 => libc::B4000000,
+        }
+    }
+}
+
+/// Parity bit of the UART frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+impl FromStr for Parity {
+    type Err = String;
+
+    fn from_str(parity: &str) -> Result<Self, Self::Err> {
+        match parity {
+            "none" => Ok(Self::None),
+            "even" => Ok(Self::Even),
+            "odd" => Ok(Self::Odd),
+            _ => Err(format!("unknown parity {:?}", parity)),
+        }
+    }
+}
+
+/// Stop bits ending the UART frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StopBits {
+    #[default]
+    One,
+    Two,
+}
+
+impl FromStr for StopBits {
+    type Err = String;
+
+    fn from_str(stop_bits: &str) -> Result<Self, Self::Err> {
+        match stop_bits {
+            "1" => Ok(Self::One),
+            "2" => Ok(Self::Two),
+            _ => Err(format!("invalid stop bit count {:?}", stop_bits)),
+        }
+    }
+}
+
+/// Configuration applied to a UART device node when it is opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpenConfig {
+    /// Open the device read-only.
+    pub read_only: bool,
+    /// Line speed, in bauds. The speed configured by the kernel is kept
+    /// if unset. Opening fails if the speed is not a [`BaudRate`], or is
+    /// not supported by the device.
+    pub baud_rate: Option<u32>,
+    /// Enable RTS/CTS hardware flow control, letting the UWBS
+    /// back-pressure the host when its receive buffer is full.
+    pub hw_flow_control: bool,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+/// Directory listing the USB devices and their interfaces.
+const USB_DEVICES_DIR: &str = "/sys/bus/usb/devices";
+
+/// Device node of a UWBS behind a UART.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Path(PathBuf),
+    /// The tty of the USB device with these vendor and product
+    /// identifiers, whose path changes across reboots and hub resets. It
+    /// is looked up on every open, see [`resolve_usb_path`].
+    UsbVid {
+        vid: u16,
+        pid: u16,
+    },
+}
+
+impl DeviceSelector {
+    /// Return the path of the device node. Fails with `NotFound` if the
+    /// USB device is not plugged, or its tty not created yet.
+    pub fn resolve(&self) -> io::Result<PathBuf> {
+        match *self {
+            Self::Path(ref path) => Ok(path.clone()),
+            Self::UsbVid { vid, pid } => resolve_usb_path(vid, pid),
+        }
+    }
+}
+
+/// Parses `usb://<vid>:<pid>`, with hexadecimal identifiers, as a USB
+/// device, and anything else as a path.
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let Some(ids) = value.strip_prefix("usb://") else {
+            return Ok(Self::Path(PathBuf::from(value)));
+        };
+        let parse = |id| u16::from_str_radix(id, 16).ok();
+        match ids.split_once(':') {
+            Some((vid, pid)) => match (parse(vid), parse(pid)) {
+                (Some(vid), Some(pid)) => Ok(Self::UsbVid { vid, pid }),
+                _ => Err(format!("invalid USB device identifiers {:?}", ids)),
+            },
+            None => Err(format!(
+                "invalid USB device {:?}, expected usb://<vid>:<pid>",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Path(ref path) => write!(f, "{}", path.display()),
+            Self::UsbVid { vid, pid } => write!(f, "usb://{:04x}:{:04x}", vid, pid),
+        }
+    }
+}
+
+/// Return the tty device node of the USB device `vid`:`pid`, created by
+/// the CDC ACM driver (`ttyACM*`) or a USB serial driver (`ttyUSB*`).
+/// Fails with `NotFound` if there is none.
+pub fn resolve_usb_path(vid: u16, pid: u16) -> io::Result<PathBuf> {
+    let name = find_usb_tty(Path::new(USB_DEVICES_DIR), vid, pid)?;
+    Ok(Path::new("/dev").join(name))
+}
+
+/// Return the name of the tty of the USB device `vid`:`pid` listed in
+/// `devices`, laid out as `/sys/bus/usb/devices`.
+fn find_usb_tty(devices: &Path, vid: u16, pid: u16) -> io::Result<OsString> {
+    let read_id = |path: PathBuf| {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|id| u16::from_str_radix(id.trim(), 16).ok())
+    };
+    for device in fs::read_dir(devices)? {
+        let device = device?.path();
+        if read_id(device.join("idVendor")) != Some(vid)
+            || read_id(device.join("idProduct")) != Some(pid)
+        {
+            continue;
+        }
+        for interface in fs::read_dir(&device)? {
+            let interface = interface?;
+            // Interfaces are named <device>:<configuration>.<interface>.
+            if !interface.file_name().to_string_lossy().contains(':') {
+                continue;
+            }
+            // ttyACM nodes are listed under tty/, ttyUSB ones directly.
+            let interface = interface.path();
+            for dir in [interface.join("tty"), interface] {
+                let Ok(entries) = fs::read_dir(dir) else {
+                    continue;
+                };
+                for entry in entries {
+                    let name = entry?.file_name();
+                    let tty = name.to_string_lossy();
+                    if tty.starts_with("ttyACM") || tty.starts_with("ttyUSB") {
+                        return Ok(name);
+                    }
+                }
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no tty for USB device {:04x}:{:04x}", vid, pid),
+    ))
+}
+
+/// Transport for a UWBS behind a UART device node.
+///
+/// The transport created with [`UartTransport::new`] is not connected:
+/// the device node is only opened, in raw mode, when it is cloned. This
+/// way every open of the chip gets a fresh file, and a USB device is
+/// looked up again.
+pub struct UartTransport {
+    device: DeviceSelector,
+    config: OpenConfig,
+    file: Option<File>,
+}
+
+impl UartTransport {
+    /// Create a transport for the device node selected by `device`,
+    /// opened with `config`.
+    pub fn new(device: DeviceSelector, config: OpenConfig) -> Self {
+        Self {
+            device,
+            config,
+            file: None,
+        }
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        self.file
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "device not opened"))
+    }
+}
+
+impl fmt::Display for UartTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.device.fmt(f)
+    }
+}
+
+impl Transport for UartTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file()?.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file()?.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let file = match self.file {
+            Some(ref file) => file.try_clone()?,
+            None => {
+                let path = self.device.resolve()?;
+                if let DeviceSelector::UsbVid { .. } = self.device {
+                    log::info!("opening {} at {}", self.device, path.display());
+                }
+                OpenOptions::new()
+                    .read(true)
+                    .write(!self.config.read_only)
+                    .create(false)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(path)
+                    .and_then(|file| configure_tty(file, &self.config))?
+            }
+        };
+        Ok(Box::new(Self {
+            device: self.device.clone(),
+            config: self.config,
+            file: Some(file),
+        }))
+    }
+
+    fn into_async(mut self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        self.file()?;
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for UartTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file
+            .as_ref()
+            .expect("UART transport not connected")
+            .as_raw_fd()
+    }
+}
+
+/// Put the terminal behind `file` in raw mode, with the line settings of
+/// `config`. Other files, e.g. a FIFO standing for the UWBS, are left as
+/// they are.
+///
+/// The termios calls go through nix with the `nix-termios` feature, and
+/// straight to libc otherwise, for the NDKs whose nix lacks termios.
+pub fn configure_tty(file: File, config: &OpenConfig) -> io::Result<File> {
+    #[cfg(feature = "nix-termios")]
+    let raw = makeraw(&file, config);
+    #[cfg(not(feature = "nix-termios"))]
+    let raw = makeraw_libc(&file, config);
+    match raw {
+        Err(err) if err.raw_os_error() == Some(libc::ENOTTY) => {
+            log::debug!("not a terminal, keeping its settings");
+            return Ok(file);
+        }
+        raw => raw?,
+    }
+    match config.baud_rate {
+        Some(baud_rate) => set_baud_rate(file, self::BaudRate::try_from(baud_rate)?),
+        None => Ok(file),
+    }
+}
+
+#[cfg(feature = "nix-termios")]
+fn makeraw(file: &File, config: &OpenConfig) -> io::Result<()> {
+    use nix::sys::termios::*;
+    let mut attrs = tcgetattr(file)?;
+    cfmakeraw(&mut attrs);
+    let flags = &mut attrs.control_flags;
+    flags.set(ControlFlags::CRTSCTS, config.hw_flow_control);
+    flags.set(ControlFlags::PARENB, config.parity != Parity::None);
+    flags.set(ControlFlags::PARODD, config.parity == Parity::Odd);
+    flags.set(ControlFlags::CSTOPB, config.stop_bits == StopBits::Two);
+    tcsetattr(file, SetArg::TCSANOW, &attrs)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "nix-termios"))]
+fn makeraw_libc(file: &File, config: &OpenConfig) -> io::Result<()> {
+    let mut attrs = tcgetattr_libc(file)?;
+    // SAFETY: attrs is a valid termios structure.
+    unsafe { libc::cfmakeraw(&mut attrs) };
+    let set = |flags: &mut libc::tcflag_t, flag: libc::tcflag_t, value: bool| {
+        if value {
+            *flags |= flag;
+        } else {
+            *flags &= !flag;
+        }
+    };
+    let flags = &mut attrs.c_cflag;
+    set(flags, libc::CRTSCTS, config.hw_flow_control);
+    set(flags, libc::PARENB, config.parity != Parity::None);
+    set(flags, libc::PARODD, config.parity == Parity::Odd);
+    set(flags, libc::CSTOPB, config.stop_bits == StopBits::Two);
+    tcsetattr_libc(file, &attrs)
+}
+
+#[cfg(not(feature = "nix-termios"))]
+fn tcgetattr_libc(file: &File) -> io::Result<libc::termios> {
+    let mut attrs = std::mem::MaybeUninit::uninit();
+    // SAFETY: tcgetattr fills attrs in when it succeeds.
+    if unsafe { libc::tcgetattr(file.as_raw_fd(), attrs.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: initialized by tcgetattr.
+    Ok(unsafe { attrs.assume_init() })
+}
+
+#[cfg(not(feature = "nix-termios"))]
+fn tcsetattr_libc(file: &File, attrs: &libc::termios) -> io::Result<()> {
+    // SAFETY: attrs is a valid termios structure.
+    if unsafe { libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, attrs) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "nix-termios")]
+fn set_baud_rate(file: File, baud_rate: BaudRate) -> io::Result<File> {
+    use nix::sys::termios::*;
+    let speed = nix::sys::termios::BaudRate::try_from(libc::speed_t::from(baud_rate))?;
+    let mut attrs = tcgetattr(&file)?;
+    cfsetispeed(&mut attrs, speed)?;
+    cfsetospeed(&mut attrs, speed)?;
+    tcsetattr(&file, SetArg::TCSANOW, &attrs)?;
+
+    // tcsetattr succeeds as long as any of the attributes could be
+    // applied: read the speed back to detect rates the driver rejected.
+    let attrs = tcgetattr(&file)?;
+    if cfgetispeed(&attrs) != speed || cfgetospeed(&attrs) != speed {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{:?} is not supported by the device", baud_rate),
+        ));
+    }
+
+    Ok(file)
+}
+
+#[cfg(not(feature = "nix-termios"))]
+fn set_baud_rate(file: File, baud_rate: BaudRate) -> io::Result<File> {
+    let speed = baud_rate.into();
+    let mut attrs = tcgetattr_libc(&file)?;
+    // SAFETY: attrs is a valid termios structure.
+    if unsafe { libc::cfsetispeed(&mut attrs, speed) } < 0
+        || unsafe { libc::cfsetospeed(&mut attrs, speed) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    tcsetattr_libc(&file, &attrs)?;
+
+    let attrs = tcgetattr_libc(&file)?;
+    // SAFETY: attrs is a valid termios structure.
+    if unsafe { libc::cfgetispeed(&attrs) != speed || libc::cfgetospeed(&attrs) != speed } {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{:?} is not supported by the device", baud_rate),
+        ));
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::termios::{cfgetispeed, cfgetospeed, tcgetattr, ControlFlags, LocalFlags};
+
+    #[test]
+    fn uart_transport_opens_device_when_cloned() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let path = nix::unistd::ttyname(&pty.slave).unwrap();
+        let mut transport =
+            UartTransport::new(DeviceSelector::Path(path.clone()), OpenConfig::default());
+        assert_eq!(
+            transport.write_all(&[0x20]).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        let mut connected = transport.try_clone().unwrap();
+        connected.write_all(&[0x20, 0x00, 0x00, 0x00]).unwrap();
+        let mut buffer = [0; 4];
+        let mut master = File::from(pty.master);
+        master.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            connected.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn usb_devices_are_resolved_from_sysfs() {
+        let devices = std::env::temp_dir().join(format!("uwb-usb-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&devices);
+        let device = |name: &str, vid: &str, pid: &str, tty: &str| {
+            let dir = devices.join(name);
+            fs::create_dir_all(dir.join(format!("{}:1.0", name)).join(tty)).unwrap();
+            fs::write(dir.join("idVendor"), format!("{}\n", vid)).unwrap();
+            fs::write(dir.join("idProduct"), format!("{}\n", pid)).unwrap();
+        };
+        device("1-1", "1fc9", "000b", "tty/ttyACM2");
+        device("1-2", "1fc9", "000c", "ttyUSB0");
+        device("2-1", "0403", "6001", "tty/ttyACM0");
+        // Interfaces are listed next to the devices.
+        fs::create_dir_all(devices.join("1-1:1.0")).unwrap();
+
+        assert_eq!(find_usb_tty(&devices, 0x1fc9, 0x000b).unwrap(), "ttyACM2");
+        assert_eq!(find_usb_tty(&devices, 0x1fc9, 0x000c).unwrap(), "ttyUSB0");
+        let err = find_usb_tty(&devices, 0x1fc9, 0x000d).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(&devices).unwrap();
+    }
+
+    #[test]
+    fn device_selectors_are_parsed() {
+        assert_eq!(
+            "/dev/ttyUWB0".parse(),
+            Ok(DeviceSelector::Path("/dev/ttyUWB0".into()))
+        );
+        let usb: DeviceSelector = "usb://1fc9:000B".parse().unwrap();
+        assert_eq!(
+            usb,
+            DeviceSelector::UsbVid {
+                vid: 0x1fc9,
+                pid: 0x000b
+            }
+        );
+        assert_eq!(usb.to_string(), "usb://1fc9:000b");
+        assert!("usb://1fc9".parse::<DeviceSelector>().is_err());
+        assert!("usb://1fc9:10000".parse::<DeviceSelector>().is_err());
+    }
+
+    #[test]
+    fn uart_transport_applies_open_config() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let path = nix::unistd::ttyname(&pty.slave).unwrap();
+        let config = OpenConfig {
+            baud_rate: Some(921600),
+            hw_flow_control: true,
+            parity: Parity::Odd,
+            stop_bits: StopBits::Two,
+            ..Default::default()
+        };
+        let _connected = UartTransport::new(DeviceSelector::Path(path.clone()), config)
+            .try_clone()
+            .unwrap();
+
+        // The pty driver clears PARENB itself, leaving PARODD to check.
+        let attrs = tcgetattr(&pty.slave).unwrap();
+        assert!(attrs
+            .control_flags
+            .contains(ControlFlags::CRTSCTS | ControlFlags::PARODD | ControlFlags::CSTOPB));
+        assert_eq!(cfgetospeed(&attrs), nix::sys::termios::BaudRate::B921600);
+        assert!(!attrs.local_flags.contains(LocalFlags::ICANON));
+
+        // Back to the defaults on the next open.
+        let _connected = UartTransport::new(
+            DeviceSelector::Path(path.clone()),
+            OpenConfig {
+                parity: Parity::Even,
+                ..Default::default()
+            },
+        )
+        .try_clone()
+        .unwrap();
+        let attrs = tcgetattr(&pty.slave).unwrap();
+        assert!(!attrs
+            .control_flags
+            .intersects(ControlFlags::CRTSCTS | ControlFlags::PARODD | ControlFlags::CSTOPB));
+    }
+
+    #[test]
+    fn uart_transport_skips_non_terminals() {
+        let config = OpenConfig {
+            baud_rate: Some(921600),
+            ..Default::default()
+        };
+        assert!(
+            UartTransport::new(DeviceSelector::Path("/dev/null".into()), config)
+                .try_clone()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn uart_transport_sets_baud_rate() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let path = nix::unistd::ttyname(&pty.slave).unwrap();
+        for baud_rate in [9600, 115200, 3000000, 4000000] {
+            let config = OpenConfig {
+                baud_rate: Some(baud_rate),
+                ..Default::default()
+            };
+            let _connected = UartTransport::new(DeviceSelector::Path(path.clone()), config)
+                .try_clone()
+                .unwrap();
+
+            let attrs = tcgetattr(&pty.slave).unwrap();
+            let baud_rate = BaudRate::try_from(baud_rate).unwrap();
+            assert_eq!(cfgetispeed(&attrs) as libc::speed_t, baud_rate.into());
+            assert_eq!(cfgetospeed(&attrs) as libc::speed_t, baud_rate.into());
+        }
+
+        // Opening fails rather than running at the previous speed.
+        let config = OpenConfig {
+            baud_rate: Some(12345),
+            ..Default::default()
+        };
+        let err = UartTransport::new(DeviceSelector::Path(path.clone()), config)
+            .try_clone()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "12345 is not a standard baud rate");
+    }
+}
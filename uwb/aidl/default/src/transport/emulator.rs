@@ -0,0 +1,312 @@
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use pdl_runtime::Packet;
+use uwb_uci_packets::{
+    DeviceResetRspBuilder, DeviceState, DeviceStatusNtfBuilder, GetCapsInfoRspBuilder,
+    GetDeviceInfoRspBuilder, SessionDeinitRspBuilder, SessionInitRspBuilder, SessionState,
+    SessionStatusNtfBuilder, SetConfigRspBuilder, StatusCode, UciControlPacket,
+};
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use super::{AsyncFdTransport, AsyncTransport, Transport};
+use crate::fragmentation::{payload_length, UCI_HEADER_SIZE};
+
+const COMMAND_MESSAGE_TYPE: u8 = 0b001;
+const RESPONSE_MESSAGE_TYPE: u8 = 0b010;
+
+const GID_CORE: u8 = 0x0;
+const GID_SESSION_CONFIG: u8 = 0x1;
+
+const CORE_DEVICE_RESET: u8 = 0x00;
+const CORE_GET_DEVICE_INFO: u8 = 0x02;
+const CORE_GET_CAPS_INFO: u8 = 0x03;
+const CORE_SET_CONFIG: u8 = 0x04;
+const SESSION_INIT: u8 = 0x00;
+const SESSION_DEINIT: u8 = 0x01;
+
+/// UCI 1.1: major version in the first byte, then the minor and
+/// maintenance versions.
+const UCI_VERSION: u16 = 0x1001;
+/// Reason code of the session state changes caused by a command.
+const STATE_CHANGE_WITH_SESSION_MANAGEMENT_COMMANDS: u8 = 0x00;
+
+/// Transport to a UWBS emulated in process, for running the UWB test
+/// suites on devices without UWB hardware.
+///
+/// The emulated UWBS answers the commands needed to initialize the
+/// stack and to initialize sessions with canned responses. Unknown
+/// commands are answered with STATUS_UNKNOWN_GID or STATUS_UNKNOWN_OID.
+/// It is reached through a pair of pipes, so that the chip goes through
+/// the same code paths as with a real device.
+///
+/// As with [`super::UartTransport`], a new UWBS is started when the
+/// transport is cloned; it stops when the last handle is dropped.
+pub struct EmulatorTransport {
+    response_delay: Duration,
+    pipes: Option<(File, File)>,
+}
+
+impl EmulatorTransport {
+    /// Create a transport whose UWBS waits for `response_delay` before
+    /// answering every command.
+    pub fn new(response_delay: Duration) -> Self {
+        Self {
+            response_delay,
+            pipes: None,
+        }
+    }
+
+    fn pipes(&mut self) -> io::Result<&mut (File, File)> {
+        self.pipes
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "device not started"))
+    }
+
+    fn start(&self) -> io::Result<(File, File)> {
+        let (rx, device_tx) = nix::unistd::pipe()?;
+        let (device_rx, tx) = nix::unistd::pipe()?;
+        let rx = File::from(rx);
+        let flags = OFlag::from_bits_truncate(fcntl(rx.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(rx.as_raw_fd(), FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        let response_delay = self.response_delay;
+        std::thread::Builder::new()
+            .name("uwbs-emulator".to_owned())
+            .spawn(move || {
+                let result = emulate(File::from(device_rx), File::from(device_tx), response_delay);
+                if let Err(err) = result {
+                    log::warn!("emulated UWBS stopped: {}", err);
+                }
+            })?;
+        Ok((rx, File::from(tx)))
+    }
+}
+
+impl fmt::Display for EmulatorTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("mock://")
+    }
+}
+
+impl Transport for EmulatorTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipes()?.0.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipes()?.1.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.pipes()?.1.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let pipes = match self.pipes {
+            Some((ref rx, ref tx)) => (rx.try_clone()?, tx.try_clone()?),
+            None => self.start()?,
+        };
+        Ok(Box::new(Self {
+            response_delay: self.response_delay,
+            pipes: Some(pipes),
+        }))
+    }
+
+    fn into_async(mut self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        self.pipes()?;
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for EmulatorTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pipes
+            .as_ref()
+            .expect("emulated UWBS not started")
+            .0
+            .as_raw_fd()
+    }
+}
+
+/// Answer the commands read from `rx` until the host closes the
+/// transport.
+fn emulate(mut rx: File, mut tx: File, response_delay: Duration) -> io::Result<()> {
+    loop {
+        let mut packet = vec![0; UCI_HEADER_SIZE];
+        match rx.read_exact(&mut packet) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        packet.resize(UCI_HEADER_SIZE + payload_length(&packet), 0);
+        rx.read_exact(&mut packet[UCI_HEADER_SIZE..])?;
+
+        let responses = respond(&packet);
+        if !responses.is_empty() {
+            std::thread::sleep(response_delay);
+        }
+        for response in responses {
+            tx.write_all(&response)?;
+        }
+    }
+}
+
+/// Build the packets sent by the emulated UWBS in reply to `command`.
+fn respond(command: &[u8]) -> Vec<Vec<u8>> {
+    let message_type = command[0] >> 5;
+    let gid = command[0] & 0x0f;
+    let oid = command[1] & 0x3f;
+    if message_type != COMMAND_MESSAGE_TYPE {
+        // Data packets are dropped.
+        return vec![];
+    }
+    let ok = StatusCode::UciStatusOk;
+    let session_id = || Some(u32::from_le_bytes(command.get(4..8)?.try_into().ok()?));
+    let packets: Vec<UciControlPacket> = match (gid, oid) {
+        (GID_CORE, CORE_DEVICE_RESET) => vec![
+            DeviceResetRspBuilder { status: ok }.build().into(),
+            DeviceStatusNtfBuilder {
+                device_state: DeviceState::DeviceStateReady,
+            }
+            .build()
+            .into(),
+        ],
+        (GID_CORE, CORE_GET_DEVICE_INFO) => vec![GetDeviceInfoRspBuilder {
+            status: ok,
+            uci_version: UCI_VERSION,
+            mac_version: UCI_VERSION,
+            phy_version: UCI_VERSION,
+            uci_test_version: UCI_VERSION,
+            vendor_spec_info: vec![],
+        }
+        .build()
+        .into()],
+        (GID_CORE, CORE_GET_CAPS_INFO) => vec![GetCapsInfoRspBuilder {
+            status: ok,
+            tlvs: vec![],
+        }
+        .build()
+        .into()],
+        (GID_CORE, CORE_SET_CONFIG) => vec![SetConfigRspBuilder {
+            status: ok,
+            cfg_status: vec![],
+        }
+        .build()
+        .into()],
+        (GID_SESSION_CONFIG, SESSION_INIT | SESSION_DEINIT) => {
+            let Some(session_token) = session_id() else {
+                return vec![status_response(gid, oid, StatusCode::UciStatusSyntaxError)];
+            };
+            let (response, session_state): (UciControlPacket, _) = if oid == SESSION_INIT {
+                (
+                    SessionInitRspBuilder { status: ok }.build().into(),
+                    SessionState::SessionStateInit,
+                )
+            } else {
+                (
+                    SessionDeinitRspBuilder { status: ok }.build().into(),
+                    SessionState::SessionStateDeinit,
+                )
+            };
+            vec![
+                response,
+                SessionStatusNtfBuilder {
+                    session_token,
+                    session_state,
+                    reason_code: STATE_CHANGE_WITH_SESSION_MANAGEMENT_COMMANDS,
+                }
+                .build()
+                .into(),
+            ]
+        }
+        (GID_CORE | GID_SESSION_CONFIG, _) => {
+            return vec![status_response(gid, oid, StatusCode::UciStatusUnknownOid)]
+        }
+        _ => return vec![status_response(gid, oid, StatusCode::UciStatusUnknownGid)],
+    };
+    packets
+        .into_iter()
+        .map(|packet| packet.encode_to_vec().unwrap())
+        .collect()
+}
+
+/// Build a response carrying only `status`, for the commands the emulated
+/// UWBS does not support.
+fn status_response(gid: u8, oid: u8, status: StatusCode) -> Vec<u8> {
+    vec![
+        RESPONSE_MESSAGE_TYPE << 5 | gid,
+        oid,
+        0x00,
+        0x01,
+        status.into(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_RESET_CMD: [u8; 5] = [0x20, 0x00, 0x00, 0x01, 0x00];
+
+    #[test]
+    fn device_reset_is_answered() {
+        assert_eq!(
+            respond(&DEVICE_RESET_CMD),
+            vec![
+                vec![0x40, 0x00, 0x00, 0x01, 0x00],
+                vec![0x60, 0x01, 0x00, 0x01, 0x01]
+            ]
+        );
+    }
+
+    #[test]
+    fn session_init_is_notified() {
+        let responses = respond(&[0x21, 0x00, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x00]);
+        assert_eq!(
+            responses,
+            vec![
+                vec![0x41, 0x00, 0x00, 0x01, 0x00],
+                vec![0x61, 0x02, 0x00, 0x06, 0x01, 0x02, 0x03, 0x04, 0x00, 0x00]
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_commands_are_refused() {
+        // Vendor group.
+        assert_eq!(
+            respond(&[0x2e, 0x01, 0x00, 0x00]),
+            vec![vec![0x4e, 0x01, 0x00, 0x01, 0x07]]
+        );
+        // CORE_GET_CONFIG.
+        assert_eq!(
+            respond(&[0x20, 0x05, 0x00, 0x00]),
+            vec![vec![0x40, 0x05, 0x00, 0x01, 0x08]]
+        );
+        // Data packets are not answered.
+        assert!(respond(&[0x01, 0x00, 0x00, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn emulator_runs_until_closed() {
+        let mut transport = EmulatorTransport::new(Duration::ZERO).try_clone().unwrap();
+        transport.write_all(&DEVICE_RESET_CMD).unwrap();
+        let mut response = [0; 10];
+        let mut received = 0;
+        while received < response.len() {
+            match transport.read(&mut response[received..]) {
+                Ok(len) => received += len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(5))
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert_eq!(
+            response,
+            [0x40, 0x00, 0x00, 0x01, 0x00, 0x60, 0x01, 0x00, 0x01, 0x01]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::{AsyncFdTransport, AsyncTransport, Transport};
+
+const DEVICE_RESET_CMD: [u8; 5] = [0x20, 0x00, 0x00, 0x01, 0x00];
+const DEVICE_RESET_RSP: [u8; 5] = [0x40, 0x00, 0x00, 0x01, 0x00];
+const DEVICE_STATUS_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x01];
+
+/// Transport over a pair of in-process pipes, for testing the chip
+/// without hardware. Being backed by file descriptors, it goes through
+/// the same readiness notifications as the device transports.
+pub struct MockTransport {
+    rx: File,
+    tx: File,
+    /// Set by [`MockUwbs::remove`].
+    removed: Arc<AtomicBool>,
+}
+
+impl MockTransport {
+    /// Create a transport, along with the UWBS side of the pipes: the
+    /// packets written by the host, and the packets sent to the host.
+    pub fn new() -> (Self, File, File) {
+        let (rx, device_tx) = nix::unistd::pipe().unwrap();
+        let (device_rx, tx) = nix::unistd::pipe().unwrap();
+        let rx = File::from(rx);
+        set_nonblocking(&rx);
+        (
+            Self {
+                rx,
+                tx: File::from(tx),
+                removed: Arc::default(),
+            },
+            File::from(device_rx),
+            File::from(device_tx),
+        )
+    }
+
+    /// Create a transport, along with the emulated UWBS at the other end.
+    pub fn with_uwbs() -> (Self, MockUwbs) {
+        let (transport, rx, tx) = Self::new();
+        let removed = transport.removed.clone();
+        (transport, MockUwbs { rx, tx, removed })
+    }
+
+    /// Create a transport over a sequenced-packet socket, where every
+    /// read returns a single packet, along with the UWBS side of the
+    /// socket.
+    pub fn seqpacket() -> (Self, File) {
+        use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+        let (socket, device) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::empty(),
+        )
+        .unwrap();
+        let rx = File::from(socket);
+        set_nonblocking(&rx);
+        let tx = rx.try_clone().unwrap();
+        (
+            Self {
+                rx,
+                tx,
+                removed: Arc::default(),
+            },
+            File::from(device),
+        )
+    }
+}
+
+impl fmt::Display for MockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("mock")
+    }
+}
+
+impl MockTransport {
+    fn check_present(&self) -> io::Result<()> {
+        match self.removed.load(Ordering::Relaxed) {
+            true => Err(io::Error::from_raw_os_error(libc::ENODEV)),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_present()?;
+        self.rx.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_present()?;
+        self.tx.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.check_present()?;
+        self.tx.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        self.check_present()?;
+        Ok(Box::new(Self {
+            rx: self.rx.try_clone()?,
+            tx: self.tx.try_clone()?,
+            removed: self.removed.clone(),
+        }))
+    }
+
+    fn into_async(self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for MockTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.rx.as_raw_fd()
+    }
+}
+
+/// UWBS end of a [`MockTransport`], driven by the test. Reads block.
+pub struct MockUwbs {
+    rx: File,
+    tx: File,
+    removed: Arc<AtomicBool>,
+}
+
+impl MockUwbs {
+    /// Send a packet to the host.
+    pub fn inject(&mut self, packet: &[u8]) {
+        self.tx.write_all(packet).unwrap();
+    }
+
+    /// Wait for the host to write `packet`, failing on any other bytes.
+    pub fn expect(&mut self, packet: &[u8]) {
+        let mut buffer = vec![0; packet.len()];
+        self.rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, packet);
+    }
+
+    /// Remove the device: every access of the host fails with ENODEV
+    /// from now on.
+    pub fn remove(&mut self) {
+        self.removed.store(true, Ordering::Relaxed);
+    }
+
+    /// Answer the DeviceResetCmd sent on close from another thread, and
+    /// give the UWBS back once done.
+    pub fn respond_to_reset(mut self) -> std::thread::JoinHandle<Self> {
+        std::thread::spawn(move || {
+            self.expect(&DEVICE_RESET_CMD);
+            self.inject(&DEVICE_RESET_RSP);
+            self.inject(&DEVICE_STATUS_NTF);
+            self
+        })
+    }
+}
+
+fn set_nonblocking(file: &File) {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+    fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+}
@@ -0,0 +1,527 @@
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use super::{AsyncFdTransport, AsyncTransport, Transport};
+use crate::fragmentation::{payload_length, UCI_HEADER_SIZE};
+use crate::health::parse_hex;
+use crate::observer::Direction;
+use crate::packet_log::format_header;
+use crate::pcapng::{self, CapturedPacket};
+
+/// First bytes of a pcapng capture: the type of the section header block.
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+/// Longest time the replay waits without checking that the host still
+/// holds the transport.
+const HOST_CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// What the replayed UWBS does once the trace is over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayEnd {
+    /// Keep the link open, dropping the packets written by the host.
+    #[default]
+    Idle,
+    /// Close the link, for the reader task to fail and the client to be
+    /// notified with an ERROR event.
+    Error,
+}
+
+impl FromStr for ReplayEnd {
+    type Err = String;
+
+    fn from_str(end: &str) -> Result<Self, Self::Err> {
+        match end {
+            "idle" => Ok(Self::Idle),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("unknown end of replay {:?}", end)),
+        }
+    }
+}
+
+/// How a trace is replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayConfig {
+    /// Factor applied to the recorded time between packets: 1 replays the
+    /// trace at its original pace, 0 as fast as possible.
+    pub time_scale: f64,
+    /// Stop the replay, as at the end of the trace with [`ReplayEnd::Error`],
+    /// when the host writes a packet other than the recorded one.
+    pub strict: bool,
+    pub end: ReplayEnd,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            time_scale: 1.0,
+            strict: false,
+            end: ReplayEnd::Idle,
+        }
+    }
+}
+
+/// Transport to a UWBS replaying a recorded trace, for reproducing the
+/// failures reported with a snoop log without the hardware.
+///
+/// The trace is either a pcapng capture, as written by the snoop log, or
+/// a text file with a packet per line: `<milliseconds> rx|tx <hex bytes>`,
+/// lines starting with `#` being ignored. The packets received from the
+/// UWBS are sent to the host with their recorded spacing, scaled by
+/// [`ReplayConfig::time_scale`]. The packets written by the host are
+/// matched against the recorded ones, in order, and the mismatches are
+/// logged.
+///
+/// As with [`super::EmulatorTransport`], the trace is replayed from the
+/// start every time the transport is opened, and the replay stops once
+/// the last handle is dropped.
+pub struct ReplayTransport {
+    path: PathBuf,
+    config: ReplayConfig,
+    pipes: Option<(File, File)>,
+    /// Held by every handle, for the replay to stop with the last one.
+    host: Arc<()>,
+}
+
+impl ReplayTransport {
+    pub fn new(path: PathBuf, config: ReplayConfig) -> Self {
+        Self {
+            path,
+            config,
+            pipes: None,
+            host: Arc::default(),
+        }
+    }
+
+    fn pipes(&mut self) -> io::Result<&mut (File, File)> {
+        self.pipes
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "replay not started"))
+    }
+
+    fn start(&self) -> io::Result<Self> {
+        let trace = load_trace(&self.path)?;
+        let (rx, device_tx) = nix::unistd::pipe()?;
+        let (device_rx, tx) = nix::unistd::pipe()?;
+        let rx = File::from(rx);
+        let flags = OFlag::from_bits_truncate(fcntl(rx.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(rx.as_raw_fd(), FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        let transport = Self {
+            path: self.path.clone(),
+            config: self.config,
+            pipes: Some((rx, File::from(tx))),
+            host: Arc::default(),
+        };
+        let host = Arc::downgrade(&transport.host);
+        let config = self.config;
+        log::info!(
+            "replaying {} packets of {}",
+            trace.len(),
+            self.path.display()
+        );
+        std::thread::Builder::new()
+            .name("uwbs-replay".to_owned())
+            .spawn(move || {
+                let result = replay(
+                    &trace,
+                    File::from(device_rx),
+                    File::from(device_tx),
+                    config,
+                    &host,
+                );
+                if let Err(err) = result {
+                    log::error!("replay stopped: {}", err);
+                }
+            })?;
+        Ok(transport)
+    }
+}
+
+impl fmt::Display for ReplayTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replay://{}", self.path.display())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipes()?.0.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipes()?.1.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.pipes()?.1.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let transport = match self.pipes {
+            Some((ref rx, ref tx)) => Self {
+                path: self.path.clone(),
+                config: self.config,
+                pipes: Some((rx.try_clone()?, tx.try_clone()?)),
+                host: self.host.clone(),
+            },
+            None => self.start()?,
+        };
+        Ok(Box::new(transport))
+    }
+
+    fn into_async(mut self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        self.pipes()?;
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for ReplayTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pipes
+            .as_ref()
+            .expect("replay not started")
+            .0
+            .as_raw_fd()
+    }
+}
+
+/// Read the packets of the trace at `path`, leaving out those of unknown
+/// direction.
+fn load_trace(path: &Path) -> io::Result<Vec<CapturedPacket>> {
+    let contents = std::fs::read(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to read {}: {}", path.display(), err),
+        )
+    })?;
+    let mut packets = if contents.starts_with(&PCAPNG_MAGIC) {
+        pcapng::read_packets(&contents)?
+    } else {
+        parse_text_trace(&String::from_utf8_lossy(&contents))?
+    };
+    let count = packets.len();
+    packets.retain(|packet| packet.direction != Direction::Monitored);
+    if packets.len() < count {
+        log::warn!(
+            "skipping {} packets of unknown direction in {}",
+            count - packets.len(),
+            path.display()
+        );
+    }
+    Ok(packets)
+}
+
+/// Parse a trace in the text format, see [`ReplayTransport`].
+fn parse_text_trace(trace: &str) -> io::Result<Vec<CapturedPacket>> {
+    trace
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid trace line {}: {:?}", number, line),
+                )
+            };
+            let mut fields = line.split_whitespace();
+            let (Some(timestamp), Some(direction), Some(packet), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let timestamp = match timestamp.parse::<f64>() {
+                Ok(milliseconds) if milliseconds >= 0.0 && milliseconds.is_finite() => {
+                    Duration::from_nanos((milliseconds * 1e6).round() as u64)
+                }
+                _ => return Err(invalid()),
+            };
+            let direction = match direction {
+                "rx" => Direction::Rx,
+                "tx" => Direction::Tx,
+                _ => return Err(invalid()),
+            };
+            let packet = parse_hex(packet).ok_or_else(invalid)?;
+            Ok(CapturedPacket {
+                timestamp,
+                direction,
+                original_length: packet.len(),
+                packet,
+            })
+        })
+        .collect()
+}
+
+/// Play `trace` back to the host, until its end or until the host drops
+/// the transport.
+fn replay(
+    trace: &[CapturedPacket],
+    mut rx: File,
+    mut tx: File,
+    config: ReplayConfig,
+    host: &Weak<()>,
+) -> io::Result<()> {
+    let mut previous = trace.first().map(|packet| packet.timestamp);
+    for (index, recorded) in trace.iter().enumerate() {
+        let gap = previous.map_or(Duration::ZERO, |previous| {
+            recorded.timestamp.saturating_sub(previous)
+        });
+        previous = Some(recorded.timestamp);
+        match recorded.direction {
+            Direction::Rx => {
+                if !wait(gap.mul_f64(config.time_scale), host) {
+                    return Ok(());
+                }
+                // The payload of a data packet left out by a filtered snoop
+                // log is replayed as zeroes.
+                let mut packet = recorded.packet.clone();
+                packet.resize(recorded.original_length, 0);
+                tx.write_all(&packet)?;
+            }
+            Direction::Tx => {
+                let Some(packet) = read_packet(&mut rx)? else {
+                    return Ok(());
+                };
+                if let Some(mismatch) = compare(recorded, &packet) {
+                    log::error!("replay: packet {} of the trace: {}", index, mismatch);
+                    if config.strict {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("packet {} does not match the trace", index),
+                        ));
+                    }
+                }
+            }
+            Direction::Monitored => (),
+        }
+    }
+    log::info!("replay: end of the trace");
+    match config.end {
+        ReplayEnd::Idle => {
+            while let Some(packet) = read_packet(&mut rx)? {
+                log::debug!("replay: dropping {:02x?} written after the trace", packet);
+            }
+            Ok(())
+        }
+        ReplayEnd::Error => Ok(()),
+    }
+}
+
+/// Sleep for `duration`. Returns false, early, once the host dropped
+/// the transport.
+fn wait(duration: Duration, host: &Weak<()>) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if host.strong_count() == 0 {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep((deadline - now).min(HOST_CHECK_INTERVAL));
+    }
+}
+
+/// Read a packet written by the host, or None once the host closed the
+/// transport.
+fn read_packet(rx: &mut File) -> io::Result<Option<Vec<u8>>> {
+    let mut packet = vec![0; UCI_HEADER_SIZE];
+    match rx.read_exact(&mut packet) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    packet.resize(UCI_HEADER_SIZE + payload_length(&packet), 0);
+    rx.read_exact(&mut packet[UCI_HEADER_SIZE..])?;
+    Ok(Some(packet))
+}
+
+/// Describe how `packet` differs from the `recorded` one, if it does. Only
+/// the bytes kept by the capture are compared.
+fn compare(recorded: &CapturedPacket, packet: &[u8]) -> Option<String> {
+    let expected = &recorded.packet;
+    if packet.len() == recorded.original_length && packet.starts_with(expected) {
+        return None;
+    }
+    if packet.get(..UCI_HEADER_SIZE) != expected.get(..UCI_HEADER_SIZE) {
+        return Some(format!(
+            "expected {}, got {}",
+            format_header(expected),
+            format_header(packet)
+        ));
+    }
+    let offset = expected
+        .iter()
+        .zip(packet)
+        .position(|(expected, byte)| expected != byte)
+        .unwrap_or(expected.len().min(packet.len()));
+    Some(format!(
+        "{}: payload differs from byte {}, expected {:02x?}, got {:02x?}",
+        format_header(packet),
+        offset - UCI_HEADER_SIZE,
+        &expected[offset..],
+        &packet[offset..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_RESET_CMD: [u8; 5] = [0x20, 0x00, 0x00, 0x01, 0x00];
+    const DEVICE_RESET_RSP: [u8; 5] = [0x40, 0x00, 0x00, 0x01, 0x00];
+    const DEVICE_STATUS_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x01];
+
+    /// A minute and a half of recorded traffic.
+    const TRACE: &str = "\
+        # Booted.\n\
+        0 rx 6001000101\n\
+        \n\
+        60000 tx 2000000100\n\
+        60002.5 rx 4000000100\n\
+        90000 rx 6001000101\n";
+
+    fn start(trace: &str, config: ReplayConfig) -> Box<dyn Transport> {
+        let path = std::env::temp_dir().join(format!(
+            "uwb-replay-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, trace).unwrap();
+        let transport = ReplayTransport::new(path.clone(), config)
+            .try_clone()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        transport
+    }
+
+    /// Read `len` bytes from the replayed UWBS, or fewer if it closed the
+    /// link.
+    fn read(transport: &mut dyn Transport, len: usize) -> Vec<u8> {
+        let start = Instant::now();
+        let mut buffer = vec![0; len];
+        let mut received = 0;
+        while received < len {
+            match transport.read(&mut buffer[received..]) {
+                Ok(0) => break,
+                Ok(len) => received += len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(start.elapsed() < Duration::from_secs(1), "nothing to read");
+                    std::thread::sleep(Duration::from_millis(5))
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        buffer.truncate(received);
+        buffer
+    }
+
+    #[test]
+    fn text_traces_are_parsed() {
+        let trace = parse_text_trace(TRACE).unwrap();
+        assert_eq!(trace.len(), 4);
+        assert_eq!(
+            trace[1],
+            CapturedPacket {
+                timestamp: Duration::from_secs(60),
+                direction: Direction::Tx,
+                packet: DEVICE_RESET_CMD.to_vec(),
+                original_length: 5,
+            }
+        );
+        assert_eq!(trace[2].timestamp, Duration::from_micros(60_002_500));
+        let err = parse_text_trace("0 rx 6001000101\n10 up 6001000101\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid trace line 2: \"10 up 6001000101\""
+        );
+    }
+
+    #[test]
+    fn trace_is_replayed_with_compressed_timing() {
+        let config = ReplayConfig {
+            time_scale: 0.001,
+            end: ReplayEnd::Error,
+            ..Default::default()
+        };
+        let mut transport = start(TRACE, config);
+        let start = Instant::now();
+        assert_eq!(read(transport.as_mut(), 5), DEVICE_STATUS_NTF);
+        // The response only comes once the command is written.
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(transport.read(&mut [0; 5]).is_err());
+        transport.write_all(&DEVICE_RESET_CMD).unwrap();
+        assert_eq!(read(transport.as_mut(), 5), DEVICE_RESET_RSP);
+        // 30 s of the trace, in 30 ms.
+        let notified = Instant::now();
+        assert_eq!(read(transport.as_mut(), 5), DEVICE_STATUS_NTF);
+        assert!(notified.elapsed() >= Duration::from_millis(25));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // The link is closed at the end of the trace.
+        assert!(read(transport.as_mut(), 1).is_empty());
+    }
+
+    #[test]
+    fn mismatches_stop_a_strict_replay() {
+        let trace = "0 tx 2000000100\n1 rx 4000000100\n";
+        let config = ReplayConfig {
+            time_scale: 0.0,
+            ..Default::default()
+        };
+        // Logged, and replayed regardless.
+        let mut transport = start(trace, config);
+        transport.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+        assert_eq!(read(transport.as_mut(), 5), DEVICE_RESET_RSP);
+
+        let mut transport = start(
+            trace,
+            ReplayConfig {
+                strict: true,
+                ..config
+            },
+        );
+        transport
+            .write_all(&[0x20, 0x00, 0x00, 0x01, 0x01])
+            .unwrap();
+        assert!(read(transport.as_mut(), 5).is_empty());
+    }
+
+    #[test]
+    fn mismatches_are_described() {
+        let recorded = CapturedPacket {
+            timestamp: Duration::ZERO,
+            direction: Direction::Tx,
+            packet: DEVICE_RESET_CMD.to_vec(),
+            original_length: 5,
+        };
+        assert_eq!(compare(&recorded, &DEVICE_RESET_CMD), None);
+        assert_eq!(
+            compare(&recorded, &[0x20, 0x02, 0x00, 0x00]).unwrap(),
+            "expected mt 1 gid 0x00 oid 0x00, 1 bytes of payload, \
+             got mt 1 gid 0x00 oid 0x02, 0 bytes of payload"
+        );
+        assert_eq!(
+            compare(&recorded, &[0x20, 0x00, 0x00, 0x01, 0x01]).unwrap(),
+            "mt 1 gid 0x00 oid 0x00, 1 bytes of payload: payload differs from byte 0, \
+             expected [00], got [01]"
+        );
+        // A data packet truncated by a filtered snoop log.
+        let recorded = CapturedPacket {
+            packet: vec![0x01, 0x00, 0x02, 0x00],
+            original_length: 6,
+            ..recorded
+        };
+        assert_eq!(
+            compare(&recorded, &[0x01, 0x00, 0x02, 0x00, 0xab, 0xcd]),
+            None
+        );
+    }
+}
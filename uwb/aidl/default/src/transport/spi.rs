@@ -0,0 +1,669 @@
+use async_trait::async_trait;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use tokio::io::unix::AsyncFd;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{AsyncTransport, Transport};
+use crate::fragmentation::{payload_length, UCI_HEADER_SIZE};
+
+/// Interval at which the UWBS is polled for pending packets when it has
+/// no data-ready line: spidev offers no readiness notification.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Size of the length header prefixed to every frame.
+const FRAME_HEADER_SIZE: usize = 2;
+
+pub const DEFAULT_SPI_SPEED_HZ: u32 = 1_000_000;
+
+const SPI_IOC_MAGIC: u8 = b'k';
+
+/// Mirror of `struct spi_ioc_transfer` from `linux/spi/spidev.h`.
+#[repr(C)]
+#[derive(Default)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+nix::ioctl_write_ptr!(spi_ioc_wr_mode, SPI_IOC_MAGIC, 1, u8);
+nix::ioctl_write_ptr!(spi_ioc_wr_bits_per_word, SPI_IOC_MAGIC, 3, u8);
+nix::ioctl_write_ptr!(spi_ioc_wr_max_speed_hz, SPI_IOC_MAGIC, 4, u32);
+nix::ioctl_write_buf!(spi_ioc_message, SPI_IOC_MAGIC, 0, SpiIocTransfer);
+
+const GPIO_IOC_MAGIC: u8 = 0xb4;
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const GPIO_CONSUMER: &[u8] = b"uwb_hal";
+/// Size of `struct gpio_v2_line_event`, read from a line on every edge.
+const GPIO_EVENT_SIZE: usize = 48;
+
+/// Mirror of `struct gpio_v2_line_config_attribute` from
+/// `linux/gpio.h`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpioV2LineConfigAttribute {
+    id: u32,
+    padding: u32,
+    value: u64,
+    mask: u64,
+}
+
+/// Mirror of `struct gpio_v2_line_request` from `linux/gpio.h`.
+#[repr(C)]
+struct GpioV2LineRequest {
+    offsets: [u32; 64],
+    consumer: [u8; 32],
+    flags: u64,
+    num_attrs: u32,
+    config_padding: [u32; 5],
+    attrs: [GpioV2LineConfigAttribute; 10],
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+/// Mirror of `struct gpio_v2_line_values` from `linux/gpio.h`.
+#[repr(C)]
+struct GpioV2LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+nix::ioctl_readwrite!(gpio_v2_get_line, GPIO_IOC_MAGIC, 0x07, GpioV2LineRequest);
+nix::ioctl_readwrite!(
+    gpio_v2_line_get_values,
+    GPIO_IOC_MAGIC,
+    0x0e,
+    GpioV2LineValues
+);
+
+/// Line of a GPIO chip, e.g. `/dev/gpiochip0:17`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpioLineConfig {
+    pub chip: PathBuf,
+    pub offset: u32,
+}
+
+impl FromStr for GpioLineConfig {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.rsplit_once(':') {
+            Some((chip, offset)) if !chip.is_empty() => match offset.parse() {
+                Ok(offset) => Ok(Self {
+                    chip: PathBuf::from(chip),
+                    offset,
+                }),
+                Err(_) => Err(format!("invalid GPIO line offset {:?}", offset)),
+            },
+            _ => Err(format!("invalid GPIO line {:?}", value)),
+        }
+    }
+}
+
+/// Settings of the SPI bus of a UWBS, applied when its device node is
+/// opened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpiConfig {
+    pub speed_hz: u32,
+    /// SPI mode, from 0 to 3.
+    pub mode: u8,
+    pub bits_per_word: u8,
+    /// Line asserted by the UWBS while it has a packet to send. The UWBS
+    /// is polled without it, see [`SpiTransport`].
+    pub data_ready: Option<GpioLineConfig>,
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        Self {
+            speed_hz: DEFAULT_SPI_SPEED_HZ,
+            mode: 0,
+            bits_per_word: 8,
+            data_ready: None,
+        }
+    }
+}
+
+/// Full-duplex transfers on the SPI bus of a UWBS.
+pub trait SpiBus: Send + Sync {
+    /// Perform a single transfer with the chip select asserted for its
+    /// whole duration.
+    fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()>;
+
+    fn try_clone(&self) -> io::Result<Box<dyn SpiBus>>;
+}
+
+/// Line asserted by the UWBS while it has a packet to send.
+#[async_trait]
+pub trait DataReadyLine: Send + Sync {
+    fn is_asserted(&mut self) -> io::Result<bool>;
+
+    /// Wait until the line is asserted, returning at once if it is
+    /// already. This is cancellation safe.
+    async fn asserted(&mut self) -> io::Result<()>;
+
+    fn try_clone(&self) -> io::Result<Box<dyn DataReadyLine>>;
+}
+
+/// SPI bus of a spidev device node.
+struct Spidev {
+    file: File,
+    speed_hz: u32,
+    bits_per_word: u8,
+}
+
+impl Spidev {
+    fn open(path: &str, config: &SpiConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(path)?;
+        // SAFETY: the arguments are valid for the duration of the calls.
+        unsafe {
+            spi_ioc_wr_mode(file.as_raw_fd(), &config.mode)?;
+            spi_ioc_wr_bits_per_word(file.as_raw_fd(), &config.bits_per_word)?;
+            spi_ioc_wr_max_speed_hz(file.as_raw_fd(), &config.speed_hz)?;
+        }
+        Ok(Self {
+            file,
+            speed_hz: config.speed_hz,
+            bits_per_word: config.bits_per_word,
+        })
+    }
+}
+
+impl SpiBus for Spidev {
+    fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+        assert_eq!(tx.len(), rx.len());
+        let transfer = SpiIocTransfer {
+            tx_buf: tx.as_ptr() as u64,
+            rx_buf: rx.as_mut_ptr() as u64,
+            len: tx.len() as u32,
+            speed_hz: self.speed_hz,
+            bits_per_word: self.bits_per_word,
+            ..Default::default()
+        };
+        // SAFETY: the transfer buffers outlive the ioctl and have the
+        // length advertised in the transfer.
+        unsafe { spi_ioc_message(self.file.as_raw_fd(), &[transfer]) }?;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn SpiBus>> {
+        Ok(Box::new(Self {
+            file: self.file.try_clone()?,
+            speed_hz: self.speed_hz,
+            bits_per_word: self.bits_per_word,
+        }))
+    }
+}
+
+/// Input line of a GPIO chip, reporting its rising edges.
+struct GpioLine {
+    /// Registration of `file` with the runtime, made on the first wait.
+    /// Dropped before `file` is closed.
+    async_fd: Option<AsyncFd<RawFd>>,
+    file: File,
+}
+
+impl GpioLine {
+    fn request(config: &GpioLineConfig) -> io::Result<Self> {
+        let chip = File::open(&config.chip)?;
+        // SAFETY: the request only holds integers, for which zero is valid.
+        let mut request: GpioV2LineRequest = unsafe { std::mem::zeroed() };
+        request.offsets[0] = config.offset;
+        request.consumer[..GPIO_CONSUMER.len()].copy_from_slice(GPIO_CONSUMER);
+        request.flags = GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_EDGE_RISING;
+        request.num_lines = 1;
+        // SAFETY: the request is valid for the duration of the call.
+        unsafe { gpio_v2_get_line(chip.as_raw_fd(), &mut request) }?;
+        // SAFETY: the ioctl returned a new file descriptor, owned by no one
+        // else.
+        let file = unsafe { File::from_raw_fd(request.fd) };
+        fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        Ok(Self {
+            async_fd: None,
+            file,
+        })
+    }
+
+    /// Discard the edge events received so far.
+    fn drain_events(&mut self) -> io::Result<()> {
+        let mut events = [0; 16 * GPIO_EVENT_SIZE];
+        loop {
+            match self.file.read(&mut events) {
+                Ok(0) => return Ok(()),
+                Ok(_) => (),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl DataReadyLine for GpioLine {
+    fn is_asserted(&mut self) -> io::Result<bool> {
+        let mut values = GpioV2LineValues { bits: 0, mask: 1 };
+        // SAFETY: the values are valid for the duration of the call.
+        unsafe { gpio_v2_line_get_values(self.file.as_raw_fd(), &mut values) }?;
+        Ok(values.bits & 1 != 0)
+    }
+
+    async fn asserted(&mut self) -> io::Result<()> {
+        if self.async_fd.is_none() {
+            self.async_fd = Some(AsyncFd::new(self.file.as_raw_fd())?);
+        }
+        loop {
+            // The events are discarded before the level is read, for an
+            // assertion right after to wake the task up.
+            self.drain_events()?;
+            if self.is_asserted()? {
+                return Ok(());
+            }
+            self.async_fd
+                .as_ref()
+                .unwrap()
+                .readable()
+                .await?
+                .clear_ready();
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn DataReadyLine>> {
+        Ok(Box::new(Self {
+            async_fd: None,
+            file: self.file.try_clone()?,
+        }))
+    }
+}
+
+/// Transport for a UWBS behind a spidev device node.
+///
+/// With a data-ready line, UCI packets are exchanged as they are: every
+/// write is a transfer of one packet, and while the UWBS asserts the line
+/// a packet is read as its header, then its payload, in two transfers.
+/// Without, the UWBS is polled using the length prefixed framing of the
+/// NXP SR1xx family: every UCI packet is preceded by its length, as a
+/// 2-byte little-endian integer, and a zero length read back from the
+/// UWBS means that no packet is pending.
+///
+/// As with [`super::UartTransport`], the device node is only opened when
+/// the transport is cloned.
+pub struct SpiTransport {
+    path: String,
+    config: SpiConfig,
+    bus: Option<Box<dyn SpiBus>>,
+    data_ready: Option<Box<dyn DataReadyLine>>,
+    /// Bytes of the last received packet not read yet.
+    pending: VecDeque<u8>,
+}
+
+impl SpiTransport {
+    /// Create a transport for the spidev device node at `path`.
+    pub fn new(path: &str, config: SpiConfig) -> Self {
+        Self {
+            path: path.to_owned(),
+            config,
+            bus: None,
+            data_ready: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Read the next packet from the UWBS into the pending bytes.
+    /// Fails with `WouldBlock` if the UWBS has no packet to send.
+    fn receive_packet(&mut self) -> io::Result<()> {
+        let bus = self.bus.as_deref_mut().ok_or_else(not_opened)?;
+        let packet = match self.data_ready {
+            Some(ref mut data_ready) => receive_signaled_packet(bus, data_ready.as_mut())?,
+            None => receive_polled_packet(bus)?,
+        };
+        self.pending.extend(packet);
+        Ok(())
+    }
+}
+
+fn not_opened() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "device not opened")
+}
+
+/// Read the packet of a UWBS asserting `data_ready`, as its header then
+/// its payload. Fails with `WouldBlock` if the line is not asserted.
+fn receive_signaled_packet(
+    bus: &mut dyn SpiBus,
+    data_ready: &mut dyn DataReadyLine,
+) -> io::Result<Vec<u8>> {
+    if !data_ready.is_asserted()? {
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+    let mut packet = vec![0; UCI_HEADER_SIZE];
+    bus.transfer(&[0; UCI_HEADER_SIZE], &mut packet)?;
+    let len = payload_length(&packet);
+    if len > 0 {
+        packet.resize(UCI_HEADER_SIZE + len, 0);
+        bus.transfer(&vec![0; len], &mut packet[UCI_HEADER_SIZE..])?;
+    }
+    Ok(packet)
+}
+
+/// Read the next length prefixed frame from a polled UWBS. Fails with
+/// `WouldBlock` if the UWBS has no packet to send.
+fn receive_polled_packet(bus: &mut dyn SpiBus) -> io::Result<Vec<u8>> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    bus.transfer(&[0; FRAME_HEADER_SIZE], &mut header)?;
+    let len = u16::from_le_bytes(header) as usize;
+    if len == 0 {
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+    let mut frame = vec![0; len];
+    bus.transfer(&vec![0; len], &mut frame)?;
+    Ok(frame)
+}
+
+impl fmt::Display for SpiTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}Hz", self.path, self.config.speed_hz)
+    }
+}
+
+impl Transport for SpiTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.receive_packet()?;
+        }
+        let len = buf.len().min(self.pending.len());
+        for (byte, pending) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *byte = pending;
+        }
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Frames cannot be split: the whole packet is always written.
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let tx = if self.data_ready.is_some() {
+            buf.to_vec()
+        } else {
+            let len = u16::try_from(buf.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet too large"))?;
+            let mut tx = Vec::with_capacity(FRAME_HEADER_SIZE + buf.len());
+            tx.extend_from_slice(&len.to_le_bytes());
+            tx.extend_from_slice(buf);
+            tx
+        };
+        // Bytes clocked in while writing are ignored: the UWBS only sends
+        // packets when polled, or once it asserted its data-ready line.
+        let bus = self.bus.as_deref_mut().ok_or_else(not_opened)?;
+        bus.transfer(&tx, &mut vec![0; tx.len()])
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let (bus, data_ready) = match self.bus {
+            Some(ref bus) => (
+                bus.try_clone()?,
+                self.data_ready
+                    .as_ref()
+                    .map(|data_ready| data_ready.try_clone())
+                    .transpose()?,
+            ),
+            None => (
+                Box::new(Spidev::open(&self.path, &self.config)?) as Box<dyn SpiBus>,
+                self.config
+                    .data_ready
+                    .as_ref()
+                    .map(|line| {
+                        GpioLine::request(line).map(|line| Box::new(line) as Box<dyn DataReadyLine>)
+                    })
+                    .transpose()?,
+            ),
+        };
+        Ok(Box::new(Self {
+            path: self.path.clone(),
+            config: self.config.clone(),
+            bus: Some(bus),
+            data_ready,
+            pending: VecDeque::new(),
+        }))
+    }
+
+    fn into_async(self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        Ok(Box::new(AsyncSpiTransport(*self)))
+    }
+}
+
+/// Asynchronous wrapper waiting for the data-ready line of the UWBS, or
+/// polling the UWBS every [`POLL_INTERVAL`] without.
+struct AsyncSpiTransport(SpiTransport);
+
+#[async_trait]
+impl AsyncTransport for AsyncSpiTransport {
+    async fn readable(&mut self) -> io::Result<()> {
+        match self.0.data_ready {
+            Some(ref mut data_ready) => data_ready.asserted().await,
+            None => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn writable(&mut self) -> io::Result<()> {
+        // Writes are synchronous transfers and never block.
+        Ok(())
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Transport {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::watch;
+
+    /// Bus recording the bytes transferred to the UWBS, and clocking in
+    /// the bytes of `rx`, or zeros once exhausted.
+    #[derive(Clone, Default)]
+    struct MockBus {
+        transfers: Arc<Mutex<Vec<Vec<u8>>>>,
+        rx: Arc<Mutex<VecDeque<u8>>>,
+    }
+
+    impl SpiBus for MockBus {
+        fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+            self.transfers.lock().unwrap().push(tx.to_vec());
+            let mut pending = self.rx.lock().unwrap();
+            for byte in rx {
+                *byte = pending.pop_front().unwrap_or(0);
+            }
+            Ok(())
+        }
+
+        fn try_clone(&self) -> io::Result<Box<dyn SpiBus>> {
+            Ok(Box::new(self.clone()))
+        }
+    }
+
+    #[derive(Clone)]
+    struct MockLine(watch::Receiver<bool>);
+
+    #[async_trait]
+    impl DataReadyLine for MockLine {
+        fn is_asserted(&mut self) -> io::Result<bool> {
+            Ok(*self.0.borrow())
+        }
+
+        async fn asserted(&mut self) -> io::Result<()> {
+            self.0
+                .wait_for(|&level| level)
+                .await
+                .map(|_| ())
+                .map_err(|_| io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn try_clone(&self) -> io::Result<Box<dyn DataReadyLine>> {
+            Ok(Box::new(self.clone()))
+        }
+    }
+
+    /// Opened transport on `bus`, with a data-ready line driven by the
+    /// returned sender.
+    fn signaled_transport(bus: &MockBus) -> (SpiTransport, watch::Sender<bool>) {
+        let (level, line) = watch::channel(false);
+        let mut transport = SpiTransport::new("/dev/spidev0.0", SpiConfig::default());
+        transport.bus = Some(Box::new(bus.clone()));
+        transport.data_ready = Some(Box::new(MockLine(line)));
+        (transport, level)
+    }
+
+    #[test]
+    fn transfer_layout_matches_kernel() {
+        assert_eq!(std::mem::size_of::<SpiIocTransfer>(), 32);
+        assert_eq!(std::mem::size_of::<GpioV2LineRequest>(), 592);
+        assert_eq!(std::mem::size_of::<GpioV2LineValues>(), 16);
+    }
+
+    #[test]
+    fn gpio_lines_are_parsed() {
+        assert_eq!(
+            "/dev/gpiochip0:17".parse(),
+            Ok(GpioLineConfig {
+                chip: PathBuf::from("/dev/gpiochip0"),
+                offset: 17
+            })
+        );
+        assert!("/dev/gpiochip0".parse::<GpioLineConfig>().is_err());
+        assert!(":17".parse::<GpioLineConfig>().is_err());
+        assert!("/dev/gpiochip0:x".parse::<GpioLineConfig>().is_err());
+    }
+
+    #[test]
+    fn signaled_packets_are_read_as_header_then_payload() {
+        const DEVICE_RESET_RSP: [u8; 5] = [0x40, 0x00, 0x00, 0x01, 0x00];
+        const DATA_PACKET: [u8; 6] = [0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb];
+        let bus = MockBus::default();
+        let (mut transport, level) = signaled_transport(&bus);
+        bus.rx
+            .lock()
+            .unwrap()
+            .extend(DEVICE_RESET_RSP.iter().chain(&DATA_PACKET));
+
+        // Nothing is transferred until the UWBS asserts the line.
+        let mut buf = [0; 64];
+        assert_eq!(
+            transport.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert!(bus.transfers.lock().unwrap().is_empty());
+
+        level.send(true).unwrap();
+        let len = transport.read(&mut buf).unwrap();
+        assert_eq!(buf[..len], DEVICE_RESET_RSP);
+        // Data packets have a 16-bit length.
+        let len = transport.read(&mut buf).unwrap();
+        assert_eq!(buf[..len], DATA_PACKET);
+        let lengths: Vec<_> = bus.transfers.lock().unwrap().iter().map(Vec::len).collect();
+        assert_eq!(lengths, [4, 1, 4, 2]);
+
+        level.send(false).unwrap();
+        assert_eq!(
+            transport.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(bus.transfers.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn writes_are_single_transfers() {
+        const DEVICE_RESET_CMD: [u8; 5] = [0x20, 0x00, 0x00, 0x01, 0x00];
+        let bus = MockBus::default();
+        let (mut transport, _level) = signaled_transport(&bus);
+        transport.write_all(&DEVICE_RESET_CMD).unwrap();
+        assert_eq!(*bus.transfers.lock().unwrap(), [DEVICE_RESET_CMD.to_vec()]);
+
+        // Length prefixed without a data-ready line.
+        let bus = MockBus::default();
+        let mut transport = SpiTransport::new("/dev/spidev0.0", SpiConfig::default());
+        transport.bus = Some(Box::new(bus.clone()));
+        transport.write_all(&DEVICE_RESET_CMD).unwrap();
+        assert_eq!(
+            *bus.transfers.lock().unwrap(),
+            [vec![0x05, 0x00, 0x20, 0x00, 0x00, 0x01, 0x00]]
+        );
+        let mut buf = [0; 64];
+        assert_eq!(
+            transport.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[tokio::test]
+    async fn readable_waits_for_data_ready() {
+        let bus = MockBus::default();
+        let (transport, level) = signaled_transport(&bus);
+        let mut transport = Box::new(transport).into_async().unwrap();
+        let readable = tokio::spawn(async move { transport.readable().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!readable.is_finished());
+        level.send(true).unwrap();
+        readable.await.unwrap().unwrap();
+    }
+
+    /// Exercise a real UWBS, selected with the `UWB_SPI_DEVICE`
+    /// environment variable (e.g. `/dev/spidev0.0`). Run on a device with
+    /// `--ignored`.
+    #[test]
+    #[ignore = "requires a UWBS on SPI"]
+    fn device_reset_over_spi() {
+        const DEVICE_RESET_CMD: [u8; 5] = [0x20, 0x00, 0x00, 0x01, 0x00];
+        const DEVICE_RESET_RSP: [u8; 5] = [0x40, 0x00, 0x00, 0x01, 0x00];
+
+        let path = std::env::var("UWB_SPI_DEVICE").expect("UWB_SPI_DEVICE is not set");
+        let mut transport = SpiTransport::new(&path, SpiConfig::default())
+            .try_clone()
+            .unwrap();
+        transport.write_all(&DEVICE_RESET_CMD).unwrap();
+
+        let start = std::time::Instant::now();
+        let mut response = [0; 5];
+        let mut received = 0;
+        while received < response.len() {
+            assert!(start.elapsed() < Duration::from_secs(1), "no response");
+            match transport.read(&mut response[received..]) {
+                Ok(len) => received += len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(POLL_INTERVAL)
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert_eq!(response, DEVICE_RESET_RSP);
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use super::{AsyncFdTransport, AsyncTransport, Transport};
+
+/// Maximum time allowed for connecting to the UWBS.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Transport for an emulated UWBS listening on a TCP port, such as the
+/// UCI device model of the emulator. Packets are exchanged as a byte
+/// stream, without any additional framing.
+///
+/// As with [`super::UartTransport`], the connection is only established
+/// when the transport is cloned, so that every open of the chip gets a
+/// fresh connection. It is shut down when its last handle is dropped.
+pub struct TcpTransport {
+    address: String,
+    stream: Option<TcpStream>,
+}
+
+impl TcpTransport {
+    /// Create a transport for the UWBS listening at `address`, given as
+    /// `host:port`.
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            stream: None,
+        }
+    }
+
+    fn stream(&mut self) -> io::Result<&mut TcpStream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "device not connected"))
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no address for {}", self.address),
+        );
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    // UCI commands are small and sent one at a time: do not
+                    // hold them back waiting for more bytes.
+                    stream.set_nodelay(true)?;
+                    stream.set_nonblocking(true)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl fmt::Display for TcpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tcp://{}", self.address)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream()?.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream()?.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.stream()?.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let stream = match self.stream {
+            Some(ref stream) => stream.try_clone()?,
+            None => self.connect()?,
+        };
+        Ok(Box::new(Self {
+            address: self.address.clone(),
+            stream: Some(stream),
+        }))
+    }
+
+    fn into_async(mut self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        self.stream()?;
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for TcpTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream
+            .as_ref()
+            .expect("TCP transport not connected")
+            .as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn tcp_transport_connects_when_cloned() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = TcpTransport::new(listener.local_addr().unwrap().to_string());
+        let mut connected = transport.try_clone().unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        connected.write_all(&[0x20, 0x00, 0x00, 0x00]).unwrap();
+        let mut buffer = [0; 4];
+        peer.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            connected.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // The peer disconnecting is seen as the end of the stream.
+        drop(peer);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(connected.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn tcp_transport_reports_refused_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let err = TcpTransport::new(address).try_clone().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+
+use super::{AsyncFdTransport, AsyncTransport, Transport};
+
+/// Address of the socket exposing the UWBS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnixAddress {
+    /// Socket bound to a filesystem path.
+    Path(String),
+    /// Socket bound to a name in the abstract namespace.
+    Abstract(String),
+}
+
+/// Transport for a UWBS exposed by a daemon over a unix stream socket.
+/// Packets are exchanged as a byte stream, without any additional
+/// framing.
+///
+/// As with [`super::UartTransport`], the connection is only established
+/// when the transport is cloned, so that every open of the chip gets a
+/// fresh connection.
+pub struct UnixTransport {
+    address: UnixAddress,
+    stream: Option<UnixStream>,
+}
+
+impl UnixTransport {
+    pub fn new(address: UnixAddress) -> Self {
+        Self {
+            address,
+            stream: None,
+        }
+    }
+
+    fn stream(&mut self) -> io::Result<&mut UnixStream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "device not connected"))
+    }
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        let stream = match self.address {
+            UnixAddress::Path(ref path) => UnixStream::connect(path)?,
+            UnixAddress::Abstract(ref name) => {
+                UnixStream::connect_addr(&SocketAddr::from_abstract_name(name)?)?
+            }
+        };
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    }
+}
+
+impl fmt::Display for UnixTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address {
+            UnixAddress::Path(ref path) => write!(f, "unix://{}", path),
+            UnixAddress::Abstract(ref name) => write!(f, "unix-abstract://{}", name),
+        }
+    }
+}
+
+impl Transport for UnixTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream()?.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream()?.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.stream()?.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let stream = match self.stream {
+            Some(ref stream) => stream.try_clone()?,
+            None => self.connect()?,
+        };
+        Ok(Box::new(Self {
+            address: self.address.clone(),
+            stream: Some(stream),
+        }))
+    }
+
+    fn into_async(mut self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        self.stream()?;
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for UnixTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream
+            .as_ref()
+            .expect("unix transport not connected")
+            .as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn unix_transport_connects_to_abstract_socket() {
+        let name = format!("uwb-hal-test-{}", std::process::id());
+        let listener =
+            UnixListener::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        let transport = UnixTransport::new(UnixAddress::Abstract(name));
+        let mut connected = transport.try_clone().unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        connected.write_all(&[0x20, 0x00, 0x00, 0x00]).unwrap();
+        let mut buffer = [0; 4];
+        peer.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            connected.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        drop(peer);
+        assert_eq!(connected.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn unix_transport_reports_missing_socket() {
+        let transport = UnixTransport::new(UnixAddress::Path("/nonexistent/uwb.sock".to_owned()));
+        assert_eq!(
+            transport.try_clone().err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
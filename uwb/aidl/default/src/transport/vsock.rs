@@ -0,0 +1,171 @@
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+
+use super::{AsyncFdTransport, AsyncTransport, Transport};
+
+/// Transport for a UWBS device model reached over vsock, such as the one
+/// run on the host by cuttlefish. Packets are exchanged as a byte
+/// stream, without any additional framing.
+///
+/// As with [`super::UartTransport`], the connection is only established
+/// when the transport is cloned, so that every open of the chip gets a
+/// fresh connection.
+pub struct VsockTransport {
+    address: String,
+    socket: Option<File>,
+}
+
+impl VsockTransport {
+    /// Create a transport for the device model listening at `address`,
+    /// given as `cid:port`.
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            socket: None,
+        }
+    }
+
+    fn socket(&mut self) -> io::Result<&mut File> {
+        self.socket
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "device not connected"))
+    }
+
+    fn connect(&self) -> io::Result<File> {
+        let invalid_address = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid vsock address {:?}", self.address),
+            )
+        };
+        let (cid, port) = self.address.split_once(':').ok_or_else(invalid_address)?;
+        let cid = cid.parse().map_err(|_| invalid_address())?;
+        let port = port.parse().map_err(|_| invalid_address())?;
+
+        let socket = socket(
+            AddressFamily::Vsock,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        connect(socket.as_raw_fd(), &VsockAddr::new(cid, port))?;
+        // Only the connection is blocking.
+        let flags = OFlag::from_bits_truncate(fcntl(socket.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(
+            socket.as_raw_fd(),
+            FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
+        )?;
+        Ok(File::from(socket))
+    }
+}
+
+impl fmt::Display for VsockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "vsock://{}", self.address)
+    }
+}
+
+impl Transport for VsockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket()?.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket()?.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.socket()?.write_all(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        let socket = match self.socket {
+            Some(ref socket) => socket.try_clone()?,
+            None => self.connect()?,
+        };
+        Ok(Box::new(Self {
+            address: self.address.clone(),
+            socket: Some(socket),
+        }))
+    }
+
+    fn into_async(mut self: Box<Self>) -> io::Result<Box<dyn AsyncTransport>> {
+        self.socket()?;
+        Ok(Box::new(AsyncFdTransport::new(*self)?))
+    }
+}
+
+impl AsRawFd for VsockTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket
+            .as_ref()
+            .expect("vsock transport not connected")
+            .as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vsock_transport_rejects_invalid_address() {
+        for address in ["", "2", "host:5000", "2:port"] {
+            let transport = VsockTransport::new(address.to_owned());
+            assert_eq!(
+                transport.try_clone().err().unwrap().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    /// Exercise the transport over the loopback, on kernels supporting
+    /// VMADDR_CID_LOCAL. Run with `--ignored` once the `vsock_loopback`
+    /// module is loaded.
+    #[test]
+    #[ignore = "requires the vsock_loopback kernel module"]
+    fn vsock_transport_connects_over_loopback() {
+        use nix::sys::socket::{accept, bind, listen, Backlog};
+
+        const VMADDR_CID_LOCAL: u32 = 1;
+        const PORT: u32 = 5000;
+
+        let listener = socket(
+            AddressFamily::Vsock,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .unwrap();
+        bind(
+            listener.as_raw_fd(),
+            &VsockAddr::new(VMADDR_CID_LOCAL, PORT),
+        )
+        .unwrap();
+        listen(&listener, Backlog::new(1).unwrap()).unwrap();
+
+        let transport = VsockTransport::new(format!("{}:{}", VMADDR_CID_LOCAL, PORT));
+        let mut connected = transport.try_clone().unwrap();
+        // SAFETY: accept returns a new file descriptor owned by the caller.
+        let mut peer = unsafe {
+            <File as std::os::fd::FromRawFd>::from_raw_fd(accept(listener.as_raw_fd()).unwrap())
+        };
+
+        connected.write_all(&[0x20, 0x00, 0x00, 0x00]).unwrap();
+        let mut buffer = [0; 4];
+        peer.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            connected.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        drop(peer);
+        assert_eq!(connected.read(&mut buffer).unwrap(), 0);
+    }
+}
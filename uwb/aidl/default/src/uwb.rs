@@ -1,32 +1,14 @@
 use android_hardware_uwb::aidl::android::hardware::uwb::{IUwb, IUwbChip};
 use android_hardware_uwb::binder;
 use binder::{Result, Strong};
-use binder_tokio::TokioRuntime;
-use tokio::runtime::Handle as TokioHandle;
-
-use crate::uwb_chip;
 
 pub struct Uwb {
     chips: Vec<Strong<dyn IUwbChip::IUwbChip>>,
 }
 
 impl Uwb {
-    pub fn from_chips(
-        chips: impl IntoIterator<Item = uwb_chip::UwbChip>,
-        handle: TokioHandle,
-    ) -> Self {
-        Self {
-            chips: chips
-                .into_iter()
-                .map(|chip| {
-                    IUwbChip::BnUwbChip::new_async_binder(
-                        chip,
-                        TokioRuntime(handle.clone()),
-                        binder::BinderFeatures::default(),
-                    )
-                })
-                .collect(),
-        }
+    pub fn from_chips(chips: Vec<Strong<dyn IUwbChip::IUwbChip>>) -> Self {
+        Self { chips }
     }
 }
 
@@ -51,3 +33,220 @@ impl IUwb::IUwb for Uwb {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_manager::UwbChipManager;
+    use crate::config::ChipConfig;
+    use crate::uwb_chip::ReaderScheduling;
+    use android_hardware_uwb::aidl::android::hardware::uwb::{
+        IUwbClientCallback::{BnUwbClientCallback, IUwbClientCallback},
+        UwbEvent::UwbEvent,
+        UwbStatus::UwbStatus,
+    };
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+
+    const CORE_GET_DEVICE_INFO_CMD: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+    const CORE_GET_CAPS_INFO_CMD: [u8; 4] = [0x20, 0x03, 0x00, 0x00];
+    /// SESSION_INIT_CMD of session 0x04030201, of type ranging.
+    const SESSION_INIT_CMD: [u8; 9] = [0x21, 0x00, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x00];
+    /// Time allowed for a callback to be called.
+    const CALLBACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// The HAL service, serving emulated chips, as seen by its clients.
+    struct Service {
+        uwb: Strong<dyn IUwb::IUwb>,
+        // Serves the chips, which stop with it.
+        _rt: tokio::runtime::Runtime,
+    }
+
+    impl Service {
+        /// Serve the chips `names`, each emulating a UWBS, as done by the
+        /// service for its configuration.
+        fn start(names: &[&str]) -> Self {
+            let rt = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let mut manager = UwbChipManager::new();
+            for name in names {
+                let config = ChipConfig::parse(name.to_string(), "mock://").unwrap();
+                manager.register(crate::create_chip(config, ReaderScheduling::Default).unwrap());
+            }
+            let uwb = IUwb::BnUwb::new_binder(
+                Uwb::from_chips(manager.chip_binders(rt.handle())),
+                binder::BinderFeatures::default(),
+            );
+            Self { uwb, _rt: rt }
+        }
+
+        fn chip(&self, name: &str) -> Strong<dyn IUwbChip::IUwbChip> {
+            self.uwb.getChip(name).unwrap()
+        }
+    }
+
+    /// Client callback object, forwarding what the HAL reports.
+    struct Client {
+        events: Mutex<mpsc::Sender<(UwbEvent, UwbStatus)>>,
+        messages: Mutex<mpsc::Sender<Vec<u8>>>,
+    }
+
+    impl binder::Interface for Client {}
+
+    impl IUwbClientCallback for Client {
+        fn onUciMessage(&self, data: &[u8]) -> Result<()> {
+            let _ = self.messages.lock().unwrap().send(data.to_vec());
+            Ok(())
+        }
+
+        fn onHalEvent(&self, event: UwbEvent, status: UwbStatus) -> Result<()> {
+            let _ = self.events.lock().unwrap().send((event, status));
+            Ok(())
+        }
+    }
+
+    /// What the HAL reported to a [`Client`].
+    struct Reports {
+        events: mpsc::Receiver<(UwbEvent, UwbStatus)>,
+        messages: mpsc::Receiver<Vec<u8>>,
+    }
+
+    impl Reports {
+        fn expect_event(&self, event: UwbEvent, status: UwbStatus) {
+            match self.events.recv_timeout(CALLBACK_TIMEOUT) {
+                Ok(reported) => assert_eq!(reported, (event, status)),
+                Err(_) => panic!("{:?} not reported", event),
+            }
+        }
+
+        /// Wait for the next message, and check its header and status.
+        fn expect_message(&self, header: [u8; 2], status: u8) -> Vec<u8> {
+            let message = self
+                .messages
+                .recv_timeout(CALLBACK_TIMEOUT)
+                .unwrap_or_else(|_| panic!("no message {:02x?} received", header));
+            assert_eq!(message[..2], header, "unexpected message {:02x?}", message);
+            assert_eq!(
+                message.get(4),
+                Some(&status),
+                "unexpected status {:02x?}",
+                message
+            );
+            message
+        }
+    }
+
+    fn client() -> (Strong<dyn IUwbClientCallback>, Reports) {
+        let (events_sender, events) = mpsc::channel();
+        let (messages_sender, messages) = mpsc::channel();
+        let client = Client {
+            events: Mutex::new(events_sender),
+            messages: Mutex::new(messages_sender),
+        };
+        (
+            BnUwbClientCallback::new_binder(client, binder::BinderFeatures::default()),
+            Reports { events, messages },
+        )
+    }
+
+    fn assert_illegal_state<T: std::fmt::Debug>(result: Result<T>) {
+        assert_eq!(
+            result.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[test]
+    fn chips_are_listed_by_name() {
+        let service = Service::start(&["0", "1"]);
+        assert_eq!(service.uwb.getChips().unwrap(), vec!["0", "1"]);
+        assert_eq!(service.chip("1").getName().unwrap(), "1");
+        assert_eq!(
+            service.uwb.getChip("2").err().unwrap().exception_code(),
+            binder::ExceptionCode::ILLEGAL_ARGUMENT
+        );
+    }
+
+    #[test]
+    fn open_and_close_are_reported() {
+        let service = Service::start(&["0"]);
+        let chip = service.chip("0");
+        let (client, reports) = client();
+        chip.open(&client).unwrap();
+        reports.expect_event(UwbEvent::OPEN_CPLT, UwbStatus::OK);
+        chip.close().unwrap();
+        reports.expect_event(UwbEvent::CLOSE_CPLT, UwbStatus::OK);
+    }
+
+    #[test]
+    fn core_init_is_reported() {
+        let service = Service::start(&["0"]);
+        let chip = service.chip("0");
+        let (client, reports) = client();
+        chip.open(&client).unwrap();
+        reports.expect_event(UwbEvent::OPEN_CPLT, UwbStatus::OK);
+        chip.coreInit().unwrap();
+        reports.expect_event(UwbEvent::POST_INIT_CPLT, UwbStatus::OK);
+        // The device info read by coreInit is delivered to the client.
+        reports.expect_message([0x40, 0x02], 0x00);
+        chip.close().unwrap();
+    }
+
+    #[test]
+    fn uci_messages_are_answered() {
+        let service = Service::start(&["0"]);
+        let chip = service.chip("0");
+        let (client, reports) = client();
+        chip.open(&client).unwrap();
+        assert_eq!(
+            chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD).unwrap(),
+            CORE_GET_DEVICE_INFO_CMD.len() as i32
+        );
+        reports.expect_message([0x40, 0x02], 0x00);
+        chip.sendUciMessage(&CORE_GET_CAPS_INFO_CMD).unwrap();
+        reports.expect_message([0x40, 0x03], 0x00);
+        // Vendor command, which the emulated UWBS does not support.
+        chip.sendUciMessage(&[0x2e, 0x01, 0x00, 0x00]).unwrap();
+        reports.expect_message([0x4e, 0x01], 0x07);
+        chip.close().unwrap();
+    }
+
+    #[test]
+    fn sessions_are_initialized() {
+        let service = Service::start(&["0"]);
+        let chip = service.chip("0");
+        let (client, reports) = client();
+        chip.open(&client).unwrap();
+        chip.sendUciMessage(&SESSION_INIT_CMD).unwrap();
+        reports.expect_message([0x41, 0x00], 0x00);
+        // SESSION_STATUS_NTF reporting SESSION_STATE_INIT.
+        let notification = reports
+            .messages
+            .recv_timeout(CALLBACK_TIMEOUT)
+            .expect("no session status notification");
+        assert_eq!(notification[..2], [0x61, 0x02]);
+        chip.sessionInit(0x04030201).unwrap();
+        chip.close().unwrap();
+    }
+
+    #[test]
+    fn calls_require_an_opened_chip() {
+        let service = Service::start(&["0"]);
+        let chip = service.chip("0");
+        assert_illegal_state(chip.coreInit());
+        assert_illegal_state(chip.sessionInit(1));
+        assert_illegal_state(chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD));
+        assert_illegal_state(chip.close());
+
+        let (client, reports) = client();
+        chip.open(&client).unwrap();
+        reports.expect_event(UwbEvent::OPEN_CPLT, UwbStatus::OK);
+        chip.close().unwrap();
+        reports.expect_event(UwbEvent::CLOSE_CPLT, UwbStatus::OK);
+        assert_illegal_state(chip.coreInit());
+        assert_illegal_state(chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD));
+        assert_illegal_state(chip.close());
+    }
+}
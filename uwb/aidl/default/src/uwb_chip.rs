@@ -6,305 +6,7286 @@ use android_hardware_uwb::binder;
 use async_trait::async_trait;
 use binder::{DeathRecipient, IBinder, Result, Strong};
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::io::unix::AsyncFd;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncRead;
 use tokio::select;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot, watch, Mutex, MutexGuard, Notify};
 use tokio_util::sync::CancellationToken;
 
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Write};
-use std::os::unix::fs::OpenOptionsExt;
+use std::io::{self, Write};
 
 use pdl_runtime::Packet;
-use uwb_uci_packets::{DeviceResetCmdBuilder, ResetConfig, UciControlPacket, UciControlPacketHal};
+use uwb_uci_packets::{
+    CapTlvType, CoreNotificationChild, CoreResponseChild, DeviceResetCmdBuilder, DeviceState,
+    ResetConfig, StatusCode, UciControlPacket, UciControlPacketChild, UciControlPacketHal,
+    UciNotificationChild, UciResponseChild,
+};
+
+use crate::calibration::{self, OEM_GET_CALIBRATION_CMD};
+use crate::cmd_tracker::{ClientCommandTracker, CmdResponseTracker, DEFAULT_RESPONSE_WINDOW};
+use crate::error::HalError;
+use crate::firmware::{FirmwareConfig, ProgressLog, MAX_FW_CHUNK_SIZE, MAX_FW_IMAGE_SIZE};
+use crate::flap_guard::{FlapGuard, FlapGuardConfig};
+use crate::flow_control::{
+    data_credit_ntf, data_message_snd_session, data_transfer_no_credit_ntf, CreditTracker,
+    Submission,
+};
+use crate::fragmentation::{
+    check_header, check_packet, Defragmenter, Fragmenter, Segmenter, StreamSync,
+    DEFAULT_MAX_DATA_PAYLOAD_SIZE, DEFAULT_MAX_RESYNC_BYTES, DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE,
+    UCI_HEADER_SIZE,
+};
+use crate::hdlc::{self, Deframer};
+use crate::health::{
+    HealthCheckConfig, HealthStatus, CORE_GET_DEVICE_INFO_CMD, HEALTH_STATUS_TIMEOUT,
+};
+use crate::observer::{Direction, ObserverId, ObserverRegistry};
+use crate::packet_log::{PacketLog, PacketLogLevel};
+use crate::packet_reader::{read_exact, read_first_bytes, read_uci_packet, FirstBytes};
+use crate::pcapng::PcapngWriter;
+use crate::reconnect::{reconnect_delay, ClientLocator};
+use crate::report::{ChipReport, ChipState, DumpFormat, OpenedState};
+use crate::session::{
+    range_data_ntf, SessionPool, SessionPoolError, SessionStats, DEFAULT_MAX_SESSIONS,
+    SESSION_DEINIT_CMD_HEADER,
+};
+use crate::snoop::{SnoopLog, SnoopMode};
+use crate::stats::{Stats, StatsRecorder};
+use crate::trace::{PacketTrace, TraceEntry, DEFAULT_TRACE_CAPACITY};
+use crate::transport::{is_device_gone, AsyncTransport, Transport, TransportReader};
+
+/// Write half of an opened chip. It has its own lock so that writes
+/// waiting on the UWBS do not hold the state lock; close takes the
+/// transport out to fence off later writes.
+type Writer = Arc<FramedWriter>;
+
+/// Transport of a [`Writer`], along with the framing of the packets
+/// written to it.
+struct FramedWriter {
+    transport: Mutex<Option<Box<dyn AsyncTransport>>>,
+    framing: Framing,
+}
+
+impl FramedWriter {
+    async fn lock(&self) -> MutexGuard<'_, Option<Box<dyn AsyncTransport>>> {
+        self.transport.lock().await
+    }
+}
+
+/// Fragments of the packets sent by the client, queued for the writer
+/// task.
+type WriteQueue = mpsc::Sender<Vec<Vec<u8>>>;
+
+/// Resources of an opened chip.
+struct Session {
+    callbacks: Strong<dyn IUwbClientCallback>,
+    /// Task supervising the reader task.
+    handle: tokio::task::JoinHandle<()>,
+    reader: tokio::task::AbortHandle,
+    serial: Writer,
+    queue: WriteQueue,
+    /// Last write error of the writer task, reported by the next
+    /// sendUciMessage.
+    write_error: watch::Receiver<Option<io::Error>>,
+    credits: Arc<CreditTracker>,
+    death_recipient: DeathRecipient,
+    token: CancellationToken,
+    /// Identifiers of the UWB sessions initialized with sessionInit.
+    /// Also updated by the reader task, on SESSION_STATUS_NTF.
+    sessions: Arc<std::sync::Mutex<SessionPool>>,
+    opened_at: Instant,
+    packet_stats: Arc<StatsRecorder>,
+    /// Responses awaited by the HAL itself.
+    responses: Arc<CmdResponseTracker>,
+    progress: Arc<ReaderProgress>,
+    /// Reported by the UWBS on coreInit.
+    device_info: Option<DeviceInfo>,
+    /// Read from the UWBS by the first [`UwbChip::chip_info`].
+    chip_info: Arc<tokio::sync::OnceCell<ChipInfo>>,
+    removal: Arc<DeviceRemoval>,
+    /// Calls to open not yet matched by a call to close.
+    open_ref_count: u32,
+    /// Sent to the UWBS on coreInit, read from the file set with
+    /// [`UwbChip::with_calibration_commands`] on open.
+    calibration_commands: Vec<Vec<u8>>,
+    /// Held by coreInit, which does not hold the state lock.
+    initializing: Arc<Mutex<()>>,
+    /// Held by [`UwbChip::firmware_update`], which does not hold the state
+    /// lock either.
+    updating: Arc<Mutex<()>>,
+    /// The UWBS was put in low power by [`UwbChip::suspend`], and not woken
+    /// up since by [`UwbChip::resume`] or a message of the client.
+    suspended: bool,
+}
+
+/// Receives the vendor messages of the UWBS, see
+/// [`UwbChip::set_vendor_callback`].
+pub type VendorCallback = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// Group of the vendor-reserved control messages.
+const VENDOR_GID: u8 = 0x0e;
+
+/// Return whether `packet` is a control packet of the vendor group.
+fn is_vendor_message(packet: &[u8]) -> bool {
+    const MESSAGE_TYPE_MASK: u8 = 0b11100000;
+    const GROUP_ID_MASK: u8 = 0b00001111;
+    !packet.is_empty()
+        && packet[0] & MESSAGE_TYPE_MASK != 0
+        && packet[0] & GROUP_ID_MASK == VENDOR_GID
+}
+
+/// Versions reported by the UWBS in its CORE_GET_DEVICE_INFO_RSP.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct DeviceInfo {
+    uci_version: u16,
+    mac_version: u16,
+    phy_version: u16,
+    uci_test_version: u16,
+    vendor_info: Vec<u8>,
+}
+
+/// Format a version encoded as in CORE_GET_DEVICE_INFO_RSP: the major
+/// version in the first octet, the minor and maintenance versions in the
+/// high and low nibbles of the second.
+fn format_version(version: u16) -> String {
+    let [major, minor] = version.to_le_bytes();
+    format!("{}.{}.{}", major, minor >> 4, minor & 0x0f)
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "UCI {}, MAC {}, PHY {}, vendor info {:02x?}",
+            format_version(self.uci_version),
+            format_version(self.mac_version),
+            format_version(self.phy_version),
+            self.vendor_info
+        )
+    }
+}
+
+/// Identity of the UWBS, see [`UwbChip::chip_info`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChipInfo {
+    /// UCI, MAC and UCI test versions implemented by the firmware, e.g.
+    /// `UCI 2.0.0, MAC 1.3.0, test 1.1.0`.
+    pub firmware_version: String,
+    /// PHY version, e.g. `PHY 1.3.0`: UCI reports no other hardware
+    /// revision.
+    pub hardware_revision: String,
+    /// Vendor specific info of the UWBS, which vendors use to carry a
+    /// unique identifier of the chip.
+    pub chip_uid: Vec<u8>,
+}
+
+impl From<DeviceInfo> for ChipInfo {
+    fn from(info: DeviceInfo) -> Self {
+        Self {
+            firmware_version: format!(
+                "UCI {}, MAC {}, test {}",
+                format_version(info.uci_version),
+                format_version(info.mac_version),
+                format_version(info.uci_test_version)
+            ),
+            hardware_revision: format!("PHY {}", format_version(info.phy_version)),
+            chip_uid: info.vendor_info,
+        }
+    }
+}
+
+impl fmt::Display for ChipInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "firmware {}, hardware {}, uid {:02x?}",
+            self.firmware_version, self.hardware_revision, self.chip_uid
+        )
+    }
+}
 
 enum State {
     Closed,
-    Opened {
-        callbacks: Strong<dyn IUwbClientCallback>,
-        handle: tokio::task::JoinHandle<()>,
-        serial: File,
-        death_recipient: DeathRecipient,
-        token: CancellationToken,
-    },
+    /// The UWBS is being connected to by open, without holding the state
+    /// lock. Other opens are refused until the chip is opened, or left as
+    /// it was if open fails.
+    Opening,
+    Opened(Box<Session>),
+    /// The chip is being closed: the reader task is terminated and the
+    /// UWBS reset without holding the state lock, and all calls are
+    /// refused until the chip is closed.
+    Resetting,
+    /// The client died and the UWBS was reset: the chip is reopened
+    /// once the client is back. It can also be opened as when closed.
+    AwaitingClient,
 }
 
-pub struct UwbChip {
-    name: String,
-    path: String,
-    state: Arc<Mutex<State>>,
+/// Default time allowed for the UWBS to answer the DeviceResetCmd sent on close.
+pub const DEFAULT_CLOSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Default time allowed for the UWBS to answer the command sent by
+/// coreInit.
+pub const DEFAULT_CORE_INIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Default time allowed for writing a packet to the UWBS when its
+/// receive buffer is full.
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Default number of packets sent by the client waiting to be written.
+pub const DEFAULT_WRITE_QUEUE_CAPACITY: usize = 64;
+
+/// Number of packets listed by dump.
+const DUMP_RECENT_PACKETS: usize = 16;
+/// Time dump waits for the state lock, which close holds while resetting
+/// the UWBS.
+const DUMP_LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Time the RESET_N line is held low to reset the UWBS.
+const RESET_GPIO_PULSE: Duration = Duration::from_millis(1);
+/// Time allowed for the UWBS to boot once RESET_N is released.
+const RESET_GPIO_SETTLE: Duration = Duration::from_millis(50);
+
+/// Time allowed for the UWBS to enter or leave low power.
+const POWER_TRANSITION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// CORE_DEVICE_SUSPEND_CMD, which puts the UWBS in low power.
+const DEVICE_SUSPEND_CMD: [u8; 4] = [0x20, 0x06, 0x00, 0x00];
+/// CORE_GET_CAPS_INFO_CMD, sent by coreInit.
+const CORE_GET_CAPS_INFO_CMD: [u8; 4] = [0x20, 0x03, 0x00, 0x00];
+/// TEST_LOOPBACK_CMD without its payload, answered with the payload.
+const TEST_LOOPBACK_CMD: [u8; 4] = [0x2d, 0x00, 0x00, 0x00];
+/// UWB_FIRMWARE_DOWNLOAD_CMD without its payload, a chunk of the firmware
+/// image, in the proprietary group.
+const FIRMWARE_DOWNLOAD_CMD: [u8; 4] = [0x2f, 0x00, 0x00, 0x00];
+/// UWB_FIRMWARE_DOWNLOAD_COMPLETE_CMD, after which the UWBS boots the
+/// downloaded image and reports DEVICE_STATE_READY.
+const FIRMWARE_DOWNLOAD_COMPLETE_CMD: [u8; 4] = [0x2f, 0x01, 0x00, 0x00];
+/// Time allowed for the UWBS to acknowledge a chunk of firmware.
+const FIRMWARE_CHUNK_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time allowed for the UWBS to boot an updated firmware.
+const FIRMWARE_BOOT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Android UCI version assumed for a UWBS not reporting it.
+const DEFAULT_ANDROID_UCI_VERSION: i32 = 1;
+
+/// Interval between two checks of the presence of a hot-pluggable UWBS.
+const HOTPLUG_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Default delay before the first retry to connect to the UWBS on open,
+/// doubled on every retry.
+pub const DEFAULT_CONNECT_BACKOFF: Duration = Duration::from_millis(125);
+
+/// Maximum time the reader task waits for the remaining bytes of a packet
+/// once its first bytes have been received.
+const PACKET_READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Delimitation of the UCI packets read from the transport.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// The transport is a byte stream: packets are delimited using the
+    /// payload length of their header.
+    #[default]
+    ByteStream,
+    /// Every read returns exactly one complete packet, as with the
+    /// character devices of some kernel UCI drivers.
+    PacketPerRead,
+    /// Packets are wrapped in HDLC-like frames, see [`hdlc::encode`], as
+    /// with the UART transport of some UWBS. Frames with an invalid CRC
+    /// are dropped.
+    Hdlc,
+}
+
+/// Tuning of the UCI reader task.
+#[derive(Clone, Copy, Debug)]
+pub struct ReaderConfig {
+    /// Maximum number of packets processed back to back before the
+    /// reader task yields to the other tasks of the runtime.
+    pub yield_after_packets: usize,
+    /// Maximum duration of continuous packet processing before the
+    /// reader task yields to the other tasks of the runtime.
+    pub yield_after: Duration,
+    /// Maximum time close waits for the reader task to terminate before
+    /// aborting it.
+    pub stop_timeout: Duration,
+    /// A DEVICE_STATUS_NTF repeating the previous one within this window
+    /// is not forwarded to the client, see [`UwbChip::with_dedup_window`].
+    /// Disabled when zero.
+    pub dedup_window: Duration,
+    /// Maximum payload size of the data packets received. A header
+    /// advertising more is dropped without waiting for its payload.
+    pub max_data_payload: usize,
+    /// Maximum number of bytes skipped in a row to find a valid header
+    /// with [`Framing::ByteStream`], after which the reader task fails.
+    pub max_resync_bytes: usize,
+    /// Scheduling the reader task runs with, for dump.
+    pub scheduling: ReaderScheduling,
 }
 
-impl UwbChip {
-    pub fn new(name: String, path: String) -> Self {
+impl Default for ReaderConfig {
+    fn default() -> Self {
         Self {
-            name,
-            path,
-            state: Arc::new(Mutex::new(State::Closed)),
+            yield_after_packets: 32,
+            yield_after: Duration::from_millis(2),
+            stop_timeout: Duration::from_secs(1),
+            dedup_window: Duration::from_millis(100),
+            max_data_payload: DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE,
+            max_resync_bytes: DEFAULT_MAX_RESYNC_BYTES,
+            scheduling: ReaderScheduling::Default,
         }
     }
 }
 
-impl State {
-    /// Terminate the reader task.
-    async fn close(&mut self) -> Result<()> {
-        if let State::Opened {
-            ref mut token,
-            ref callbacks,
-            ref mut death_recipient,
-            ref mut handle,
-            ref mut serial,
-        } = *self
-        {
-            log::info!("waiting for task cancellation");
-            callbacks.as_binder().unlink_to_death(death_recipient)?;
-            token.cancel();
-            handle.await.unwrap();
-            let packet: UciControlPacket = DeviceResetCmdBuilder {
-                reset_config: ResetConfig::UwbsReset,
-            }
-            .build()
-            .into();
-            // DeviceResetCmd need to be send to reset the device to stop all running
-            // activities on UWBS.
-            let packet_vec: Vec<UciControlPacketHal> = packet.into();
-            for hal_packet in packet_vec.into_iter() {
-                serial
-                    .write(&hal_packet.encode_to_vec().unwrap())
-                    .map(|written| written as i32)
-                    .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
-            }
-            consume_device_reset_rsp_and_ntf(
-                &mut serial
-                    .try_clone()
-                    .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?,
-            );
-            log::info!("task successfully cancelled");
-            callbacks.onHalEvent(UwbEvent::CLOSE_CPLT, UwbStatus::OK)?;
-            *self = State::Closed;
+/// Scheduling of the threads running the reader task. The reader is a
+/// task of the runtime rather than a thread of its own, so it cannot be
+/// given a real-time policy: the nice value of the whole process is
+/// raised instead, see [`ReaderScheduling::apply`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReaderScheduling {
+    #[default]
+    Default,
+    /// The process runs with this nice value.
+    Nice(i32),
+    /// Setting the nice value failed with `errno`, usually for lack of
+    /// CAP_SYS_NICE, leaving the default scheduling.
+    Refused { nice: i32, errno: i32 },
+}
+
+impl ReaderScheduling {
+    /// Set the nice value of the calling thread, which the threads it
+    /// creates afterwards inherit: called before the runtime is created,
+    /// it applies to all the threads of the runtime.
+    pub fn apply(nice: i32) -> Self {
+        // SAFETY: setpriority only takes integers.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } < 0 {
+            let errno = io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or_default();
+            return Self::Refused { nice, errno };
         }
-        Ok(())
+        Self::Nice(nice)
     }
 }
 
-fn consume_device_reset_rsp_and_ntf(reader: &mut File) {
-    // Poll the DeviceResetRsp and DeviceStatusNtf before hal is closed to prevent
-    // the host from getting response and notifications from a 'powered down' UWBS.
-    // Do nothing when these packets are received.
-    const DEVICE_RESET_RSP: [u8; 5] = [64, 0, 0, 1, 0];
-    const DEVICE_STATUS_NTF: [u8; 5] = [96, 1, 0, 1, 1];
-    let mut buffer = vec![0; DEVICE_RESET_RSP.len() + DEVICE_STATUS_NTF.len()];
-    read_exact(reader, &mut buffer).unwrap();
+impl fmt::Display for ReaderScheduling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Default => f.write_str("default"),
+            Self::Nice(nice) => write!(f, "nice {}", nice),
+            Self::Refused { nice, errno } => write!(
+                f,
+                "default, nice {} refused: {}",
+                nice,
+                io::Error::from_raw_os_error(errno)
+            ),
+        }
+    }
+}
 
-    // Make sure received packets are the expected ones.
-    assert_eq!(&buffer[0..DEVICE_RESET_RSP.len()], &DEVICE_RESET_RSP);
-    assert_eq!(&buffer[DEVICE_RESET_RSP.len()..], &DEVICE_STATUS_NTF);
+/// Step of the packet processing the reader task is at, reported when it
+/// does not terminate in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReaderPhase {
+    /// Waiting for the first bytes of a packet.
+    Waiting,
+    /// Reading the remaining bytes of a packet.
+    Reading,
+    /// Handing a packet over to the observers and the client.
+    Delivering,
 }
 
-pub fn makeraw(file: File) -> io::Result<File> {
-    // Configure the file descriptor as raw fd.
-    use nix::sys::termios::*;
-    let mut attrs = tcgetattr(&file)?;
-    cfmakeraw(&mut attrs);
-    tcsetattr(&file, SetArg::TCSANOW, &attrs)?;
+/// Phase of the reader task, shared with close.
+#[derive(Default)]
+struct ReaderProgress(AtomicU8);
 
-    Ok(file)
-}
+impl ReaderProgress {
+    fn enter(&self, phase: ReaderPhase) {
+        self.0.store(phase as u8, Ordering::Relaxed);
+    }
 
-/// Wrapper around Read::read to handle EWOULDBLOCK.
-/// /!\ will actively wait for more data, make sure to call
-/// this method only when data is immediately expected.
-fn read_exact(file: &mut File, mut buf: &mut [u8]) -> io::Result<()> {
-    while buf.len() > 0 {
-        match file.read(buf) {
-            Ok(0) => panic!("unexpectedly reached end of file"),
-            Ok(read_len) => buf = &mut buf[read_len..],
-            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
-            Err(err) => return Err(err),
+    fn phase(&self) -> ReaderPhase {
+        match self.0.load(Ordering::Relaxed) {
+            0 => ReaderPhase::Waiting,
+            1 => ReaderPhase::Reading,
+            _ => ReaderPhase::Delivering,
         }
     }
-    Ok(())
 }
 
-impl binder::Interface for UwbChip {}
+/// How a session is torn down, on close or when the client dies.
+#[derive(Clone)]
+struct TeardownConfig {
+    monitor: bool,
+    framing: Framing,
+    /// Time allowed for the UWBS to answer the DeviceResetCmd.
+    reset_timeout: Duration,
+    /// Time allowed for the reader task to terminate.
+    stop_timeout: Duration,
+    /// Maximum payload size of the data packets read while waiting for
+    /// the reset.
+    max_data_payload: usize,
+    reset_gpio: Option<PathBuf>,
+}
 
-#[async_trait]
-impl IUwbChipAsyncServer for UwbChip {
-    async fn getName(&self) -> Result<String> {
-        Ok(self.name.clone())
-    }
+/// Traffic and lifecycle counters of a chip, kept across close and open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UwbChipStats {
+    /// Packets written to the UWBS, before fragmentation.
+    pub tx_packets: u64,
+    /// Packets read from the UWBS.
+    pub rx_packets: u64,
+    /// Bytes of the packets written to the UWBS, and read from it.
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    /// Packets which could not be written to the UWBS, or did not fit in
+    /// the write queue.
+    pub tx_errors: u64,
+    /// Packets dropped by the reader task, and reader task failures.
+    pub rx_errors: u64,
+    pub open_count: u64,
+    pub close_count: u64,
+    /// Attempts to reach the client after it died.
+    pub reconnect_attempts: u64,
+    /// Error of the last failed attempt to reach the client.
+    pub last_reconnect_error: Option<binder::StatusCode>,
+    /// Reader task failures, after which the chip was closed.
+    pub reader_failures: u64,
+    /// Cause of the last reader task failure.
+    pub last_reader_failure: Option<ReaderFailure>,
+    /// Firmware downloads completed on open, and failed attempts.
+    pub firmware_downloads: u64,
+    pub firmware_download_failures: u64,
+    /// Duration of the last firmware download completed.
+    pub last_firmware_download: Option<Duration>,
+    /// Reader task failures caused by the removal of the device, and the
+    /// errno of the last one.
+    pub device_removals: u64,
+    pub last_device_removal: Option<i32>,
+    /// Frames dropped by the reader task with [`Framing::Hdlc`], for an
+    /// invalid CRC or a truncated frame. Counted in `rx_errors` as well.
+    pub framing_errors: u64,
+    /// Data messages dropped from the queue of a session waiting for data
+    /// credits, to make room for newer ones.
+    pub data_messages_dropped: u64,
+    /// DEVICE_STATUS_NTF not forwarded to the client for repeating the
+    /// previous one.
+    pub rx_deduped: u64,
+    /// Bytes skipped by the reader task to find a valid header after the
+    /// byte stream got corrupted.
+    pub rx_skipped_bytes: u64,
+}
 
-    async fn open(&self, callbacks: &Strong<dyn IUwbClientCallback>) -> Result<()> {
-        log::debug!("open: {:?}", &self.path);
+/// Cause of a reader task failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReaderFailure {
+    /// Reading from the UWBS or delivering a packet to the client failed.
+    Io(io::ErrorKind),
+    /// The device of the UWBS is gone, see [`is_device_gone`].
+    DeviceRemoved,
+    Panicked,
+}
 
-        let mut state = self.state.lock().await;
+/// Removal of the device of an opened chip, noticed by the reader or the
+/// writer task.
+#[derive(Default)]
+struct DeviceRemoval {
+    token: CancellationToken,
+    errno: std::sync::OnceLock<i32>,
+}
 
-        if matches!(*state, State::Opened { .. }) {
-            log::error!("the state is already opened");
-            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+impl DeviceRemoval {
+    /// Record `err` if it means the device is gone, and return whether it
+    /// does.
+    fn report(&self, err: &io::Error) -> bool {
+        if !is_device_gone(err) {
+            return false;
         }
+        self.errno.get_or_init(|| err.raw_os_error().unwrap());
+        self.token.cancel();
+        true
+    }
 
-        let serial = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(false)
-            .custom_flags(libc::O_NONBLOCK)
-            .open(&self.path)
-            .and_then(makeraw)
-            .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
-
-        let state_death_recipient = self.state.clone();
-        let mut death_recipient = DeathRecipient::new(move || {
-            let mut state = state_death_recipient.blocking_lock();
-            log::info!("Uwb service has died");
-            if let State::Opened { ref mut token, .. } = *state {
-                token.cancel();
-                *state = State::Closed;
+    fn is_removed(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait for the removal, returning the error which revealed it.
+    async fn removed(&self) -> io::Error {
+        self.token.cancelled().await;
+        io::Error::from_raw_os_error(*self.errno.get().unwrap())
+    }
+}
+
+pub struct UwbChip<T: Transport> {
+    name: String,
+    transport: T,
+    state: Arc<Mutex<State>>,
+    observers: Arc<ObserverRegistry>,
+    reader_config: ReaderConfig,
+    forced_yields: Arc<AtomicU64>,
+    invalid_packets: Arc<AtomicU64>,
+    stats: Arc<std::sync::Mutex<UwbChipStats>>,
+    packet_stats: Arc<StatsRecorder>,
+    trace: Arc<PacketTrace>,
+    packet_log: Arc<PacketLog>,
+    monitor: bool,
+    flap_guard: Arc<std::sync::Mutex<FlapGuard>>,
+    close_timeout: Duration,
+    core_init_timeout: Duration,
+    write_timeout: Duration,
+    write_queue_capacity: usize,
+    vendor_callback: Arc<std::sync::Mutex<Option<VendorCallback>>>,
+    /// Maximum number of concurrent sessions, reported by the UWBS.
+    max_sessions: AtomicUsize,
+    /// Maximum payload size of the data packets written to the UWBS,
+    /// reported by the UWBS.
+    max_data_payload_size: AtomicUsize,
+    /// Android UCI version advertised to the client, configured or queried
+    /// once from the UWBS.
+    android_uci_version: tokio::sync::OnceCell<i32>,
+    connect_retries: u32,
+    connect_backoff: Duration,
+    /// Cancelled by close to abandon an open still retrying to connect.
+    opening: std::sync::Mutex<Option<CancellationToken>>,
+    watchdog_timeout: Option<Duration>,
+    health_check: Option<HealthCheckConfig>,
+    firmware: Option<FirmwareConfig>,
+    calibration_commands: Option<PathBuf>,
+    /// Commands of the client awaiting their response, and their round
+    /// trips.
+    commands: Arc<ClientCommandTracker>,
+    response_window: Duration,
+    reconnect: Option<Arc<dyn ClientLocator>>,
+    /// Notified when the client died and the chip awaits its return.
+    client_lost: Arc<Notify>,
+    /// Device node of a hot-pluggable UWBS.
+    hotplug: Option<PathBuf>,
+    /// Cleared while the device node of a hot-pluggable UWBS is missing.
+    present: Arc<AtomicBool>,
+    /// Value file of the sysfs GPIO driving the RESET_N line of the UWBS.
+    reset_gpio: Option<PathBuf>,
+    snoop: Option<Arc<SnoopLog>>,
+    fragmenter: Fragmenter,
+    framing: Framing,
+    reassembly: bool,
+    reset_on_open: bool,
+}
+
+impl<T: Transport + 'static> UwbChip<T> {
+    /// Create a chip for the UWBS reached through `transport`.
+    pub fn with_transport(name: String, transport: T) -> Self {
+        let observers = Arc::new(ObserverRegistry::default());
+        let packet_log = Arc::new(PacketLog::default());
+        observers.register("log", {
+            let packet_log = packet_log.clone();
+            move |direction, _, packet| {
+                packet_log.record(direction, packet);
+                Ok(())
+            }
+        });
+        let trace = Arc::new(PacketTrace::new(DEFAULT_TRACE_CAPACITY));
+        observers.register("trace", {
+            let trace = trace.clone();
+            move |direction, timestamp, packet| {
+                trace.push(timestamp, direction, packet);
+                Ok(())
+            }
+        });
+        let packet_stats = Arc::new(StatsRecorder::default());
+        observers.register("stats", {
+            let packet_stats = packet_stats.clone();
+            move |direction, _, packet| {
+                packet_stats.record_packet(direction, packet);
+                Ok(())
             }
         });
+        Self {
+            name,
+            transport,
+            state: Arc::new(Mutex::new(State::Closed)),
+            observers,
+            reader_config: ReaderConfig::default(),
+            forced_yields: Arc::new(AtomicU64::new(0)),
+            invalid_packets: Arc::new(AtomicU64::new(0)),
+            stats: Arc::default(),
+            packet_stats,
+            trace,
+            packet_log,
+            monitor: false,
+            flap_guard: Arc::new(std::sync::Mutex::new(FlapGuard::new(
+                FlapGuardConfig::default(),
+            ))),
+            close_timeout: DEFAULT_CLOSE_TIMEOUT,
+            core_init_timeout: DEFAULT_CORE_INIT_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            write_queue_capacity: DEFAULT_WRITE_QUEUE_CAPACITY,
+            vendor_callback: Arc::default(),
+            max_sessions: AtomicUsize::new(DEFAULT_MAX_SESSIONS),
+            max_data_payload_size: AtomicUsize::new(DEFAULT_MAX_DATA_PAYLOAD_SIZE),
+            android_uci_version: tokio::sync::OnceCell::new(),
+            connect_retries: 0,
+            connect_backoff: DEFAULT_CONNECT_BACKOFF,
+            opening: std::sync::Mutex::default(),
+            watchdog_timeout: None,
+            health_check: None,
+            firmware: None,
+            calibration_commands: None,
+            commands: Arc::default(),
+            response_window: DEFAULT_RESPONSE_WINDOW,
+            reconnect: None,
+            client_lost: Arc::default(),
+            hotplug: None,
+            present: Arc::new(AtomicBool::new(true)),
+            reset_gpio: None,
+            snoop: None,
+            fragmenter: Fragmenter::default(),
+            framing: Framing::default(),
+            reassembly: false,
+            reset_on_open: false,
+        }
+    }
 
-        callbacks.as_binder().link_to_death(&mut death_recipient)?;
+    /// Bound the time spent waiting for the UWBS to answer the
+    /// DeviceResetCmd sent on close.
+    pub fn with_close_timeout(mut self, close_timeout: Duration) -> Self {
+        self.close_timeout = close_timeout;
+        self
+    }
 
-        let token = CancellationToken::new();
-        let cloned_token = token.clone();
+    /// Advertise `version` as the Android UCI version supported by the
+    /// UWBS, instead of querying it. Must be positive.
+    pub fn with_android_uci_version(mut self, version: Option<i32>) -> Self {
+        assert!(version.is_none_or(|version| version > 0));
+        self.android_uci_version = tokio::sync::OnceCell::new_with(version);
+        self
+    }
 
-        let client_callbacks = callbacks.clone();
+    /// Bound the time spent waiting for the UWBS to answer the command
+    /// sent by coreInit.
+    pub fn with_core_init_timeout(mut self, core_init_timeout: Duration) -> Self {
+        self.core_init_timeout = core_init_timeout;
+        self
+    }
 
-        let reader = serial
-            .try_clone()
-            .map_err(|_| binder::StatusCode::UNKNOWN_ERROR)?;
+    /// Bound the time close waits for the reader task to terminate, after
+    /// which the task is aborted.
+    pub fn with_reader_stop_timeout(mut self, stop_timeout: Duration) -> Self {
+        self.reader_config.stop_timeout = stop_timeout;
+        self
+    }
 
-        let join_handle = tokio::task::spawn(async move {
-            log::info!("UCI reader task started");
-            let mut reader = AsyncFd::new(reader).unwrap();
-
-            loop {
-                const MESSAGE_TYPE_MASK: u8 = 0b11100000;
-                const DATA_MESSAGE_TYPE: u8 = 0b000;
-                const UWB_HEADER_SIZE: usize = 4;
-                let mut buffer = vec![0; UWB_HEADER_SIZE];
-
-                // The only time where the task can be safely
-                // cancelled is when no packet bytes have been read.
-                //
-                // - read_exact() cannot be used here since it is not
-                //   cancellation safe.
-                // - read() cannot be used because it cannot be cancelled:
-                //   the syscall is executed blocking on the threadpool
-                //   and completes after termination of the task when
-                //   the pipe receives more data.
-                let read_len = loop {
-                    // On some platforms, the readiness detecting mechanism
-                    // relies on edge-triggered notifications. This means that
-                    // the OS will only notify Tokio when the file descriptor
-                    // transitions from not-ready to ready. For this to work
-                    // you should first try to read or write and only poll for
-                    // readiness if that fails with an error of
-                    // std::io::ErrorKind::WouldBlock.
-                    match reader.get_mut().read(&mut buffer) {
-                        Ok(0) => {
-                            log::error!("file unexpectedly closed");
-                            return;
-                        }
-                        Ok(read_len) => break read_len,
-                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
-                        Err(_) => panic!("unexpected read failure"),
-                    }
+    /// Drop the DEVICE_STATUS_NTF repeating the status of the previous one
+    /// within `dedup_window`, which some UWBS send several times when
+    /// changing state. Zero forwards all of them.
+    pub fn with_dedup_window(mut self, dedup_window: Duration) -> Self {
+        self.reader_config.dedup_window = dedup_window;
+        self
+    }
 
-                    let mut guard = select! {
-                        _ = cloned_token.cancelled() => {
-                            log::info!("task is cancelled!");
-                            return;
-                        },
-                        result = reader.readable() => result.unwrap()
-                    };
+    /// Set the maximum payload size of the data packets read from the
+    /// UWBS, up to the 65535 bytes a data packet header can advertise.
+    /// Larger packets are dropped as malformed.
+    pub fn with_max_rx_data_payload(mut self, max_data_payload: usize) -> Self {
+        self.reader_config.max_data_payload = max_data_payload.min(u16::MAX as usize);
+        self
+    }
 
-                    guard.clear_ready();
-                };
+    /// Set the maximum number of bytes skipped to find a valid header
+    /// after the byte stream got corrupted, after which the chip is closed
+    /// and the client notified of the error.
+    pub fn with_max_resync_bytes(mut self, max_resync_bytes: usize) -> Self {
+        self.reader_config.max_resync_bytes = max_resync_bytes;
+        self
+    }
 
-                // Read the remaining header bytes, if truncated.
-                read_exact(reader.get_mut(), &mut buffer[read_len..]).unwrap();
+    /// Record the scheduling applied to the threads of the runtime, which
+    /// the reader task runs on, see [`ReaderScheduling::apply`].
+    pub fn with_reader_scheduling(mut self, scheduling: ReaderScheduling) -> Self {
+        self.reader_config.scheduling = scheduling;
+        self
+    }
 
-                let common_header = buffer[0];
-                let mt = (common_header & MESSAGE_TYPE_MASK) >> 5;
-                let payload_length = if mt == DATA_MESSAGE_TYPE {
-                    let payload_length_fields: [u8; 2] = buffer[2..=3].try_into().unwrap();
-                    u16::from_le_bytes(payload_length_fields) as usize
-                } else {
-                    buffer[3] as usize
-                };
+    /// Set the maximum payload size of the control packets written to the
+    /// UWBS. Larger control packets are fragmented.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.fragmenter = Fragmenter::new(max_packet_size);
+        self
+    }
 
-                let length = payload_length + UWB_HEADER_SIZE;
-                buffer.resize(length, 0);
+    /// Enable the reassembly of the packets fragmented by the UWBS before
+    /// they are delivered to the client. Otherwise every fragment is
+    /// delivered as is.
+    pub fn with_reassembly(mut self, reassembly: bool) -> Self {
+        self.reassembly = reassembly;
+        self
+    }
 
-                // Read the payload bytes.
-                read_exact(reader.get_mut(), &mut buffer[UWB_HEADER_SIZE..]).unwrap();
+    /// Reset the UWBS with a DeviceResetCmd on open, before any packet is
+    /// delivered to the client, for the UWBS not to carry the sessions and
+    /// configuration left by a HAL which did not close it. Open fails with
+    /// `CommandTimeout` if the UWBS does not answer within the close
+    /// timeout.
+    pub fn with_reset_on_open(mut self, reset_on_open: bool) -> Self {
+        self.reset_on_open = reset_on_open;
+        self
+    }
 
-                log::debug!(" <-- {:?}", buffer);
-                client_callbacks.onUciMessage(&buffer).unwrap();
-            }
-        });
+    /// Set how packets are delimited on the transport.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
 
-        callbacks.onHalEvent(UwbEvent::OPEN_CPLT, UwbStatus::OK)?;
+    /// Set the number of packets kept in the trace. Zero disables it.
+    pub fn with_trace_capacity(self, capacity: usize) -> Self {
+        self.trace.set_capacity(capacity);
+        self
+    }
 
-        *state = State::Opened {
-            callbacks: callbacks.clone(),
-            handle: join_handle,
-            serial,
-            death_recipient,
-            token,
-        };
+    /// Retry connecting to the UWBS up to `retries` times when open fails
+    /// to reach it, for device models starting after the HAL and device
+    /// nodes created, or made accessible, after the HAL. Other errors, e.g.
+    /// the device being busy, are not retried.
+    pub fn with_connect_retries(mut self, retries: u32) -> Self {
+        self.connect_retries = retries;
+        self
+    }
+
+    /// Wait `backoff` before the first retry to connect to the UWBS,
+    /// doubling it on every retry.
+    pub fn with_connect_backoff(mut self, backoff: Duration) -> Self {
+        self.connect_backoff = backoff;
+        self
+    }
+
+    /// Report an error and close the chip when the UWBS stays silent for
+    /// `watchdog_timeout`. The watchdog is armed by the first packet
+    /// received after open, so that the UWBS startup is not mistaken for
+    /// a crash.
+    pub fn with_watchdog_timeout(mut self, watchdog_timeout: Option<Duration>) -> Self {
+        self.watchdog_timeout = watchdog_timeout;
+        self
+    }
+
+    /// Probe the UWBS when idle, see [`HealthCheckConfig`], and report an
+    /// error and close the chip when it stops answering. Unlike the
+    /// watchdog, this does not rely on the UWBS sending packets on its
+    /// own. Ignored in monitor mode, where the HAL does not write.
+    pub fn with_health_check(mut self, health_check: Option<HealthCheckConfig>) -> Self {
+        self.health_check = health_check;
+        self
+    }
+
+    /// Push a firmware image to the UWBS on open, before it is expected
+    /// to speak UCI. Ignored in monitor mode, where the HAL does not write.
+    pub fn with_firmware(mut self, firmware: Option<FirmwareConfig>) -> Self {
+        self.firmware = firmware;
+        self
+    }
+
+    /// Send the UCI commands listed in the file at `path` to the UWBS on
+    /// coreInit, see [`calibration::load_commands`], e.g. to set the
+    /// country code. The file is read on every open. Ignored in monitor
+    /// mode.
+    pub fn with_calibration_commands(mut self, path: Option<PathBuf>) -> Self {
+        self.calibration_commands = path;
+        self
+    }
+
+    /// Report the commands of the client left without response for
+    /// `window`. Only logged and counted, the client handling its own
+    /// timeouts.
+    pub fn with_response_window(mut self, window: Duration) -> Self {
+        assert!(!window.is_zero(), "empty response window");
+        self.response_window = window;
+        self
+    }
+
+    /// Reopen the chip for the client found by `locator` when the client
+    /// dies, see [`Self::reconnect_on_client_death`]. Otherwise the chip
+    /// is left closed.
+    pub fn with_reconnect(mut self, locator: Option<Arc<dyn ClientLocator>>) -> Self {
+        self.reconnect = locator;
+        self
+    }
+
+    /// Watch the device node at `path`, for a UWBS plugged after the HAL
+    /// started or unplugged while in use, see [`Self::watch_device`]. The
+    /// chip cannot be opened while the node is missing.
+    pub fn with_hotplug(mut self, path: Option<PathBuf>) -> Self {
+        self.present.store(path.is_none(), Ordering::Relaxed);
+        self.hotplug = path;
+        self
+    }
+
+    /// Reset the UWBS through the sysfs GPIO value file at `path`, driving
+    /// its RESET_N line, when it does not answer the DeviceResetCmd sent
+    /// on close.
+    pub fn with_reset_gpio(mut self, path: Option<PathBuf>) -> Self {
+        self.reset_gpio = path;
+        self
+    }
+
+    /// Record the packets in `snoop`, whose mode can be changed at runtime
+    /// with [`Self::set_snoop_mode`].
+    pub fn with_snoop_log(mut self, snoop: Option<SnoopLog>) -> Self {
+        self.snoop = snoop.map(Arc::new);
+        if let Some(ref snoop) = self.snoop {
+            self.observers.register("snoop", {
+                let snoop = snoop.clone();
+                move |direction, timestamp, packet| {
+                    snoop.record(timestamp, direction, packet);
+                    Ok(())
+                }
+            });
+        }
+        self
+    }
+
+    /// Bound the time spent waiting for the UWBS to accept a packet. A
+    /// packet of the client not written in time fails the next call to
+    /// sendUciMessage with [`HalError::WriteTimeout`].
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Set the number of packets sent by the client which can wait to be
+    /// written, past which sendUciMessage fails. At least one.
+    pub fn with_write_queue_capacity(mut self, capacity: usize) -> Self {
+        self.write_queue_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return a snapshot of the chip counters.
+    pub fn stats(&self) -> UwbChipStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Return a snapshot of the packet statistics, since the last open and
+    /// over the lifetime of the chip.
+    pub fn packet_stats(&self) -> Stats {
+        self.packet_stats.snapshot()
+    }
+
+    /// Deliver the vendor messages of the UWBS, control packets of GID
+    /// 0xE, to `callback` instead of the client. Without a callback, they
+    /// are delivered to the client as any other packet.
+    pub fn set_vendor_callback(&self, callback: Option<VendorCallback>) {
+        *self.vendor_callback.lock().unwrap() = callback;
+    }
 
+    /// Change what the snoop log records. Fails with
+    /// UNSUPPORTED_OPERATION if the chip has no snoop log.
+    pub fn set_snoop_mode(&self, mode: SnoopMode) -> Result<()> {
+        let snoop = self
+            .snoop
+            .as_ref()
+            .ok_or(binder::ExceptionCode::UNSUPPORTED_OPERATION)?;
+        log::info!("{}: snoop log mode {:?}", self.name, mode);
+        snoop.set_mode(mode);
         Ok(())
     }
 
-    async fn close(&self) -> Result<()> {
-        log::debug!("close");
+    /// Change how much of the UCI packets is logged. The payload of the
+    /// data packets is only logged at [`PacketLogLevel::Full`] with
+    /// `data_payloads` set.
+    pub fn set_packet_log_level(&self, level: PacketLogLevel, data_payloads: bool) {
+        log::info!(
+            "{}: packet log level {:?}, data payloads {}",
+            self.name,
+            level,
+            data_payloads
+        );
+        self.packet_log.set_level(level);
+        self.packet_log.set_data_payloads(data_payloads);
+    }
 
-        let mut state = self.state.lock().await;
+    /// Write the state of the chip and its last packets, for dumpsys. Only
+    /// the headers of the packets are written unless `verbose` is set.
+    pub fn dump(
+        &self,
+        writer: &mut dyn io::Write,
+        verbose: bool,
+        format: DumpFormat,
+    ) -> io::Result<()> {
+        self.report(verbose).write(writer, format)
+    }
 
-        if let State::Opened { .. } = *state {
-            state.close().await
-        } else {
-            Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+    /// Write the state and counters of the chip, without its last packets.
+    pub fn dump_stats(&self, writer: &mut dyn io::Write, format: DumpFormat) -> io::Result<()> {
+        let report = ChipReport {
+            last_packets: None,
+            ..self.report(false)
+        };
+        report.write(writer, format)
+    }
+
+    /// Collect the state of the chip, its counters and its last packets,
+    /// truncated to their headers unless `verbose` is set. Called from a
+    /// binder thread: the state lock is only waited for briefly, so that a
+    /// stuck chip can still be dumped.
+    pub fn report(&self, verbose: bool) -> ChipReport {
+        let state = match self.try_lock_state().as_deref() {
+            None => ChipState::Unknown,
+            Some(State::Opened(session)) if session.suspended => {
+                ChipState::Suspended(Self::opened_state(session))
+            }
+            Some(State::Opened(session)) => ChipState::Opened(Self::opened_state(session)),
+            Some(State::Closed) => ChipState::Closed,
+            Some(State::Opening) => ChipState::Opening,
+            Some(State::Resetting) => ChipState::Resetting,
+            Some(State::AwaitingClient) => ChipState::AwaitingClient,
+        };
+        let last_packets = self
+            .trace
+            .recent(DUMP_RECENT_PACKETS)
+            .into_iter()
+            .map(|mut entry| {
+                if !verbose {
+                    entry.packet.truncate(UCI_HEADER_SIZE);
+                }
+                entry
+            })
+            .collect();
+        ChipReport {
+            name: self.name.clone(),
+            transport: self.transport.to_string(),
+            state,
+            stats: self.stats(),
+            client_commands: self.commands.stats(),
+            hdlc: self.framing == Framing::Hdlc,
+            firmware: self.firmware.as_ref().map(|firmware| firmware.path.clone()),
+            invalid_packets: self.invalid_packets.load(Ordering::Relaxed),
+            forced_yields: self.forced_yields.load(Ordering::Relaxed),
+            reader_scheduling: self.reader_config.scheduling,
+            packet_log: self.packet_log.level(),
+            data_payloads: self.packet_log.data_payloads(),
+            snoop: self
+                .snoop
+                .as_ref()
+                .map(|snoop| (snoop.mode(), snoop.dropped())),
+            packet_stats: self.packet_stats(),
+            last_packets: Some(last_packets),
         }
     }
 
-    async fn coreInit(&self) -> Result<()> {
-        log::debug!("coreInit");
+    fn opened_state(session: &Session) -> OpenedState {
+        let sessions = session.sessions.lock().unwrap();
+        OpenedState {
+            elapsed: session.opened_at.elapsed(),
+            references: session.open_ref_count,
+            client_alive: session.callbacks.as_binder().is_binder_alive(),
+            device: session.device_info.as_ref().map(ToString::to_string),
+            session_capacity: sessions.capacity(),
+            sessions: sessions
+                .ids()
+                .into_iter()
+                .map(|id| (id, sessions.stats(id).unwrap()))
+                .collect(),
+            data_messages_awaiting_credits: session.credits.queued(),
+        }
+    }
 
-        if let State::Opened { ref callbacks, .. } = *self.state.lock().await {
-            callbacks.onHalEvent(UwbEvent::POST_INIT_CPLT, UwbStatus::OK)?;
-            Ok(())
-        } else {
-            Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+    /// Take the packets recorded in the trace, oldest first.
+    pub fn drain_trace(&self) -> Vec<TraceEntry> {
+        self.trace.drain()
+    }
+
+    /// Drain the trace into a pcapng capture written at `path`.
+    pub fn dump_pcapng(&self, path: &Path) -> io::Result<()> {
+        let mut pcapng = PcapngWriter::new(io::BufWriter::new(File::create(path)?))?;
+        for entry in self.drain_trace() {
+            pcapng.write_packet(entry.timestamp, entry.direction, &entry.packet)?;
+        }
+        pcapng.finish()?;
+        Ok(())
+    }
+
+    /// Reset the UWBS through `serial` and wait for it to be ready, dropping
+    /// the packets read meanwhile: the reader task is not started yet.
+    async fn reset_before_open(&self, serial: &dyn Transport) -> std::result::Result<(), HalError> {
+        let mut transport = serial
+            .try_clone()
+            .and_then(|serial| serial.into_async())
+            .map_err(|err| {
+                HalError::Transport(format!("failed to set up {}: {}", self.transport, err))
+            })?;
+        send_device_reset(
+            transport.as_mut(),
+            self.framing,
+            &self.observers,
+            self.close_timeout,
+            &self.packet_stats,
+        )
+        .await
+        .map_err(|err| HalError::Transport(format!("failed to send the reset: {}", err)))?;
+        consume_device_reset_rsp_and_ntf(
+            &mut TransportReader::new(transport),
+            self.framing,
+            self.reader_config.max_data_payload,
+            &self.observers,
+            self.close_timeout,
+        )
+        .await
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::TimedOut => HalError::CommandTimeout(self.close_timeout),
+            _ => HalError::Transport(format!("no device reset response: {}", err)),
+        })?;
+        log::info!("{}: UWBS reset on open", self.name);
+        Ok(())
+    }
+
+    /// Push the firmware image through `serial`, starting over up to the
+    /// configured number of retries.
+    async fn download_firmware(
+        &self,
+        firmware: &FirmwareConfig,
+        serial: &dyn Transport,
+    ) -> io::Result<()> {
+        let image = std::fs::read(&firmware.path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to read {}: {}", firmware.path.display(), err),
+            )
+        })?;
+        let mut attempt = 0;
+        loop {
+            log::info!(
+                "{}: downloading {} ({} bytes)",
+                self.name,
+                firmware.path.display(),
+                image.len()
+            );
+            let start = Instant::now();
+            let result = match serial.try_clone().and_then(|serial| serial.into_async()) {
+                Ok(mut transport) => {
+                    firmware
+                        .downloader
+                        .download(transport.as_mut(), &image)
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            let mut stats = self.stats.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    let elapsed = start.elapsed();
+                    log::info!("{}: firmware downloaded in {:?}", self.name, elapsed);
+                    stats.firmware_downloads += 1;
+                    stats.last_firmware_download = Some(elapsed);
+                    return Ok(());
+                }
+                Err(err) => {
+                    stats.firmware_download_failures += 1;
+                    if attempt >= firmware.retries {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    log::warn!(
+                        "{}: firmware download failed, retrying ({}/{}): {}",
+                        self.name,
+                        attempt,
+                        firmware.retries,
+                        err
+                    );
+                }
+            }
         }
     }
 
-    async fn sessionInit(&self, _id: i32) -> Result<()> {
-        log::debug!("sessionInit");
+    /// Save the calibration held in the RAM of the UWBS to `path`, read
+    /// with OEM_GET_CALIBRATION_CMD. Fails with `NotConnected` if the chip
+    /// is not opened.
+    pub async fn save_calibration(&self, path: &Path) -> io::Result<()> {
+        log::debug!("save_calibration {}", path.display());
 
+        let response = self.send_calibration_cmd(&OEM_GET_CALIBRATION_CMD).await?;
+        let data = calibration::calibration_data(&response)?;
+        calibration::save(path, data)?;
+        log::info!(
+            "{}: saved {} bytes of calibration to {}",
+            self.name,
+            data.len(),
+            path.display()
+        );
         Ok(())
     }
 
-    async fn getSupportedAndroidUciVersion(&self) -> Result<i32> {
-        Ok(1)
+    /// Write the calibration saved to `path` by [`Self::save_calibration`]
+    /// to the UWBS, with OEM_SET_CALIBRATION_CMD. Fails with `InvalidData`
+    /// if the file is corrupted, without writing anything to the UWBS.
+    pub async fn restore_calibration(&self, path: &Path) -> io::Result<()> {
+        log::debug!("restore_calibration {}", path.display());
+
+        let data = calibration::load(path)?;
+        let command = calibration::set_calibration_cmd(&data)?;
+        let response = self.send_calibration_cmd(&command).await?;
+        calibration::check_set_calibration_rsp(&response)?;
+        log::info!(
+            "{}: restored {} bytes of calibration from {}",
+            self.name,
+            data.len(),
+            path.display()
+        );
+        Ok(())
     }
 
-    async fn sendUciMessage(&self, data: &[u8]) -> Result<i32> {
-        log::debug!("sendUciMessage");
+    /// Send a calibration command of the HAL itself, and return its
+    /// response.
+    async fn send_calibration_cmd(&self, command: &[u8]) -> io::Result<Vec<u8>> {
+        if self.monitor {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "refusing to write in monitor mode",
+            ));
+        }
+        // The state lock is not held while waiting for the response, for
+        // the other calls to proceed.
+        let channel = match *self.state.lock().await {
+            State::Opened(ref session) => session.channel(),
+            _ => return Err(io::Error::new(io::ErrorKind::NotConnected, "not opened")),
+        };
+        channel
+            .send_and_wait(command, &self.observers, self.core_init_timeout)
+            .await
+            .map_err(|status| io::Error::other(status.to_string()))
+    }
 
-        if let State::Opened { ref mut serial, .. } = &mut *self.state.lock().await {
-            log::debug!(" --> {:?}", data);
-            let result = serial
-                .write_all(data)
-                .map(|_| data.len() as i32)
-                .map_err(|_| binder::StatusCode::UNKNOWN_ERROR.into());
-            log::debug!(" status: {:?}", result);
-            result
-        } else {
-            Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+    /// Send TEST_LOOPBACK_CMD carrying `payload`, and return the payload of
+    /// TEST_LOOPBACK_RSP, which the UWBS echoes back: a check of the
+    /// wiring to the UWBS. Fails with ILLEGAL_STATE if the chip is not
+    /// opened, with ILLEGAL_ARGUMENT if `payload` does not fit in a packet,
+    /// and with [`HalError::LoopbackMismatch`] if the payloads differ.
+    pub async fn loopback_test(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        log::debug!("loopback_test {:02x?}", payload);
+
+        let max_payload_size = self
+            .max_data_payload_size
+            .load(Ordering::Relaxed)
+            .min(u8::MAX as usize);
+        if payload.len() > max_payload_size {
+            return Err(binder::ExceptionCode::ILLEGAL_ARGUMENT.into());
+        }
+        if self.monitor {
+            return Err(HalError::Refused("refusing to write in monitor mode".to_owned()).into());
+        }
+        // The state lock is not held while waiting for the echo, for the
+        // other calls to proceed.
+        let channel = match *self.state.lock().await {
+            State::Opened(ref session) => session.channel(),
+            _ => return Err(binder::ExceptionCode::ILLEGAL_STATE.into()),
+        };
+        let mut command = TEST_LOOPBACK_CMD.to_vec();
+        command[3] = payload.len() as u8;
+        command.extend_from_slice(payload);
+        let response = channel
+            .send_and_wait(&command, &self.observers, self.core_init_timeout)
+            .await?;
+        let echoed = response.get(UCI_HEADER_SIZE..).unwrap_or_default();
+        if echoed != payload {
+            return Err(HalError::LoopbackMismatch {
+                sent: payload.len(),
+                received: echoed.len(),
+            }
+            .into());
+        }
+        Ok(echoed.to_vec())
+    }
+
+    /// Return the identity of the UWBS, read with CORE_GET_DEVICE_INFO_CMD
+    /// the first time it is asked for during a session. Fails with
+    /// ILLEGAL_STATE if the chip is not opened.
+    pub async fn chip_info(&self) -> Result<ChipInfo> {
+        if self.monitor {
+            return Err(HalError::Refused("refusing to write in monitor mode".to_owned()).into());
+        }
+        // The state lock is not held while the UWBS answers, for the other
+        // calls to proceed.
+        let (chip_info, channel) = match *self.state.lock().await {
+            State::Opened(ref session) => (session.chip_info.clone(), session.channel()),
+            _ => return Err(binder::ExceptionCode::ILLEGAL_STATE.into()),
+        };
+        let info = chip_info
+            .get_or_try_init(|| async {
+                let response = channel
+                    .send_and_wait(
+                        &CORE_GET_DEVICE_INFO_CMD,
+                        &self.observers,
+                        self.core_init_timeout,
+                    )
+                    .await?;
+                check_status(&response, "get device info")?;
+                let info = UciControlPacket::parse(&response)
+                    .ok()
+                    .and_then(|packet| device_info(&packet))
+                    .ok_or_else(|| {
+                        HalError::Transport(format!("malformed device info {:02x?}", response))
+                    })?;
+                log::info!("{}: {}", self.name, info);
+                Ok::<_, binder::Status>(ChipInfo::from(info))
+            })
+            .await?;
+        Ok(info.clone())
+    }
+
+    /// Flash `image` to an opened UWBS over UCI, in chunks of
+    /// [`MAX_FW_CHUNK_SIZE`] bytes, and wait for it to boot the image.
+    /// Fails with ILLEGAL_ARGUMENT if the image is empty or larger than
+    /// [`MAX_FW_IMAGE_SIZE`], and with ILLEGAL_STATE if the chip is not
+    /// opened. A UWBS failing the update is left in an unknown state: the
+    /// chip is then closed, failing with `FirmwareUpdateFailed`.
+    pub async fn firmware_update(&self, image: &[u8]) -> Result<()> {
+        log::debug!("firmware_update {} bytes", image.len());
+
+        if image.is_empty() || image.len() > MAX_FW_IMAGE_SIZE {
+            return Err(binder::ExceptionCode::ILLEGAL_ARGUMENT.into());
+        }
+        if self.monitor {
+            return Err(HalError::Refused("refusing to write in monitor mode".to_owned()).into());
+        }
+        // The state lock is not held during the download, for the other
+        // calls to proceed. Updates run one at a time.
+        let (updating, channel) = match *self.state.lock().await {
+            State::Opened(ref session) => (session.updating.clone(), session.channel()),
+            _ => return Err(binder::ExceptionCode::ILLEGAL_STATE.into()),
+        };
+        let _updating = updating.lock().await;
+        let Err(err) = self.download_firmware_update(&channel, image).await else {
+            log::info!("{}: firmware updated", self.name);
+            return Ok(());
+        };
+        log::error!("{}: firmware update failed: {}", self.name, err);
+        let mut state = self.state.lock().await;
+        // Unless the chip was closed in the meantime.
+        match *state {
+            State::Opened(ref session) if Arc::ptr_eq(&session.updating, &updating) => (),
+            _ => return Err(HalError::FirmwareUpdateFailed(err).into()),
+        }
+        let State::Opened(mut session) = std::mem::replace(&mut *state, State::Resetting) else {
+            unreachable!()
+        };
+        if let Err(err) = session
+            .callbacks
+            .as_binder()
+            .unlink_to_death(&mut session.death_recipient)
+        {
+            log::warn!("failed to unlink death recipient: {:?}", err);
+        }
+        drop(state);
+        if let Err(err) = session
+            .reset(&self.observers, &self.teardown_config(), true)
+            .await
+        {
+            log::warn!("failed to reset the UWBS: {:?}", err);
+        }
+        *self.state.lock().await = State::Closed;
+        self.stats.lock().unwrap().close_count += 1;
+        Err(HalError::FirmwareUpdateFailed(err).into())
+    }
+
+    async fn download_firmware_update(
+        &self,
+        channel: &CommandChannel,
+        image: &[u8],
+    ) -> std::result::Result<(), String> {
+        let count = image.len().div_ceil(MAX_FW_CHUNK_SIZE);
+        let mut progress = ProgressLog::new("firmware update", count);
+        for (index, chunk) in image.chunks(MAX_FW_CHUNK_SIZE).enumerate() {
+            let mut command = FIRMWARE_DOWNLOAD_CMD.to_vec();
+            command[3] = chunk.len() as u8;
+            command.extend_from_slice(chunk);
+            channel
+                .send_and_wait(&command, &self.observers, FIRMWARE_CHUNK_TIMEOUT)
+                .await
+                .and_then(|response| check_status(&response, "firmware download"))
+                .map_err(|status| format!("chunk {} of {}: {}", index + 1, count, status))?;
+            progress.record(index + 1);
+        }
+
+        // Registered before the command, so that the notification of a
+        // UWBS booting right away is not missed.
+        let (observer, ready) = self.watch_ready("firmware update");
+        let result = async {
+            channel
+                .send_and_wait(
+                    &FIRMWARE_DOWNLOAD_COMPLETE_CMD,
+                    &self.observers,
+                    FIRMWARE_CHUNK_TIMEOUT,
+                )
+                .await
+                .and_then(|response| check_status(&response, "firmware download complete"))
+                .map_err(|status| status.to_string())?;
+            match tokio::time::timeout(FIRMWARE_BOOT_TIMEOUT, ready).await {
+                Ok(Ok(())) => Ok(()),
+                _ => Err(format!("UWBS not ready after {:?}", FIRMWARE_BOOT_TIMEOUT)),
+            }
+        }
+        .await;
+        self.observers.unregister(observer);
+        result
+    }
+
+    /// Watch for the DEVICE_STATUS_NTF reporting DEVICE_STATE_READY: the
+    /// receiver completes on the first one received from now on. The
+    /// observer, registered as `name`, is to be unregistered once done.
+    fn watch_ready(&self, name: &'static str) -> (ObserverId, oneshot::Receiver<()>) {
+        let (ready_sender, ready) = oneshot::channel();
+        let ready_sender = std::sync::Mutex::new(Some(ready_sender));
+        let observer = self.observers.register(name, move |direction, _, packet| {
+            let state = UciControlPacket::parse(packet)
+                .ok()
+                .and_then(|packet| device_state(&packet));
+            if direction == Direction::Rx && state == Some(DeviceState::DeviceStateReady) {
+                if let Some(sender) = ready_sender.lock().unwrap().take() {
+                    let _ = sender.send(());
+                }
+            }
+            Ok(())
+        });
+        (observer, ready)
+    }
+
+    /// Return the ranging statistics of a session initialized with
+    /// sessionInit, counted from its RANGE_DATA_NTF. Fails with
+    /// ILLEGAL_ARGUMENT if the session is not initialized. Like dump, does
+    /// not wait for a state lock held for long.
+    pub fn session_stats(&self, id: i32) -> Result<SessionStats> {
+        let state = self.try_lock_state();
+        let Some(State::Opened(session)) = state.as_deref() else {
+            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+        };
+        let sessions = session.sessions.lock().unwrap();
+        let stats = sessions.stats(sessions.token(id));
+        stats.ok_or_else(|| binder::ExceptionCode::ILLEGAL_ARGUMENT.into())
+    }
+
+    /// Lock the state from a binder thread, giving up after
+    /// [`DUMP_LOCK_TIMEOUT`].
+    fn try_lock_state(&self) -> Option<tokio::sync::MutexGuard<'_, State>> {
+        let deadline = Instant::now() + DUMP_LOCK_TIMEOUT;
+        loop {
+            match self.state.try_lock() {
+                Ok(state) => return Some(state),
+                Err(_) if Instant::now() >= deadline => return None,
+                Err(_) => std::thread::sleep(Duration::from_millis(5)),
+            }
         }
     }
+
+    /// Reopen the chip whenever its client died, once the locator set
+    /// with [`Self::with_reconnect`] finds the client again. The locator
+    /// is polled with an exponential backoff. Runs until the process
+    /// exits, and returns immediately if reconnection is not enabled.
+    pub async fn reconnect_on_client_death(self: Arc<Self>) {
+        let Some(ref locator) = self.reconnect else {
+            return;
+        };
+        loop {
+            self.client_lost.notified().await;
+            let mut attempt = 0;
+            let callbacks = loop {
+                tokio::time::sleep(reconnect_delay(attempt)).await;
+                attempt = attempt.saturating_add(1);
+                if !matches!(*self.state.lock().await, State::AwaitingClient) {
+                    // Opened or closed by a new client in the meantime.
+                    break None;
+                }
+                let result = locator.locate();
+                let mut stats = self.stats.lock().unwrap();
+                stats.reconnect_attempts += 1;
+                match result {
+                    Ok(callbacks) => break Some(callbacks),
+                    Err(err) => {
+                        log::debug!("{}: client not reachable: {:?}", self.name, err);
+                        stats.last_reconnect_error = Some(err);
+                    }
+                }
+            };
+            let Some(callbacks) = callbacks else {
+                continue;
+            };
+            log::info!(
+                "{}: client is back after {} attempts, reopening",
+                self.name,
+                attempt
+            );
+            if let Err(err) = self.open(&callbacks).await {
+                log::error!("{}: failed to reopen for the client: {:?}", self.name, err);
+            }
+        }
+    }
+
+    pub fn hot_pluggable(&self) -> bool {
+        self.hotplug.is_some()
+    }
+
+    /// Track the device node set with [`Self::with_hotplug`], calling
+    /// `plugged` whenever it appears and tearing the chip down whenever
+    /// it disappears. Runs until the process exits, and returns
+    /// immediately if hot-plug is not enabled.
+    pub async fn watch_device(self: Arc<Self>, mut plugged: impl FnMut() + Send) {
+        let Some(ref path) = self.hotplug else {
+            return;
+        };
+        loop {
+            let present = path.exists();
+            if present != self.present.load(Ordering::Relaxed) {
+                if present {
+                    log::info!("{}: {} plugged", self.name, path.display());
+                    self.present.store(true, Ordering::Relaxed);
+                    plugged();
+                } else {
+                    log::info!("{}: {} unplugged", self.name, path.display());
+                    self.unplug().await;
+                }
+            }
+            tokio::time::sleep(HOTPLUG_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Connect to the UWBS, retrying as set by
+    /// [`Self::with_connect_retries`] until `cancel` is cancelled or the
+    /// client dies.
+    async fn connect(
+        &self,
+        callbacks: &Strong<dyn IUwbClientCallback>,
+        cancel: &CancellationToken,
+    ) -> Result<Box<dyn Transport>> {
+        let mut backoff = self.connect_backoff;
+        let mut attempts = 0;
+        loop {
+            let err = match self.transport.try_clone() {
+                Ok(serial) => return Ok(serial),
+                Err(err) => err,
+            };
+            if attempts == self.connect_retries || !is_not_ready(&err) {
+                log::error!("{}: failed to open {}: {}", self.name, self.transport, err);
+                return Err(open_error(&self.transport, err).into());
+            }
+            log::warn!(
+                "{}: failed to open {}, retrying in {:?} ({}/{}): {}",
+                self.name,
+                self.transport,
+                backoff,
+                attempts + 1,
+                self.connect_retries,
+                err
+            );
+            select! {
+                _ = cancel.cancelled() => {
+                    log::info!("{}: open abandoned by close", self.name);
+                    return Err(HalError::NotAvailable("open abandoned by close".to_owned()).into());
+                }
+                _ = tokio::time::sleep(backoff) => (),
+            }
+            if !callbacks.as_binder().is_binder_alive() {
+                log::info!("{}: open abandoned, the client died", self.name);
+                return Err(binder::StatusCode::DEAD_OBJECT.into());
+            }
+            attempts += 1;
+            backoff *= 2;
+        }
+    }
+
+    /// Connect to the UWBS, then download its firmware and reset it as
+    /// configured. Failures other than connecting are reported to the
+    /// client with an ERROR event.
+    async fn prepare_transport(
+        &self,
+        callbacks: &Strong<dyn IUwbClientCallback>,
+        cancel: &CancellationToken,
+    ) -> Result<Box<dyn Transport>> {
+        let serial = self.connect(callbacks, cancel).await?;
+
+        if let Some(firmware) = self.firmware.as_ref().filter(|_| !self.monitor) {
+            if let Err(err) = self.download_firmware(firmware, serial.as_ref()).await {
+                log::error!("{}: firmware download failed: {}", self.name, err);
+                if let Err(err) = callbacks.onHalEvent(UwbEvent::ERROR, UwbStatus::ERR_TRANSPORT) {
+                    log::warn!("failed to report the firmware download error: {:?}", err);
+                }
+                return Err(
+                    HalError::Transport(format!("firmware download failed: {}", err)).into(),
+                );
+            }
+        }
+
+        if self.reset_on_open && !self.monitor {
+            if let Err(err) = self.reset_before_open(serial.as_ref()).await {
+                log::error!("{}: device reset on open failed: {}", self.name, err);
+                let status = UwbStatus(err.code() as i32);
+                if let Err(err) = callbacks.onHalEvent(UwbEvent::ERROR, status) {
+                    log::warn!("failed to report the device reset error: {:?}", err);
+                }
+                return Err(err.into());
+            }
+        }
+        Ok(serial)
+    }
+
+    /// Tear the chip down after its device node disappeared: the reader
+    /// task is terminated and the client notified with an ERROR event.
+    /// The UWBS is gone, so it is not reset.
+    async fn unplug(&self) {
+        self.present.store(false, Ordering::Relaxed);
+        let mut state = self.state.lock().await;
+        if !matches!(*state, State::Opened(_)) {
+            return;
+        }
+        let State::Opened(mut session) = std::mem::replace(&mut *state, State::Closed) else {
+            unreachable!()
+        };
+        if let Err(err) = session
+            .callbacks
+            .as_binder()
+            .unlink_to_death(&mut session.death_recipient)
+        {
+            log::warn!("failed to unlink death recipient: {:?}", err);
+        }
+        session.token.cancel();
+        session.credits.close();
+        // The reader task is waiting for the state lock only if it
+        // failed, and then returns on cancellation.
+        if let Err(err) = session.handle.await {
+            log::warn!("UCI reader task failed: {}", err);
+        }
+        session.serial.lock().await.take();
+        if let Err(err) = session
+            .callbacks
+            .onHalEvent(UwbEvent::ERROR, UwbStatus::ERR_TRANSPORT)
+        {
+            log::warn!("failed to report the unplug: {:?}", err);
+        }
+        self.stats.lock().unwrap().close_count += 1;
+        self.packet_stats.record_close();
+        self.packet_stats.record_error();
+    }
+
+    fn death_handler(&self) -> ClientDeathHandler {
+        ClientDeathHandler {
+            state: self.state.clone(),
+            observers: self.observers.clone(),
+            stats: self.stats.clone(),
+            client_lost: self.reconnect.as_ref().map(|_| self.client_lost.clone()),
+            teardown: self.teardown_config(),
+            runtime: tokio::runtime::Handle::current(),
+        }
+    }
+
+    fn teardown_config(&self) -> TeardownConfig {
+        TeardownConfig {
+            monitor: self.monitor,
+            framing: self.framing,
+            reset_timeout: self.close_timeout,
+            stop_timeout: self.reader_config.stop_timeout,
+            max_data_payload: self.reader_config.max_data_payload,
+            reset_gpio: self.reset_gpio.clone(),
+        }
+    }
+
+    /// Learn the capabilities of the UWBS from CORE_GET_CAPS_INFO_CMD, sent
+    /// once by coreInit. The state lock is not held across the round trip.
+    async fn query_capabilities(&self, channel: &CommandChannel, initializing: &Arc<Mutex<()>>) {
+        let response = match channel
+            .send_and_wait(
+                &CORE_GET_CAPS_INFO_CMD,
+                &self.observers,
+                self.core_init_timeout,
+            )
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                log::warn!("{}: failed to query the capabilities: {:?}", self.name, err);
+                return;
+            }
+        };
+        let packet = UciControlPacket::parse(&response).ok();
+        let max_sessions = packet
+            .as_ref()
+            .and_then(|packet| capability(packet, CapTlvType::SupportedMaxRangingSessionNumber));
+        match max_sessions {
+            // No session could ever be initialized.
+            Some(0) => log::warn!("{}: ignoring a maximum of 0 sessions", self.name),
+            Some(max_sessions) => {
+                log::info!("{}: up to {} sessions", self.name, max_sessions);
+                self.max_sessions
+                    .store(max_sessions as usize, Ordering::Relaxed);
+                // Unless the chip was closed in the meantime.
+                if let State::Opened(ref session) = *self.state.lock().await {
+                    if Arc::ptr_eq(&session.initializing, initializing) {
+                        session
+                            .sessions
+                            .lock()
+                            .unwrap()
+                            .set_capacity(max_sessions as usize);
+                    }
+                }
+            }
+            None => (),
+        }
+        let max_data_payload_size = packet
+            .as_ref()
+            .and_then(|packet| capability(packet, CapTlvType::SupportedMaxDataPacketPayloadSize));
+        if let Some(max_data_payload_size) = max_data_payload_size {
+            log::info!(
+                "{}: data packets of up to {} bytes",
+                self.name,
+                max_data_payload_size
+            );
+            self.max_data_payload_size
+                .store(max_data_payload_size as usize, Ordering::Relaxed);
+        }
+        let version = packet
+            .as_ref()
+            .and_then(|packet| capability(packet, CapTlvType::SupportedAndroidUciProfiles))
+            .and_then(|version| i32::try_from(version).ok());
+        if let Some(version) = version {
+            // A configured version takes precedence.
+            let _ = self.android_uci_version.set(version);
+        }
+    }
+
+    /// Put the UWBS in low power, for the system to suspend. The UWBS is
+    /// woken up by [`Self::resume`] or by the next message of the client.
+    /// Fails with ILLEGAL_STATE if the chip is not opened.
+    pub async fn suspend(&self) -> Result<()> {
+        log::debug!("suspend");
+        if self.monitor {
+            return Err(binder::ExceptionCode::UNSUPPORTED_OPERATION.into());
+        }
+
+        // The state lock is not held while the UWBS answers.
+        let (initializing, channel) = {
+            let state = self.state.lock().await;
+            let State::Opened(ref session) = *state else {
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+            };
+            (session.initializing.clone(), session.channel())
+        };
+        let response = channel
+            .send_and_wait(
+                &DEVICE_SUSPEND_CMD,
+                &self.observers,
+                POWER_TRANSITION_TIMEOUT,
+            )
+            .await?;
+        let status = response.get(UCI_HEADER_SIZE).copied();
+        if status != Some(StatusCode::UciStatusOk.into()) {
+            log::error!(
+                "{}: device suspend failed with status {:02x?}",
+                self.name,
+                status
+            );
+            return Err(HalError::CommandFailed {
+                command: "device suspend",
+                status,
+            }
+            .into());
+        }
+        // Unless the chip was closed in the meantime.
+        if let State::Opened(ref mut session) = *self.state.lock().await {
+            if Arc::ptr_eq(&session.initializing, &initializing) {
+                session.suspended = true;
+            }
+        }
+        log::info!("{}: suspended", self.name);
+        Ok(())
+    }
+
+    /// Wake the UWBS up after the system resumed, and wait for it to report
+    /// DEVICE_STATE_READY. Fails with ILLEGAL_STATE if the chip is not
+    /// suspended, the chip then remaining suspended if the UWBS does not
+    /// wake up.
+    pub async fn resume(&self) -> Result<()> {
+        log::debug!("resume");
+
+        let (initializing, channel) = {
+            let state = self.state.lock().await;
+            let State::Opened(ref session) = *state else {
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+            };
+            if !session.suspended {
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+            }
+            (session.initializing.clone(), session.channel())
+        };
+        let (observer, ready) = self.watch_ready("resume");
+        let result = async {
+            // UCI has no command for leaving low power: any command wakes
+            // the UWBS up, and this one has no side effect.
+            channel
+                .send_and_wait(
+                    &CORE_GET_DEVICE_INFO_CMD,
+                    &self.observers,
+                    POWER_TRANSITION_TIMEOUT,
+                )
+                .await?;
+            match tokio::time::timeout(POWER_TRANSITION_TIMEOUT, ready).await {
+                Ok(Ok(())) => Ok::<_, binder::Status>(()),
+                _ => Err(HalError::CommandTimeout(POWER_TRANSITION_TIMEOUT).into()),
+            }
+        }
+        .await;
+        self.observers.unregister(observer);
+        result?;
+        if let State::Opened(ref mut session) = *self.state.lock().await {
+            if Arc::ptr_eq(&session.initializing, &initializing) {
+                session.suspended = false;
+            }
+        }
+        log::info!("{}: resumed", self.name);
+        Ok(())
+    }
+
+    /// Create a handle reporting the state of the chip from the panic hook.
+    pub fn crash_reporter(&self) -> CrashReporter {
+        CrashReporter {
+            name: self.name.clone(),
+            transport: self.transport.to_string(),
+            state: self.state.clone(),
+            forced_yields: self.forced_yields.clone(),
+            invalid_packets: self.invalid_packets.clone(),
+            stats: self.stats.clone(),
+            monitor: self.monitor,
+            flap_guard: self.flap_guard.clone(),
+        }
+    }
+
+    /// Enable monitor mode: the device is opened read-only and the chip
+    /// only decodes the traffic exchanged between a third party host and
+    /// the UWBS. All writes, including the reset on close, are refused.
+    pub fn with_monitor(mut self, monitor: bool) -> Self {
+        self.monitor = monitor;
+        self
+    }
+}
+
+/// Checks on demand that the UWBS of a chip answers, for an external
+/// watchdog: unlike the health check of [`UwbChip::with_health_check`], it
+/// only reports the problems.
+pub struct UwbHealthChecker<T: Transport>(Arc<UwbChip<T>>);
+
+impl<T: Transport + 'static> UwbHealthChecker<T> {
+    pub fn new(chip: Arc<UwbChip<T>>) -> Self {
+        Self(chip)
+    }
+
+    /// Probe the UWBS of the opened chip with CORE_GET_DEVICE_INFO_CMD,
+    /// which it answers in any state, within [`HEALTH_STATUS_TIMEOUT`].
+    /// The UWBS is healthy if the response has an OK status.
+    pub async fn check(&self) -> HealthStatus {
+        let chip = &self.0;
+        if chip.monitor {
+            return HealthStatus::NotApplicable;
+        }
+        let Ok(state) = tokio::time::timeout(HEALTH_STATUS_TIMEOUT, chip.state.lock()).await else {
+            return HealthStatus::Degraded(format!("chip busy for {:?}", HEALTH_STATUS_TIMEOUT));
+        };
+        // The state lock is released while the probe awaits its response.
+        let channel = match *state {
+            State::Opened(ref session) if session.suspended => {
+                return HealthStatus::Degraded("suspended".to_owned())
+            }
+            State::Opened(ref session) => session.channel(),
+            State::Closed => return HealthStatus::NotApplicable,
+            State::Opening => return HealthStatus::Degraded("opening".to_owned()),
+            State::Resetting => return HealthStatus::Degraded("resetting".to_owned()),
+            State::AwaitingClient => return HealthStatus::Degraded("awaiting client".to_owned()),
+        };
+        drop(state);
+        match channel
+            .send_and_wait(
+                &CORE_GET_DEVICE_INFO_CMD,
+                &chip.observers,
+                HEALTH_STATUS_TIMEOUT,
+            )
+            .await
+        {
+            Ok(response) => match response.get(UCI_HEADER_SIZE) {
+                Some(0x00) => HealthStatus::Ok,
+                status => {
+                    HealthStatus::Degraded(format!("probe failed with status {:02x?}", status))
+                }
+            },
+            Err(status) => HealthStatus::Degraded(status.to_string()),
+        }
+    }
+}
+
+/// Reports the state of a chip when the process panics.
+pub struct CrashReporter {
+    name: String,
+    transport: String,
+    state: Arc<Mutex<State>>,
+    forced_yields: Arc<AtomicU64>,
+    invalid_packets: Arc<AtomicU64>,
+    stats: Arc<std::sync::Mutex<UwbChipStats>>,
+    monitor: bool,
+    flap_guard: Arc<std::sync::Mutex<FlapGuard>>,
+}
+
+impl CrashReporter {
+    /// Write a one line summary of the chip state.
+    /// This never blocks: the panicking thread may be holding the state lock.
+    pub fn report(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let state = match self.state.try_lock() {
+            Ok(state) => match *state {
+                State::Closed => "closed",
+                State::Opening => "opening",
+                State::Opened(ref session) if session.suspended => "suspended",
+                State::Opened(_) => "opened",
+                State::Resetting => "resetting",
+                State::AwaitingClient => "awaiting_client",
+            },
+            Err(_) => "busy",
+        };
+        write!(
+            out,
+            "chip {} ({}): state={} monitor={} forced_yields={} invalid_packets={}",
+            self.name,
+            self.transport,
+            state,
+            self.monitor,
+            self.forced_yields.load(Ordering::Relaxed),
+            self.invalid_packets.load(Ordering::Relaxed)
+        )?;
+        if let Ok(stats) = self.stats.try_lock() {
+            write!(
+                out,
+                " tx_packets={} rx_packets={} tx_errors={} rx_errors={} opens={} closes={}",
+                stats.tx_packets,
+                stats.rx_packets,
+                stats.tx_errors,
+                stats.rx_errors,
+                stats.open_count,
+                stats.close_count
+            )?;
+        }
+        if let Ok(flap_guard) = self.flap_guard.try_lock() {
+            write!(
+                out,
+                " cycles={} rejected_opens={}",
+                flap_guard.cycles, flap_guard.rejected
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Resets the UWBS when the client of the chip dies.
+struct ClientDeathHandler {
+    state: Arc<Mutex<State>>,
+    observers: Arc<ObserverRegistry>,
+    stats: Arc<std::sync::Mutex<UwbChipStats>>,
+    /// Set when the chip is to be reopened for the next client.
+    client_lost: Option<Arc<Notify>>,
+    teardown: TeardownConfig,
+    runtime: tokio::runtime::Handle,
+}
+
+impl ClientDeathHandler {
+    /// Called from the binder thread delivering the death notification.
+    fn client_died(&self) {
+        let mut state = self.state.clone().blocking_lock_owned();
+        log::info!("Uwb service has died");
+        if !matches!(*state, State::Opened(_)) {
+            return;
+        }
+        let State::Opened(session) = std::mem::replace(&mut *state, State::Resetting) else {
+            unreachable!()
+        };
+        // Reset the UWBS for the next client, as on close. The state lock
+        // is held until then, so that a new client opening the chip right
+        // away waits for the reset rather than being refused.
+        let observers = self.observers.clone();
+        let stats = self.stats.clone();
+        let client_lost = self.client_lost.clone();
+        let teardown = self.teardown.clone();
+        self.runtime.spawn(async move {
+            if let Err(err) = session.reset(&observers, &teardown, false).await {
+                log::warn!("failed to reset the UWBS: {:?}", err);
+            }
+            stats.lock().unwrap().close_count += 1;
+            match client_lost {
+                Some(client_lost) => {
+                    log::info!("awaiting the client to reopen the chip");
+                    *state = State::AwaitingClient;
+                    client_lost.notify_one();
+                }
+                None => *state = State::Closed,
+            }
+        });
+    }
+}
+
+/// Sends the commands of the HAL itself, see [`Session::channel`].
+struct CommandChannel {
+    serial: Writer,
+    responses: Arc<CmdResponseTracker>,
+    packet_stats: Arc<StatsRecorder>,
+}
+
+impl CommandChannel {
+    /// See [`Session::send_and_wait`].
+    async fn send_and_wait(
+        &self,
+        command: &[u8],
+        observers: &ObserverRegistry,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let response = self.responses.register(command);
+        self.send_and_receive(command, response, observers, timeout)
+            .await
+    }
+
+    /// Like [`Session::send_and_wait`], but the response is delivered to the
+    /// client as well.
+    async fn send_and_forward(
+        &self,
+        command: &[u8],
+        observers: &ObserverRegistry,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let response = self.responses.register_forwarded(command);
+        self.send_and_receive(command, response, observers, timeout)
+            .await
+    }
+
+    async fn send_and_receive(
+        &self,
+        command: &[u8],
+        response: oneshot::Receiver<Vec<u8>>,
+        observers: &ObserverRegistry,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        send_and_receive(
+            &self.serial,
+            &self.responses,
+            &self.packet_stats,
+            command,
+            response,
+            observers,
+            timeout,
+        )
+        .await
+    }
+
+    /// Send the calibration commands in order, each once the previous one
+    /// is answered. Stops at the first command failing, which is only
+    /// logged: the UWBS remains usable without calibration.
+    async fn send_calibration_commands(
+        &self,
+        commands: &[Vec<u8>],
+        name: &str,
+        observers: &ObserverRegistry,
+        timeout: Duration,
+    ) {
+        let count = commands.len();
+        for (index, command) in commands.iter().enumerate() {
+            let error = match self.send_and_wait(command, observers, timeout).await {
+                Ok(response) if calibration::is_ok_rsp(&response) => continue,
+                Ok(response) => format!("status {:02x?}", response.get(UCI_HEADER_SIZE).copied()),
+                Err(err) => format!("{:?}", err),
+            };
+            log::error!(
+                "{}: calibration command {} of {} {:02x?} failed, skipping the rest: {}",
+                name,
+                index + 1,
+                count,
+                command,
+                error
+            );
+            return;
+        }
+        if count > 0 {
+            log::info!("{}: sent {} calibration commands", name, count);
+        }
+    }
+}
+
+impl Session {
+    /// Send a command of the HAL itself, and wait for its response, which
+    /// is not delivered to the client.
+    async fn send_and_wait(
+        &self,
+        command: &[u8],
+        observers: &ObserverRegistry,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        self.channel()
+            .send_and_wait(command, observers, timeout)
+            .await
+    }
+
+    /// Get what is needed to send commands of the HAL without holding the
+    /// state lock.
+    fn channel(&self) -> CommandChannel {
+        CommandChannel {
+            serial: self.serial.clone(),
+            responses: self.responses.clone(),
+            packet_stats: self.packet_stats.clone(),
+        }
+    }
+
+    /// Deinitialize the sessions left by the client with SESSION_DEINIT_CMD,
+    /// which some UWBS handle faster than a reset. Stops at the first
+    /// command left unanswered: the reset that follows stops the
+    /// remaining sessions anyway.
+    async fn deinit_sessions(&self, observers: &ObserverRegistry, timeout: Duration) {
+        let tokens = self.sessions.lock().unwrap().ids();
+        for token in tokens {
+            let mut command = SESSION_DEINIT_CMD_HEADER.to_vec();
+            command.extend_from_slice(&token.to_le_bytes());
+            match self.send_and_wait(&command, observers, timeout).await {
+                Ok(response) => {
+                    let status = response.get(UCI_HEADER_SIZE).copied();
+                    if status == Some(StatusCode::UciStatusOk.into()) {
+                        log::info!("session {:#x} deinitialized", token);
+                        self.sessions.lock().unwrap().remove(token);
+                    } else {
+                        log::warn!(
+                            "failed to deinitialize session {:#x}: {:02x?}",
+                            token,
+                            status
+                        );
+                    }
+                }
+                Err(err) => {
+                    log::warn!("failed to deinitialize session {:#x}: {:?}", token, err);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Terminate the reader task and reset the UWBS, through the reset
+    /// GPIO if it does not answer the DeviceResetCmd. The reader task is
+    /// aborted if it does not terminate in time. CLOSE_CPLT is only
+    /// reported if `notify_client` is set, the client being otherwise dead.
+    async fn reset(
+        self,
+        observers: &ObserverRegistry,
+        config: &TeardownConfig,
+        notify_client: bool,
+    ) -> Result<()> {
+        let TeardownConfig {
+            monitor,
+            framing,
+            reset_timeout: timeout,
+            stop_timeout,
+            max_data_payload,
+            ref reset_gpio,
+        } = *config;
+        // The device is gone: neither the sessions nor the UWBS can be
+        // reset.
+        let removed = self.removal.is_removed();
+        if !monitor && !removed {
+            self.deinit_sessions(observers, timeout).await;
+        }
+        let Session {
+            callbacks,
+            mut handle,
+            reader,
+            serial,
+            credits,
+            token,
+            sessions,
+            progress,
+            packet_stats,
+            ..
+        } = self;
+        let sessions = sessions.lock().unwrap().clone();
+        if !sessions.is_empty() {
+            log::warn!("closing with active sessions {}", sessions);
+        }
+        let close_complete = |status| match notify_client {
+            true => callbacks.onHalEvent(UwbEvent::CLOSE_CPLT, status),
+            false => Ok(()),
+        };
+        packet_stats.record_close();
+        log::info!("waiting for task cancellation");
+        token.cancel();
+        // Drop the data messages waiting for credits.
+        credits.close();
+        match tokio::time::timeout(stop_timeout, &mut handle).await {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => log::error!("UCI reader supervisor failed: {}", err),
+            // The task is stuck in a blocking read or client callback, and
+            // only stops once it yields. The chip is closed regardless.
+            Err(_) => {
+                log::error!(
+                    "UCI reader task still in phase {:?} after {:?}, aborting it",
+                    progress.phase(),
+                    stop_timeout
+                );
+                reader.abort();
+                handle.abort();
+            }
+        }
+        // Wait for the write in flight, if any, to complete and
+        // prevent any further write.
+        let Some(mut serial) = serial.lock().await.take() else {
+            close_complete(UwbStatus::FAILED)?;
+            return Err(HalError::Transport("transport already closed".to_owned()).into());
+        };
+        if removed {
+            log::info!("device removed, skipping device reset");
+            close_complete(UwbStatus::OK)?;
+            return Ok(());
+        }
+        if monitor {
+            log::info!("monitor mode, skipping device reset");
+            log::info!("task successfully cancelled");
+            close_complete(UwbStatus::OK)?;
+            return Ok(());
+        }
+        // DeviceResetCmd need to be send to reset the device to stop all running
+        // activities on UWBS.
+        if let Err(err) =
+            send_device_reset(serial.as_mut(), framing, observers, timeout, &packet_stats).await
+        {
+            log::error!("failed to send the device reset: {}", err);
+            close_complete(UwbStatus::FAILED)?;
+            return Err(HalError::Transport(format!("failed to send the reset: {}", err)).into());
+        }
+        let result = consume_device_reset_rsp_and_ntf(
+            &mut TransportReader::new(serial),
+            framing,
+            max_data_payload,
+            observers,
+            timeout,
+        )
+        .await;
+        match result {
+            Ok(()) => log::info!("UWBS reset by DeviceResetCmd"),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => match reset_gpio.as_deref() {
+                Some(path) => {
+                    log::warn!("no device reset response, resetting through GPIO: {}", err);
+                    if let Err(err) = toggle_reset_gpio(path).await {
+                        log::error!("failed to reset the UWBS through {:?}: {}", path, err);
+                    }
+                }
+                // The UWBS is wedged and will not answer: the reset has been
+                // requested, so let the close complete regardless.
+                None => log::warn!("no device reset response, closing anyway: {}", err),
+            },
+            Err(err) => {
+                log::error!("failed to receive the device reset response: {}", err);
+                close_complete(UwbStatus::FAILED)?;
+                return Err(
+                    HalError::Transport(format!("no device reset response: {}", err)).into(),
+                );
+            }
+        }
+        log::info!("task successfully cancelled");
+        close_complete(UwbStatus::OK)?;
+        Ok(())
+    }
+}
+
+/// Decode `packet`, a control packet or a data packet, to catch a packet
+/// that the UWBS would fail to parse. Enabled in debug builds, and in
+/// release builds with the `validate-uci` feature.
+#[cfg(any(debug_assertions, feature = "validate-uci"))]
+fn validate_packet(packet: &[u8]) -> std::result::Result<(), pdl_runtime::DecodeError> {
+    use uwb_uci_packets::UciDataPacket;
+    match packet.first().map(|byte| byte >> 5) {
+        Some(0) => UciDataPacket::parse(packet).map(|_| ()),
+        _ => UciControlPacket::parse(packet).map(|_| ()),
+    }
+}
+
+/// Return the status of a DEVICE_STATUS_NTF, or `None` for any other
+/// packet.
+fn device_status_ntf(packet: &[u8]) -> Option<u8> {
+    const DEVICE_STATUS_NTF_HEADER: [u8; 2] = [0x60, 0x01];
+    if packet.len() != UCI_HEADER_SIZE + 1
+        || packet[0] != DEVICE_STATUS_NTF_HEADER[0]
+        || packet[1] & 0x3f != DEVICE_STATUS_NTF_HEADER[1]
+    {
+        return None;
+    }
+    Some(packet[UCI_HEADER_SIZE])
+}
+
+/// Write a command of the HAL itself, whose response is awaited with
+/// `response`, and wait for it for up to `timeout`.
+async fn send_and_receive(
+    serial: &Writer,
+    responses: &CmdResponseTracker,
+    packet_stats: &StatsRecorder,
+    command: &[u8],
+    response: oneshot::Receiver<Vec<u8>>,
+    observers: &ObserverRegistry,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    {
+        let framing = serial.framing;
+        let mut serial = serial.lock().await;
+        let serial = serial
+            .as_mut()
+            .ok_or(binder::ExceptionCode::ILLEGAL_STATE)?;
+        observers.notify(Direction::Tx, command);
+        if let Err(err) = write_all(serial.as_mut(), framing, command, timeout, packet_stats).await
+        {
+            log::error!("failed to send {:02x?}: {}", command, err);
+            drop(response);
+            responses.cancel(command);
+            return Err(
+                HalError::Transport(format!("failed to send {:02x?}: {}", command, err)).into(),
+            );
+        }
+    }
+    match tokio::time::timeout(timeout, response).await {
+        Ok(Ok(packet)) => Ok(packet),
+        Ok(Err(_)) => Err(HalError::Transport("UCI reader task stopped".to_owned()).into()),
+        Err(_) => {
+            responses.cancel(command);
+            log::error!("no response to {:02x?} after {:?}", command, timeout);
+            Err(HalError::CommandTimeout(timeout).into())
+        }
+    }
+}
+
+/// Probes the UWBS of an opened chip, see [`UwbChip::with_health_check`].
+struct HealthCheck {
+    config: HealthCheckConfig,
+    serial: Writer,
+    responses: Arc<CmdResponseTracker>,
+    packet_stats: Arc<StatsRecorder>,
+    observers: Arc<ObserverRegistry>,
+    stats: Arc<std::sync::Mutex<UwbChipStats>>,
+    commands: Arc<ClientCommandTracker>,
+    token: CancellationToken,
+    /// Cancelled once the UWBS missed too many probes, failing the reader
+    /// task.
+    unresponsive: CancellationToken,
+}
+
+impl HealthCheck {
+    /// Probe the UWBS each time it stayed silent for the idle time, unless
+    /// a command of the client awaits its response, for at most as long
+    /// as the UWBS is given to answer a probe after the idle time. Any
+    /// packet received from the UWBS while waiting counts as an answer.
+    async fn run(self) {
+        let rx_packets = || self.stats.lock().unwrap().rx_packets;
+        let mut last_rx_packets = rx_packets();
+        let mut misses = 0;
+        loop {
+            select! {
+                _ = self.token.cancelled() => return,
+                _ = tokio::time::sleep(self.config.idle) => (),
+            }
+            if rx_packets() != last_rx_packets {
+                last_rx_packets = rx_packets();
+                misses = 0;
+                continue;
+            }
+            if self
+                .commands
+                .is_outstanding(self.config.idle + self.config.timeout)
+            {
+                continue;
+            }
+            let command = &self.config.command;
+            let response = self.responses.register(command);
+            let result = select! {
+                _ = self.token.cancelled() => return,
+                result = send_and_receive(
+                    &self.serial,
+                    &self.responses,
+                    &self.packet_stats,
+                    command,
+                    response,
+                    &self.observers,
+                    self.config.timeout,
+                ) => result,
+            };
+            if result.is_ok() || rx_packets() != last_rx_packets {
+                misses = 0;
+            } else {
+                misses += 1;
+                log::warn!(
+                    "UWBS missed {} of {} health checks",
+                    misses,
+                    self.config.max_misses
+                );
+                if misses >= self.config.max_misses {
+                    self.unresponsive.cancel();
+                    return;
+                }
+            }
+            last_rx_packets = rx_packets();
+        }
+    }
+}
+
+/// Wait until the watchdog `deadline`, or forever if it is not armed.
+async fn watchdog(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Size of the largest UCI packet accepted, with data packets of at most
+/// `max_data_payload` bytes of payload.
+fn max_packet_size(max_data_payload: usize) -> usize {
+    UCI_HEADER_SIZE + max_data_payload.max(u8::MAX as usize)
+}
+
+/// Return whether opening a UWBS failed with `err` because it is not
+/// ready yet: its device node or socket is yet to be created, or its
+/// permissions to be set by ueventd.
+fn is_not_ready(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::ConnectionRefused
+    )
+}
+
+/// Error returned by open when `transport` could not be opened.
+fn open_error(transport: &dyn fmt::Display, err: io::Error) -> HalError {
+    match err.kind() {
+        // The socket of an emulated or remote UWBS is not listening yet,
+        // or the device is unplugged: the client may retry later.
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            HalError::NotAvailable(format!("{} is not available: {}", transport, err))
+        }
+        _ if is_device_gone(&err) => {
+            HalError::NotAvailable(format!("{} is not available: {}", transport, err))
+        }
+        _ if err.raw_os_error() == Some(libc::EBUSY) => HalError::Transport(format!(
+            "{} is busy, held by another process: {}",
+            transport, err
+        )),
+        _ => HalError::Transport(format!("failed to open {}: {}", transport, err)),
+    }
+}
+
+/// Attempts at delivering a packet to the client failing with
+/// FAILED_TRANSACTION, e.g. with its binder buffer full, after which the
+/// packet is dropped.
+const DELIVERY_ATTEMPTS: u32 = 3;
+
+/// Delay between two attempts at delivering a packet to the client.
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Deliver `packet` to the client, retrying on transient failures.
+/// Returns whether the packet was delivered. Fails with
+/// `ConnectionAborted` if the client is dead, and `BrokenPipe` on other
+/// failures.
+async fn deliver_packet(
+    callbacks: &Strong<dyn IUwbClientCallback>,
+    packet: &[u8],
+) -> io::Result<bool> {
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        let Err(err) = callbacks.onUciMessage(packet) else {
+            return Ok(true);
+        };
+        match err.transaction_error() {
+            binder::StatusCode::DEAD_OBJECT => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "client died",
+                ))
+            }
+            binder::StatusCode::FAILED_TRANSACTION => {
+                log::warn!("failed to deliver a packet, attempt {}: {:?}", attempt, err);
+                if attempt < DELIVERY_ATTEMPTS {
+                    tokio::time::sleep(DELIVERY_RETRY_DELAY).await;
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    format!("failed to deliver a packet: {:?}", err),
+                ))
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Read a complete UCI packet, failing with `TimedOut` if it could not be
+/// received before `deadline`. With [`Framing::PacketPerRead`], fails with
+/// `InvalidData` if the packet read is inconsistent. With
+/// [`Framing::Hdlc`], the frames are read one byte at a time through
+/// `deframer`, failing with `InvalidData` for an invalid frame. Fails
+/// with `InvalidData` as well for a data packet larger than
+/// `max_data_payload`, whose payload is left unread.
+async fn read_packet<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    framing: Framing,
+    max_data_payload: usize,
+    deframer: &mut Deframer,
+    deadline: Instant,
+) -> io::Result<Vec<u8>> {
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    match framing {
+        Framing::ByteStream => {
+            let mut buffer = vec![0; UCI_HEADER_SIZE];
+            read_exact(reader, &mut buffer, remaining()).await?;
+            buffer.resize(
+                check_header(&buffer, max_data_payload)? + UCI_HEADER_SIZE,
+                0,
+            );
+            read_exact(reader, &mut buffer[UCI_HEADER_SIZE..], remaining()).await?;
+            Ok(buffer)
+        }
+        Framing::PacketPerRead => {
+            let mut buffer = vec![0; max_packet_size(max_data_payload)];
+            let FirstBytes { len, .. } =
+                tokio::time::timeout(remaining(), read_first_bytes(reader, &mut buffer))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no packet received"))??;
+            buffer.truncate(len);
+            check_packet(&buffer)?;
+            check_header(&buffer, max_data_payload)?;
+            Ok(buffer)
+        }
+        Framing::Hdlc => loop {
+            let mut byte = [0];
+            read_exact(reader, &mut byte, remaining()).await?;
+            if let Some(packet) = deframer.push(byte[0])? {
+                check_packet(&packet)?;
+                check_header(&packet, max_data_payload)?;
+                return Ok(packet);
+            }
+        },
+    }
+}
+
+/// Return the status of a DeviceResetRsp, or None for any other packet.
+fn device_reset_status(packet: &UciControlPacket) -> Option<StatusCode> {
+    let UciControlPacketChild::UciResponse(rsp) = packet.specialize() else {
+        return None;
+    };
+    let UciResponseChild::CoreResponse(rsp) = rsp.specialize() else {
+        return None;
+    };
+    let CoreResponseChild::DeviceResetRsp(rsp) = rsp.specialize() else {
+        return None;
+    };
+    Some(rsp.get_status())
+}
+
+/// Return the versions reported by a successful GetDeviceInfoRsp, or None
+/// for any other packet.
+fn device_info(packet: &UciControlPacket) -> Option<DeviceInfo> {
+    let UciControlPacketChild::UciResponse(rsp) = packet.specialize() else {
+        return None;
+    };
+    let UciResponseChild::CoreResponse(rsp) = rsp.specialize() else {
+        return None;
+    };
+    let CoreResponseChild::GetDeviceInfoRsp(rsp) = rsp.specialize() else {
+        return None;
+    };
+    if rsp.get_status() != StatusCode::UciStatusOk {
+        return None;
+    }
+    Some(DeviceInfo {
+        uci_version: rsp.get_uci_version(),
+        mac_version: rsp.get_mac_version(),
+        phy_version: rsp.get_phy_version(),
+        uci_test_version: rsp.get_uci_test_version(),
+        vendor_info: rsp.get_vendor_spec_info().to_vec(),
+    })
+}
+
+/// Return the Android UCI version implied by the UCI version of the
+/// UWBS, if known: UCI 2 comes with version 2 of the Android extensions.
+fn derived_android_uci_version(info: &DeviceInfo) -> Option<i32> {
+    let [major, _] = info.uci_version.to_le_bytes();
+    match major {
+        2 => Some(2),
+        _ => None,
+    }
+}
+
+/// Return the capability `tag` listed by a GetCapsInfoRsp, or None for any
+/// other packet or if the UWBS does not report it. The value is a
+/// little-endian integer.
+fn capability(packet: &UciControlPacket, tag: CapTlvType) -> Option<u32> {
+    let UciControlPacketChild::UciResponse(rsp) = packet.specialize() else {
+        return None;
+    };
+    let UciResponseChild::CoreResponse(rsp) = rsp.specialize() else {
+        return None;
+    };
+    let CoreResponseChild::GetCapsInfoRsp(rsp) = rsp.specialize() else {
+        return None;
+    };
+    if rsp.get_status() != StatusCode::UciStatusOk {
+        return None;
+    }
+    let tlv = rsp.get_tlvs().into_iter().find(|tlv| tlv.t == tag)?;
+    if tlv.v.is_empty() || tlv.v.len() > 4 {
+        return None;
+    }
+    let mut value = [0; 4];
+    value[..tlv.v.len()].copy_from_slice(&tlv.v);
+    Some(u32::from_le_bytes(value))
+}
+
+/// Fail with `CommandFailed` unless `response` has an OK status.
+fn check_status(response: &[u8], command: &'static str) -> Result<()> {
+    let status = response.get(UCI_HEADER_SIZE).copied();
+    if status != Some(StatusCode::UciStatusOk.into()) {
+        return Err(HalError::CommandFailed { command, status }.into());
+    }
+    Ok(())
+}
+
+/// Return the state reported by a DeviceStatusNtf, or None for any other packet.
+fn device_state(packet: &UciControlPacket) -> Option<DeviceState> {
+    let UciControlPacketChild::UciNotification(ntf) = packet.specialize() else {
+        return None;
+    };
+    let UciNotificationChild::CoreNotification(ntf) = ntf.specialize() else {
+        return None;
+    };
+    let CoreNotificationChild::DeviceStatusNtf(ntf) = ntf.specialize() else {
+        return None;
+    };
+    Some(ntf.get_device_state())
+}
+
+/// Write a DeviceResetCmd to the UWBS.
+async fn send_device_reset(
+    serial: &mut dyn AsyncTransport,
+    framing: Framing,
+    observers: &ObserverRegistry,
+    timeout: Duration,
+    packet_stats: &StatsRecorder,
+) -> io::Result<()> {
+    let packet: UciControlPacket = DeviceResetCmdBuilder {
+        reset_config: ResetConfig::UwbsReset,
+    }
+    .build()
+    .into();
+    let packet_vec: Vec<UciControlPacketHal> = packet.into();
+    for hal_packet in packet_vec.into_iter() {
+        let hal_packet = hal_packet.encode_to_vec().unwrap();
+        observers.notify(Direction::Tx, &hal_packet);
+        write_all(serial, framing, &hal_packet, timeout, packet_stats).await?;
+    }
+    Ok(())
+}
+
+async fn consume_device_reset_rsp_and_ntf<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    framing: Framing,
+    max_data_payload: usize,
+    observers: &ObserverRegistry,
+    timeout: Duration,
+) -> io::Result<()> {
+    // Poll the DeviceResetRsp and DeviceStatusNtf before hal is closed to prevent
+    // the host from getting response and notifications from a 'powered down' UWBS.
+    // Do nothing when these packets are received. Other packets, such as vendor
+    // notifications still in flight, are dropped.
+    let deadline = Instant::now() + timeout;
+    let mut rsp_received = false;
+    let mut ntf_received = false;
+    let mut deframer = Deframer::default();
+    while !(rsp_received && ntf_received) {
+        let buffer =
+            match read_packet(reader, framing, max_data_payload, &mut deframer, deadline).await {
+                Ok(buffer) => buffer,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    log::warn!("skipping packet: {}", err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+        observers.notify(Direction::Rx, &buffer);
+
+        let packet = match UciControlPacket::parse(&buffer) {
+            Ok(packet) => packet,
+            Err(err) => {
+                log::warn!("skipping malformed packet {:02x?}: {:?}", buffer, err);
+                continue;
+            }
+        };
+        if let Some(status) = device_reset_status(&packet) {
+            if status != StatusCode::UciStatusOk {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("device reset failed with status {:?}", status),
+                ));
+            }
+            rsp_received = true;
+        } else if device_state(&packet) == Some(DeviceState::DeviceStateReady) {
+            ntf_received = true;
+        } else {
+            log::debug!("skipping packet received during reset: {:02x?}", buffer);
+        }
+    }
+    Ok(())
+}
+
+/// Reset the UWBS by pulsing its RESET_N line low through the sysfs GPIO
+/// value file at `path`, and wait for it to boot.
+async fn toggle_reset_gpio(path: &Path) -> io::Result<()> {
+    let mut value = std::fs::OpenOptions::new().write(true).open(path)?;
+    value.write_all(b"0\n")?;
+    tokio::time::sleep(RESET_GPIO_PULSE).await;
+    value.write_all(b"1\n")?;
+    tokio::time::sleep(RESET_GPIO_SETTLE).await;
+    log::info!("UWBS reset through {:?}", path);
+    Ok(())
+}
+
+/// Write all of `buf`, framed as per `framing`, waiting for the transport
+/// to become writable when the UWBS is not accepting more bytes.
+/// Fails with `TimedOut` if `buf` could not be written within `timeout`.
+async fn write_all(
+    writer: &mut dyn AsyncTransport,
+    framing: Framing,
+    buf: &[u8],
+    timeout: Duration,
+    stats: &StatsRecorder,
+) -> io::Result<()> {
+    let frame;
+    let mut buf = match framing {
+        Framing::Hdlc => {
+            frame = hdlc::encode(buf);
+            &frame[..]
+        }
+        Framing::ByteStream | Framing::PacketPerRead => buf,
+    };
+    let len = buf.len();
+    let result = tokio::time::timeout(timeout, async {
+        while !buf.is_empty() {
+            match writer.get_mut().write(buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => buf = &buf[written..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    stats.record_write_would_block();
+                    writer.writable().await?
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    })
+    .await;
+    result.unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "{} of {} bytes written after {:?}",
+                len - buf.len(),
+                len,
+                timeout
+            ),
+        ))
+    })
+}
+
+/// Writer task, writing the packets queued by sendUciMessage.
+struct QueueWriter {
+    serial: Writer,
+    observers: Arc<ObserverRegistry>,
+    stats: Arc<std::sync::Mutex<UwbChipStats>>,
+    packet_stats: Arc<StatsRecorder>,
+    /// Commands written, awaiting their response.
+    commands: Arc<ClientCommandTracker>,
+    timeout: Duration,
+    write_error: watch::Sender<Option<io::Error>>,
+    removal: Arc<DeviceRemoval>,
+}
+
+impl QueueWriter {
+    /// Write the packets of `queue` until the chip is closed, which drops
+    /// the queue or takes the transport out. Write errors are published in
+    /// `write_error`, and the next packets are written regardless, unless
+    /// the device is gone: the reader task then closes the chip.
+    async fn run(self, mut queue: mpsc::Receiver<Vec<Vec<u8>>>) {
+        while let Some(fragments) = queue.recv().await {
+            let mut writer = self.serial.lock().await;
+            // The transport is taken out on close.
+            let Some(serial) = writer.as_mut() else {
+                return;
+            };
+            let mut result = Ok(());
+            let len: usize = fragments.iter().map(Vec::len).sum();
+            for fragment in &fragments {
+                self.observers.notify(Direction::Tx, fragment);
+                result = write_all(
+                    serial.as_mut(),
+                    self.serial.framing,
+                    fragment,
+                    self.timeout,
+                    &self.packet_stats,
+                )
+                .await;
+                if result.is_err() {
+                    break;
+                }
+                self.commands.sent(fragment);
+            }
+            // Counted before releasing the writer, for close to see the
+            // packet.
+            match result {
+                Ok(()) => {
+                    let mut stats = self.stats.lock().unwrap();
+                    stats.tx_packets += 1;
+                    stats.tx_bytes += len as u64;
+                }
+                Err(err) => {
+                    log::error!("failed to write a queued packet: {}", err);
+                    self.stats.lock().unwrap().tx_errors += 1;
+                    self.packet_stats.record_error();
+                    self.removal.report(&err);
+                    self.write_error.send_replace(Some(err));
+                }
+            }
+        }
+    }
+}
+
+/// Report the commands of the client left without response for `window`,
+/// until `token` is cancelled.
+async fn watch_responses(
+    commands: Arc<ClientCommandTracker>,
+    window: Duration,
+    token: CancellationToken,
+) {
+    loop {
+        select! {
+            _ = token.cancelled() => return,
+            _ = tokio::time::sleep(window / 2) => (),
+        }
+        for (header, elapsed) in commands.expire(window) {
+            log::warn!(
+                "lost response: gid={:#x} oid={:#x} header={:02x?} waited={:?}",
+                header[0] & 0x0f,
+                header[1] & 0x3f,
+                header,
+                elapsed
+            );
+        }
+    }
+}
+
+impl<T: Transport + 'static> binder::Interface for UwbChip<T> {}
+
+#[async_trait]
+impl<T: Transport + 'static> IUwbChipAsyncServer for UwbChip<T> {
+    async fn getName(&self) -> Result<String> {
+        Ok(self.name.clone())
+    }
+
+    async fn open(&self, callbacks: &Strong<dyn IUwbClientCallback>) -> Result<()> {
+        log::debug!("open: {}", self.transport);
+        if self.monitor {
+            log::info!(
+                "{}: opening in monitor mode, writes are disabled",
+                self.name
+            );
+        }
+
+        let mut state = self.state.lock().await;
+
+        // The framework may open the chip again while initializing, without
+        // closing it: the chip is only reopened once closed as many times.
+        // Only the client of the session may do so, as the packets and
+        // events keep going to its callbacks.
+        if let State::Opened(ref mut session) = *state {
+            if session.callbacks.as_binder() != callbacks.as_binder() {
+                log::error!("{}: already opened by another client", self.name);
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+            }
+            session.open_ref_count += 1;
+            log::info!(
+                "{}: already opened, {} references",
+                self.name,
+                session.open_ref_count
+            );
+            callbacks.onHalEvent(UwbEvent::OPEN_CPLT, UwbStatus::OK)?;
+            return Ok(());
+        }
+
+        if let State::Opening = *state {
+            log::error!("{}: already being opened", self.name);
+            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+        }
+
+        if !matches!(*state, State::Closed | State::AwaitingClient) {
+            log::error!("the state is already opened");
+            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+        }
+
+        if !self.present.load(Ordering::Relaxed) {
+            log::error!("{}: {} is not plugged", self.name, self.transport);
+            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+        }
+
+        if let Err(backoff) = self
+            .flap_guard
+            .lock()
+            .unwrap()
+            .check_open(tokio::time::Instant::now())
+        {
+            log::error!("{}: open rate limited, retry in {:?}", self.name, backoff);
+            return Err(
+                HalError::Refused(format!("open rate limited, retry in {:?}", backoff)).into(),
+            );
+        }
+
+        // Connecting to the UWBS may take long, with retries, a firmware
+        // download or a reset: the state lock is not held meanwhile, and
+        // concurrent opens are refused rather than left waiting for it.
+        let opening = CancellationToken::new();
+        *self.opening.lock().unwrap() = Some(opening.clone());
+        let previous = std::mem::replace(&mut *state, State::Opening);
+        drop(state);
+        let serial = self.prepare_transport(callbacks, &opening).await;
+        let mut state = self.state.lock().await;
+        // Left as it was unless the chip is opened.
+        *state = previous;
+        self.opening.lock().unwrap().take();
+        let serial = serial?;
+        if opening.is_cancelled() {
+            log::info!("{}: open abandoned by close", self.name);
+            return Err(HalError::NotAvailable("open abandoned by close".to_owned()).into());
+        }
+
+        // Read on every open, for the calibration to be updated without
+        // restarting the HAL. coreInit completes without it.
+        let calibration_commands = match self.calibration_commands.as_ref() {
+            Some(path) if !self.monitor => calibration::load_commands(path).unwrap_or_else(|err| {
+                log::error!("{}: no calibration commands: {}", self.name, err);
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        };
+
+        let death_handler = self.death_handler();
+        let mut death_recipient = DeathRecipient::new(move || death_handler.client_died());
+        let supervisor_death_handler = self.death_handler();
+
+        callbacks.as_binder().link_to_death(&mut death_recipient)?;
+
+        let token = CancellationToken::new();
+        let cloned_token = token.clone();
+
+        let client_callbacks = callbacks.clone();
+        let observers = self.observers.clone();
+        let reader_config = self.reader_config;
+        let framing = self.framing;
+        let watchdog_timeout = self.watchdog_timeout;
+        let forced_yields = self.forced_yields.clone();
+        let stats = self.stats.clone();
+        let rx_direction = if self.monitor {
+            Direction::Monitored
+        } else {
+            Direction::Rx
+        };
+
+        let into_async_error =
+            |err| HalError::Transport(format!("failed to set up {}: {}", self.transport, err));
+        let mut reader = serial
+            .try_clone()
+            .and_then(|reader| reader.into_async())
+            .map(TransportReader::new)
+            .map_err(into_async_error)?;
+        let serial = serial.into_async().map_err(into_async_error)?;
+        let serial = Arc::new(FramedWriter {
+            transport: Mutex::new(Some(serial)),
+            framing,
+        });
+        let (queue, queued_packets) = mpsc::channel(self.write_queue_capacity);
+        let (write_error_sender, write_error) = watch::channel(None);
+        let removal = Arc::new(DeviceRemoval::default());
+        let reader_removal = removal.clone();
+        let supervisor_removal = removal.clone();
+        // The responses to the commands of the previous session can no
+        // longer come.
+        self.commands.clear();
+        tokio::task::spawn(
+            QueueWriter {
+                serial: serial.clone(),
+                observers: self.observers.clone(),
+                stats: self.stats.clone(),
+                packet_stats: self.packet_stats.clone(),
+                commands: self.commands.clone(),
+                timeout: self.write_timeout,
+                write_error: write_error_sender,
+                removal: removal.clone(),
+            }
+            .run(queued_packets),
+        );
+        let credits = Arc::new(CreditTracker::default());
+        let reader_credits = credits.clone();
+        let reader_queue = queue.clone();
+        let responses = Arc::new(CmdResponseTracker::default());
+        let reader_responses = responses.clone();
+        let sessions = Arc::new(std::sync::Mutex::new(SessionPool::new(
+            self.max_sessions.load(Ordering::Relaxed),
+        )));
+        let reader_sessions = sessions.clone();
+        let vendor_callback = self.vendor_callback.clone();
+        let progress = Arc::new(ReaderProgress::default());
+        let reader_progress = progress.clone();
+        let reader_commands = self.commands.clone();
+        let response_watch = tokio::task::spawn(watch_responses(
+            self.commands.clone(),
+            self.response_window,
+            token.clone(),
+        ))
+        .abort_handle();
+        let unresponsive = CancellationToken::new();
+        let reader_unresponsive = unresponsive.clone();
+        let health_check = self
+            .health_check
+            .clone()
+            .filter(|_| !self.monitor)
+            .map(|config| {
+                tokio::task::spawn(
+                    HealthCheck {
+                        config,
+                        serial: serial.clone(),
+                        responses: responses.clone(),
+                        packet_stats: self.packet_stats.clone(),
+                        observers: self.observers.clone(),
+                        stats: self.stats.clone(),
+                        commands: self.commands.clone(),
+                        token: token.clone(),
+                        unresponsive,
+                    }
+                    .run(),
+                )
+                .abort_handle()
+            });
+
+        let reader_state = self.state.clone();
+        let reader_stats = self.stats.clone();
+        let reader_packet_stats = self.packet_stats.clone();
+        let supervisor_packet_stats = self.packet_stats.clone();
+        let reader_token = token.clone();
+
+        let mut defragmenter = self.reassembly.then(Defragmenter::default);
+
+        let reader_task = tokio::task::spawn(async move {
+            log::info!("UCI reader task started");
+            let result: io::Result<()> = async {
+                // When the UWBS streams packets continuously the reads below
+                // never return WouldBlock and the task would never yield,
+                // starving the binder handlers sharing the runtime worker.
+                let mut packets_since_yield = 0;
+                let mut last_yield = Instant::now();
+                // Deadline of the watchdog, armed by the first packet.
+                let mut watchdog_deadline = None;
+                // With HDLC framing, packets of the frames read but not
+                // processed yet.
+                let mut deframer = Deframer::default();
+                let mut deframed_packets: VecDeque<Vec<u8>> = VecDeque::new();
+                // Status of the last DEVICE_STATUS_NTF, and when it came.
+                let mut last_device_status: Option<(u8, Instant)> = None;
+                // Reused for every packet, sized for the largest one.
+                let buffer_size = max_packet_size(reader_config.max_data_payload);
+                let mut buffer = Vec::with_capacity(buffer_size);
+                let first_read_len = match framing {
+                    Framing::ByteStream => UCI_HEADER_SIZE,
+                    Framing::PacketPerRead | Framing::Hdlc => buffer_size,
+                };
+                // With a byte stream, finds the headers, and counts the
+                // header bytes kept at the start of the buffer while
+                // resynchronizing.
+                let mut stream_sync =
+                    StreamSync::new(reader_config.max_data_payload, reader_config.max_resync_bytes);
+                let mut carried = 0;
+
+                loop {
+                    reader_progress.enter(ReaderPhase::Waiting);
+                    buffer.resize(buffer_size, 0);
+
+                    // The only time where the task can be safely
+                    // cancelled is when no packet bytes have been read.
+                    let read_len = loop {
+                        if let Some(packet) = deframed_packets.pop_front() {
+                            buffer[..packet.len()].copy_from_slice(&packet);
+                            break packet.len();
+                        }
+
+                        // Bytes already received are read first.
+                        let first_bytes = select! {
+                            biased;
+                            result = read_first_bytes(
+                                &mut reader,
+                                &mut buffer[carried..first_read_len],
+                            ) => result?,
+                            _ = cloned_token.cancelled() => {
+                                log::info!("task is cancelled!");
+                                return Ok(());
+                            },
+                            err = reader_removal.removed() => return Err(err),
+                            _ = reader_unresponsive.cancelled() => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::TimedOut,
+                                    "UWBS not answering the health checks",
+                                ));
+                            },
+                            _ = watchdog(watchdog_deadline) => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::TimedOut,
+                                    format!("no packet received for {:?}", watchdog_timeout.unwrap()),
+                                ));
+                            },
+                        };
+                        if first_bytes.waited {
+                            packets_since_yield = 0;
+                            last_yield = Instant::now();
+                        }
+
+                        if framing == Framing::Hdlc {
+                            for &byte in &buffer[..first_bytes.len] {
+                                match deframer.push(byte) {
+                                    Ok(Some(packet)) => deframed_packets.push_back(packet),
+                                    Ok(None) => (),
+                                    Err(err) => {
+                                        log::warn!("dropping frame: {}", err);
+                                        let mut stats = stats.lock().unwrap();
+                                        stats.rx_errors += 1;
+                                        stats.framing_errors += 1;
+                                        reader_packet_stats.record_malformed_packet();
+                                    }
+                                }
+                            }
+                            continue;
+                        }
+                        break std::mem::take(&mut carried) + first_bytes.len;
+                    };
+
+                    reader_progress.enter(ReaderPhase::Reading);
+                    let packet_len = match framing {
+                        Framing::ByteStream => {
+                            buffer.truncate(read_len);
+                            let read_packet = read_uci_packet(
+                                &mut reader,
+                                &mut buffer,
+                                &mut stream_sync,
+                                PACKET_READ_TIMEOUT,
+                            );
+                            // The partial packet is discarded on close.
+                            let result = select! {
+                                _ = cloned_token.cancelled() => {
+                                    log::info!("task is cancelled!");
+                                    return Ok(());
+                                },
+                                result = read_packet => result?,
+                            };
+                            if result.is_none() {
+                                if stream_sync.skipped() == 1 {
+                                    log::warn!(
+                                        "invalid header {:02x?}, resynchronizing",
+                                        &buffer[..UCI_HEADER_SIZE]
+                                    );
+                                    stats.lock().unwrap().rx_errors += 1;
+                                    reader_packet_stats.record_malformed_packet();
+                                }
+                                // Check the header again one byte further.
+                                buffer.copy_within(1..UCI_HEADER_SIZE, 0);
+                                carried = UCI_HEADER_SIZE - 1;
+                                continue;
+                            }
+                            let skipped = stream_sync.take_skipped();
+                            if skipped > 0 {
+                                log::warn!("skipped {} bytes to resynchronize", skipped);
+                                stats.lock().unwrap().rx_skipped_bytes += skipped as u64;
+                            }
+                            buffer.len()
+                        }
+                        Framing::PacketPerRead | Framing::Hdlc => {
+                            let packet = &buffer[..read_len];
+                            let max_data_payload = reader_config.max_data_payload;
+                            let checked = check_packet(packet)
+                                .and_then(|()| check_header(packet, max_data_payload));
+                            if let Err(err) = checked {
+                                log::warn!("dropping packet: {}", err);
+                                stats.lock().unwrap().rx_errors += 1;
+                                reader_packet_stats.record_malformed_packet();
+                                continue;
+                            }
+                            read_len
+                        }
+                    };
+                    let buffer = &buffer[..packet_len];
+                    {
+                        let mut stats = stats.lock().unwrap();
+                        stats.rx_packets += 1;
+                        stats.rx_bytes += buffer.len() as u64;
+                    }
+                    watchdog_deadline =
+                        watchdog_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+                    reader_progress.enter(ReaderPhase::Delivering);
+                    observers.notify(rx_direction, buffer);
+                    let packet = match defragmenter {
+                        Some(ref mut defragmenter) => {
+                            defragmenter.push(buffer).unwrap_or_else(|err| {
+                                log::warn!("dropping packet: {}", err);
+                                stats.lock().unwrap().rx_errors += 1;
+                                reader_packet_stats.record_malformed_packet();
+                                None
+                            })
+                        }
+                        None => Some(Cow::Borrowed(buffer)),
+                    };
+                    if let Some(packet) = packet.filter(|packet| reader_responses.deliver(packet)) {
+                        if let Some((session_token, available)) = data_credit_ntf(&packet) {
+                            // Release the oldest data message queued for
+                            // the credit.
+                            let message = reader_credits.grant(session_token, available);
+                            if message.is_some_and(|message| reader_queue.try_send(message).is_err()) {
+                                log::error!(
+                                    "write queue full, dropping data message of session {:#x}",
+                                    session_token
+                                );
+                                stats.lock().unwrap().tx_errors += 1;
+                            }
+                        }
+                        if let Some(session_token) = data_transfer_no_credit_ntf(&packet) {
+                            reader_credits.grant(session_token, false);
+                        }
+                        if let Some(token) = reader_sessions.lock().unwrap().received(&packet) {
+                            reader_credits.remove(token as u32);
+                        }
+                        if let Some((id, statuses)) = range_data_ntf(&packet) {
+                            reader_sessions
+                                .lock()
+                                .unwrap()
+                                .record_ranging(id, &statuses);
+                        }
+                        reader_commands.received(&packet);
+                        let repeated_status = device_status_ntf(&packet)
+                            .filter(|_| !reader_config.dedup_window.is_zero())
+                            .is_some_and(|status| {
+                                let now = Instant::now();
+                                let previous = last_device_status.replace((status, now));
+                                previous.is_some_and(|(previous, received)| {
+                                    previous == status
+                                        && now - received < reader_config.dedup_window
+                                })
+                            });
+                        let vendor_callback = is_vendor_message(&packet)
+                            .then(|| vendor_callback.lock().unwrap().clone())
+                            .flatten();
+                        if repeated_status {
+                            log::debug!("dropping repeated device status {:02x?}", packet);
+                            stats.lock().unwrap().rx_deduped += 1;
+                        } else if let Some(vendor_callback) = vendor_callback {
+                            vendor_callback(&packet);
+                        } else {
+                            let delivered = deliver_packet(&client_callbacks, &packet).await;
+                            if !matches!(delivered, Ok(true)) {
+                                reader_packet_stats.record_delivery_failure();
+                            }
+                            if !delivered? {
+                                log::error!("dropping undelivered packet");
+                                stats.lock().unwrap().rx_errors += 1;
+                            }
+                        }
+                    }
+
+                    packets_since_yield += 1;
+                    if packets_since_yield >= reader_config.yield_after_packets
+                        || last_yield.elapsed() >= reader_config.yield_after
+                    {
+                        forced_yields.fetch_add(1, Ordering::Relaxed);
+                        tokio::task::yield_now().await;
+                        if cloned_token.is_cancelled() {
+                            log::info!("task is cancelled!");
+                            return Ok(());
+                        }
+                        packets_since_yield = 0;
+                        last_yield = Instant::now();
+                    }
+                }
+            }
+            .await;
+
+            for header in defragmenter.iter_mut().flat_map(Defragmenter::discard) {
+                log::error!("discarding incomplete packet {:02x?}", header);
+            }
+            result
+        });
+        let reader = reader_task.abort_handle();
+
+        // Close the chip when the reader task fails or panics, so that it
+        // can be reopened from scratch.
+        let join_handle = tokio::task::spawn(async move {
+            let result = reader_task.await;
+            response_watch.abort();
+            if let Some(health_check) = health_check {
+                health_check.abort();
+            }
+            let failure = match result {
+                Ok(Ok(())) => return,
+                // The client died before its death notification came: tear
+                // the session down as the notification would.
+                Ok(Err(err)) if err.kind() == io::ErrorKind::ConnectionAborted => {
+                    log::warn!("UCI reader task stopped: {}", err);
+                    tokio::task::spawn_blocking(move || supervisor_death_handler.client_died());
+                    return;
+                }
+                Ok(Err(err)) if supervisor_removal.report(&err) => {
+                    log::error!("UCI reader task failed, device removed: {}", err);
+                    ReaderFailure::DeviceRemoved
+                }
+                Ok(Err(err)) => {
+                    log::error!("UCI reader task failed: {}", err);
+                    ReaderFailure::Io(err.kind())
+                }
+                Err(err) if err.is_panic() => {
+                    log::error!("UCI reader task panicked");
+                    ReaderFailure::Panicked
+                }
+                // Aborted by close.
+                Err(_) => return,
+            };
+            {
+                let mut stats = reader_stats.lock().unwrap();
+                stats.rx_errors += 1;
+                stats.reader_failures += 1;
+                stats.last_reader_failure = Some(failure);
+                if failure == ReaderFailure::DeviceRemoved {
+                    stats.device_removals += 1;
+                    stats.last_device_removal = supervisor_removal.errno.get().copied();
+                }
+            }
+            supervisor_packet_stats.record_error();
+            // A concurrent close is terminating this task.
+            let mut state = select! {
+                biased;
+                _ = reader_token.cancelled() => return,
+                state = reader_state.lock() => state,
+            };
+            if reader_token.is_cancelled() {
+                return;
+            }
+            if let State::Opened(ref mut session) = *state {
+                let Session {
+                    ref callbacks,
+                    ref mut death_recipient,
+                    ref credits,
+                    ref serial,
+                    ..
+                } = **session;
+                credits.close();
+                if let Err(err) = callbacks.as_binder().unlink_to_death(death_recipient) {
+                    log::warn!("failed to unlink death recipient: {:?}", err);
+                }
+                let status = match failure {
+                    // Without the device, the UWBS cannot be reset: its file
+                    // is only closed.
+                    ReaderFailure::DeviceRemoved => {
+                        serial.lock().await.take();
+                        UwbStatus::ERR_TRANSPORT
+                    }
+                    _ => UwbStatus::FAILED,
+                };
+                if let Err(err) = callbacks.onHalEvent(UwbEvent::ERROR, status) {
+                    log::warn!("failed to report the reader error: {:?}", err);
+                }
+                // Release the device so that the chip can be reopened.
+                *state = State::Closed;
+                supervisor_packet_stats.record_close();
+            }
+        });
+
+        let mut session = Session {
+            callbacks: callbacks.clone(),
+            handle: join_handle,
+            reader,
+            serial,
+            queue,
+            write_error,
+            credits,
+            death_recipient,
+            token,
+            sessions,
+            opened_at: Instant::now(),
+            packet_stats: self.packet_stats.clone(),
+            responses,
+            progress,
+            device_info: None,
+            chip_info: Arc::default(),
+            removal,
+            open_ref_count: 1,
+            calibration_commands,
+            initializing: Arc::default(),
+            updating: Arc::default(),
+            suspended: false,
+        };
+        // Without a client to report to, the tasks of the session must not
+        // be left running.
+        if let Err(err) = callbacks.onHalEvent(UwbEvent::OPEN_CPLT, UwbStatus::OK) {
+            log::error!("{}: failed to report the open: {:?}", self.name, err);
+            if let Err(err) = callbacks
+                .as_binder()
+                .unlink_to_death(&mut session.death_recipient)
+            {
+                log::warn!("failed to unlink death recipient: {:?}", err);
+            }
+            if let Err(err) = session
+                .reset(&self.observers, &self.teardown_config(), false)
+                .await
+            {
+                log::warn!("failed to reset the UWBS: {:?}", err);
+            }
+            return Err(err);
+        }
+        *state = State::Opened(Box::new(session));
+        self.stats.lock().unwrap().open_count += 1;
+        self.packet_stats.record_open();
+        match self.android_uci_version.get() {
+            Some(version) => log::info!(
+                "{}: opened, advertising Android UCI version {}",
+                self.name,
+                version
+            ),
+            None => log::info!(
+                "{}: opened, Android UCI version to be queried from the UWBS",
+                self.name
+            ),
+        }
+
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        log::debug!("close");
+
+        // Abandon an open still connecting to the UWBS.
+        let open_abandoned = match *self.opening.lock().unwrap() {
+            Some(ref opening) => {
+                opening.cancel();
+                true
+            }
+            None => false,
+        };
+        let mut state = self.state.lock().await;
+
+        if let State::Opened(ref mut session) = *state {
+            if session.open_ref_count > 1 {
+                session.open_ref_count -= 1;
+                log::info!(
+                    "{}: still opened, {} references",
+                    self.name,
+                    session.open_ref_count
+                );
+                session
+                    .callbacks
+                    .onHalEvent(UwbEvent::CLOSE_CPLT, UwbStatus::OK)?;
+                return Ok(());
+            }
+            session
+                .callbacks
+                .as_binder()
+                .unlink_to_death(&mut session.death_recipient)?;
+            let State::Opened(session) = std::mem::replace(&mut *state, State::Resetting) else {
+                unreachable!()
+            };
+            // Calls made while the UWBS is reset are refused rather than
+            // left waiting for the state lock.
+            drop(state);
+            let result = session
+                .reset(&self.observers, &self.teardown_config(), true)
+                .await;
+            *self.state.lock().await = State::Closed;
+            self.stats.lock().unwrap().close_count += 1;
+            self.flap_guard
+                .lock()
+                .unwrap()
+                .record_close(tokio::time::Instant::now());
+            log::info!(
+                "reader task yielded {} times under continuous traffic",
+                self.forced_yields.load(Ordering::Relaxed)
+            );
+            log::info!("{}: {:?}", self.name, self.stats());
+            result
+        } else if let State::AwaitingClient = *state {
+            // The UWBS was reset when the client died.
+            log::info!("{}: no longer awaiting the client", self.name);
+            *state = State::Closed;
+            Ok(())
+        } else if open_abandoned {
+            Ok(())
+        } else {
+            Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+        }
+    }
+
+    async fn coreInit(&self) -> Result<()> {
+        log::debug!("coreInit");
+
+        // The state lock is not held while the UWBS initializes, for the
+        // other calls to proceed. Calls to coreInit run one at a time.
+        let (initializing, channel, callbacks, calibration_commands) = {
+            let state = self.state.lock().await;
+            let State::Opened(ref session) = *state else {
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+            };
+            (
+                session.initializing.clone(),
+                session.channel(),
+                session.callbacks.clone(),
+                session.calibration_commands.clone(),
+            )
+        };
+        let _initializing = initializing.lock().await;
+        if self.monitor {
+            // The UWBS is initialized by the host being monitored.
+            callbacks.onHalEvent(UwbEvent::POST_INIT_CPLT, UwbStatus::OK)?;
+            return Ok(());
+        }
+        // UCI has no initialization command: the response to
+        // CORE_GET_DEVICE_INFO_CMD tells the UWBS booted and accepts
+        // commands. The response is delivered to the client as well, the
+        // stack keeping track of the device info.
+        let result = match channel
+            .send_and_forward(
+                &CORE_GET_DEVICE_INFO_CMD,
+                &self.observers,
+                self.core_init_timeout,
+            )
+            .await
+        {
+            Ok(response) => {
+                let info = UciControlPacket::parse(&response)
+                    .ok()
+                    .and_then(|packet| device_info(&packet));
+                if let Some(ref info) = info {
+                    log::info!("{}: {}", self.name, info);
+                }
+                // Unless the chip was closed in the meantime.
+                if let State::Opened(ref mut session) = *self.state.lock().await {
+                    if Arc::ptr_eq(&session.initializing, &initializing) {
+                        session.device_info = info;
+                    }
+                }
+                let status = response.get(UCI_HEADER_SIZE).copied();
+                if status == Some(StatusCode::UciStatusOk.into()) {
+                    self.query_capabilities(&channel, &initializing).await;
+                    Ok(())
+                } else {
+                    Err(HalError::CommandFailed {
+                        command: "core init",
+                        status,
+                    }
+                    .into())
+                }
+            }
+            // A slow UWBS may still accept the commands of the client.
+            Err(err) if err.service_specific_error() == UwbStatus::ERR_CMD_TIMEOUT.0 => {
+                log::warn!("{}: no device info: {:?}", self.name, err);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        };
+        if result.is_ok() {
+            channel
+                .send_calibration_commands(
+                    &calibration_commands,
+                    &self.name,
+                    &self.observers,
+                    self.core_init_timeout,
+                )
+                .await;
+        }
+        if let Err(ref err) = result {
+            log::error!("{}: core init failed: {:?}", self.name, err);
+            // The failure of core init is what the client needs to know.
+            if let Err(err) = callbacks.onHalEvent(UwbEvent::POST_INIT_CPLT, UwbStatus::FAILED) {
+                log::warn!("failed to report the core init failure: {:?}", err);
+            }
+            return result;
+        }
+        callbacks.onHalEvent(UwbEvent::POST_INIT_CPLT, UwbStatus::OK)?;
+        Ok(())
+    }
+
+    async fn sessionInit(&self, id: i32) -> Result<()> {
+        log::debug!("sessionInit {}", id);
+
+        let State::Opened(ref mut session) = *self.state.lock().await else {
+            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+        };
+        let result = session.sessions.lock().unwrap().insert(id);
+        match result {
+            Ok(()) => Ok(()),
+            Err(err @ SessionPoolError::Duplicate(_)) => {
+                log::error!("{}: {}", self.name, err);
+                Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+            }
+            Err(err @ SessionPoolError::Capacity(_)) => {
+                log::error!("{}: cannot initialize session {}: {}", self.name, id, err);
+                Err(binder::Status::new_service_specific_error_str(
+                    u8::from(StatusCode::UciStatusMaxSessionsExceeded).into(),
+                    Some(err.to_string()),
+                ))
+            }
+        }
+    }
+
+    async fn getSupportedAndroidUciVersion(&self) -> Result<i32> {
+        log::debug!("getSupportedAndroidUciVersion");
+
+        // Configured, or learned by coreInit from the capabilities.
+        if let Some(version) = self.android_uci_version.get() {
+            return Ok(*version);
+        }
+        let state = self.state.lock().await;
+        let State::Opened(ref session) = *state else {
+            return Err(binder::ExceptionCode::ILLEGAL_STATE.into());
+        };
+        Ok(session
+            .device_info
+            .as_ref()
+            .and_then(derived_android_uci_version)
+            .unwrap_or_else(|| {
+                log::info!(
+                    "{}: Android UCI version not reported, assuming {}",
+                    self.name,
+                    DEFAULT_ANDROID_UCI_VERSION
+                );
+                DEFAULT_ANDROID_UCI_VERSION
+            }))
+    }
+
+    async fn sendUciMessage(&self, data: &[u8]) -> Result<i32> {
+        log::debug!("sendUciMessage");
+
+        if self.monitor {
+            log::error!("{}: refusing to write in monitor mode", self.name);
+            return Err(binder::ExceptionCode::UNSUPPORTED_OPERATION.into());
+        }
+
+        // A packet shorter than its header advertises would have the UWBS
+        // take the next packets as the end of its payload.
+        if let Err(err) = check_packet(data) {
+            log::error!("{}: refusing to send invalid packet: {}", self.name, err);
+            self.invalid_packets.fetch_add(1, Ordering::Relaxed);
+            self.packet_stats.record_malformed_packet();
+            return Err(binder::ExceptionCode::ILLEGAL_ARGUMENT.into());
+        }
+
+        let fragments = self.fragmenter.fragment(data).map_err(|err| {
+            log::error!("invalid packet: {}", err);
+            binder::Status::from(binder::ExceptionCode::ILLEGAL_ARGUMENT)
+        })?;
+
+        // Only hold the state lock for the time needed to get the queue,
+        // the packets being written by the writer task.
+        let (queue, credits, sessions) = match *self.state.lock().await {
+            State::Opened(ref mut session) => {
+                let Session {
+                    ref queue,
+                    ref mut write_error,
+                    ref credits,
+                    ref sessions,
+                    ref mut suspended,
+                    ..
+                } = **session;
+                // Any command wakes the UWBS up.
+                if std::mem::take(suspended) {
+                    log::info!("{}: woken up by the client", self.name);
+                }
+                // The writer task failed to write a previous packet.
+                if write_error.has_changed().unwrap_or(false) {
+                    if let Some(ref err) = *write_error.borrow_and_update() {
+                        log::error!("{}: previous write failed: {}", self.name, err);
+                        if err.kind() == io::ErrorKind::TimedOut {
+                            return Err(HalError::WriteTimeout(self.write_timeout).into());
+                        }
+                        let message = match is_device_gone(err) {
+                            true => format!("device removed: {}", err),
+                            false => format!("previous write failed: {}", err),
+                        };
+                        return Err(HalError::Transport(message).into());
+                    }
+                }
+                (queue.clone(), credits.clone(), sessions.clone())
+            }
+            State::Closed | State::Opening | State::Resetting | State::AwaitingClient => {
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+            }
+        };
+        // Data messages are segmented to the size accepted by the UWBS,
+        // keeping the sequence number given by the client.
+        let fragments = match data_message_snd_session(data) {
+            Some(_) => Segmenter::new(self.max_data_payload_size.load(Ordering::Relaxed))
+                .segment(data)
+                .map_err(|err| {
+                    log::error!("invalid packet: {}", err);
+                    binder::Status::from(binder::ExceptionCode::ILLEGAL_ARGUMENT)
+                })?,
+            None => fragments,
+        };
+
+        // The packets are validated as written to the UWBS, once the data
+        // messages are segmented.
+        #[cfg(any(debug_assertions, feature = "validate-uci"))]
+        for fragment in &fragments {
+            if let Err(err) = validate_packet(fragment) {
+                log::error!(
+                    "{}: refusing to send undecodable packet {:02x?}: {:?}",
+                    self.name,
+                    data,
+                    err
+                );
+                self.invalid_packets.fetch_add(1, Ordering::Relaxed);
+                self.packet_stats.record_malformed_packet();
+                return Err(binder::ExceptionCode::ILLEGAL_ARGUMENT.into());
+            }
+        }
+        // Data messages consume the credit of their session, or are
+        // queued until the UWBS grants one, see the reader task. Control
+        // messages are not subject to credits.
+        let fragments = match data_message_snd_session(data) {
+            Some(session_token) => match credits.submit(session_token, fragments) {
+                Ok(Submission::Send(fragments)) => fragments,
+                Ok(Submission::Queued { dropped }) => {
+                    if dropped {
+                        log::warn!(
+                            "{}: no data credit for session {:#x}, dropping its oldest queued message",
+                            self.name,
+                            session_token
+                        );
+                        self.stats.lock().unwrap().data_messages_dropped += 1;
+                    }
+                    return Ok(data.len() as i32);
+                }
+                Err(_) => return Err(binder::ExceptionCode::ILLEGAL_STATE.into()),
+            },
+            None => fragments,
+        };
+        // The IUwbChip interface has no counterpart to sessionInit: the
+        // session is forgotten once the UWBS confirms its deinitialization.
+        sessions.lock().unwrap().command_sent(data);
+        match queue.try_send(fragments) {
+            Ok(()) => (),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                log::error!("{}: write queue full, dropping packet", self.name);
+                self.stats.lock().unwrap().tx_errors += 1;
+                return Err(HalError::Refused("write queue full".to_owned()).into());
+            }
+            // The writer task stopped, the chip being closed.
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                return Err(binder::ExceptionCode::ILLEGAL_STATE.into())
+            }
+        }
+        Ok(data.len() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::{ChunkedDownloader, FirmwareDownloader};
+    use crate::flow_control::DATA_QUEUE_CAPACITY;
+    use crate::transport::{
+        configure_tty, DeviceSelector, MockTransport, MockUwbs, OpenConfig, UartTransport,
+    };
+    use android_hardware_uwb::aidl::android::hardware::uwb::IUwbClientCallback::BnUwbClientCallback;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::OwnedFd;
+    use std::sync::atomic::AtomicBool;
+
+    const DEVICE_RESET_CMD: [u8; 5] = [0x20, 0x00, 0x00, 0x01, 0x00];
+    const DEVICE_RESET_RSP: [u8; 5] = [0x40, 0x00, 0x00, 0x01, 0x00];
+    const DEVICE_STATUS_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x01];
+    const DEVICE_STATUS_ACTIVE_NTF: [u8; 5] = [0x60, 0x01, 0x00, 0x01, 0x02];
+
+    /// Records the events and messages delivered to the client callbacks.
+    #[derive(Default)]
+    struct Recorder {
+        events: std::sync::Mutex<Vec<(UwbEvent, UwbStatus)>>,
+        messages: std::sync::Mutex<Vec<Vec<u8>>>,
+        /// Fail the delivery of the messages past the count given with
+        /// the status given, as a client in trouble.
+        reject_messages: std::sync::Mutex<Option<(usize, binder::StatusCode)>>,
+        /// Number of deliveries failed.
+        rejections: AtomicUsize,
+        /// Fail the delivery of the events.
+        reject_events: AtomicBool,
+    }
+
+    struct TestCallbacks(Arc<Recorder>);
+
+    impl binder::Interface for TestCallbacks {}
+
+    impl IUwbClientCallback for TestCallbacks {
+        fn onUciMessage(&self, data: &[u8]) -> Result<()> {
+            let mut messages = self.0.messages.lock().unwrap();
+            if let Some((accepted, status)) = *self.0.reject_messages.lock().unwrap() {
+                if messages.len() >= accepted {
+                    self.0.rejections.fetch_add(1, Ordering::Relaxed);
+                    return Err(status.into());
+                }
+            }
+            messages.push(data.to_vec());
+            Ok(())
+        }
+
+        fn onHalEvent(&self, event: UwbEvent, status: UwbStatus) -> Result<()> {
+            if self.0.reject_events.load(Ordering::Relaxed) {
+                return Err(binder::StatusCode::FAILED_TRANSACTION.into());
+            }
+            self.0.events.lock().unwrap().push((event, status));
+            Ok(())
+        }
+    }
+
+    fn callbacks() -> (Arc<Recorder>, Strong<dyn IUwbClientCallback>) {
+        let recorder = Arc::new(Recorder::default());
+        let callbacks = BnUwbClientCallback::new_binder(
+            TestCallbacks(recorder.clone()),
+            binder::BinderFeatures::default(),
+        );
+        (recorder, callbacks)
+    }
+
+    /// DATA_MESSAGE_SND for session 0 with the largest possible payload.
+    fn large_data_packet() -> Vec<u8> {
+        let mut packet = vec![0x01, 0x00, 0xff, 0xff];
+        packet.extend((0..u16::MAX).map(|i| (i % 251) as u8));
+        packet
+    }
+
+    fn uart_chip(path: String) -> UwbChip<UartTransport> {
+        UwbChip::with_transport(
+            "0".to_owned(),
+            UartTransport::new(DeviceSelector::Path(path.into()), OpenConfig::default()),
+        )
+    }
+
+    /// Create a raw pseudoterminal pair. Returns the master side, the
+    /// slave side (which must be kept open), and the slave path.
+    fn pty() -> (File, OwnedFd, String) {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let path = nix::unistd::ttyname(&pty.slave).unwrap();
+        let slave = configure_tty(File::from(pty.slave), &OpenConfig::default()).unwrap();
+        (
+            File::from(pty.master),
+            slave.into(),
+            path.to_str().unwrap().to_owned(),
+        )
+    }
+
+    fn set_nonblocking(file: &File) {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+        use std::os::fd::AsRawFd;
+        fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+    }
+
+    /// Return a unique path in the temporary directory.
+    fn temp_path(prefix: &str) -> std::path::PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            prefix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// Emulate the UWBS answering the DeviceResetCmd sent on close.
+    fn respond_to_reset(mut master: File) -> std::thread::JoinHandle<File> {
+        std::thread::spawn(move || {
+            let mut command = [0; 5];
+            master.read_exact(&mut command).unwrap();
+            assert_eq!(command, DEVICE_RESET_CMD);
+            master.write_all(&DEVICE_RESET_RSP).unwrap();
+            master.write_all(&DEVICE_STATUS_NTF).unwrap();
+            master
+        })
+    }
+
+    const SESSION_DEINIT_RSP: [u8; 5] = [0x41, 0x01, 0x00, 0x01, 0x00];
+
+    /// SESSION_DEINIT_CMD of session `id`.
+    fn session_deinit_cmd(id: i32) -> Vec<u8> {
+        let mut command = SESSION_DEINIT_CMD_HEADER.to_vec();
+        command.extend_from_slice(&id.to_le_bytes());
+        command
+    }
+
+    /// SESSION_STATUS_NTF reporting session `id` as deinitialized.
+    fn session_deinit_ntf(id: i32) -> Vec<u8> {
+        let mut ntf = vec![0x61, 0x02, 0x00, 0x06];
+        ntf.extend_from_slice(&id.to_le_bytes());
+        ntf.extend_from_slice(&[0x01, 0x00]);
+        ntf
+    }
+
+    /// Answer the SESSION_DEINIT_CMD sent on close for each of the
+    /// sessions `ids`, then the DeviceResetCmd, from another thread.
+    fn respond_to_close(mut master: File, ids: Vec<i32>) -> std::thread::JoinHandle<File> {
+        std::thread::spawn(move || {
+            for id in ids {
+                let mut command = [0; 8];
+                master.read_exact(&mut command).unwrap();
+                assert_eq!(command.to_vec(), session_deinit_cmd(id));
+                master.write_all(&SESSION_DEINIT_RSP).unwrap();
+                master.write_all(&session_deinit_ntf(id)).unwrap();
+            }
+            respond_to_reset(master).join().unwrap()
+        })
+    }
+
+    /// Let the writer task write the packets queued by sendUciMessage,
+    /// before reading them from the blocking end of the transport.
+    async fn flush_writes() {
+        tokio::task::yield_now().await;
+    }
+
+    /// Read `transport` asynchronously, as done once the reset is sent.
+    fn reader(transport: MockTransport) -> TransportReader {
+        TransportReader::new(Box::new(transport).into_async().unwrap())
+    }
+
+    /// Wait until `condition` holds, failing the test after one second.
+    async fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "condition not met"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn reader_yields_under_notification_flood() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_writer = stop.clone();
+        std::thread::spawn(move || {
+            while !stop_writer.load(Ordering::Relaxed)
+                && master.write_all(&DEVICE_STATUS_NTF).is_ok()
+            {}
+        });
+        // Let the writer fill up the pty buffer before the reader starts.
+        std::thread::sleep(Duration::from_millis(50));
+
+        chip.open(&callbacks).await.unwrap();
+
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(chip.getName().await.unwrap(), "0");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(chip.forced_yields.load(Ordering::Relaxed) > 0);
+        assert!(!recorder.messages.lock().unwrap().is_empty());
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[tokio::test]
+    async fn packet_stream_is_delimited() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Vendor notifications and data packets of all sizes, up to the
+        // largest control packet.
+        let packets: Vec<Vec<u8>> = (0..3000)
+            .map(|i: usize| {
+                let mut packet = match i % 3 {
+                    0 => {
+                        let length = (i * 7 % 1024) as u16;
+                        let [low, high] = length.to_le_bytes();
+                        vec![0x02, 0x00, low, high]
+                    }
+                    _ => vec![0x6e, (i % 64) as u8, 0x00, (i % 256) as u8],
+                };
+                let length = crate::fragmentation::payload_length(&packet);
+                packet.extend((0..length).map(|byte| (i + byte) as u8));
+                packet
+            })
+            .collect();
+        let writer = std::thread::spawn({
+            let packets = packets.clone();
+            move || {
+                for packet in packets {
+                    master.write_all(&packet).unwrap();
+                }
+                master
+            }
+        });
+        let start = Instant::now();
+        while recorder.messages.lock().unwrap().len() < packets.len() {
+            assert!(start.elapsed() < Duration::from_secs(10), "packets lost");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let _master = writer.join().unwrap();
+        assert!(*recorder.messages.lock().unwrap() == packets);
+        let stats = chip.stats();
+        assert_eq!(stats.rx_packets, packets.len() as u64);
+        assert_eq!(stats.rx_errors, 0);
+    }
+
+    #[tokio::test]
+    async fn monitor_mode_refuses_writes() {
+        let (mut master, _slave, path) = pty();
+        let chip = UwbChip::with_transport(
+            "0".to_owned(),
+            UartTransport::new(
+                DeviceSelector::Path(path.into()),
+                OpenConfig {
+                    read_only: true,
+                    ..Default::default()
+                },
+            ),
+        )
+        .with_monitor(true);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        let status = chip
+            .sendUciMessage(&[0x20, 0x02, 0x00, 0x00])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::UNSUPPORTED_OPERATION
+        );
+
+        // Received packets are still delivered.
+        master.write_all(&DEVICE_STATUS_NTF).unwrap();
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+
+        // Close completes without writing the device reset command.
+        chip.close().await.unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+        set_nonblocking(&master);
+        let mut buffer = [0; 16];
+        assert_eq!(
+            master.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[tokio::test]
+    async fn rapid_reopen_is_refused() {
+        let (master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (_recorder, callbacks) = callbacks();
+
+        chip.open(&callbacks).await.unwrap();
+        let responder = respond_to_reset(master);
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+
+        let status = chip.open(&callbacks).await.unwrap_err();
+        assert_eq!(status.service_specific_error(), UwbStatus::REFUSED.0);
+    }
+
+    // The reader task is stopped in the middle of a packet.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn close_aborts_stuck_reader() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        let stop_timeout = Duration::from_millis(50);
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_close_timeout(Duration::from_millis(10))
+            .with_reader_stop_timeout(stop_timeout);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // The UWBS stops in the middle of a header, and never answers the
+        // reset.
+        device_tx.write_all(&DEVICE_STATUS_NTF[..1]).unwrap();
+        let progress = match *chip.state.lock().await {
+            State::Opened(ref session) => session.progress.clone(),
+            _ => unreachable!(),
+        };
+        wait_for(|| progress.phase() == ReaderPhase::Reading).await;
+
+        let start = Instant::now();
+        chip.close().await.unwrap();
+        assert!(start.elapsed() < PACKET_READ_TIMEOUT);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn close_falls_back_to_reset_gpio() {
+        let (transport, _device_rx, _device_tx) = MockTransport::new();
+        let gpio = temp_path("reset_gpio");
+        std::fs::write(&gpio, "1\n").unwrap();
+        // The UWBS never answers the reset.
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_close_timeout(Duration::from_millis(10))
+            .with_reset_gpio(Some(gpio.clone()));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        chip.close().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&gpio).unwrap(), "0\n1\n");
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+        std::fs::remove_file(&gpio).unwrap();
+    }
+
+    // The reset response is waited for on a runtime worker.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn calls_are_refused_while_resetting() {
+        let (transport, _device_rx, _device_tx) = MockTransport::new();
+        // The UWBS never answers the reset.
+        let timeout = Duration::from_millis(300);
+        let chip = Arc::new(
+            UwbChip::with_transport("0".to_owned(), transport).with_close_timeout(timeout),
+        );
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        let closer = tokio::spawn({
+            let chip = chip.clone();
+            async move { chip.close().await }
+        });
+        let reporter = chip.crash_reporter();
+        wait_for(|| {
+            let mut report = String::new();
+            reporter.report(&mut report).unwrap();
+            report.contains("state=resetting")
+        })
+        .await;
+
+        let start = Instant::now();
+        for status in [
+            chip.open(&callbacks).await.unwrap_err(),
+            chip.coreInit().await.unwrap_err(),
+            chip.sendUciMessage(&[0x20, 0x02, 0x00, 0x00])
+                .await
+                .unwrap_err(),
+        ] {
+            assert_eq!(
+                status.exception_code(),
+                binder::ExceptionCode::ILLEGAL_STATE
+            );
+        }
+        assert!(start.elapsed() < timeout / 2);
+
+        closer.await.unwrap().unwrap();
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_calls_keep_state_consistent() {
+        let (transport, mut device_rx, _device_tx) = MockTransport::new();
+        let mut chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_close_timeout(Duration::from_millis(10))
+            .with_core_init_timeout(Duration::from_millis(10));
+        chip.flap_guard = Arc::new(std::sync::Mutex::new(FlapGuard::new(FlapGuardConfig {
+            min_interval: Duration::ZERO,
+            max_cycles: usize::MAX,
+            ..Default::default()
+        })));
+        let chip = Arc::new(chip);
+        // Drain the packets written by the chip.
+        std::thread::spawn(move || while device_rx.read(&mut [0; 256]).unwrap_or(0) > 0 {});
+
+        let tasks: Vec<_> = (0..4)
+            .map(|task| {
+                let chip = chip.clone();
+                tokio::spawn(async move {
+                    let (_recorder, callbacks) = callbacks();
+                    for i in 0..50 {
+                        match (task + i) % 4 {
+                            0 => _ = chip.open(&callbacks).await,
+                            1 => _ = chip.close().await,
+                            2 => _ = chip.coreInit().await,
+                            _ => _ = chip.sendUciMessage(&[0x20, 0x02, 0x00, 0x00]).await,
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // Every close completes, leaving the chip closed.
+        if matches!(*chip.state.lock().await, State::Opened(_)) {
+            chip.close().await.unwrap();
+        }
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        chip.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn chip_reopens_after_reader_failure() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, failing_callbacks) = callbacks();
+        *recorder.reject_messages.lock().unwrap() = Some((0, binder::StatusCode::UNKNOWN_ERROR));
+        chip.open(&failing_callbacks).await.unwrap();
+
+        // The reader task fails to deliver the notification.
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| recorder.events.lock().unwrap().len() == 2).await;
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::ERROR, UwbStatus::FAILED)
+            ]
+        );
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        let stats = chip.stats();
+        assert_eq!(stats.reader_failures, 1);
+        assert_eq!(
+            stats.last_reader_failure,
+            Some(ReaderFailure::Io(io::ErrorKind::BrokenPipe))
+        );
+
+        // Traffic flows again once reopened.
+        let (new_recorder, new_callbacks) = callbacks();
+        chip.open(&new_callbacks).await.unwrap();
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| !new_recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *new_recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_header_is_skipped() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+
+        // Data header advertising 0xffff bytes, which never come.
+        let start = Instant::now();
+        uwbs.inject(&[0x01, 0x00, 0xff, 0xff, 0xde, 0xad]);
+        wait_for(|| chip.packet_stats().lifetime.malformed_packets == 1).await;
+        assert!(start.elapsed() < PACKET_READ_TIMEOUT);
+        assert_eq!(chip.stats().rx_errors, 1);
+
+        // The reader skips the bytes read until the next packet.
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+        assert!(matches!(*chip.state.lock().await, State::Opened(_)));
+        assert_eq!(chip.stats().rx_skipped_bytes, 6);
+        assert_eq!(chip.packet_stats().lifetime.malformed_packets, 1);
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn byte_stream_is_resynchronized() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+
+        // A stray byte in front of every packet.
+        let packets = [
+            DEVICE_STATUS_NTF,
+            DEVICE_STATUS_ACTIVE_NTF,
+            DEVICE_STATUS_NTF,
+        ];
+        for packet in packets {
+            uwbs.inject(&[&[0xff][..], &packet].concat());
+        }
+        wait_for(|| recorder.messages.lock().unwrap().len() == 3).await;
+        assert_eq!(*recorder.messages.lock().unwrap(), packets.map(Vec::from));
+        let stats = chip.stats();
+        assert_eq!((stats.rx_packets, stats.rx_skipped_bytes), (3, 3));
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn byte_stream_without_valid_header_is_an_error() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport).with_max_resync_bytes(8);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        uwbs.inject(&[0xff; 16]);
+        wait_for(|| chip.stats().reader_failures == 1).await;
+        assert_eq!(
+            chip.stats().last_reader_failure,
+            Some(ReaderFailure::Io(io::ErrorKind::InvalidData))
+        );
+        wait_for(|| recorder.events.lock().unwrap().len() == 2).await;
+        assert_eq!(
+            recorder.events.lock().unwrap()[1],
+            (UwbEvent::ERROR, UwbStatus::FAILED)
+        );
+    }
+
+    #[tokio::test]
+    async fn undeliverable_packets_are_dropped() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        *recorder.reject_messages.lock().unwrap() =
+            Some((1, binder::StatusCode::FAILED_TRANSACTION));
+        chip.open(&callbacks).await.unwrap();
+
+        // The second notification fails to be delivered on every attempt.
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        uwbs.inject(&DEVICE_STATUS_ACTIVE_NTF);
+        wait_for(|| chip.stats().rx_errors == 1).await;
+        assert_eq!(
+            recorder.rejections.load(Ordering::Relaxed),
+            DELIVERY_ATTEMPTS as usize
+        );
+        assert_eq!(recorder.messages.lock().unwrap().len(), 1);
+        assert!(matches!(*chip.state.lock().await, State::Opened(_)));
+        assert_eq!(chip.stats().reader_failures, 0);
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn repeated_device_status_is_dropped() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_dedup_window(Duration::from_millis(50));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Only the status changes are forwarded within the window.
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        uwbs.inject(&DEVICE_STATUS_ACTIVE_NTF);
+        wait_for(|| chip.stats().rx_packets == 3).await;
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+        assert_eq!(chip.stats().rx_deduped, 1);
+
+        // A repeat after the window is forwarded.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        uwbs.inject(&DEVICE_STATUS_ACTIVE_NTF);
+        wait_for(|| recorder.messages.lock().unwrap().len() == 3).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![
+                DEVICE_STATUS_NTF.to_vec(),
+                DEVICE_STATUS_ACTIVE_NTF.to_vec(),
+                DEVICE_STATUS_ACTIVE_NTF.to_vec()
+            ]
+        );
+        assert_eq!(chip.stats().rx_deduped, 1);
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn zero_dedup_window_forwards_repeats() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip =
+            UwbChip::with_transport("0".to_owned(), transport).with_dedup_window(Duration::ZERO);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+        assert_eq!(chip.stats().rx_deduped, 0);
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn dead_client_found_on_delivery_is_handled_as_a_death() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        *recorder.reject_messages.lock().unwrap() = Some((0, binder::StatusCode::DEAD_OBJECT));
+        chip.open(&callbacks).await.unwrap();
+
+        // The UWBS is reset as when the death notification comes.
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        let responder = uwbs.respond_to_reset();
+        wait_for(|| chip.stats().close_count == 1).await;
+        responder.join().unwrap();
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(chip.stats().reader_failures, 0);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+    }
+
+    #[tokio::test]
+    async fn open_is_undone_if_it_cannot_be_reported() {
+        let (transport, uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, failing_callbacks) = callbacks();
+        recorder.reject_events.store(true, Ordering::Relaxed);
+
+        let responder = uwbs.respond_to_reset();
+        assert!(chip.open(&failing_callbacks).await.is_err());
+        let uwbs = responder.join().unwrap();
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(chip.stats().open_count, 0);
+
+        // The transport was released by the tasks of the session.
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_is_reference_counted() {
+        let (transport, uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        chip.open(&callbacks).await.unwrap();
+
+        // The first close only drops a reference.
+        chip.close().await.unwrap();
+        assert!(matches!(*chip.state.lock().await, State::Opened(_)));
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+        assert_eq!(chip.stats().close_count, 1);
+    }
+
+    #[tokio::test]
+    async fn removed_device_closes_chip() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        uwbs.remove();
+
+        // The write of the client reveals the removal, and the chip is
+        // closed without waiting for the reader to notice it.
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        wait_for(|| recorder.events.lock().unwrap().len() == 2).await;
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::ERROR, UwbStatus::ERR_TRANSPORT)
+            ]
+        );
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        let stats = chip.stats();
+        assert_eq!(
+            stats.last_reader_failure,
+            Some(ReaderFailure::DeviceRemoved)
+        );
+        assert_eq!(stats.device_removals, 1);
+        assert_eq!(stats.last_device_removal, Some(libc::ENODEV));
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("  device removals: 1 (last No such device (os error 19))\n"),
+            "{}",
+            output
+        );
+
+        // Reopening fails right away while the device is absent.
+        let (_recorder, callbacks) = callbacks();
+        let status = chip.open(&callbacks).await.unwrap_err();
+        assert_eq!(status.service_specific_error(), UwbStatus::FAILED.0);
+    }
+
+    #[tokio::test]
+    async fn partial_packet_does_not_hold_runtime() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        let progress = match *chip.state.lock().await {
+            State::Opened(ref session) => session.progress.clone(),
+            _ => unreachable!(),
+        };
+
+        // Truncated header, then truncated payload.
+        let packet = [0x60, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03];
+        for chunk in [&packet[..2], &packet[2..5]] {
+            master.write_all(chunk).unwrap();
+            wait_for(|| progress.phase() == ReaderPhase::Reading).await;
+            let start = Instant::now();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert!(start.elapsed() < PACKET_READ_TIMEOUT / 2);
+        }
+        master.write_all(&packet[5..]).unwrap();
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(*recorder.messages.lock().unwrap(), vec![packet.to_vec()]);
+
+        let responder = respond_to_reset(master);
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_payload_reports_error() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Device status notification with the payload one byte short.
+        master.write_all(&[0x60, 0x01, 0x00, 0x02, 0x01]).unwrap();
+        wait_for(|| {
+            recorder
+                .events
+                .lock()
+                .unwrap()
+                .contains(&(UwbEvent::ERROR, UwbStatus::FAILED))
+        })
+        .await;
+        assert!(recorder.messages.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reset_skips_interleaved_packets() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        // Vendor notification, malformed response, and pending ranging
+        // notification, followed by the notification and the response out
+        // of order.
+        device_tx
+            .write_all(&[0x6e, 0x01, 0x00, 0x02, 0xaa, 0xbb])
+            .unwrap();
+        device_tx.write_all(&[0x40, 0x00, 0x00, 0x00]).unwrap();
+        device_tx
+            .write_all(&[0x62, 0x00, 0x00, 0x01, 0x00])
+            .unwrap();
+        device_tx.write_all(&DEVICE_STATUS_NTF).unwrap();
+        device_tx.write_all(&DEVICE_RESET_RSP).unwrap();
+
+        let observers = ObserverRegistry::default();
+        let received = Arc::new(AtomicU64::new(0));
+        let received_clone = received.clone();
+        observers.register("count", move |_, _, _| {
+            received_clone.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        });
+        consume_device_reset_rsp_and_ntf(
+            &mut reader(transport),
+            Framing::ByteStream,
+            DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE,
+            &observers,
+            DEFAULT_CLOSE_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(received.load(Ordering::Relaxed), 5);
+    }
+
+    #[tokio::test]
+    async fn reset_waits_for_ready_state() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        // DeviceStatusNtf reporting DEVICE_STATE_ERROR.
+        device_tx
+            .write_all(&[0x60, 0x01, 0x00, 0x01, 0xff])
+            .unwrap();
+        device_tx.write_all(&DEVICE_RESET_RSP).unwrap();
+
+        let err = consume_device_reset_rsp_and_ntf(
+            &mut reader(transport),
+            Framing::ByteStream,
+            DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE,
+            &ObserverRegistry::default(),
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn close_times_out_without_reset_response() {
+        let (mut master, slave, path) = pty();
+        let timeout = Duration::from_millis(200);
+        let chip = uart_chip(path).with_close_timeout(timeout);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        drop(slave);
+
+        let start = Instant::now();
+        chip.close().await.unwrap();
+        assert!(start.elapsed() <= timeout + timeout / 10);
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+
+        // The device has been released: once the reset command is drained,
+        // the pty reports that no slave is open anymore.
+        let mut command = [0; 5];
+        master.read_exact(&mut command).unwrap();
+        assert_eq!(command, DEVICE_RESET_CMD);
+        set_nonblocking(&master);
+        assert_eq!(
+            master.read(&mut command).unwrap_err().raw_os_error(),
+            Some(libc::EIO)
+        );
+    }
+
+    #[tokio::test]
+    async fn close_fails_on_unexpected_reset_response() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        let responder = std::thread::spawn(move || {
+            let mut command = [0; 5];
+            master.read_exact(&mut command).unwrap();
+            // Device reset response with status FAILED.
+            master.write_all(&[0x40, 0x00, 0x00, 0x01, 0x01]).unwrap();
+            master.write_all(&DEVICE_STATUS_NTF).unwrap();
+            master
+        });
+        assert!(chip.close().await.is_err());
+        responder.join().unwrap();
+
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::FAILED)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn close_fails_when_the_reset_cannot_be_written() {
+        let (transport, _device) = MockTransport::seqpacket();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_write_timeout(Duration::from_millis(100))
+            .with_close_timeout(Duration::from_millis(100))
+            .with_write_queue_capacity(512);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // The UWBS reads nothing: the socket fills up, and the device
+        // reset cannot be written.
+        let mut command = vec![0x2e, 0x00, 0x00, 0xff];
+        command.resize(UCI_HEADER_SIZE + 0xff, 0);
+        for _ in 0..512 {
+            chip.sendUciMessage(&command).await.unwrap();
+        }
+        wait_for(|| chip.stats().tx_errors > 0).await;
+        assert!(chip.close().await.is_err());
+
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::FAILED)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_failure_reports_error_and_allows_reopen() {
+        let (master, _slave, path) = pty();
+        let link = temp_path("uwb-chip");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        let chip = uart_chip(link.to_str().unwrap().to_owned());
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Hanging up the pty makes reads on the chip side fail.
+        drop(master);
+        wait_for(|| {
+            recorder
+                .events
+                .lock()
+                .unwrap()
+                .contains(&(UwbEvent::ERROR, UwbStatus::FAILED))
+        })
+        .await;
+        wait_for(|| matches!(chip.state.try_lock().as_deref(), Ok(State::Closed))).await;
+
+        // The chip can be opened again once the device is back.
+        let (_master, _slave, path) = pty();
+        std::fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        chip.open(&callbacks).await.unwrap();
+        std::fs::remove_file(&link).unwrap();
+    }
+
+    /// Open a chip over a [`MockTransport`].
+    async fn mock_chip() -> (UwbChip<MockTransport>, MockUwbs, Arc<Recorder>) {
+        let (transport, uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        (chip, uwbs, recorder)
+    }
+
+    /// Run coreInit on `chip`, the UWBS reporting the capabilities `caps`.
+    async fn core_init_with_caps(
+        chip: &UwbChip<MockTransport>,
+        mut uwbs: MockUwbs,
+        caps: &[(CapTlvType, &[u8])],
+    ) -> MockUwbs {
+        let mut rsp = vec![0x40, 0x03, 0x00, 0x00, 0x00, caps.len() as u8];
+        for (tag, value) in caps {
+            rsp.extend([u8::from(*tag), value.len() as u8]);
+            rsp.extend(*value);
+        }
+        rsp[3] = (rsp.len() - UCI_HEADER_SIZE) as u8;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x00]);
+            uwbs.expect(&CORE_GET_CAPS_INFO_CMD);
+            uwbs.inject(&rsp);
+            uwbs
+        });
+        chip.coreInit().await.unwrap();
+        device.join().unwrap()
+    }
+
+    /// Locator failing to reach the client `failures` times before
+    /// finding it.
+    struct MockLocator {
+        failures: std::sync::Mutex<u32>,
+        attempts: std::sync::Mutex<Vec<Instant>>,
+        callbacks: Strong<dyn IUwbClientCallback>,
+    }
+
+    impl ClientLocator for MockLocator {
+        fn locate(
+            &self,
+        ) -> std::result::Result<Strong<dyn IUwbClientCallback>, binder::StatusCode> {
+            self.attempts.lock().unwrap().push(Instant::now());
+            let mut failures = self.failures.lock().unwrap();
+            if *failures == 0 {
+                return Ok(self.callbacks.clone());
+            }
+            *failures -= 1;
+            Err(binder::StatusCode::NAME_NOT_FOUND)
+        }
+    }
+
+    #[tokio::test]
+    async fn chip_reopens_when_client_is_back() {
+        let (transport, uwbs) = MockTransport::with_uwbs();
+        let (new_recorder, new_callbacks) = callbacks();
+        let locator = Arc::new(MockLocator {
+            failures: std::sync::Mutex::new(2),
+            attempts: Default::default(),
+            callbacks: new_callbacks,
+        });
+        let chip = Arc::new(
+            UwbChip::with_transport("0".to_owned(), transport)
+                .with_reconnect(Some(locator.clone())),
+        );
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        tokio::spawn(chip.clone().reconnect_on_client_death());
+
+        let responder = uwbs.respond_to_reset();
+        let death_handler = chip.death_handler();
+        let died = Instant::now();
+        tokio::task::spawn_blocking(move || death_handler.client_died())
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        while new_recorder.events.lock().unwrap().is_empty() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "chip not reopened"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // Joined once the reset task, run by this thread, completed.
+        let _uwbs = responder.join().unwrap();
+        assert_eq!(
+            *new_recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+        assert!(matches!(*chip.state.lock().await, State::Opened(_)));
+
+        // Every attempt waits twice as long as the previous one.
+        let attempts = locator.attempts.lock().unwrap().clone();
+        assert_eq!(attempts.len(), 3);
+        let mut previous = died;
+        for (attempt, instant) in attempts.into_iter().enumerate() {
+            assert!(instant - previous >= reconnect_delay(attempt as u32));
+            previous = instant;
+        }
+        let stats = chip.stats();
+        assert_eq!(stats.reconnect_attempts, 3);
+        assert_eq!(
+            stats.last_reconnect_error,
+            Some(binder::StatusCode::NAME_NOT_FOUND)
+        );
+    }
+
+    #[tokio::test]
+    async fn core_init_waits_for_uwbs() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        // UCI 1.1, MAC and PHY 1.3, test 1.0, vendor info [aa].
+        let response = [
+            0x40, 0x02, 0x00, 0x0b, 0x00, 0x01, 0x10, 0x01, 0x30, 0x01, 0x30, 0x01, 0x00, 0x01,
+            0xaa,
+        ];
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&response);
+            uwbs
+        });
+        chip.coreInit().await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::POST_INIT_CPLT, UwbStatus::OK)
+            ]
+        );
+        // The response is delivered to the client as well.
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(*recorder.messages.lock().unwrap(), vec![response.to_vec()]);
+
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("device: UCI 1.1.0, MAC 1.3.0, PHY 1.3.0, vendor info [aa]\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[tokio::test]
+    async fn calls_proceed_during_core_init() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        let chip = chip.with_core_init_timeout(Duration::from_secs(5));
+        // UCI 1.1, MAC and PHY 1.3, test 1.0, vendor info [aa].
+        let response = [
+            0x40, 0x02, 0x00, 0x0b, 0x00, 0x01, 0x10, 0x01, 0x30, 0x01, 0x30, 0x01, 0x00, 0x01,
+            0xaa,
+        ];
+        let (core_init, uwbs) = tokio::join!(chip.coreInit(), async {
+            // coreInit is awaiting the response of the UWBS.
+            assert_eq!(chip.getName().await.unwrap(), "0");
+            assert_eq!(chip.stats().open_count, 1);
+            chip.sessionInit(1).await.unwrap();
+            let command = [0x21, 0x05, 0x00, 0x00];
+            assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+                uwbs.expect(&command);
+                uwbs.inject(&response);
+                // No capability.
+                uwbs.expect(&CORE_GET_CAPS_INFO_CMD);
+                uwbs.inject(&[0x40, 0x03, 0x00, 0x02, 0x00, 0x00]);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        core_init.unwrap();
+        if let State::Opened(ref session) = *chip.state.lock().await {
+            assert!(session.device_info.is_some());
+        } else {
+            panic!("chip closed");
+        }
+
+        let (loopback, uwbs) = tokio::join!(chip.loopback_test(&[0x5a]), async {
+            // loopback_test is awaiting the echo of the UWBS.
+            assert_eq!(chip.getName().await.unwrap(), "0");
+            let command = [0x21, 0x05, 0x00, 0x00];
+            assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&[0x2d, 0x00, 0x00, 0x01, 0x5a]);
+                uwbs.expect(&command);
+                uwbs.inject(&[0x4d, 0x00, 0x00, 0x01, 0x5a]);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(loopback.unwrap(), vec![0x5a]);
+
+        let (info, _uwbs) = tokio::join!(chip.chip_info(), async {
+            // chip_info is awaiting the device info of the UWBS.
+            let command = [0x21, 0x05, 0x00, 0x00];
+            assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+                uwbs.expect(&command);
+                uwbs.inject(&response);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        info.unwrap();
+    }
+
+    #[tokio::test]
+    async fn android_uci_version_falls_back_without_caps() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        let mut uwbs = core_init_with_caps(&chip, uwbs, &[]).await;
+
+        // The UWBS is not queried: the next packet it reads is the one
+        // sent by the client.
+        assert_eq!(chip.getSupportedAndroidUciVersion().await.unwrap(), 1);
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+    }
+
+    #[tokio::test]
+    async fn android_uci_version_is_read_from_caps() {
+        let (transport, uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let status = chip.getSupportedAndroidUciVersion().await.unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        let (_recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+        let mut uwbs = core_init_with_caps(
+            &chip,
+            uwbs,
+            &[
+                (CapTlvType::SupportedMaxRangingSessionNumber, &[0x05]),
+                (CapTlvType::SupportedAndroidUciProfiles, &[0x02]),
+            ],
+        )
+        .await;
+        assert_eq!(chip.getSupportedAndroidUciVersion().await.unwrap(), 2);
+        // Once learned, the version outlives the session.
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&DEVICE_RESET_CMD);
+            uwbs.inject(&DEVICE_RESET_RSP);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        chip.close().await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(chip.getSupportedAndroidUciVersion().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn configured_android_uci_version_is_not_queried() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip =
+            UwbChip::with_transport("0".to_owned(), transport).with_android_uci_version(Some(2));
+        // Known before open.
+        assert_eq!(chip.getSupportedAndroidUciVersion().await.unwrap(), 2);
+
+        let (_recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+        assert_eq!(chip.getSupportedAndroidUciVersion().await.unwrap(), 2);
+        // The first packet read by the UWBS is the one sent by the client.
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+    }
+
+    #[tokio::test]
+    async fn android_uci_version_is_derived_from_device_info() {
+        let (chip, mut uwbs, _recorder) = mock_chip().await;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            // UCI 2.0, MAC and PHY 1.3, test 1.0, no vendor info.
+            uwbs.inject(&[
+                0x40, 0x02, 0x00, 0x0a, 0x00, 0x02, 0x00, 0x01, 0x30, 0x01, 0x30, 0x01, 0x00, 0x00,
+            ]);
+            // No capability.
+            uwbs.expect(&CORE_GET_CAPS_INFO_CMD);
+            uwbs.inject(&[0x40, 0x03, 0x00, 0x02, 0x00, 0x00]);
+            uwbs
+        });
+        chip.coreInit().await.unwrap();
+        assert_eq!(chip.getSupportedAndroidUciVersion().await.unwrap(), 2);
+        let _uwbs = device.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn core_init_continues_without_response() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_core_init_timeout(Duration::from_millis(50));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs
+        });
+        chip.coreInit().await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::POST_INIT_CPLT, UwbStatus::OK)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn vendor_messages_are_routed_to_vendor_callback() {
+        let vendor_ntf = [0x6e, 0x01, 0x00, 0x01, 0x00];
+        // Data packet with the same first octet, but no GID.
+        let data = [0x0e, 0x00, 0x01, 0x00, 0xaa];
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+
+        // Without a vendor callback, vendor messages reach the client.
+        uwbs.inject(&vendor_ntf);
+        wait_for(|| recorder.messages.lock().unwrap().len() == 1).await;
+        assert_eq!(recorder.messages.lock().unwrap()[0], vendor_ntf);
+
+        let vendor_messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        chip.set_vendor_callback(Some(Arc::new({
+            let vendor_messages = vendor_messages.clone();
+            move |packet: &[u8]| vendor_messages.lock().unwrap().push(packet.to_vec())
+        })));
+        uwbs.inject(&vendor_ntf);
+        uwbs.inject(&data);
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| recorder.messages.lock().unwrap().len() == 3).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![
+                vendor_ntf.to_vec(),
+                data.to_vec(),
+                DEVICE_STATUS_NTF.to_vec()
+            ]
+        );
+        assert_eq!(*vendor_messages.lock().unwrap(), vec![vendor_ntf.to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn reopen_after_client_death_sees_clean_device() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&DEVICE_RESET_CMD);
+            // Ranging notification left over from the previous session.
+            uwbs.inject(&[0x62, 0x00, 0x00, 0x01, 0x00]);
+            uwbs.inject(&DEVICE_RESET_RSP);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        let death_handler = chip.death_handler();
+        tokio::task::spawn_blocking(move || death_handler.client_died())
+            .await
+            .unwrap();
+
+        // Waits for the reset, instead of being refused.
+        let (new_recorder, new_callbacks) = callbacks();
+        chip.open(&new_callbacks).await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(
+            *new_recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+        assert!(new_recorder.messages.lock().unwrap().is_empty());
+        // The dead client is not notified.
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+        assert_eq!(chip.stats().close_count, 1);
+    }
+
+    /// Return whether the UWBS of `chip` is suspended.
+    async fn is_suspended(chip: &UwbChip<MockTransport>) -> bool {
+        matches!(
+            *chip.state.lock().await,
+            State::Opened(ref session) if session.suspended
+        )
+    }
+
+    #[tokio::test]
+    async fn suspended_chip_is_resumed() {
+        let (chip, uwbs, recorder) = mock_chip().await;
+        let (suspend, mut uwbs) = tokio::join!(chip.suspend(), async {
+            // suspend is awaiting the response of the UWBS.
+            chip.sessionInit(1).await.unwrap();
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&DEVICE_SUSPEND_CMD);
+                uwbs.inject(&[0x40, 0x06, 0x00, 0x01, 0x00]);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        suspend.unwrap();
+        assert!(is_suspended(&chip).await);
+        assert!(matches!(chip.report(false).state, ChipState::Suspended(_)));
+
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x00]);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        chip.resume().await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert!(!is_suspended(&chip).await);
+        // The client, which did not call open, gets no OPEN_CPLT.
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+        // The responses awaited by the chip are not delivered, unlike the
+        // notification of the UWBS being ready.
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+        assert_eq!(
+            chip.resume().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[tokio::test]
+    async fn messages_wake_suspended_chip() {
+        let (chip, mut uwbs, _recorder) = mock_chip().await;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&DEVICE_SUSPEND_CMD);
+            uwbs.inject(&[0x40, 0x06, 0x00, 0x01, 0x00]);
+            uwbs
+        });
+        chip.suspend().await.unwrap();
+        let mut uwbs = device.join().unwrap();
+
+        chip.sendUciMessage(&[0x20, 0x02, 0x00, 0x00])
+            .await
+            .unwrap();
+        flush_writes().await;
+        uwbs.expect(&[0x20, 0x02, 0x00, 0x00]);
+        assert!(!is_suspended(&chip).await);
+        assert_eq!(
+            chip.resume().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[tokio::test]
+    async fn suspend_fails_without_response() {
+        let (chip, _uwbs, _recorder) = mock_chip().await;
+        let err = chip.suspend().await.unwrap_err();
+        assert_eq!(err.service_specific_error(), UwbStatus::ERR_CMD_TIMEOUT.0);
+        assert!(!is_suspended(&chip).await);
+    }
+
+    #[tokio::test]
+    async fn resume_waits_for_ready_state() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&DEVICE_SUSPEND_CMD);
+            uwbs.inject(&[0x40, 0x06, 0x00, 0x01, 0x00]);
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x00]);
+            uwbs
+        });
+        chip.suspend().await.unwrap();
+        let err = chip.resume().await.unwrap_err();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(err.service_specific_error(), UwbStatus::ERR_CMD_TIMEOUT.0);
+        assert!(is_suspended(&chip).await);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+    }
+
+    #[test]
+    #[ignore = "requires CAP_SYS_NICE"]
+    fn reader_scheduling_sets_the_nice_value() {
+        // The nice value is per thread: the other tests keep theirs.
+        std::thread::spawn(|| {
+            assert_eq!(ReaderScheduling::apply(-5), ReaderScheduling::Nice(-5));
+            // SAFETY: getpriority only takes integers.
+            assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, -5);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn suspend_requires_opened_chip() {
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        assert_eq!(
+            chip.suspend().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+        assert_eq!(
+            chip.resume().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[tokio::test]
+    async fn close_resets_uwbs() {
+        let (chip, uwbs, recorder) = mock_chip().await;
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::CLOSE_CPLT, UwbStatus::OK)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn received_packets_are_delivered() {
+        let (_chip, mut uwbs, recorder) = mock_chip().await;
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn sent_packets_reach_uwbs() {
+        let (chip, mut uwbs, _recorder) = mock_chip().await;
+        let command = [0x20, 0x02, 0x00, 0x00];
+        assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+        flush_writes().await;
+        uwbs.expect(&command);
+    }
+
+    #[tokio::test]
+    async fn send_waits_for_slow_consumer() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path).with_write_timeout(Duration::from_secs(5));
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Large enough to overflow the pty buffers.
+        let data = large_data_packet();
+        let len = data.len();
+        let expected = data.clone();
+        let expected_len = expected.len();
+        let consumer = std::thread::spawn(move || {
+            let mut received = vec![0; expected_len];
+            for chunk in received.chunks_mut(1024) {
+                master.read_exact(chunk).unwrap();
+                std::thread::sleep(Duration::from_micros(100));
+            }
+            received
+        });
+
+        assert_eq!(chip.sendUciMessage(&data).await.unwrap(), len as i32);
+        // The writer task waits for the consumer.
+        wait_for(|| consumer.is_finished()).await;
+        assert_eq!(consumer.join().unwrap(), expected);
+        assert_eq!(chip.stats().tx_errors, 0);
+    }
+
+    #[tokio::test]
+    async fn stalled_write_does_not_block_other_calls() {
+        let (mut master, _slave, path) = pty();
+        let chip = Arc::new(uart_chip(path).with_write_timeout(Duration::from_secs(5)));
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Nobody reads the pty: the write stalls once its buffers are full.
+        let data = large_data_packet();
+        let len = data.len();
+        let expected = data.clone();
+        let expected_len = expected.len();
+        assert_eq!(chip.sendUciMessage(&data).await.unwrap(), len as i32);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = Instant::now();
+        assert_eq!(chip.getName().await.unwrap(), "0");
+        chip.sessionInit(1).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        let consumer = std::thread::spawn(move || {
+            let mut received = vec![0; expected_len];
+            master.read_exact(&mut received).unwrap();
+            (master, received)
+        });
+        wait_for(|| consumer.is_finished()).await;
+        let (master, received) = consumer.join().unwrap();
+        assert_eq!(received, expected);
+
+        // Close still fences off writes.
+        let responder = respond_to_close(master, vec![1]);
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+        assert_eq!(
+            chip.sendUciMessage(&[0x20, 0x02, 0x00, 0x00])
+                .await
+                .unwrap_err()
+                .exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_packets_are_written_in_order() {
+        let (chip, mut uwbs, _recorder) = mock_chip().await;
+        let commands: Vec<_> = (0..10u8).map(|i| [0x2e, i, 0x00, 0x01, i]).collect();
+        for command in &commands {
+            assert_eq!(chip.sendUciMessage(command).await.unwrap(), 5);
+        }
+        flush_writes().await;
+        for command in &commands {
+            uwbs.expect(command);
+        }
+    }
+
+    #[tokio::test]
+    async fn full_write_queue_and_write_errors_are_reported() {
+        let (_master, _slave, path) = pty();
+        let chip = uart_chip(path)
+            .with_write_timeout(Duration::from_millis(100))
+            .with_write_queue_capacity(1);
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Nobody reads the pty: the writer task stalls on the first
+        // packet, and the second one fills the queue.
+        let command = [0x20, 0x02, 0x00, 0x00];
+        chip.sendUciMessage(&large_data_packet()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        chip.sendUciMessage(&command).await.unwrap();
+        assert_eq!(
+            chip.sendUciMessage(&command)
+                .await
+                .unwrap_err()
+                .service_specific_error(),
+            UwbStatus::REFUSED.0
+        );
+        assert_eq!(chip.stats().tx_errors, 1);
+
+        // The write timing out is reported by the next call, once.
+        wait_for(|| chip.stats().tx_errors > 1).await;
+        assert_eq!(
+            chip.sendUciMessage(&command)
+                .await
+                .unwrap_err()
+                .service_specific_error(),
+            UwbStatus::ERR_TRANSPORT.0
+        );
+        assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn stalled_writes_time_out() {
+        let (transport, _device) = MockTransport::seqpacket();
+        let timeout = Duration::from_millis(100);
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_write_timeout(timeout)
+            .with_write_queue_capacity(512);
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // The UWBS reads nothing: the socket fills up, and the writer task
+        // stalls.
+        let mut command = vec![0x2e, 0x00, 0x00, 0xff];
+        command.resize(UCI_HEADER_SIZE + 0xff, 0);
+        let start = Instant::now();
+        for _ in 0..512 {
+            chip.sendUciMessage(&command).await.unwrap();
+        }
+        wait_for(|| chip.stats().tx_errors > 0).await;
+        assert!(start.elapsed() < DEFAULT_WRITE_TIMEOUT);
+        let err = chip.sendUciMessage(&command).await.unwrap_err();
+        assert_eq!(err.service_specific_error(), UwbStatus::ERR_TRANSPORT.0);
+        assert!(
+            err.to_string().contains("write timed out after 100ms"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_are_tracked() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        assert!(chip.sessionInit(1).await.is_err());
+        chip.open(&callbacks).await.unwrap();
+
+        chip.sessionInit(1).await.unwrap();
+        chip.sessionInit(2).await.unwrap();
+        assert_eq!(
+            chip.sessionInit(1).await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        // A session is forgotten once the UWBS confirms its
+        // deinitialization, not when it is requested.
+        chip.sendUciMessage(&session_deinit_cmd(1)).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&session_deinit_cmd(1));
+        assert!(chip.sessionInit(1).await.is_err());
+        uwbs.inject(&SESSION_DEINIT_RSP);
+        wait_for(|| recorder.messages.lock().unwrap().len() == 1).await;
+        chip.sessionInit(1).await.unwrap();
+
+        // Nor when the UWBS fails to deinitialize it.
+        chip.sendUciMessage(&session_deinit_cmd(2)).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&session_deinit_cmd(2));
+        uwbs.inject(&[0x41, 0x01, 0x00, 0x01, 0x01]);
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+        assert!(chip.sessionInit(2).await.is_err());
+        assert!(chip.session_stats(2).is_ok());
+    }
+
+    #[tokio::test]
+    async fn sessions_are_tracked_by_handle() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let handle = |id: i32| 0x0a0b0c00 + id;
+        let mut messages = 0;
+        // Twice as many sessions as the UWBS supports, one after the other.
+        for id in 1..=2 * DEFAULT_MAX_SESSIONS as i32 {
+            // SESSION_INIT_CMD of session `id`, answered with the handle
+            // assigned by a UCI 2.0 UWBS.
+            let mut command = vec![0x21, 0x00, 0x00, 0x05];
+            command.extend(id.to_le_bytes());
+            command.push(0x00);
+            chip.sendUciMessage(&command).await.unwrap();
+            flush_writes().await;
+            uwbs.expect(&command);
+            let mut rsp = vec![0x41, 0x00, 0x00, 0x05, 0x00];
+            rsp.extend(handle(id).to_le_bytes());
+            uwbs.inject(&rsp);
+            messages += 1;
+            wait_for(|| recorder.messages.lock().unwrap().len() == messages).await;
+            chip.sessionInit(id).await.unwrap();
+            assert!(chip.session_stats(id).is_ok());
+
+            // Deinitialized by handle.
+            uwbs.inject(&session_deinit_ntf(handle(id)));
+            messages += 1;
+            wait_for(|| recorder.messages.lock().unwrap().len() == messages).await;
+            assert!(chip.session_stats(id).is_err());
+        }
+
+        // The sessions left are deinitialized by handle on close.
+        let mut command = vec![0x21, 0x00, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00];
+        chip.sendUciMessage(&command).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&command);
+        command = vec![0x41, 0x00, 0x00, 0x05, 0x00];
+        command.extend(handle(1).to_le_bytes());
+        uwbs.inject(&command);
+        messages += 1;
+        wait_for(|| recorder.messages.lock().unwrap().len() == messages).await;
+        chip.sessionInit(1).await.unwrap();
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&session_deinit_cmd(handle(1)));
+            uwbs.inject(&SESSION_DEINIT_RSP);
+            uwbs.expect(&DEVICE_RESET_CMD);
+            uwbs.inject(&DEVICE_RESET_RSP);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        chip.close().await.unwrap();
+        let _uwbs = device.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn ranging_is_counted_per_session() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        chip.sessionInit(1).await.unwrap();
+        // Two-way RANGE_DATA_NTF of session 1, with short MAC addresses.
+        let range_data_ntf = |statuses: &[u8]| {
+            let mut packet = vec![0x62, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            packet.extend(1u32.to_le_bytes());
+            packet.extend([0x00; 5]);
+            packet.push(0x01);
+            packet.extend([0x00; 10]);
+            packet.push(statuses.len() as u8);
+            for &status in statuses {
+                let mut measurement = [0x00; 31];
+                measurement[2] = status;
+                packet.extend(measurement);
+            }
+            packet[3] = (packet.len() - UCI_HEADER_SIZE) as u8;
+            packet
+        };
+        // A success and a peer out of range, then a success.
+        uwbs.inject(&range_data_ntf(&[0x00, 0x21]));
+        uwbs.inject(&range_data_ntf(&[0x00]));
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+
+        assert_eq!(
+            chip.session_stats(1).unwrap(),
+            SessionStats {
+                ranging_count: 3,
+                ranging_success: 2,
+                ranging_oor: 1,
+                last_status: 0x00,
+            }
+        );
+        assert_eq!(
+            chip.session_stats(2).unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_ARGUMENT
+        );
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("session 1: ranging 3, success 2, out of range 1, last status 0x00\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[tokio::test]
+    async fn calibration_is_saved_and_restored() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let path = temp_path("uwb_calibration");
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&OEM_GET_CALIBRATION_CMD);
+            uwbs.inject(&[0x4e, 0x30, 0x00, 0x04, 0x00, 0x11, 0x22, 0x33]);
+            uwbs.expect(&[0x2e, 0x31, 0x00, 0x03, 0x11, 0x22, 0x33]);
+            uwbs.inject(&[0x4e, 0x31, 0x00, 0x01, 0x00]);
+            uwbs
+        });
+        chip.save_calibration(&path).await.unwrap();
+        chip.restore_calibration(&path).await.unwrap();
+        let mut uwbs = device.join().unwrap();
+        // The responses are not delivered to the client.
+        assert!(recorder.messages.lock().unwrap().is_empty());
+
+        // A corrupted file is not written to the UWBS: the next packet it
+        // reads is the one sent by the client.
+        let mut file = std::fs::read(&path).unwrap();
+        file[3] ^= 0xff;
+        std::fs::write(&path, &file).unwrap();
+        let err = chip.restore_calibration(&path).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+        std::fs::remove_file(&path).unwrap();
+
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("1".to_owned(), transport);
+        let err = chip.save_calibration(&path).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn calls_proceed_during_calibration() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        let path = temp_path("uwb_calibration");
+        let (saved, _uwbs) = tokio::join!(chip.save_calibration(&path), async {
+            // save_calibration is awaiting the response of the UWBS.
+            let command = [0x21, 0x05, 0x00, 0x00];
+            assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&OEM_GET_CALIBRATION_CMD);
+                uwbs.expect(&command);
+                uwbs.inject(&[0x4e, 0x30, 0x00, 0x04, 0x00, 0x11, 0x22, 0x33]);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        saved.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn loopback_test_checks_echo() {
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("1".to_owned(), transport);
+        let status = chip.loopback_test(&[0x01]).await.unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&[0x2d, 0x00, 0x00, 0x02, 0x5a, 0xa5]);
+            uwbs.inject(&[0x4d, 0x00, 0x00, 0x02, 0x5a, 0xa5]);
+            uwbs.expect(&[0x2d, 0x00, 0x00, 0x02, 0x5a, 0xa5]);
+            // A flipped bit.
+            uwbs.inject(&[0x4d, 0x00, 0x00, 0x02, 0x5a, 0xa4]);
+        });
+        assert_eq!(
+            chip.loopback_test(&[0x5a, 0xa5]).await.unwrap(),
+            [0x5a, 0xa5]
+        );
+        let status = chip.loopback_test(&[0x5a, 0xa5]).await.unwrap_err();
+        assert_eq!(status.service_specific_error(), UwbStatus::FAILED.0);
+        assert!(
+            status.to_string().contains("loopback payload mismatch"),
+            "{}",
+            status
+        );
+        device.join().unwrap();
+        // The responses are not delivered to the client.
+        assert!(recorder.messages.lock().unwrap().is_empty());
+
+        let status = chip.loopback_test(&[0; 256]).await.unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::ILLEGAL_ARGUMENT
+        );
+    }
+
+    #[tokio::test]
+    async fn chip_info_is_read_once_per_session() {
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("1".to_owned(), transport);
+        let status = chip.chip_info().await.unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&[
+                0x40, 0x02, 0x00, 0x0e, 0x00, 0x02, 0x00, 0x01, 0x30, 0x01, 0x31, 0x01, 0x10, 0x04,
+                0xde, 0xad, 0xbe, 0xef,
+            ]);
+            uwbs
+        });
+        let info = chip.chip_info().await.unwrap();
+        assert_eq!(
+            info,
+            ChipInfo {
+                firmware_version: "UCI 2.0.0, MAC 1.3.0, test 1.1.0".to_owned(),
+                hardware_revision: "PHY 1.3.1".to_owned(),
+                chip_uid: vec![0xde, 0xad, 0xbe, 0xef],
+            }
+        );
+        let _uwbs = device.join().unwrap();
+        // The response is not delivered to the client. The UWBS no longer
+        // answers: the cached info is returned without asking it again.
+        assert!(recorder.messages.lock().unwrap().is_empty());
+        assert_eq!(chip.chip_info().await.unwrap(), info);
+    }
+
+    #[tokio::test]
+    async fn firmware_update_flashes_chunks() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let status = chip
+            .firmware_update(&vec![0; MAX_FW_IMAGE_SIZE + 1])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::ILLEGAL_ARGUMENT
+        );
+
+        let image: Vec<u8> = (0..=255).cycle().take(MAX_FW_CHUNK_SIZE + 2).collect();
+        let device = std::thread::spawn({
+            let image = image.clone();
+            move || {
+                let mut command = vec![0x2f, 0x00, 0x00, 0xff];
+                command.extend_from_slice(&image[..MAX_FW_CHUNK_SIZE]);
+                uwbs.expect(&command);
+                uwbs.inject(&[0x4f, 0x00, 0x00, 0x01, 0x00]);
+                let mut command = vec![0x2f, 0x00, 0x00, 0x02];
+                command.extend_from_slice(&image[MAX_FW_CHUNK_SIZE..]);
+                uwbs.expect(&command);
+                uwbs.inject(&[0x4f, 0x00, 0x00, 0x01, 0x00]);
+                uwbs.expect(&FIRMWARE_DOWNLOAD_COMPLETE_CMD);
+                uwbs.inject(&[0x4f, 0x01, 0x00, 0x01, 0x00]);
+                uwbs.inject(&DEVICE_STATUS_NTF);
+
+                // The first chunk of the next update is rejected.
+                uwbs.expect(&[0x2f, 0x00, 0x00, 0x01, 0xaa]);
+                uwbs.inject(&[0x4f, 0x00, 0x00, 0x01, 0x01]);
+                uwbs.expect(&DEVICE_RESET_CMD);
+                uwbs.inject(&DEVICE_RESET_RSP);
+                uwbs.inject(&DEVICE_STATUS_NTF);
+            }
+        });
+        chip.firmware_update(&image).await.unwrap();
+        // Only the notification of the UWBS booting the image is
+        // delivered to the client.
+        wait_for(|| recorder.messages.lock().unwrap().len() == 1).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+
+        let status = chip.firmware_update(&[0xaa]).await.unwrap_err();
+        device.join().unwrap();
+        assert_eq!(status.service_specific_error(), UwbStatus::FAILED.0);
+        let message = status.to_string();
+        assert!(
+            message.contains("firmware update failed: chunk 1 of 1: ")
+                && message.contains("firmware download failed with status Some(01)"),
+            "{}",
+            message
+        );
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+        );
+        let status = chip.firmware_update(&[0xaa]).await.unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[tokio::test]
+    async fn calls_proceed_during_firmware_update() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        let (updated, _uwbs) = tokio::join!(chip.firmware_update(&[0xaa]), async {
+            // firmware_update is awaiting the response of the UWBS.
+            let command = [0x21, 0x05, 0x00, 0x00];
+            assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&[0x2f, 0x00, 0x00, 0x01, 0xaa]);
+                uwbs.expect(&command);
+                uwbs.inject(&[0x4f, 0x00, 0x00, 0x01, 0x00]);
+                uwbs.expect(&FIRMWARE_DOWNLOAD_COMPLETE_CMD);
+                uwbs.inject(&[0x4f, 0x01, 0x00, 0x01, 0x00]);
+                uwbs.inject(&DEVICE_STATUS_NTF);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        updated.unwrap();
+    }
+
+    #[tokio::test]
+    async fn health_checker_probes_opened_chip() {
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = Arc::new(UwbChip::with_transport("1".to_owned(), transport));
+        assert_eq!(
+            UwbHealthChecker::new(chip).check().await,
+            HealthStatus::NotApplicable
+        );
+
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let checker = UwbHealthChecker::new(Arc::new(chip));
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x00]);
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            // Rejected.
+            uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x01]);
+        });
+        assert_eq!(checker.check().await, HealthStatus::Ok);
+        assert_eq!(
+            checker.check().await,
+            HealthStatus::Degraded("probe failed with status Some(01)".to_owned())
+        );
+        device.join().unwrap();
+        // The responses are not delivered to the client.
+        assert!(recorder.messages.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn calls_proceed_during_health_check() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        let chip = Arc::new(chip);
+        let checker = UwbHealthChecker::new(chip.clone());
+        let (status, _uwbs) = tokio::join!(checker.check(), async {
+            // The health check is awaiting the response of the UWBS.
+            let command = [0x21, 0x05, 0x00, 0x00];
+            assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 4);
+            let mut uwbs = uwbs;
+            tokio::task::spawn_blocking(move || {
+                uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+                uwbs.expect(&command);
+                uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x00]);
+                uwbs
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(status, HealthStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn core_init_sends_calibration_commands() {
+        let path = temp_path("uwb_calibration_commands");
+        std::fs::write(&path, "2e0100024652\n2e0200011a\n2e03000100\n").unwrap();
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_calibration_commands(Some(path.clone()));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        // Updates apply from the next open.
+        std::fs::remove_file(&path).unwrap();
+
+        let core_init_rsp = [0x40, 0x02, 0x00, 0x01, 0x00];
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+            uwbs.inject(&core_init_rsp);
+            // No capability.
+            uwbs.expect(&CORE_GET_CAPS_INFO_CMD);
+            uwbs.inject(&[0x40, 0x03, 0x00, 0x02, 0x00, 0x00]);
+            uwbs.expect(&[0x2e, 0x01, 0x00, 0x02, 0x46, 0x52]);
+            uwbs.inject(&[0x4e, 0x01, 0x00, 0x01, 0x00]);
+            uwbs.expect(&[0x2e, 0x02, 0x00, 0x01, 0x1a]);
+            // Rejected.
+            uwbs.inject(&[0x4e, 0x02, 0x00, 0x01, 0x01]);
+            uwbs
+        });
+        chip.coreInit().await.unwrap();
+        let mut uwbs = device.join().unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::POST_INIT_CPLT, UwbStatus::OK)
+            ]
+        );
+
+        // The last command is not sent: the next packet the UWBS reads is
+        // the one sent by the client.
+        chip.sendUciMessage(&TEST_LOOPBACK_CMD).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&TEST_LOOPBACK_CMD);
+        // Only the response to CORE_GET_DEVICE_INFO_CMD is delivered to the client.
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![core_init_rsp.to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn session_init_stops_at_max_sessions() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        for id in 0..DEFAULT_MAX_SESSIONS as i32 {
+            chip.sessionInit(id).await.unwrap();
+        }
+        let status = chip.sessionInit(5).await.unwrap_err();
+        assert_eq!(
+            status.exception_code(),
+            binder::ExceptionCode::SERVICE_SPECIFIC
+        );
+        assert_eq!(status.service_specific_error(), 0x13);
+
+        // The UWBS supports one more session.
+        let _uwbs = core_init_with_caps(
+            &chip,
+            uwbs,
+            &[(CapTlvType::SupportedMaxRangingSessionNumber, &[0x06])],
+        )
+        .await;
+        chip.sessionInit(5).await.unwrap();
+        assert_eq!(
+            chip.sessionInit(6)
+                .await
+                .unwrap_err()
+                .service_specific_error(),
+            0x13
+        );
+    }
+
+    #[tokio::test]
+    async fn zero_max_sessions_is_ignored() {
+        let (chip, uwbs, _recorder) = mock_chip().await;
+        let _uwbs = core_init_with_caps(
+            &chip,
+            uwbs,
+            &[(CapTlvType::SupportedMaxRangingSessionNumber, &[0x00])],
+        )
+        .await;
+        for id in 0..DEFAULT_MAX_SESSIONS as i32 {
+            chip.sessionInit(id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn data_messages_are_segmented() {
+        let (chip, uwbs, recorder) = mock_chip().await;
+        chip.sessionInit(1).await.unwrap();
+        // The UWBS accepts 16 bytes of data payload.
+        let mut uwbs = core_init_with_caps(
+            &chip,
+            uwbs,
+            &[(CapTlvType::SupportedMaxDataPacketPayloadSize, &[0x10, 0x00])],
+        )
+        .await;
+
+        // DATA_MESSAGE_SND for session 1, numbered 0xffff by the client,
+        // with 2 bytes of application data. The sequence number is kept.
+        let mut message = vec![0x01, 0x00, 0x12, 0x00, 0x01, 0x00, 0x00, 0x00];
+        message.extend([0xaa; 8]);
+        message.extend([0xff, 0xff, 0x02, 0x00, 0x11, 0x22]);
+        let mut first_segment = message[..20].to_vec();
+        first_segment[0] = 0x11;
+        first_segment[2] = 0x10;
+        let last_segment = [0x01, 0x00, 0x02, 0x00, 0x11, 0x22];
+        // After the response to CORE_GET_DEVICE_INFO_CMD.
+        for count in 2..=3 {
+            chip.sendUciMessage(&message).await.unwrap();
+            flush_writes().await;
+            uwbs.expect(&first_segment);
+            uwbs.expect(&last_segment);
+            // DATA_CREDIT_NTF for the next message.
+            uwbs.inject(&[0x62, 0x04, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x01]);
+            wait_for(|| recorder.messages.lock().unwrap().len() == count).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn close_with_active_sessions() {
+        let (master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        chip.sessionInit(1).await.unwrap();
+
+        let responder = respond_to_close(master, vec![1]);
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+        );
+        // Sessions do not outlive the chip.
+        assert!(chip.session_stats(1).is_err());
+    }
+
+    #[tokio::test]
+    async fn close_deinits_remaining_sessions() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        for id in 1..=3 {
+            chip.sessionInit(id).await.unwrap();
+        }
+        // The client deinitialized session 2.
+        uwbs.inject(&session_deinit_ntf(2));
+        wait_for(|| recorder.messages.lock().unwrap().len() == 1).await;
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("sessions {1, 3} of 5"), "{}", output);
+
+        let device = std::thread::spawn(move || {
+            for id in [1, 3] {
+                uwbs.expect(&session_deinit_cmd(id));
+                uwbs.inject(&SESSION_DEINIT_RSP);
+                uwbs.inject(&session_deinit_ntf(id));
+            }
+            uwbs.expect(&DEVICE_RESET_CMD);
+            uwbs.inject(&DEVICE_RESET_RSP);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        chip.close().await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+        );
+    }
+
+    #[tokio::test]
+    async fn tcp_peer_disconnect_closes_chip() {
+        use crate::transport::TcpTransport;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let chip = UwbChip::with_transport("0".to_owned(), TcpTransport::new(address));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        chip.sendUciMessage(&DEVICE_RESET_CMD).await.unwrap();
+        flush_writes().await;
+        let mut buffer = [0; 5];
+        peer.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, DEVICE_RESET_CMD);
+        peer.write_all(&DEVICE_STATUS_NTF).unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 1).await;
+
+        drop(peer);
+        wait_for(|| {
+            recorder
+                .events
+                .lock()
+                .unwrap()
+                .contains(&(UwbEvent::ERROR, UwbStatus::FAILED))
+        })
+        .await;
+        assert_eq!(
+            chip.close().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        // The UWBS is gone: opening fails without panicking.
+        drop(listener);
+        assert_eq!(
+            chip.open(&callbacks)
+                .await
+                .unwrap_err()
+                .service_specific_error(),
+            UwbStatus::FAILED.0
+        );
+    }
+
+    #[tokio::test]
+    async fn unix_socket_chip_reconnects_after_close() {
+        use crate::transport::{UnixAddress, UnixTransport};
+        use std::os::unix::net::UnixListener;
+
+        let path = temp_path("uwb-socket");
+        let mut chip = UwbChip::with_transport(
+            "0".to_owned(),
+            UnixTransport::new(UnixAddress::Path(path.to_str().unwrap().to_owned())),
+        );
+        chip.flap_guard = Arc::new(std::sync::Mutex::new(FlapGuard::new(FlapGuardConfig {
+            min_interval: Duration::ZERO,
+            ..Default::default()
+        })));
+        let (recorder, callbacks) = callbacks();
+
+        // The daemon is not started yet.
+        assert_eq!(
+            chip.open(&callbacks)
+                .await
+                .unwrap_err()
+                .service_specific_error(),
+            UwbStatus::FAILED.0
+        );
+
+        for messages in 1..=2 {
+            // (Re)start the daemon.
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            chip.open(&callbacks).await.unwrap();
+            let (peer, _) = listener.accept().unwrap();
+            let mut peer = File::from(OwnedFd::from(peer));
+
+            chip.sendUciMessage(&DEVICE_STATUS_NTF).await.unwrap();
+            flush_writes().await;
+            let mut buffer = [0; 5];
+            peer.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, DEVICE_STATUS_NTF);
+            peer.write_all(&DEVICE_STATUS_NTF).unwrap();
+            wait_for(|| recorder.messages.lock().unwrap().len() == messages).await;
+
+            let responder = respond_to_reset(peer);
+            chip.close().await.unwrap();
+            responder.join().unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_retries_connection() {
+        use crate::transport::{UnixAddress, UnixTransport};
+        use std::os::unix::net::UnixListener;
+
+        let path = temp_path("uwb-socket");
+        let chip = UwbChip::with_transport(
+            "0".to_owned(),
+            UnixTransport::new(UnixAddress::Path(path.to_str().unwrap().to_owned())),
+        )
+        .with_connect_retries(5);
+        let (_recorder, callbacks) = callbacks();
+
+        // The daemon starts after the first attempts.
+        let daemon = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                std::thread::sleep(2 * DEFAULT_CONNECT_BACKOFF);
+                let listener = UnixListener::bind(&path).unwrap();
+                listener.accept().unwrap().0
+            }
+        });
+        chip.open(&callbacks).await.unwrap();
+        let _peer = daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_waits_for_device_node() {
+        let (_master, _slave, pty_path) = pty();
+        let path = temp_path("uwb-tty");
+        let chip = uart_chip(path.to_str().unwrap().to_owned()).with_connect_retries(5);
+        let (_recorder, callbacks) = callbacks();
+
+        // The driver creates the device node after the first attempts.
+        let driver = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                std::thread::sleep(2 * DEFAULT_CONNECT_BACKOFF);
+                std::os::unix::fs::symlink(pty_path, path).unwrap();
+            }
+        });
+        chip.open(&callbacks).await.unwrap();
+        driver.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn close_abandons_retrying_open() {
+        let path = temp_path("uwb-tty");
+        let chip = uart_chip(path.to_str().unwrap().to_owned())
+            .with_connect_retries(5)
+            .with_connect_backoff(Duration::from_secs(1));
+        let (recorder, callbacks) = callbacks();
+
+        let start = Instant::now();
+        let (open, close) = tokio::join!(chip.open(&callbacks), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            chip.close().await
+        });
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(open.is_err());
+        close.unwrap();
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert!(recorder.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn busy_devices_are_reported() {
+        let err = open_error(&"/dev/ttyUWB0", io::Error::from_raw_os_error(libc::EBUSY));
+        assert_eq!(err.code(), crate::error::HalErrorCode::ErrTransport);
+        assert!(
+            err.to_string().starts_with("/dev/ttyUWB0 is busy"),
+            "{}",
+            err
+        );
+        assert!(!is_not_ready(&io::Error::from_raw_os_error(libc::EBUSY)));
+        assert!(is_not_ready(&io::Error::from_raw_os_error(libc::EACCES)));
+    }
+
+    /// Downloader failing its first `failures` attempts after writing a
+    /// few bytes, as a bootloader dropping off mid-download.
+    struct FlakyDownloader {
+        failures: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl FirmwareDownloader for FlakyDownloader {
+        async fn download(
+            &self,
+            transport: &mut dyn AsyncTransport,
+            image: &[u8],
+        ) -> io::Result<()> {
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                transport.get_mut().write_all(&image[..4])?;
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            ChunkedDownloader::new(100).download(transport, image).await
+        }
+    }
+
+    fn flaky_firmware(path: std::path::PathBuf, failures: usize) -> FirmwareConfig {
+        FirmwareConfig {
+            retries: 1,
+            downloader: Arc::new(FlakyDownloader {
+                failures: AtomicUsize::new(failures),
+            }),
+            ..FirmwareConfig::new(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn open_downloads_firmware() {
+        let path = temp_path("uwb-firmware");
+        let image: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        std::fs::write(&path, &image).unwrap();
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_firmware(Some(flaky_firmware(path.clone(), 1)));
+        let (recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Started over after the failure.
+        uwbs.expect(&image[..4]);
+        uwbs.expect(&image);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+        let stats = chip.stats();
+        assert_eq!(stats.firmware_downloads, 1);
+        assert_eq!(stats.firmware_download_failures, 1);
+        assert!(stats.last_firmware_download.is_some());
+
+        // The UWBS speaks UCI from then on.
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+    }
+
+    #[tokio::test]
+    async fn open_fails_without_firmware() {
+        let path = temp_path("uwb-firmware");
+        std::fs::write(&path, [0xaa; 8]).unwrap();
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_firmware(Some(flaky_firmware(path.clone(), 2)));
+        let (recorder, client) = callbacks();
+        let status = chip.open(&client).await.unwrap_err();
+        assert_eq!(status.service_specific_error(), UwbStatus::ERR_TRANSPORT.0);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::ERROR, UwbStatus::ERR_TRANSPORT)]
+        );
+        uwbs.expect(&[0xaa; 4]);
+        uwbs.expect(&[0xaa; 4]);
+        assert_eq!(chip.stats().firmware_download_failures, 2);
+        assert_eq!(chip.stats().firmware_downloads, 0);
+
+        // Nor without image, which is not worth retrying.
+        std::fs::remove_file(&path).unwrap();
+        let status = chip.open(&client).await.unwrap_err();
+        assert_eq!(status.service_specific_error(), UwbStatus::ERR_TRANSPORT.0);
+        assert_eq!(chip.stats().firmware_download_failures, 2);
+    }
+
+    /// Downloader taking its time, for the chip to stay in the opening
+    /// state.
+    struct SlowDownloader;
+
+    #[async_trait]
+    impl FirmwareDownloader for SlowDownloader {
+        async fn download(&self, _: &mut dyn AsyncTransport, _: &[u8]) -> io::Result<()> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_opens_are_refused() {
+        let path = temp_path("uwb-firmware");
+        std::fs::write(&path, [0xaa; 8]).unwrap();
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport).with_firmware(Some(
+            FirmwareConfig {
+                downloader: Arc::new(SlowDownloader),
+                ..FirmwareConfig::new(path.clone())
+            },
+        ));
+        let (recorder, client) = callbacks();
+        let (first, second) = tokio::join!(chip.open(&client), async {
+            tokio::task::yield_now().await;
+            assert!(matches!(*chip.state.lock().await, State::Opening));
+            chip.open(&client).await
+        });
+        std::fs::remove_file(&path).unwrap();
+        first.unwrap();
+        assert_eq!(
+            second.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+        let State::Opened(ref session) = *chip.state.lock().await else {
+            panic!("chip not opened");
+        };
+        assert_eq!(session.open_ref_count, 1);
+    }
+
+    #[tokio::test]
+    async fn reopen_is_reserved_to_the_client() {
+        let (transport, _uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, client) = callbacks();
+        let (other_recorder, other_client) = callbacks();
+        chip.open(&client).await.unwrap();
+        chip.open(&client).await.unwrap();
+        assert_eq!(
+            chip.open(&other_client).await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK),
+                (UwbEvent::OPEN_CPLT, UwbStatus::OK)
+            ]
+        );
+        assert!(other_recorder.events.lock().unwrap().is_empty());
+        let State::Opened(ref session) = *chip.state.lock().await else {
+            panic!("chip not opened");
+        };
+        assert_eq!(session.open_ref_count, 2);
+        assert!(session.callbacks.as_binder() == client.as_binder());
+    }
+
+    #[tokio::test]
+    async fn open_resets_the_uwbs_first() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport).with_reset_on_open(true);
+        let (recorder, client) = callbacks();
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&DEVICE_RESET_CMD);
+            // Left by the previous session.
+            uwbs.inject(&DEVICE_STATUS_ACTIVE_NTF);
+            uwbs.inject(&DEVICE_RESET_RSP);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        chip.open(&client).await.unwrap();
+        let mut uwbs = device.join().unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+
+        // The packets of the reset exchange never reach the client.
+        uwbs.inject(&DEVICE_STATUS_ACTIVE_NTF);
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_ACTIVE_NTF.to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn open_yields_while_waiting_for_the_reset() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport).with_reset_on_open(true);
+        let (_recorder, client) = callbacks();
+        let (respond, responded) = std::sync::mpsc::channel();
+        let device = std::thread::spawn(move || {
+            uwbs.expect(&DEVICE_RESET_CMD);
+            responded.recv().unwrap();
+            uwbs.inject(&DEVICE_RESET_RSP);
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            uwbs
+        });
+        // The test runtime has a single thread: the UWBS only answers once
+        // the open let the other calls run.
+        let (opened, ()) = tokio::join!(chip.open(&client), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(chip.getName().await.unwrap(), "0");
+            respond.send(()).unwrap();
+        });
+        opened.unwrap();
+        let _uwbs = device.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_fails_if_the_uwbs_does_not_reset() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let timeout = Duration::from_millis(50);
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_reset_on_open(true)
+            .with_close_timeout(timeout);
+        let (recorder, client) = callbacks();
+        let status = chip.open(&client).await.unwrap_err();
+        assert_eq!(
+            status.service_specific_error(),
+            UwbStatus::ERR_CMD_TIMEOUT.0
+        );
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::ERROR, UwbStatus::ERR_CMD_TIMEOUT)]
+        );
+        uwbs.expect(&DEVICE_RESET_CMD);
+
+        // The open can be retried.
+        let device = uwbs.respond_to_reset();
+        chip.open(&client).await.unwrap();
+        let _uwbs = device.join().unwrap();
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::OPEN_CPLT, UwbStatus::OK))
+        );
+    }
+
+    #[tokio::test]
+    async fn emulated_uwbs_answers_commands() {
+        use crate::transport::EmulatorTransport;
+
+        let chip = UwbChip::with_transport(
+            "0".to_owned(),
+            EmulatorTransport::new(Duration::from_millis(10)),
+        );
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // CORE_GET_DEVICE_INFO.
+        chip.sendUciMessage(&[0x20, 0x02, 0x00, 0x00])
+            .await
+            .unwrap();
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            recorder.messages.lock().unwrap()[0][..5],
+            [0x40, 0x02, 0x00, 0x0a, 0x00]
+        );
+
+        chip.close().await.unwrap();
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_is_stopped_by_close() {
+        use crate::transport::{ReplayConfig, ReplayTransport};
+
+        let path = temp_path("uwb-replay");
+        // The second notification comes a minute later.
+        std::fs::write(&path, "0 rx 6001000101\n60000 rx 6001000102\n").unwrap();
+        let mut chip = UwbChip::with_transport(
+            "0".to_owned(),
+            ReplayTransport::new(path.clone(), ReplayConfig::default()),
+        )
+        .with_close_timeout(Duration::from_millis(50));
+        chip.flap_guard = Arc::new(std::sync::Mutex::new(FlapGuard::new(FlapGuardConfig {
+            min_interval: Duration::ZERO,
+            ..Default::default()
+        })));
+        let (recorder, callbacks) = callbacks();
+        // The trace is replayed from the start on every open.
+        for _ in 0..2 {
+            chip.open(&callbacks).await.unwrap();
+            wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+            assert_eq!(
+                recorder
+                    .messages
+                    .lock()
+                    .unwrap()
+                    .drain(..)
+                    .collect::<Vec<_>>(),
+                vec![DEVICE_STATUS_NTF.to_vec()]
+            );
+
+            let start = Instant::now();
+            chip.close().await.unwrap();
+            assert!(start.elapsed() < Duration::from_secs(1));
+            assert_eq!(
+                recorder.events.lock().unwrap().last(),
+                Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn watchdog_reports_silent_uwbs() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let timeout = Duration::from_millis(100);
+        let chip =
+            UwbChip::with_transport("0".to_owned(), transport).with_watchdog_timeout(Some(timeout));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        let error = (UwbEvent::ERROR, UwbStatus::FAILED);
+
+        // Not armed until the UWBS sends its first packet.
+        tokio::time::sleep(2 * timeout).await;
+        assert!(!recorder.events.lock().unwrap().contains(&error));
+
+        // Traffic keeps the watchdog from firing.
+        for _ in 0..4 {
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            tokio::time::sleep(timeout / 2).await;
+        }
+        assert!(!recorder.events.lock().unwrap().contains(&error));
+
+        wait_for(|| recorder.events.lock().unwrap().contains(&error)).await;
+        assert_eq!(
+            chip.close().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    /// Health check probing after `idle_ms`, waiting 50 ms for each of up
+    /// to three probes.
+    fn health_check(idle_ms: u64) -> HealthCheckConfig {
+        HealthCheckConfig {
+            timeout: Duration::from_millis(50),
+            max_misses: 3,
+            ..HealthCheckConfig::new(Duration::from_millis(idle_ms))
+        }
+    }
+
+    #[tokio::test]
+    async fn health_check_keeps_responsive_uwbs_open() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_health_check(Some(health_check(50)));
+        let (recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+
+        let device = std::thread::spawn(move || {
+            for _ in 0..5 {
+                uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+                uwbs.inject(&[0x40, 0x02, 0x00, 0x01, 0x00]);
+            }
+            uwbs
+        });
+        wait_for(|| device.is_finished()).await;
+        let _uwbs = device.join().unwrap();
+        // The probe responses are not delivered to the client.
+        assert!(recorder.messages.lock().unwrap().is_empty());
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+    }
+
+    #[tokio::test]
+    async fn health_check_reports_unresponsive_uwbs() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let config = health_check(50);
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_health_check(Some(config.clone()));
+        let (recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+        let opened_at = Instant::now();
+
+        let error = (UwbEvent::ERROR, UwbStatus::FAILED);
+        wait_for(|| recorder.events.lock().unwrap().contains(&error)).await;
+        assert!(opened_at.elapsed() >= 3 * (config.idle + config.timeout));
+        for _ in 0..3 {
+            uwbs.expect(&CORE_GET_DEVICE_INFO_CMD);
+        }
+        // Left for the client to reopen.
+        assert_eq!(
+            chip.close().await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+    }
+
+    #[tokio::test]
+    async fn health_check_is_suppressed_by_activity() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let config = health_check(100);
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_health_check(Some(config.clone()));
+        let probes = Arc::new(AtomicUsize::new(0));
+        chip.observers.register("probes", {
+            let probes = probes.clone();
+            move |direction, _, packet| {
+                if direction == Direction::Tx && packet == CORE_GET_DEVICE_INFO_CMD {
+                    probes.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+        });
+        let (recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+
+        // Not while a command of the client awaits its response.
+        chip.sendUciMessage(&CORE_GET_CAPS_INFO_CMD).await.unwrap();
+        tokio::time::sleep(config.idle + config.timeout).await;
+        assert_eq!(probes.load(Ordering::Relaxed), 0);
+        uwbs.inject(&[0x40, 0x03, 0x00, 0x02, 0x00, 0x00]);
+        wait_for(|| probes.load(Ordering::Relaxed) == 1).await;
+
+        // Nor while the UWBS is sending packets.
+        for _ in 0..6 {
+            uwbs.inject(&DEVICE_STATUS_NTF);
+            tokio::time::sleep(config.idle / 2).await;
+        }
+        assert_eq!(probes.load(Ordering::Relaxed), 1);
+        assert!(!recorder
+            .events
+            .lock()
+            .unwrap()
+            .contains(&(UwbEvent::ERROR, UwbStatus::FAILED)));
+    }
+
+    #[tokio::test]
+    async fn pty_open_message_close_flow() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![(UwbEvent::OPEN_CPLT, UwbStatus::OK)]
+        );
+
+        master.write_all(&DEVICE_STATUS_NTF).unwrap();
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+
+        // CORE_GET_DEVICE_INFO.
+        let command = [0x20, 0x02, 0x00, 0x00];
+        chip.sendUciMessage(&command).await.unwrap();
+        flush_writes().await;
+        let mut buffer = [0; 4];
+        master.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, command);
+
+        let responder = respond_to_reset(master);
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+        );
+    }
+
+    #[tokio::test]
+    async fn packet_split_across_writes_is_delivered() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Truncated header, then the payload on its own. The remaining
+        // bytes are read synchronously by the chip, so they are written
+        // from another thread, as a device would.
+        let writer = std::thread::spawn(move || {
+            for chunk in [
+                &DEVICE_STATUS_NTF[..1],
+                &DEVICE_STATUS_NTF[1..3],
+                &DEVICE_STATUS_NTF[3..4],
+                &DEVICE_STATUS_NTF[4..],
+            ] {
+                master.write_all(chunk).unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            master
+        });
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![DEVICE_STATUS_NTF.to_vec()]
+        );
+        writer.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn close_waits_for_delayed_reset_response() {
+        let (mut master, _slave, path) = pty();
+        let chip = uart_chip(path);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        let delay = DEFAULT_CLOSE_TIMEOUT / 2;
+        let responder = std::thread::spawn(move || {
+            let mut command = [0; 5];
+            master.read_exact(&mut command).unwrap();
+            assert_eq!(command, DEVICE_RESET_CMD);
+            std::thread::sleep(delay);
+            master.write_all(&DEVICE_RESET_RSP).unwrap();
+            master.write_all(&DEVICE_STATUS_NTF).unwrap();
+            master
+        });
+        let start = Instant::now();
+        chip.close().await.unwrap();
+        assert!(start.elapsed() >= delay);
+        responder.join().unwrap();
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&(UwbEvent::CLOSE_CPLT, UwbStatus::OK))
+        );
+    }
+
+    #[tokio::test]
+    async fn unplugged_chip_is_torn_down_until_replugged() {
+        // The device node is a link to a pty, removed to unplug the UWBS.
+        let link = temp_path("uwb-hotplug");
+        let mut chip = UwbChip::with_transport(
+            "0".to_owned(),
+            UartTransport::new(DeviceSelector::Path(link.clone()), OpenConfig::default()),
+        )
+        .with_hotplug(Some(link.clone()));
+        chip.flap_guard = Arc::new(std::sync::Mutex::new(FlapGuard::new(FlapGuardConfig {
+            min_interval: Duration::ZERO,
+            ..Default::default()
+        })));
+        let chip = Arc::new(chip);
+        let plugs = Arc::new(AtomicU64::new(0));
+        tokio::spawn(chip.clone().watch_device({
+            let plugs = plugs.clone();
+            move || {
+                plugs.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        let (recorder, callbacks) = callbacks();
+        assert_eq!(
+            chip.open(&callbacks).await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        let (_master, _slave, path) = pty();
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        wait_for(|| plugs.load(Ordering::Relaxed) == 1).await;
+        chip.open(&callbacks).await.unwrap();
+
+        // The reader task is terminated although the pty is still open.
+        std::fs::remove_file(&link).unwrap();
+        wait_for(|| recorder.events.lock().unwrap().len() == 2).await;
+        assert_eq!(
+            recorder.events.lock().unwrap()[1],
+            (UwbEvent::ERROR, UwbStatus::ERR_TRANSPORT)
+        );
+        assert!(matches!(*chip.state.lock().await, State::Closed));
+        assert_eq!(
+            chip.open(&callbacks).await.unwrap_err().exception_code(),
+            binder::ExceptionCode::ILLEGAL_STATE
+        );
+
+        let (_master, _slave, path) = pty();
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        wait_for(|| plugs.load(Ordering::Relaxed) == 2).await;
+        chip.open(&callbacks).await.unwrap();
+        std::fs::remove_file(&link).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stats_survive_close() {
+        const N: u64 = 5;
+        let (mut master, _slave, path) = pty();
+        let mut chip = uart_chip(path);
+        chip.flap_guard = Arc::new(std::sync::Mutex::new(FlapGuard::new(FlapGuardConfig {
+            min_interval: Duration::ZERO,
+            ..Default::default()
+        })));
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        for _ in 0..N {
+            chip.sendUciMessage(&DEVICE_STATUS_NTF).await.unwrap();
+        }
+        flush_writes().await;
+        let mut buffer = [0; 5 * N as usize];
+        master.read_exact(&mut buffer).unwrap();
+        master.write_all(&DEVICE_STATUS_NTF).unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 1).await;
+
+        let responder = respond_to_reset(master);
+        chip.close().await.unwrap();
+        let _master = responder.join().unwrap();
+        chip.open(&callbacks).await.unwrap();
+
+        assert_eq!(
+            chip.stats(),
+            UwbChipStats {
+                tx_packets: N,
+                rx_packets: 1,
+                tx_bytes: 5 * N,
+                rx_bytes: 5,
+                tx_errors: 0,
+                rx_errors: 0,
+                open_count: 2,
+                close_count: 1,
+                ..Default::default()
+            }
+        );
+        // The packet statistics since open start over.
+        let packet_stats = chip.packet_stats();
+        assert_eq!(packet_stats.since_open, Default::default());
+        assert_eq!(packet_stats.lifetime.tx.notification.packets, N);
+        assert_eq!(packet_stats.lifetime.tx.command.packets, 1);
+        assert_eq!(packet_stats.lifetime.rx.notification.packets, 2);
+        assert!(packet_stats.last_close.is_some());
+    }
+
+    #[tokio::test]
+    async fn fragments_are_delivered_without_reassembly() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        let fragments = [
+            [0x7e, 0x01, 0x00, 0x01, 0xaa],
+            [0x6e, 0x01, 0x00, 0x01, 0xbb],
+        ];
+        device_tx.write_all(&fragments.concat()).unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+        assert_eq!(*recorder.messages.lock().unwrap(), fragments);
+    }
+
+    #[tokio::test]
+    async fn packets_are_fragmented_and_reassembled() {
+        let (transport, mut device_rx, mut device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_max_packet_size(2)
+            .with_reassembly(true);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Vendor notification split in two fragments.
+        device_tx
+            .write_all(&[0x7e, 0x01, 0x00, 0x01, 0xaa])
+            .unwrap();
+        device_tx
+            .write_all(&[0x6e, 0x01, 0x00, 0x01, 0xbb])
+            .unwrap();
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            vec![vec![0x6e, 0x01, 0x00, 0x02, 0xaa, 0xbb]]
+        );
+
+        let command = [0x2e, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03];
+        assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 7);
+        flush_writes().await;
+        let mut buffer = [0; 11];
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(
+            buffer,
+            [0x3e, 0x01, 0x00, 0x02, 0x01, 0x02, 0x2e, 0x01, 0x00, 0x01, 0x03]
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_control_packet_is_fragmented() {
+        let (transport, mut device_rx, _device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // Vendor command with a 300 bytes payload, which does not fit in
+        // the length field.
+        let mut command = vec![0x2e, 0x01, 0x00, 0x2c];
+        command.extend((0..300).map(|i| i as u8));
+        assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 304);
+        flush_writes().await;
+        let mut buffer = [0; 308];
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[..4], [0x3e, 0x01, 0x00, 0xff]);
+        assert_eq!(buffer[4..259], command[4..259]);
+        assert_eq!(buffer[259..263], [0x2e, 0x01, 0x00, 0x2d]);
+        assert_eq!(buffer[263..], command[259..]);
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate-uci"))]
+    #[test]
+    fn packets_are_decoded_before_sending() {
+        // CORE_GET_DEVICE_INFO_CMD, and DATA_MESSAGE_SND.
+        assert!(validate_packet(&[0x20, 0x02, 0x00, 0x00]).is_ok());
+        assert!(validate_packet(&[0x01, 0x00, 0x02, 0x00, 0xab, 0xcd]).is_ok());
+        // Truncated header.
+        assert!(validate_packet(&[0x20, 0x02]).is_err());
+        // Payloads shorter than advertised.
+        assert!(validate_packet(&[0x20, 0x04, 0x00, 0x03, 0x01]).is_err());
+        assert!(validate_packet(&[0x01, 0x00, 0x08, 0x00, 0xab]).is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_packet_is_not_sent() {
+        let (transport, mut device_rx, _device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // The header advertises a 200 bytes payload.
+        let err = chip
+            .sendUciMessage(&[0x2e, 0x01, 0x00, 0xc8, 0x01, 0x02])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.exception_code(),
+            binder::ExceptionCode::ILLEGAL_ARGUMENT
+        );
+        assert_eq!(chip.invalid_packets.load(Ordering::Relaxed), 1);
+
+        let command = [0x2e, 0x01, 0x00, 0x02, 0x01, 0x02];
+        assert_eq!(chip.sendUciMessage(&command).await.unwrap(), 6);
+        flush_writes().await;
+        let mut buffer = [0; 6];
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, command);
+    }
+
+    #[tokio::test]
+    async fn data_messages_wait_for_credits() {
+        let (transport, mut device_rx, mut device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // DATA_MESSAGE_SND for session 1.
+        let data = |byte| [0x01, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, byte];
+        let mut buffer = [0; 9];
+        assert_eq!(chip.sendUciMessage(&data(0xaa)).await.unwrap(), 9);
+        flush_writes().await;
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data(0xaa));
+
+        // The credit has been consumed: the next messages are queued, and
+        // control messages are written regardless.
+        assert_eq!(chip.sendUciMessage(&data(0xbb)).await.unwrap(), 9);
+        assert_eq!(chip.sendUciMessage(&data(0xcc)).await.unwrap(), 9);
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        let mut command = [0; 4];
+        device_rx.read_exact(&mut command).unwrap();
+        assert_eq!(command, CORE_GET_DEVICE_INFO_CMD);
+
+        // DATA_CREDIT_NTF making a credit available for session 1, each
+        // releasing a queued message.
+        let credit_ntf = [0x62, 0x04, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x01];
+        for (count, byte) in [(1, 0xbb), (2, 0xcc)] {
+            device_tx.write_all(&credit_ntf).unwrap();
+            wait_for(|| recorder.messages.lock().unwrap().len() == count).await;
+            flush_writes().await;
+            device_rx.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, data(byte));
+        }
+
+        // A credit left unused once the queue is empty.
+        device_tx.write_all(&credit_ntf).unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 3).await;
+        assert_eq!(chip.sendUciMessage(&data(0xdd)).await.unwrap(), 9);
+        flush_writes().await;
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data(0xdd));
+
+        // The oldest message is dropped from a full queue.
+        for byte in 0..=DATA_QUEUE_CAPACITY as u8 {
+            chip.sendUciMessage(&data(byte)).await.unwrap();
+        }
+        assert_eq!(chip.stats().data_messages_dropped, 1);
+        device_tx.write_all(&credit_ntf).unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 4).await;
+        flush_writes().await;
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data(1));
+    }
+
+    #[tokio::test]
+    async fn session_deinit_drops_queued_data_messages() {
+        let (transport, mut device_rx, mut device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport);
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+
+        // DATA_MESSAGE_SND for session 1, the second one left queued.
+        let data = [0x01, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0xaa];
+        chip.sendUciMessage(&data).await.unwrap();
+        chip.sendUciMessage(&data).await.unwrap();
+        flush_writes().await;
+        let mut buffer = [0; 9];
+        device_rx.read_exact(&mut buffer).unwrap();
+
+        device_tx.write_all(&session_deinit_ntf(1)).unwrap();
+        // A DATA_TRANSFER_STATUS_NTF reporting a message sent without
+        // credit revokes the credit of the new session.
+        device_tx
+            .write_all(&[
+                0x62, 0x05, 0x00, 0x08, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00,
+            ])
+            .unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+        chip.sendUciMessage(&data).await.unwrap();
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("data messages awaiting credits: 1"),
+            "{}",
+            output
+        );
+
+        // The credit only releases the message sent since the session was
+        // deinitialized.
+        let credit_ntf = [0x62, 0x04, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x01];
+        device_tx.write_all(&credit_ntf).unwrap();
+        device_tx.write_all(&credit_ntf).unwrap();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 4).await;
+        flush_writes().await;
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data);
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("data messages awaiting credits: 0"),
+            "{}",
+            output
+        );
+    }
+
+    /// Packets sent by the UWBS in the framing tests, as a vendor
+    /// notification split in two fragments, and a data packet.
+    const FRAMED_PACKETS: [&[u8]; 3] = [
+        &[0x7e, 0x01, 0x00, 0x01, 0xaa],
+        &[0x6e, 0x01, 0x00, 0x01, 0xbb],
+        &[0x01, 0x00, 0x02, 0x00, 0x01, 0x02],
+    ];
+
+    async fn receive_framed_packets(
+        chip: UwbChip<MockTransport>,
+        send: impl FnOnce(),
+    ) -> Vec<Vec<u8>> {
+        let (recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        send();
+        wait_for(|| recorder.messages.lock().unwrap().len() == 2).await;
+        let messages = recorder.messages.lock().unwrap().clone();
+        messages
+    }
+
+    #[tokio::test]
+    async fn byte_stream_and_packet_framing_deliver_same_packets() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport).with_reassembly(true);
+        let stream = receive_framed_packets(chip, || {
+            // Packets are not delimited on a byte stream.
+            device_tx.write_all(&FRAMED_PACKETS.concat()).unwrap();
+        })
+        .await;
+
+        let (transport, mut device) = MockTransport::seqpacket();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_framing(Framing::PacketPerRead)
+            .with_reassembly(true);
+        let packet = receive_framed_packets(chip, || {
+            for packet in FRAMED_PACKETS {
+                device.write_all(packet).unwrap();
+            }
+        })
+        .await;
+
+        let expected = vec![
+            vec![0x6e, 0x01, 0x00, 0x02, 0xaa, 0xbb],
+            vec![0x01, 0x00, 0x02, 0x00, 0x01, 0x02],
+        ];
+        assert_eq!(stream, expected);
+        assert_eq!(packet, expected);
+    }
+
+    #[tokio::test]
+    async fn packet_framing_drops_inconsistent_packets() {
+        let (transport, mut device) = MockTransport::seqpacket();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_framing(Framing::PacketPerRead)
+            .with_reassembly(true);
+        let messages = receive_framed_packets(chip, || {
+            // Shorter than the header.
+            device.write_all(&[0x60, 0x01]).unwrap();
+            // Truncated payload.
+            device.write_all(&[0x60, 0x01, 0x00, 0x02, 0x01]).unwrap();
+            // Trailing bytes.
+            device.write_all(&[0x60, 0x01, 0x00, 0x00, 0x01]).unwrap();
+            for packet in FRAMED_PACKETS {
+                device.write_all(packet).unwrap();
+            }
+        })
+        .await;
+        assert_eq!(
+            messages,
+            vec![
+                vec![0x6e, 0x01, 0x00, 0x02, 0xaa, 0xbb],
+                vec![0x01, 0x00, 0x02, 0x00, 0x01, 0x02],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_with_packet_framing() {
+        let (transport, mut device) = MockTransport::seqpacket();
+        device.write_all(&[0x60, 0x01, 0x00, 0x02, 0x01]).unwrap();
+        device.write_all(&DEVICE_RESET_RSP).unwrap();
+        device.write_all(&DEVICE_STATUS_NTF).unwrap();
+        consume_device_reset_rsp_and_ntf(
+            &mut reader(transport),
+            Framing::PacketPerRead,
+            DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE,
+            &ObserverRegistry::default(),
+            DEFAULT_CLOSE_TIMEOUT,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn hdlc_framing_drops_corrupted_frames() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_framing(Framing::Hdlc)
+            .with_reassembly(true);
+        let stats = chip.stats.clone();
+        let messages = receive_framed_packets(chip, || {
+            let mut corrupted = hdlc::encode(&[0x60, 0x01, 0x00, 0x01, 0x01]);
+            corrupted[5] ^= 0xff;
+            let mut stream = corrupted;
+            for packet in FRAMED_PACKETS {
+                stream.extend(hdlc::encode(packet));
+            }
+            // Frames are not aligned on the writes.
+            for chunk in stream.chunks(3) {
+                device_tx.write_all(chunk).unwrap();
+            }
+        })
+        .await;
+        assert_eq!(
+            messages,
+            vec![
+                vec![0x6e, 0x01, 0x00, 0x02, 0xaa, 0xbb],
+                vec![0x01, 0x00, 0x02, 0x00, 0x01, 0x02],
+            ]
+        );
+        let stats = *stats.lock().unwrap();
+        assert_eq!((stats.rx_packets, stats.framing_errors), (3, 1));
+
+        let (transport, mut device_rx, _device_tx) = MockTransport::new();
+        let chip = UwbChip::with_transport("0".to_owned(), transport).with_framing(Framing::Hdlc);
+        let (_recorder, callbacks) = callbacks();
+        chip.open(&callbacks).await.unwrap();
+        chip.sendUciMessage(&CORE_GET_DEVICE_INFO_CMD)
+            .await
+            .unwrap();
+        flush_writes().await;
+        let frame = hdlc::encode(&CORE_GET_DEVICE_INFO_CMD);
+        let mut buffer = vec![0; frame.len()];
+        device_rx.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, frame);
+    }
+
+    #[tokio::test]
+    async fn reset_with_hdlc_framing() {
+        let (transport, _device_rx, mut device_tx) = MockTransport::new();
+        let mut stream = hdlc::encode(&[0x60, 0x01, 0x00, 0x02, 0x01]);
+        // Frames sharing their flags.
+        stream.extend(&hdlc::encode(&DEVICE_RESET_RSP)[1..]);
+        stream.extend(&hdlc::encode(&DEVICE_STATUS_NTF)[1..]);
+        device_tx.write_all(&stream).unwrap();
+        consume_device_reset_rsp_and_ntf(
+            &mut reader(transport),
+            Framing::Hdlc,
+            DEFAULT_MAX_RX_DATA_PAYLOAD_SIZE,
+            &ObserverRegistry::default(),
+            DEFAULT_CLOSE_TIMEOUT,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn dump_lists_state_and_last_packets() {
+        let (chip, mut uwbs, recorder) = mock_chip().await;
+        let command = [0x2e, 0x01, 0x00, 0x02, 0xaa, 0xbb];
+        chip.sendUciMessage(&command).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&command);
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| !recorder.messages.lock().unwrap().is_empty()).await;
+
+        let dump = |verbose| {
+            let mut output = Vec::new();
+            chip.dump(&mut output, verbose, DumpFormat::Text).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let output = dump(false);
+        assert!(output.contains("state: opened for "), "{}", output);
+        assert!(output.contains("client alive"), "{}", output);
+        assert!(output.contains("device: unknown"), "{}", output);
+        assert!(output.contains("tx: 1 packets, 6 bytes, 0 errors; rx: 1 packets, 5 bytes"));
+        assert!(output.contains("Tx [2e, 01, 00, 02]\n"), "{}", output);
+        assert!(output.contains("Rx [60, 01, 00, 01]\n"), "{}", output);
+        assert!(
+            output.contains("since open: tx cmd 1/6B rsp 0/0B ntf 0/0B data 0/0B; rx cmd 0/0B"),
+            "{}",
+            output
+        );
+        assert!(output.contains("last open: "), "{}", output);
+        assert!(!output.contains("last close: "), "{}", output);
+        let output = dump(true);
+        assert!(
+            output.contains("Tx [2e, 01, 00, 02, aa, bb]\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("Rx [60, 01, 00, 01, 01]\n"), "{}", output);
+
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Json).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("{\"schema_version\":1,"), "{}", output);
+        assert!(output.contains("\"state\":\"opened\""), "{}", output);
+        assert!(
+            output.contains("{\"timestamp_us\":") && output.contains("\"packet\":\"2e010002\"}"),
+            "{}",
+            output
+        );
+        let mut output = Vec::new();
+        chip.dump_stats(&mut output, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("tx: 1 packets, 6 bytes"), "{}", output);
+        assert!(!output.contains("last packets"), "{}", output);
+
+        // A chip stuck with the state lock held is still dumped.
+        let state = chip.state.lock().await;
+        let output = dump(false);
+        assert!(output.contains("state lock held, possibly stuck in close"));
+        assert!(output.contains("tx: 1 packets"));
+        drop(state);
+
+        let responder = uwbs.respond_to_reset();
+        chip.close().await.unwrap();
+        responder.join().unwrap();
+        assert!(dump(false).contains("state: closed"));
+    }
+
+    #[tokio::test]
+    async fn lost_responses_are_counted() {
+        let (transport, mut uwbs) = MockTransport::with_uwbs();
+        let chip = UwbChip::with_transport("0".to_owned(), transport)
+            .with_response_window(Duration::from_millis(100));
+        let (recorder, client) = callbacks();
+        chip.open(&client).await.unwrap();
+
+        chip.sendUciMessage(&CORE_GET_CAPS_INFO_CMD).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&CORE_GET_CAPS_INFO_CMD);
+        uwbs.inject(&[0x40, 0x03, 0x00, 0x02, 0x00, 0x00]);
+        wait_for(|| chip.commands.stats().responses == 1).await;
+
+        // Not answered by the notifications which follow.
+        let command = [0x2e, 0x01, 0x00, 0x02, 0xaa, 0xbb];
+        chip.sendUciMessage(&command).await.unwrap();
+        flush_writes().await;
+        uwbs.expect(&command);
+        uwbs.inject(&DEVICE_STATUS_NTF);
+        wait_for(|| chip.commands.stats().lost_responses == 1).await;
+        // The client gets all the packets regardless.
+        assert_eq!(recorder.messages.lock().unwrap().len(), 2);
+
+        let mut output = Vec::new();
+        chip.dump(&mut output, false, DumpFormat::Text).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("client commands: 1 responses, latency mean "),
+            "{}",
+            output
+        );
+        assert!(output.contains(", 1 lost\n"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn crash_reporter_does_not_block() {
+        let chip = uart_chip("/dev/null".to_owned());
+        let reporter = chip.crash_reporter();
+
+        let mut report = String::new();
+        reporter.report(&mut report).unwrap();
+        assert_eq!(
+            report,
+            "chip 0 (/dev/null): state=closed monitor=false forced_yields=0 invalid_packets=0 \
+             tx_packets=0 rx_packets=0 tx_errors=0 rx_errors=0 opens=0 closes=0 \
+             cycles=0 rejected_opens=0"
+        );
+
+        let _guard = chip.state.lock().await;
+        let mut report = String::new();
+        reporter.report(&mut report).unwrap();
+        assert!(report.contains("state=busy"));
+    }
 }